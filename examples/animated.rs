@@ -1,6 +1,8 @@
 use bevy::prelude::*;
 use bevy_egui::{egui, EguiContexts, EguiPlugin};
-use bevy_fsc_point_cloud::{PointCloudAsset, PointCloudPlaybackControls, PotreePointCloud};
+use bevy_fsc_point_cloud::{
+    PointCloudAsset, PointCloudBundle, PointCloudPlaybackControls, PotreePointCloud,
+};
 use smooth_bevy_cameras::{
     controllers::fps::{FpsCameraBundle, FpsCameraController, FpsCameraPlugin},
     LookTransformPlugin,
@@ -11,7 +13,7 @@ fn main() {
         .add_plugins((
             DefaultPlugins.set(WindowPlugin::default()),
             EguiPlugin,
-            bevy_fsc_point_cloud::PointCloudPlugin,
+            bevy_fsc_point_cloud::PointCloudPlugin::default(),
             FpsCameraPlugin::default(),
             LookTransformPlugin,
         ))
@@ -38,17 +40,15 @@ fn startup(mut commands: Commands, asset_server: Res<AssetServer>) {
 
     let point_cloud: Handle<PointCloudAsset> = asset_server.load("replay.opd");
 
-    commands
-        .spawn(PotreePointCloud {
+    commands.spawn(PointCloudBundle {
+        point_cloud: PotreePointCloud {
             mesh: point_cloud.clone(),
             point_size: 1.0,
-        })
-        .insert(SpatialBundle {
-            transform: Transform::from_rotation(Quat::from_rotation_x(
-                -std::f32::consts::FRAC_PI_2,
-            )),
             ..Default::default()
-        });
+        },
+        transform: Transform::from_rotation(Quat::from_rotation_x(-std::f32::consts::FRAC_PI_2)),
+        ..Default::default()
+    });
 
     commands.insert_resource(PointCloud(point_cloud));
 }
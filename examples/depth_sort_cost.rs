@@ -0,0 +1,118 @@
+//! Measures what a `PointDepthSort` of 1M points costs per frame. The same alpha blended cloud
+//! is rendered with the headless renderer with and without the sort, and the median frame
+//! times are compared. Every frame is read back, so the frame times include the GPU work.
+//!
+//! Run with `cargo run --release --example depth_sort_cost --features headless`.
+
+use std::time::{Duration, Instant};
+
+use bevy::{
+    core_pipeline::tonemapping::{DebandDither, Tonemapping},
+    prelude::*,
+    render::renderer::RenderAdapterInfo,
+};
+use bevy_fsc_point_cloud::{
+    EyeDomeSettings, HeadlessRenderer, PointBlendMode, PointCloudAsset, PointCloudBundle,
+    PointCloudPlugin, PointDepthSort, PotreePointCloud,
+};
+
+const POINTS: usize = 1_000_000;
+const FRAMES: usize = 30;
+
+fn main() {
+    let mut renderer = HeadlessRenderer::new(UVec2::new(512, 512), PointCloudPlugin::default());
+    let target = renderer.render_target();
+    let world = renderer.world_mut();
+    world.insert_resource(Msaa::Off);
+    world.spawn((
+        Camera3dBundle {
+            camera: Camera {
+                target,
+                ..default()
+            },
+            tonemapping: Tonemapping::None,
+            dither: DebandDither::Disabled,
+            transform: Transform::from_xyz(0.0, 0.0, 3.0).looking_at(Vec3::ZERO, Vec3::Y),
+            ..default()
+        },
+        EyeDomeSettings {
+            enabled: false,
+            ..default()
+        },
+    ));
+
+    // Points spread evenly through a cube, in the pseudo random order of an LCG.
+    let mut state = 1u32;
+    let mut random = move || {
+        state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+        (state >> 8) as f32 / (1 << 24) as f32
+    };
+    let positions: Vec<_> = (0..POINTS)
+        .map(|_| Vec3::new(random(), random(), random()) * 2.0 - 1.0)
+        .collect();
+    let colors = positions
+        .iter()
+        .map(|position| {
+            let color = (*position * 0.5 + 0.5) * 255.0;
+            [color.x as u8, color.y as u8, color.z as u8, 128]
+        })
+        .collect();
+    let mesh = world
+        .resource_mut::<Assets<PointCloudAsset>>()
+        .add(PointCloudAsset::from_points(positions, colors).unwrap());
+    let point_cloud = world
+        .spawn((
+            PointCloudBundle {
+                point_cloud: PotreePointCloud {
+                    mesh,
+                    point_size: 0.01,
+                    ..default()
+                },
+                ..default()
+            },
+            PointBlendMode::AlphaBlend,
+        ))
+        .id();
+
+    settle(&mut renderer);
+    let unsorted = median_frame_time(&mut renderer);
+    renderer
+        .world_mut()
+        .entity_mut(point_cloud)
+        .insert(PointDepthSort);
+    settle(&mut renderer);
+    let sorted = median_frame_time(&mut renderer);
+
+    let adapter = &renderer.world_mut().resource::<RenderAdapterInfo>().0;
+    println!("{} ({:?})", adapter.name, adapter.backend);
+    println!("{POINTS} points, median of {FRAMES} frames");
+    println!("without sort: {unsorted:.2?}");
+    println!("with sort:    {sorted:.2?}");
+    println!("sort:         {:.2?}", sorted.saturating_sub(unsorted));
+}
+
+/// Renders until two frames in a row are the same, so that the pipelines have compiled and
+/// the points are uploaded.
+fn settle(renderer: &mut HeadlessRenderer) {
+    let mut previous = None;
+    for _ in 0..100 {
+        let frame = renderer.render(1);
+        if frame.is_some() && frame == previous {
+            return;
+        }
+        previous = frame;
+    }
+    panic!("the frame didn't settle");
+}
+
+fn median_frame_time(renderer: &mut HeadlessRenderer) -> Duration {
+    let mut times: Vec<_> = (0..FRAMES)
+        .map(|_| {
+            let start = Instant::now();
+            renderer.render(1);
+            start.elapsed()
+        })
+        .collect();
+    times.sort();
+    times[FRAMES / 2]
+}
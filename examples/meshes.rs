@@ -0,0 +1,56 @@
+use bevy::prelude::*;
+use bevy_fsc_point_cloud::{PointCloudAsset, PointCloudBundle, PotreePointCloud};
+
+fn main() {
+    App::new()
+        .add_plugins((
+            DefaultPlugins.set(WindowPlugin::default()),
+            bevy_fsc_point_cloud::PointCloudPlugin::default(),
+        ))
+        .add_systems(Startup, startup)
+        .add_systems(Update, orbit)
+        .run();
+}
+
+fn startup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.spawn(Camera3dBundle::default());
+    commands.spawn(DirectionalLightBundle {
+        transform: Transform::from_xyz(1.0, 2.0, 0.5).looking_at(Vec3::ZERO, Vec3::Y),
+        ..Default::default()
+    });
+
+    // Points are depth tested against the meshes drawn in the main pass, so the cube hides the
+    // points behind it, and the points in front of the cube hide it.
+    commands.spawn(PbrBundle {
+        mesh: meshes.add(shape::Cube { size: 0.4 }.into()),
+        material: materials.add(Color::rgb(0.8, 0.3, 0.2).into()),
+        ..Default::default()
+    });
+
+    let mesh: Handle<PointCloudAsset> = asset_server.load("laman_mahkota.laz");
+
+    commands.spawn(PointCloudBundle {
+        point_cloud: PotreePointCloud {
+            mesh,
+            point_size: 0.007,
+            ..Default::default()
+        },
+        // The loader normalizes the cloud to the unit cube, center it on the origin.
+        transform: Transform::from_translation(Vec3::splat(-0.5)),
+        ..Default::default()
+    });
+}
+
+/// Circles the camera around the cloud and the cube.
+fn orbit(time: Res<Time>, mut cameras: Query<&mut Transform, With<Camera>>) {
+    let angle = time.elapsed_seconds() * 0.2;
+    for mut transform in &mut cameras {
+        *transform = Transform::from_xyz(angle.cos() * 1.5, 0.6, angle.sin() * 1.5)
+            .looking_at(Vec3::ZERO, Vec3::Y);
+    }
+}
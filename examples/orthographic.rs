@@ -0,0 +1,46 @@
+use bevy::{prelude::*, render::camera::ScalingMode};
+use bevy_fsc_point_cloud::{PointCloudAsset, PointCloudBundle, PotreePointCloud};
+
+fn main() {
+    App::new()
+        .add_plugins((
+            DefaultPlugins.set(WindowPlugin::default()),
+            bevy_fsc_point_cloud::PointCloudPlugin::default(),
+        ))
+        .add_systems(Startup, startup)
+        .add_systems(Update, orbit)
+        .run();
+}
+
+fn startup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn(Camera3dBundle {
+        projection: OrthographicProjection {
+            scaling_mode: ScalingMode::FixedVertical(1.5),
+            ..Default::default()
+        }
+        .into(),
+        ..Default::default()
+    });
+
+    let mesh: Handle<PointCloudAsset> = asset_server.load("laman_mahkota.laz");
+
+    commands.spawn(PointCloudBundle {
+        point_cloud: PotreePointCloud {
+            mesh,
+            point_size: 0.007,
+            ..Default::default()
+        },
+        // The loader normalizes the cloud to the unit cube, center it on the origin.
+        transform: Transform::from_translation(Vec3::splat(-0.5)),
+        ..Default::default()
+    });
+}
+
+/// Circles the camera around the cloud, looking down at it at an angle.
+fn orbit(time: Res<Time>, mut cameras: Query<&mut Transform, With<Camera>>) {
+    let angle = time.elapsed_seconds() * 0.2;
+    for mut transform in &mut cameras {
+        *transform = Transform::from_xyz(angle.cos() * 10.0, 6.0, angle.sin() * 10.0)
+            .looking_at(Vec3::ZERO, Vec3::Y);
+    }
+}
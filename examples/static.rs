@@ -1,6 +1,6 @@
 use bevy::prelude::*;
 use bevy_fsc_point_cloud::{
-    ClippingPlaneBundle, ClippingPlaneRange, PointCloudAsset, PotreePointCloud,
+    ClippingPlaneBundle, ClippingPlaneRange, PointCloudAsset, PointCloudBundle, PotreePointCloud,
 };
 use smooth_bevy_cameras::{
     controllers::fps::{FpsCameraBundle, FpsCameraController, FpsCameraPlugin},
@@ -13,12 +13,37 @@ fn main() {
             DefaultPlugins.set(WindowPlugin::default()),
             LookTransformPlugin,
             FpsCameraPlugin::default(),
-            bevy_fsc_point_cloud::PointCloudPlugin,
+            bevy_fsc_point_cloud::PointCloudPlugin::default(),
         ))
         .add_systems(Startup, startup)
+        .add_systems(Update, adjust_point_size)
         .run();
 }
 
+/// Hold `+` or `-` to grow or shrink the points. The point size is extracted every frame, so
+/// changes show up right away.
+fn adjust_point_size(
+    keys: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    mut point_clouds: Query<&mut PotreePointCloud>,
+) {
+    let mut direction = 0.0;
+    if keys.any_pressed([KeyCode::Equals, KeyCode::NumpadAdd]) {
+        direction += 1.0;
+    }
+    if keys.any_pressed([KeyCode::Minus, KeyCode::NumpadSubtract]) {
+        direction -= 1.0;
+    }
+    if direction == 0.0 {
+        return;
+    }
+    // Doubles or halves the size every second.
+    let factor = 2.0_f32.powf(direction * time.delta_seconds());
+    for mut point_cloud in &mut point_clouds {
+        point_cloud.point_size *= factor;
+    }
+}
+
 fn startup(mut commands: Commands, asset_server: Res<AssetServer>) {
     commands
         .spawn(Camera3dBundle::default())
@@ -34,12 +59,14 @@ fn startup(mut commands: Commands, asset_server: Res<AssetServer>) {
 
     let mesh: Handle<PointCloudAsset> = asset_server.load("laman_mahkota.laz");
 
-    commands
-        .spawn(PotreePointCloud {
+    commands.spawn(PointCloudBundle {
+        point_cloud: PotreePointCloud {
             mesh,
             point_size: 0.007,
-        })
-        .insert(SpatialBundle::default());
+            ..Default::default()
+        },
+        ..Default::default()
+    });
 
     commands.spawn(ClippingPlaneBundle {
         range: ClippingPlaneRange {
@@ -0,0 +1,224 @@
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::VertexAttributeValues,
+        render_asset::RenderAssets,
+        render_resource::{
+            BindGroupEntries, BufferInitDescriptor, BufferUsages, PrimitiveTopology,
+        },
+        renderer::RenderDevice,
+    },
+    utils::{HashMap, HashSet},
+};
+
+use crate::{
+    instancing::PreparedPointCloudInstances, morph::gpu_morph_color_format,
+    pipeline::PointCloudPipeline, PointBlendMode, PointCloudAsset, PotreePointCloud,
+    ATTRIBUTE_COLOR_PACKED, ATTRIBUTE_COLOR_RGBA16,
+};
+
+/// Draws a [`PotreePointCloud`] together with the other batched clouds with the same
+/// [`PointBlendMode`] and color format, in a single draw call. Meant for scenes with many small
+/// clouds, where drawing them one by one is bound by the number of draw calls.
+///
+/// The points of the batched assets are copied into one [`PointCloudBatch`] asset, which is
+/// built again whenever a cloud joins or leaves the batch or one of its assets changes, but not
+/// when the clouds move. Each cloud keeps its transform and [`PotreePointCloud::color`]. All
+/// other settings, like the point size and the [`PointColorMode`](crate::PointColorMode), are
+/// taken from the first cloud of the batch. Normals and scalar fields aren't copied.
+///
+/// The batch is culled as a whole, never cloud by cloud, so it draws all of its points as long
+/// as any camera sees its entity. Batch clouds that are seen together, and keep the large ones
+/// separate. [`PointCloudLod`](crate::PointCloudLod), [`PointDepthSort`](crate::PointDepthSort),
+/// [`PointCloudSelection`](crate::PointCloudSelection), precomputed occlusion and render
+/// layers don't apply to batched clouds, and the points take up GPU memory twice, once in their
+/// own assets and once in the batch. Animated assets and hidden clouds aren't batched.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct BatchedPointCloud;
+
+/// The entity that draws [`BatchedPointCloud`]s, spawned and despawned by the
+/// [`PointCloudPlugin`](crate::PointCloudPlugin). It shows up in
+/// [`VisiblePointClouds`](crate::VisiblePointClouds) instead of the clouds it draws.
+#[derive(Component, Debug)]
+pub struct PointCloudBatch {
+    key: BatchKey,
+    /// The batched clouds and their assets, in the order their points are stored.
+    members: Vec<(Entity, AssetId<PointCloudAsset>)>,
+    /// The index of the first point of every member in `asset`.
+    first_points: Vec<u32>,
+    pub asset: Handle<PointCloudAsset>,
+}
+
+impl PointCloudBatch {
+    /// The batched clouds, in the order their points are stored.
+    pub fn clouds(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.members.iter().map(|&(entity, _)| entity)
+    }
+}
+
+/// The blend mode and the color format, see [`gpu_morph_color_format`].
+type BatchKey = (PointBlendMode, u32);
+
+pub(crate) fn batch_point_clouds(
+    mut commands: Commands,
+    mut assets: ResMut<Assets<PointCloudAsset>>,
+    mut asset_events: EventReader<AssetEvent<PointCloudAsset>>,
+    point_clouds: Query<(Entity, &PotreePointCloud, &InheritedVisibility), With<BatchedPointCloud>>,
+    mut batches: Query<(Entity, &mut PointCloudBatch)>,
+) {
+    let modified: HashSet<_> = asset_events
+        .read()
+        .filter_map(|event| match event {
+            AssetEvent::Modified { id } => Some(*id),
+            _ => None,
+        })
+        .collect();
+    let mut groups = HashMap::<BatchKey, Vec<_>>::new();
+    for (entity, point_cloud, visibility) in &point_clouds {
+        let Some(asset) = assets.get(&point_cloud.mesh) else {
+            continue;
+        };
+        if !visibility.get() || asset.animation.is_some() {
+            continue;
+        }
+        groups
+            .entry((point_cloud.blend_mode, gpu_morph_color_format(asset)))
+            .or_default()
+            .push((entity, point_cloud.mesh.id()));
+    }
+    for members in groups.values_mut() {
+        members.sort_unstable();
+    }
+
+    for (entity, mut batch) in &mut batches {
+        let Some(members) = groups.remove(&batch.key) else {
+            commands.entity(entity).despawn();
+            continue;
+        };
+        if members == batch.members && !members.iter().any(|(_, id)| modified.contains(id)) {
+            continue;
+        }
+        let (asset, first_points) = merge_assets(&assets, batch.key.1, &members);
+        assets.insert(batch.asset.id(), asset);
+        batch.members = members;
+        batch.first_points = first_points;
+    }
+    for (key, members) in groups {
+        let (asset, first_points) = merge_assets(&assets, key.1, &members);
+        commands.spawn((
+            SpatialBundle::default(),
+            PointCloudBatch {
+                key,
+                members,
+                first_points,
+                asset: assets.add(asset),
+            },
+        ));
+    }
+}
+
+/// Concatenates the positions and colors of the assets of `members`.
+fn merge_assets(
+    assets: &Assets<PointCloudAsset>,
+    color_format: u32,
+    members: &[(Entity, AssetId<PointCloudAsset>)],
+) -> (PointCloudAsset, Vec<u32>) {
+    let mut positions = Vec::new();
+    let mut colors = Vec::new();
+    let mut high_precision_colors = Vec::new();
+    let mut first_points = Vec::with_capacity(members.len());
+    for &(_, id) in members {
+        first_points.push(positions.len() as u32);
+        let Some(asset) = assets.get(id) else {
+            continue;
+        };
+        let Some(VertexAttributeValues::Float32x3(asset_positions)) =
+            asset.mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            continue;
+        };
+        positions.extend_from_slice(asset_positions);
+        match (
+            color_format,
+            asset.mesh.attribute(ATTRIBUTE_COLOR_PACKED),
+            asset.mesh.attribute(ATTRIBUTE_COLOR_RGBA16),
+        ) {
+            (1, Some(VertexAttributeValues::Uint32(asset_colors)), _) => {
+                colors.extend_from_slice(asset_colors);
+            }
+            (2, _, Some(VertexAttributeValues::Uint32x2(asset_colors))) => {
+                high_precision_colors.extend_from_slice(asset_colors);
+            }
+            _ => {}
+        }
+    }
+
+    let num_points = positions.len();
+    let mut mesh = Mesh::new(PrimitiveTopology::PointList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    if colors.len() == num_points && color_format == 1 {
+        mesh.insert_attribute(ATTRIBUTE_COLOR_PACKED, colors);
+    }
+    if high_precision_colors.len() == num_points && color_format == 2 {
+        mesh.insert_attribute(ATTRIBUTE_COLOR_RGBA16, high_precision_colors);
+    }
+    (PointCloudAsset::new(mesh), first_points)
+}
+
+/// The clouds of a [`PointCloudBatch`] in the render world.
+#[derive(Component)]
+pub struct ExtractedPointCloudBatch {
+    /// The transform matrix of each cloud, followed by its tint.
+    instances: Vec<[f32; 20]>,
+    first_points: Vec<u32>,
+}
+
+impl ExtractedPointCloudBatch {
+    pub(crate) fn new(batch: &PointCloudBatch, instances: Vec<[f32; 20]>) -> Self {
+        Self {
+            instances,
+            first_points: batch.first_points.clone(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+}
+
+pub(crate) fn prepare_point_cloud_batches(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    pipeline: Res<PointCloudPipeline>,
+    render_assets: Res<RenderAssets<PointCloudAsset>>,
+    query: Query<(Entity, &Handle<PointCloudAsset>, &ExtractedPointCloudBatch)>,
+) {
+    for (entity, handle, batch) in &query {
+        let Some(asset) = render_assets.get(handle) else {
+            continue;
+        };
+        // Like the instances of an `InstancedPointCloud`, with the index of the first point of
+        // each cloud, so the shader can find the cloud of a point.
+        let mut contents =
+            bytemuck::bytes_of(&[asset.num_points, batch.instances.len() as u32, 0, 0]).to_vec();
+        for (instance, &first_point) in batch.instances.iter().zip(&batch.first_points) {
+            contents.extend_from_slice(bytemuck::bytes_of(instance));
+            contents.extend_from_slice(bytemuck::bytes_of(&[first_point, 0, 0, 0]));
+        }
+        let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("Point cloud batch buffer"),
+            contents: &contents,
+            usage: BufferUsages::STORAGE,
+        });
+        let bind_group = render_device.create_bind_group(
+            "point cloud batch bind group",
+            &pipeline.instance_layout,
+            &BindGroupEntries::single(buffer.as_entire_binding()),
+        );
+        // All points are drawn as a single instance.
+        commands.entity(entity).insert(PreparedPointCloudInstances {
+            bind_group,
+            count: 1,
+        });
+    }
+}
@@ -9,7 +9,9 @@ use bevy::{
 /// The range of signed distances from the plane that don't get clipped.
 ///
 /// The plane origin and normal will be extracted from the [`GlobalTransform`],
-/// assuming normal axis is pointing
+/// with the normal along the local X axis. The default keeps the half-space in front
+/// of the plane, for section cuts. Points are clipped in the vertex shader against the planes
+/// every frame, so planes can be moved interactively with their transforms.
 #[derive(Clone, Component, Debug, ShaderType)]
 pub struct ClippingPlaneRange {
     /// The minimum (signed) distance from a visible point's centroid to the plane.
@@ -33,6 +35,22 @@ pub struct ClippingPlaneBundle {
     pub transform: TransformBundle,
 }
 
+impl ClippingPlaneBundle {
+    /// Keeps the world space points `p` with `plane.xyz().dot(p) + plane.w >= 0.0`.
+    pub fn from_plane(plane: Vec4) -> Self {
+        let normal = plane.xyz();
+        let length = normal.length();
+        Self {
+            range: default(),
+            transform: TransformBundle::from_transform(Transform {
+                translation: -normal * plane.w / (length * length),
+                rotation: Quat::from_rotation_arc(Vec3::X, normal / length),
+                ..default()
+            }),
+        }
+    }
+}
+
 #[derive(Clone, Component, Debug, Default, ShaderType)]
 pub(crate) struct GpuClippingPlaneRange {
     pub origin: Vec3,
@@ -41,6 +59,24 @@ pub(crate) struct GpuClippingPlaneRange {
     pub max_sdist: f32,
 }
 
+impl GpuClippingPlaneRange {
+    pub fn new(range: &ClippingPlaneRange, transform: &GlobalTransform) -> Self {
+        let (_, rotation, translation) = transform.to_scale_rotation_translation();
+        Self {
+            origin: translation,
+            unit_normal: rotation * Vec3::X,
+            min_sdist: range.min_sdist,
+            max_sdist: range.max_sdist,
+        }
+    }
+
+    /// Whether a world space position passes the plane, the same way the shader tests it.
+    pub fn contains(&self, position: Vec3) -> bool {
+        let sdist = (position - self.origin).dot(self.unit_normal);
+        sdist >= self.min_sdist && sdist <= self.max_sdist
+    }
+}
+
 #[derive(Debug, Default, ShaderType)]
 pub(crate) struct GpuClippingPlaneRanges {
     pub ranges: [GpuClippingPlaneRange; MAX_CLIPPING_PLANES],
@@ -60,13 +96,8 @@ pub(crate) fn extract_clipping_planes(
     let mut iter = clipping_planes.iter();
     let mut gpu_planes = GpuClippingPlaneRanges::default();
     for (range, transform) in iter.by_ref() {
-        let (_, rotation, translation) = transform.to_scale_rotation_translation();
-        gpu_planes.ranges[gpu_planes.num_ranges as usize] = GpuClippingPlaneRange {
-            origin: translation,
-            unit_normal: rotation * Vec3::X,
-            min_sdist: range.min_sdist,
-            max_sdist: range.max_sdist,
-        };
+        gpu_planes.ranges[gpu_planes.num_ranges as usize] =
+            GpuClippingPlaneRange::new(range, transform);
         gpu_planes.num_ranges += 1;
         if gpu_planes.num_ranges as usize == MAX_CLIPPING_PLANES {
             break;
@@ -0,0 +1,351 @@
+use std::ops::Range;
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssets,
+        render_resource::{BindGroup, BindGroupEntries, Extent3d, TextureDimension, TextureFormat},
+        renderer::RenderDevice,
+    },
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    pipeline::PointCloudPipeline, PointCloudAsset, ATTRIBUTE_COLOR, ATTRIBUTE_COLOR_PACKED,
+    ATTRIBUTE_COLOR_RGBA16,
+};
+
+/// The maximum number of stops in a [`ColorRamp`]. Additional stops are ignored.
+pub const MAX_COLOR_RAMP_STOPS: usize = 16;
+
+/// Selects how the points of a [`PotreePointCloud`](crate::PotreePointCloud) are colored.
+///
+/// Insert it next to the point cloud; clouds without it use [`PointColorMode::Rgb`].
+#[derive(Component, Clone, Debug, Default, Serialize, Deserialize)]
+pub enum PointColorMode {
+    /// Use the colors stored in the asset. Assets without colors are colored by position, see
+    /// [`PointColorMode::Height`] and [`PointColorMode::Uniform`] for them instead.
+    #[default]
+    Rgb,
+    /// Map the world space height of the points, their `y`, through a color ramp, like
+    /// [`PointColorMode::Scalar`]. Works for every asset, including those without colors.
+    Height { min: f32, max: f32, ramp: ColorRamp },
+    /// Draw every point in the same color, for example for assets without colors.
+    Uniform(Color),
+    /// Map one of the asset's [scalar fields](PointCloudAsset::scalar_field_names) through a
+    /// color ramp. Values at or below `min` get the first stop of the ramp, values at or above
+    /// `max` get the last one. If `min` equals `max`, the values at or below it get the first
+    /// stop and the others the last.
+    ///
+    /// Falls back to [`PointColorMode::Rgb`] if the asset has no field named `field`.
+    Scalar {
+        field: String,
+        min: f32,
+        max: f32,
+        ramp: ColorRamp,
+    },
+    /// Map one of the asset's scalar fields through a transfer function: an image whose pixels
+    /// give the color and opacity over `range`, from left to right. The image is sampled
+    /// through its middle row, so it's usually one pixel high. [`transfer_function_image`]
+    /// builds one from control points.
+    ///
+    /// Translucent points are dithered, or use alpha to coverage with MSAA. Falls back to
+    /// [`PointColorMode::Rgb`] if the asset has no field named `field`, until the image has
+    /// loaded, and for [`InstancedPointCloud`](crate::InstancedPointCloud)s and
+    /// [`PointCloudMorph`](crate::PointCloudMorph)s. The image handle isn't serialized.
+    TransferFunction {
+        field: String,
+        range: Range<f32>,
+        #[serde(skip)]
+        texture: Handle<Image>,
+    },
+}
+
+/// Evenly spaced colors that a normalized scalar value is interpolated through.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ColorRamp {
+    pub stops: Vec<Color>,
+}
+
+impl ColorRamp {
+    pub fn grayscale() -> Self {
+        Self {
+            stops: vec![Color::BLACK, Color::WHITE],
+        }
+    }
+
+    pub fn viridis() -> Self {
+        Self {
+            stops: vec![
+                Color::rgb_u8(68, 1, 84),
+                Color::rgb_u8(59, 82, 139),
+                Color::rgb_u8(33, 145, 140),
+                Color::rgb_u8(94, 201, 98),
+                Color::rgb_u8(253, 231, 37),
+            ],
+        }
+    }
+
+    pub fn rainbow() -> Self {
+        Self {
+            stops: vec![
+                Color::BLUE,
+                Color::CYAN,
+                Color::GREEN,
+                Color::YELLOW,
+                Color::RED,
+            ],
+        }
+    }
+}
+
+impl Default for ColorRamp {
+    fn default() -> Self {
+        Self::viridis()
+    }
+}
+
+/// Builds the image of a [`PointColorMode::TransferFunction`] from control points, so that
+/// transfer functions can be edited at runtime without an image file.
+///
+/// Each control point is a position from `0.0` to `1.0` along the function's range, and the
+/// color and opacity there. The points don't need to be sorted. The image is interpolated
+/// linearly between them and clamped to the first and last point outside of them.
+pub fn transfer_function_image(control_points: &[(f32, Color)], width: u32) -> Image {
+    let mut control_points = control_points.to_vec();
+    control_points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let width = width.max(1);
+    let data = (0..width)
+        .flat_map(|x| {
+            let t = (x as f32 + 0.5) / width as f32;
+            let color = match control_points
+                .iter()
+                .position(|&(position, _)| position > t)
+            {
+                Some(0) => control_points[0].1,
+                Some(i) => {
+                    let ((start, from), (end, to)) = (control_points[i - 1], control_points[i]);
+                    let mix = (t - start) / (end - start);
+                    let from = Vec4::from(from.as_rgba_f32());
+                    let to = Vec4::from(to.as_rgba_f32());
+                    Color::from(from.lerp(to, mix))
+                }
+                None => control_points
+                    .last()
+                    .map_or(Color::WHITE, |&(_, color)| color),
+            };
+            color.as_rgba_u8()
+        })
+        .collect();
+    Image::new(
+        Extent3d {
+            width,
+            height: 1,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+/// Packs a color in the layout of [`ATTRIBUTE_COLOR_PACKED`](crate::ATTRIBUTE_COLOR_PACKED).
+pub(crate) fn pack_rgba8(color: [u8; 4]) -> u32 {
+    u32::from_le_bytes(color)
+}
+
+/// Packs an opaque color of [`ATTRIBUTE_COLOR`](crate::ATTRIBUTE_COLOR) in the layout of
+/// [`ATTRIBUTE_COLOR_PACKED`](crate::ATTRIBUTE_COLOR_PACKED).
+pub(crate) fn pack_rgb_f32(color: [f32; 3]) -> u32 {
+    let [r, g, b] = color.map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8);
+    pack_rgba8([r, g, b, u8::MAX])
+}
+
+/// Packs a color in the layout of [`ATTRIBUTE_COLOR_RGBA16`](crate::ATTRIBUTE_COLOR_RGBA16).
+pub(crate) fn pack_rgba16([r, g, b, a]: [u16; 4]) -> [u32; 2] {
+    [r as u32 | (g as u32) << 16, b as u32 | (a as u32) << 16]
+}
+
+/// Converts the [`ATTRIBUTE_COLOR`](crate::ATTRIBUTE_COLOR) of new and modified assets, so
+/// code reading the assets sees the colors that are drawn.
+pub(crate) fn pack_point_cloud_colors(
+    mut assets: ResMut<Assets<PointCloudAsset>>,
+    mut asset_events: EventReader<AssetEvent<PointCloudAsset>>,
+) {
+    for event in asset_events.read() {
+        let (AssetEvent::Added { id } | AssetEvent::Modified { id }) = event else {
+            continue;
+        };
+        // Only borrow the asset mutably when it changes, which modifies it again.
+        if assets
+            .get(*id)
+            .is_some_and(|asset| asset.mesh.contains_attribute(crate::ATTRIBUTE_COLOR))
+        {
+            assets.get_mut(*id).unwrap().pack_colors();
+        }
+    }
+}
+
+pub(crate) const GPU_COLOR_MODE_RGB: u32 = 0;
+pub(crate) const GPU_COLOR_MODE_SCALAR: u32 = 1;
+pub(crate) const GPU_COLOR_MODE_TRANSFER_FUNCTION: u32 = 2;
+pub(crate) const GPU_COLOR_MODE_HEIGHT: u32 = 3;
+
+/// The color mode parameters as laid out in [`PointCloudUniform`](crate::PointCloudUniform).
+pub(crate) struct GpuPointColorMode {
+    pub mode: u32,
+    pub scalar_offset: u32,
+    pub scalar_min: f32,
+    pub scalar_max: f32,
+    pub ramp_len: u32,
+    pub ramp: [Vec4; MAX_COLOR_RAMP_STOPS],
+}
+
+impl GpuPointColorMode {
+    pub fn new(mode: Option<&PointColorMode>, asset: Option<&PointCloudAsset>) -> Self {
+        let mut gpu_mode = Self {
+            mode: GPU_COLOR_MODE_RGB,
+            scalar_offset: 0,
+            scalar_min: 0.0,
+            scalar_max: 1.0,
+            ramp_len: 0,
+            ramp: [Vec4::ZERO; MAX_COLOR_RAMP_STOPS],
+        };
+        let Some(asset) = asset else {
+            return gpu_mode;
+        };
+        match mode {
+            Some(PointColorMode::Scalar {
+                field,
+                min,
+                max,
+                ramp,
+            }) => {
+                if let Some(index) = asset.scalar_field_index(field) {
+                    gpu_mode.mode = GPU_COLOR_MODE_SCALAR;
+                    gpu_mode.scalar_offset = (index * asset.num_points()) as u32;
+                    gpu_mode.scalar_min = *min;
+                    gpu_mode.scalar_max = *max;
+                    gpu_mode.set_ramp(&ramp.stops);
+                }
+            }
+            Some(PointColorMode::Height { min, max, ramp }) => {
+                gpu_mode.mode = GPU_COLOR_MODE_HEIGHT;
+                gpu_mode.scalar_min = *min;
+                gpu_mode.scalar_max = *max;
+                gpu_mode.set_ramp(&ramp.stops);
+            }
+            Some(PointColorMode::Uniform(color)) => {
+                // A ramp with a single stop has the same color everywhere.
+                gpu_mode.mode = GPU_COLOR_MODE_HEIGHT;
+                gpu_mode.set_ramp(&[*color]);
+            }
+            Some(PointColorMode::TransferFunction { field, range, .. }) => {
+                if let Some(index) = asset.scalar_field_index(field) {
+                    gpu_mode.mode = GPU_COLOR_MODE_TRANSFER_FUNCTION;
+                    gpu_mode.scalar_offset = (index * asset.num_points()) as u32;
+                    gpu_mode.scalar_min = range.start;
+                    gpu_mode.scalar_max = range.end;
+                }
+            }
+            Some(PointColorMode::Rgb) | None => {}
+        }
+        // The shader divides by the width of the range. An empty range splits the values at
+        // `min` instead, those at or below it get the start of the ramp and the others its end.
+        if gpu_mode.scalar_max == gpu_mode.scalar_min {
+            gpu_mode.scalar_max += gpu_mode.scalar_min.abs().max(1.0) * f32::EPSILON;
+        }
+        gpu_mode
+    }
+
+    fn set_ramp(&mut self, stops: &[Color]) {
+        for (gpu_stop, stop) in self.ramp.iter_mut().zip(stops) {
+            *gpu_stop = Vec4::from(stop.as_linear_rgba_f32());
+            self.ramp_len += 1;
+        }
+    }
+}
+
+/// The image of a [`PointColorMode::TransferFunction`] in the render world.
+#[derive(Component)]
+pub struct ExtractedTransferFunction {
+    pub texture: Handle<Image>,
+}
+
+#[derive(Component)]
+pub struct PreparedTransferFunction {
+    pub bind_group: BindGroup,
+}
+
+pub(crate) fn prepare_transfer_functions(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    pipeline: Res<PointCloudPipeline>,
+    images: Res<RenderAssets<Image>>,
+    query: Query<(Entity, &ExtractedTransferFunction)>,
+) {
+    for (entity, transfer_function) in &query {
+        let Some(image) = images.get(&transfer_function.texture) else {
+            continue;
+        };
+        let bind_group = render_device.create_bind_group(
+            "point cloud transfer function bind group",
+            &pipeline.transfer_function_layout,
+            &BindGroupEntries::sequential((&image.texture_view, &image.sampler)),
+        );
+        commands
+            .entity(entity)
+            .insert(PreparedTransferFunction { bind_group });
+    }
+}
+
+impl PointCloudAsset {
+    /// Replaces the colors of the asset, one per point. Modify the asset through
+    /// [`Assets::get_mut`] to update them; only the colors are uploaded again, not the
+    /// positions, so this is cheap enough to recolor clouds every frame. Updates with the wrong
+    /// number of colors are rejected with a warning.
+    ///
+    /// Assets with 16 bit colors keep them, the 8 bit colors are scaled up.
+    pub fn update_colors(&mut self, colors: &[[u8; 4]]) {
+        if colors.len() != self.num_points() {
+            warn!(
+                "Ignoring {} colors for a point cloud with {} points",
+                colors.len(),
+                self.num_points()
+            );
+            return;
+        }
+        self.mesh.remove_attribute(ATTRIBUTE_COLOR);
+        if self.mesh.contains_attribute(ATTRIBUTE_COLOR_RGBA16) {
+            let colors: Vec<[u32; 2]> = colors
+                .iter()
+                .map(|color| pack_rgba16(color.map(|channel| channel as u16 * 257)))
+                .collect();
+            self.mesh.insert_attribute(ATTRIBUTE_COLOR_RGBA16, colors);
+        } else {
+            let colors: Vec<u32> = colors.iter().copied().map(pack_rgba8).collect();
+            self.mesh.insert_attribute(ATTRIBUTE_COLOR_PACKED, colors);
+        }
+        self.upload_colors();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_scalar_ranges_are_widened() {
+        let asset = PointCloudAsset::from_points(vec![Vec3::ZERO], vec![[255; 4]]).unwrap();
+        for value in [0.0, 2.5, -1e6] {
+            let mode = PointColorMode::Height {
+                min: value,
+                max: value,
+                ramp: ColorRamp::default(),
+            };
+            let gpu_mode = GpuPointColorMode::new(Some(&mode), Some(&asset));
+            assert!(gpu_mode.scalar_max - gpu_mode.scalar_min > 0.0);
+            assert_eq!(gpu_mode.scalar_min, value);
+        }
+    }
+}
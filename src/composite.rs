@@ -0,0 +1,137 @@
+use std::ops::Range;
+
+use bevy::{
+    prelude::*,
+    render::{mesh::VertexAttributeValues, render_resource::PrimitiveTopology},
+};
+use opd_parser::Frames;
+
+use crate::{color::pack_rgba8, PointCloudAsset, PotreePointCloud, ATTRIBUTE_COLOR_PACKED};
+
+/// Draws a range of frames of an animated [`PointCloudAsset`] at once, as a static cloud.
+///
+/// Useful to show the area swept by an animation over time. When inserted next to a
+/// [`PotreePointCloud`], the composite is built once `source` has loaded and replaces
+/// [`PotreePointCloud::mesh`]. It is rebuilt whenever this component changes.
+#[derive(Component, Clone, Debug)]
+pub struct FrameComposite {
+    pub source: Handle<PointCloudAsset>,
+    /// The animation frames to draw. Indices past the last frame are ignored.
+    pub range: Range<usize>,
+    pub mode: FrameCompositeMode,
+}
+
+#[derive(Clone, Debug, Default)]
+pub enum FrameCompositeMode {
+    /// Every frame is drawn with the colors of the source asset.
+    #[default]
+    Overlay,
+    /// The last frame of the range is drawn with the source colors, and earlier frames fade
+    /// linearly towards `faded`. Points are opaque, so use the background color for `faded` to
+    /// make old frames appear transparent.
+    FadeByRecency { faded: Color },
+}
+
+impl PointCloudAsset {
+    /// Builds a static asset containing the points of every animation frame in `range`.
+    ///
+    /// Returns `None` if the asset isn't animated or the range contains no frames.
+    pub fn frame_composite(
+        &self,
+        range: Range<usize>,
+        mode: &FrameCompositeMode,
+    ) -> Option<PointCloudAsset> {
+        // Like playback, only 8 bit frames are supported.
+        let Some(Frames::I8(frames)) = &self.animation else {
+            return None;
+        };
+        let frames = frames.get(range.start..range.end.min(frames.len()))?;
+        if frames.is_empty() {
+            return None;
+        }
+        let base_positions = self.mesh.attribute(Mesh::ATTRIBUTE_POSITION)?.as_float3()?;
+        let base_colors = match self.mesh.attribute(ATTRIBUTE_COLOR_PACKED) {
+            Some(VertexAttributeValues::Uint32(colors)) => Some(colors.as_slice()),
+            _ => None,
+        };
+
+        let num_points = base_positions.len() * frames.len();
+        let mut positions: Vec<[f32; 3]> = Vec::with_capacity(num_points);
+        let mut colors: Vec<u32> = Vec::with_capacity(num_points);
+        for (frame_index, frame) in frames.iter().enumerate() {
+            let offsets = frame.into_iter().map(Vec3::from);
+            for (base, offset) in base_positions.iter().zip(offsets) {
+                positions.push((Vec3::from(*base) + offset * self.animation_scale).into());
+            }
+
+            let recency = (frame_index + 1) as f32 / frames.len() as f32;
+            for point in 0..base_positions.len() {
+                let color = base_colors
+                    .map(|colors| colors[point].to_le_bytes())
+                    .unwrap_or([u8::MAX; 4]);
+                let color = match mode {
+                    FrameCompositeMode::Overlay => color,
+                    FrameCompositeMode::FadeByRecency { faded } => {
+                        let faded = faded.as_rgba_u8();
+                        std::array::from_fn(|channel| {
+                            (faded[channel] as f32
+                                + (color[channel] as f32 - faded[channel] as f32) * recency)
+                                as u8
+                        })
+                    }
+                };
+                colors.push(pack_rgba8(color));
+            }
+        }
+
+        let mut mesh = Mesh::new(PrimitiveTopology::PointList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        if base_colors.is_some() || matches!(mode, FrameCompositeMode::FadeByRecency { .. }) {
+            mesh.insert_attribute(ATTRIBUTE_COLOR_PACKED, colors);
+        }
+        if let Some(VertexAttributeValues::Float32x3(normals)) =
+            self.mesh.attribute(Mesh::ATTRIBUTE_NORMAL)
+        {
+            mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals.repeat(frames.len()));
+        }
+        let mut composite = PointCloudAsset::new(mesh);
+        composite.origin = self.origin;
+        composite.scalar_fields = self
+            .scalar_fields
+            .iter()
+            .map(|(name, values)| (name.clone(), values.repeat(frames.len())))
+            .collect();
+        Some(composite)
+    }
+}
+
+/// Composites that are waiting for their source asset to load.
+#[derive(Component)]
+pub(crate) struct PendingFrameComposite;
+
+pub(crate) fn build_frame_composites(
+    mut commands: Commands,
+    mut assets: ResMut<Assets<PointCloudAsset>>,
+    changed: Query<Entity, Changed<FrameComposite>>,
+    mut pending: Query<
+        (Entity, &FrameComposite, &mut PotreePointCloud),
+        With<PendingFrameComposite>,
+    >,
+) {
+    for entity in &changed {
+        commands.entity(entity).insert(PendingFrameComposite);
+    }
+    for (entity, composite, mut point_cloud) in &mut pending {
+        let Some(source) = assets.get(&composite.source) else {
+            continue;
+        };
+        commands.entity(entity).remove::<PendingFrameComposite>();
+        match source.frame_composite(composite.range.clone(), &composite.mode) {
+            Some(asset) => point_cloud.mesh = assets.add(asset),
+            None => warn!(
+                "Can't composite frames {:?} of a point cloud without them",
+                composite.range
+            ),
+        }
+    }
+}
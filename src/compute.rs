@@ -0,0 +1,283 @@
+use std::borrow::Cow;
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::ExtractResource,
+        render_asset::RenderAssets,
+        render_graph::{Node, NodeRunError, RenderGraphContext},
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+    },
+    utils::HashMap,
+};
+
+use crate::{pipeline::PointCloudPipeline, PointCloudAsset};
+
+/// The workgroup size that [`PrePointCompute`] shaders must declare. Passes are dispatched with
+/// enough workgroups to cover every point, so shaders must ignore invocations with an index of
+/// `num_points` or more.
+pub const PRE_POINT_COMPUTE_WORKGROUP_SIZE: u32 = 64;
+
+/// User compute shaders that modify the points of an asset on the GPU every frame, before any
+/// view is rendered. Useful for effects like wind, deformation, or simulations.
+///
+/// Each pass runs once per frame for its asset, no matter how many entities draw it.
+/// The shader is dispatched with [`PRE_POINT_COMPUTE_WORKGROUP_SIZE`] invocations per
+/// workgroup, and gets a bind group with [`PrePointComputePipeline::layout`] at group 0:
+///
+/// ```wgsl
+/// struct Params {
+///     num_points: u32,
+///     // 0 without colors, 1 for `ATTRIBUTE_COLOR_PACKED`, 2 for `ATTRIBUTE_COLOR_RGBA16`.
+///     color_format: u32,
+///     time: f32,
+///     delta_time: f32,
+/// }
+/// // Three f32 per point, tightly packed.
+/// @group(0) @binding(0) var<storage, read_write> positions: array<f32>;
+/// // One u32 or two u32 per point, depending on `color_format`.
+/// @group(0) @binding(1) var<storage, read_write> colors: array<u32>;
+/// @group(0) @binding(2) var<uniform> params: Params;
+/// ```
+///
+/// The buffers are the ones the asset is drawn from, so writes persist across frames and are
+/// seen by every view. All passes of a frame are recorded into one compute pass ahead of the
+/// point cloud render passes, and wgpu orders the storage writes before the reads of the
+/// render passes, so no extra synchronization is needed. Passes for the same asset run in
+/// registration order, but invocations within a pass must not write to the same points.
+#[derive(Resource, Clone, Default)]
+pub struct PrePointCompute {
+    passes: Vec<PrePointComputePass>,
+}
+
+#[derive(Clone)]
+struct PrePointComputePass {
+    asset: Handle<PointCloudAsset>,
+    shader: Handle<Shader>,
+    entry_point: Cow<'static, str>,
+}
+
+impl PrePointCompute {
+    /// Runs `entry_point` of `shader` over the points of `asset` every frame.
+    pub fn register(
+        &mut self,
+        asset: Handle<PointCloudAsset>,
+        shader: Handle<Shader>,
+        entry_point: impl Into<Cow<'static, str>>,
+    ) {
+        self.passes.push(PrePointComputePass {
+            asset,
+            shader,
+            entry_point: entry_point.into(),
+        });
+    }
+
+    /// Whether any pass is registered for `asset`.
+    pub fn modifies(&self, asset: &Handle<PointCloudAsset>) -> bool {
+        self.passes.iter().any(|pass| pass.asset == *asset)
+    }
+
+    /// Removes all passes registered for `asset`.
+    pub fn unregister(&mut self, asset: &Handle<PointCloudAsset>) {
+        self.passes.retain(|pass| pass.asset != *asset);
+    }
+}
+
+impl ExtractResource for PrePointCompute {
+    type Source = Self;
+
+    fn extract_resource(source: &Self::Source) -> Self {
+        source.clone()
+    }
+}
+
+#[derive(Clone, ShaderType)]
+struct PrePointComputeParams {
+    num_points: u32,
+    color_format: u32,
+    time: f32,
+    delta_time: f32,
+}
+
+#[derive(Resource)]
+pub struct PrePointComputePipeline {
+    /// The layout of the bind group passed to [`PrePointCompute`] shaders at group 0.
+    pub layout: BindGroupLayout,
+    pipelines: HashMap<(Handle<Shader>, Cow<'static, str>), CachedComputePipelineId>,
+    params: DynamicUniformBuffer<PrePointComputeParams>,
+}
+
+impl FromWorld for PrePointComputePipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let point_stream = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("PrePointComputeLayout"),
+            entries: &[
+                // Positions
+                point_stream(0),
+                // Colors
+                point_stream(1),
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: Some(PrePointComputeParams::min_size()),
+                    },
+                    count: None,
+                },
+            ],
+        });
+        Self {
+            layout,
+            pipelines: default(),
+            params: default(),
+        }
+    }
+}
+
+struct PreparedPrePointComputePass {
+    pipeline_id: CachedComputePipelineId,
+    bind_group: BindGroup,
+    params_offset: u32,
+    workgroups: u32,
+}
+
+#[derive(Resource, Default)]
+pub(crate) struct PreparedPrePointComputes {
+    passes: Vec<PreparedPrePointComputePass>,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn prepare_pre_point_computes(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    pipeline_cache: Res<PipelineCache>,
+    point_cloud_pipeline: Res<PointCloudPipeline>,
+    mut pipeline: ResMut<PrePointComputePipeline>,
+    render_assets: Res<RenderAssets<PointCloudAsset>>,
+    computes: Res<PrePointCompute>,
+    time: Res<Time>,
+    mut prepared: ResMut<PreparedPrePointComputes>,
+) {
+    let pipeline = &mut *pipeline;
+    prepared.passes.clear();
+    pipeline.params.clear();
+    let mut passes = Vec::with_capacity(computes.passes.len());
+    for pass in &computes.passes {
+        let Some(asset) = render_assets.get(&pass.asset) else {
+            continue;
+        };
+        if asset.num_points == 0 {
+            continue;
+        }
+        let layout = pipeline.layout.clone();
+        let pipeline_id = *pipeline
+            .pipelines
+            .entry((pass.shader.clone(), pass.entry_point.clone()))
+            .or_insert_with(|| {
+                pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                    label: Some("pre_point_compute_pipeline".into()),
+                    layout: vec![layout],
+                    push_constant_ranges: default(),
+                    shader: pass.shader.clone(),
+                    shader_defs: default(),
+                    entry_point: pass.entry_point.clone(),
+                })
+            });
+        let params_offset = pipeline.params.push(PrePointComputeParams {
+            num_points: asset.num_points,
+            color_format: match (asset.colored, asset.high_precision_color) {
+                (false, _) => 0,
+                (true, false) => 1,
+                (true, true) => 2,
+            },
+            time: time.elapsed_seconds_wrapped(),
+            delta_time: time.delta_seconds(),
+        });
+        passes.push((
+            pipeline_id,
+            asset,
+            params_offset,
+            asset.num_points.div_ceil(PRE_POINT_COMPUTE_WORKGROUP_SIZE),
+        ));
+    }
+    pipeline.params.write_buffer(&render_device, &render_queue);
+    let Some(params) = pipeline.params.binding() else {
+        return;
+    };
+
+    for (pipeline_id, asset, params_offset, workgroups) in passes {
+        let bind_group = render_device.create_bind_group(
+            "pre point compute bind group",
+            &pipeline.layout,
+            &BindGroupEntries::sequential((
+                asset.position_buffer.as_entire_binding(),
+                asset
+                    .color_buffer
+                    .as_ref()
+                    .unwrap_or(&point_cloud_pipeline.placeholder_buffer)
+                    .as_entire_binding(),
+                params.clone(),
+            )),
+        );
+        prepared.passes.push(PreparedPrePointComputePass {
+            pipeline_id,
+            bind_group,
+            params_offset,
+            workgroups,
+        });
+    }
+}
+
+/// Runs the [`PrePointCompute`] passes, ahead of the camera driver.
+pub struct PrePointComputeNode;
+
+impl PrePointComputeNode {
+    pub const NAME: &'static str = "pre_point_compute";
+}
+
+impl Node for PrePointComputeNode {
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let prepared = world.resource::<PreparedPrePointComputes>();
+        if prepared.passes.is_empty() {
+            return Ok(());
+        }
+        #[cfg(feature = "trace")]
+        let _span = info_span!("pre_point_compute", passes = prepared.passes.len()).entered();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let mut pass =
+            render_context
+                .command_encoder()
+                .begin_compute_pass(&ComputePassDescriptor {
+                    label: Some("pre_point_compute"),
+                });
+        for prepared_pass in &prepared.passes {
+            let Some(pipeline) = pipeline_cache.get_compute_pipeline(prepared_pass.pipeline_id)
+            else {
+                continue;
+            };
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &prepared_pass.bind_group, &[prepared_pass.params_offset]);
+            pass.dispatch_workgroups(prepared_pass.workgroups, 1, 1);
+        }
+        Ok(())
+    }
+}
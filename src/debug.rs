@@ -0,0 +1,84 @@
+use bevy::{prelude::*, render::primitives::Aabb};
+
+use crate::{PointCloudAsset, PotreePointCloud};
+
+/// Draws debugging [`Gizmos`] for point clouds. Everything is off by default.
+///
+/// Needs the [`GizmoPlugin`](bevy::gizmos::GizmoPlugin), which is part of the
+/// [`DefaultPlugins`].
+#[derive(Resource, Clone, Debug)]
+pub struct PointCloudDebug {
+    /// Draw the bounds of every visible [`PotreePointCloud`], see [`PointCloudAsset::aabb`].
+    pub bounds: bool,
+    pub bounds_color: Color,
+    /// Draw the bounds of the resident nodes of every
+    /// [`PotreeOctree`](crate::PotreeOctree), with a different hue for every level.
+    pub octree_nodes: bool,
+}
+
+impl Default for PointCloudDebug {
+    fn default() -> Self {
+        Self {
+            bounds: false,
+            bounds_color: Color::YELLOW,
+            octree_nodes: false,
+        }
+    }
+}
+
+/// The gizmo transform of a unit cube that covers `aabb`, placed in the world by `transform`.
+fn aabb_transform(aabb: &Aabb, transform: &GlobalTransform) -> Transform {
+    let cube = Transform {
+        translation: aabb.center.into(),
+        scale: Vec3::from(aabb.half_extents) * 2.0,
+        ..default()
+    };
+    (*transform * GlobalTransform::from(cube)).compute_transform()
+}
+
+pub(crate) fn draw_point_cloud_bounds(
+    debug: Res<PointCloudDebug>,
+    mut gizmos: Gizmos,
+    assets: Res<Assets<PointCloudAsset>>,
+    point_clouds: Query<(&PotreePointCloud, &GlobalTransform, &InheritedVisibility)>,
+) {
+    if !debug.bounds {
+        return;
+    }
+    for (point_cloud, transform, visibility) in &point_clouds {
+        if !visibility.get() {
+            continue;
+        }
+        if let Some(asset) = assets.get(&point_cloud.mesh) {
+            gizmos.cuboid(aabb_transform(&asset.aabb, transform), debug.bounds_color);
+        }
+    }
+}
+
+#[cfg(feature = "potree")]
+pub(crate) fn draw_octree_node_bounds(
+    debug: Res<PointCloudDebug>,
+    mut gizmos: Gizmos,
+    octree_assets: Res<Assets<crate::PotreeOctreeAsset>>,
+    octrees: Query<(&crate::PotreeOctree, &GlobalTransform, &Children)>,
+    nodes: Query<&crate::PotreeOctreeNodeIndex>,
+) {
+    if !debug.octree_nodes {
+        return;
+    }
+    for (octree, transform, children) in &octrees {
+        let Some(asset) = octree_assets.get(&octree.octree) else {
+            continue;
+        };
+        for &index in nodes.iter_many(children) {
+            let Some(node) = asset.nodes().get(index.0) else {
+                continue;
+            };
+            let hue = (node.level as f32 * 47.0) % 360.0;
+            gizmos.cuboid(
+                aabb_transform(&node.aabb, transform),
+                Color::hsl(hue, 1.0, 0.5),
+            );
+        }
+    }
+}
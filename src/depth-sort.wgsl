@@ -0,0 +1,148 @@
+// Bitonic sort of point indices by view space depth, back to front, see `PointDepthSort`.
+//
+// The arrays are padded to a power of two of at least `LOCAL_LEN` elements. Sequences of up to
+// `LOCAL_LEN` elements are sorted and merged in workgroup memory, longer distances are
+// compared directly in the storage buffers, one dispatch per step.
+
+struct Params {
+    // The view space depth of a model space position is `dot(depth_plane.xyz, p) + depth_plane.w`.
+    depth_plane: vec4<f32>,
+    num_points: u32,
+    // The length of the bitonic sequences being merged.
+    block: u32,
+    // The distance between the compared elements.
+    distance: u32,
+}
+
+@group(0) @binding(0) var<storage, read> positions: array<f32>;
+@group(0) @binding(1) var<storage, read_write> keys: array<u32>;
+@group(0) @binding(2) var<storage, read_write> indices: array<u32>;
+@group(0) @binding(3) var<uniform> params: Params;
+
+const WORKGROUP_SIZE: u32 = 256u;
+const LOCAL_LEN: u32 = 512u;
+// Sorts after every point, so padding ends up past `num_points`.
+const PADDING_KEY: u32 = 0xffffffffu;
+
+var<workgroup> local_keys: array<u32, 512>;
+var<workgroup> local_indices: array<u32, 512>;
+
+// Large dispatches are split over two dimensions.
+fn workgroup_index(group: vec3<u32>, groups: vec3<u32>) -> u32 {
+    return group.x + group.y * groups.x;
+}
+
+@compute @workgroup_size(256)
+fn compute_keys(
+    @builtin(local_invocation_index) thread: u32,
+    @builtin(workgroup_id) group: vec3<u32>,
+    @builtin(num_workgroups) groups: vec3<u32>,
+) {
+    let i = workgroup_index(group, groups) * WORKGROUP_SIZE + thread;
+    if i < params.num_points {
+        let p = vec3(positions[i * 3u], positions[i * 3u + 1u], positions[i * 3u + 2u]);
+        let depth = max(dot(params.depth_plane.xyz, p) + params.depth_plane.w, 0.0);
+        // Positive floats order like their bits, so inverting them puts far points first.
+        keys[i] = PADDING_KEY - 1u - (bitcast<u32>(depth) & 0x7fffffffu);
+    } else {
+        keys[i] = PADDING_KEY;
+    }
+    indices[i] = i;
+}
+
+fn compare_and_swap_local(a: u32, b: u32, ascending: bool) {
+    let key_a = local_keys[a];
+    let key_b = local_keys[b];
+    if (ascending && key_a > key_b) || (!ascending && key_a < key_b) {
+        local_keys[a] = key_b;
+        local_keys[b] = key_a;
+        let index_a = local_indices[a];
+        local_indices[a] = local_indices[b];
+        local_indices[b] = index_a;
+    }
+}
+
+fn load_local(offset: u32, thread: u32) {
+    local_keys[thread] = keys[offset + thread];
+    local_keys[thread + WORKGROUP_SIZE] = keys[offset + thread + WORKGROUP_SIZE];
+    local_indices[thread] = indices[offset + thread];
+    local_indices[thread + WORKGROUP_SIZE] = indices[offset + thread + WORKGROUP_SIZE];
+    workgroupBarrier();
+}
+
+fn store_local(offset: u32, thread: u32) {
+    keys[offset + thread] = local_keys[thread];
+    keys[offset + thread + WORKGROUP_SIZE] = local_keys[thread + WORKGROUP_SIZE];
+    indices[offset + thread] = local_indices[thread];
+    indices[offset + thread + WORKGROUP_SIZE] = local_indices[thread + WORKGROUP_SIZE];
+}
+
+// Runs the steps of merging `block` long sequences from `distance` down to 1.
+fn merge_local_steps(offset: u32, block: u32, distance: u32, thread: u32) {
+    var d = distance;
+    loop {
+        if d == 0u {
+            break;
+        }
+        let i = 2u * d * (thread / d) + thread % d;
+        compare_and_swap_local(i, i + d, ((offset + i) & block) == 0u);
+        workgroupBarrier();
+        d = d / 2u;
+    }
+}
+
+// Sorts every `LOCAL_LEN` elements, in alternating directions.
+@compute @workgroup_size(256)
+fn sort_local(
+    @builtin(local_invocation_index) thread: u32,
+    @builtin(workgroup_id) group: vec3<u32>,
+    @builtin(num_workgroups) groups: vec3<u32>,
+) {
+    let offset = workgroup_index(group, groups) * LOCAL_LEN;
+    load_local(offset, thread);
+    var block = 2u;
+    loop {
+        if block > LOCAL_LEN {
+            break;
+        }
+        merge_local_steps(offset, block, block / 2u, thread);
+        block = block * 2u;
+    }
+    store_local(offset, thread);
+}
+
+// The steps of merging `params.block` long sequences with a distance below `LOCAL_LEN`.
+@compute @workgroup_size(256)
+fn merge_local(
+    @builtin(local_invocation_index) thread: u32,
+    @builtin(workgroup_id) group: vec3<u32>,
+    @builtin(num_workgroups) groups: vec3<u32>,
+) {
+    let offset = workgroup_index(group, groups) * LOCAL_LEN;
+    load_local(offset, thread);
+    merge_local_steps(offset, params.block, LOCAL_LEN / 2u, thread);
+    store_local(offset, thread);
+}
+
+// A single step of merging `params.block` long sequences, `params.distance` apart.
+@compute @workgroup_size(256)
+fn merge_global(
+    @builtin(local_invocation_index) thread: u32,
+    @builtin(workgroup_id) group: vec3<u32>,
+    @builtin(num_workgroups) groups: vec3<u32>,
+) {
+    let t = workgroup_index(group, groups) * WORKGROUP_SIZE + thread;
+    let d = params.distance;
+    let a = 2u * d * (t / d) + t % d;
+    let b = a + d;
+    let ascending = (a & params.block) == 0u;
+    let key_a = keys[a];
+    let key_b = keys[b];
+    if (ascending && key_a > key_b) || (!ascending && key_a < key_b) {
+        keys[a] = key_b;
+        keys[b] = key_a;
+        let index_a = indices[a];
+        indices[a] = indices[b];
+        indices[b] = index_a;
+    }
+}
@@ -0,0 +1,27 @@
+use bevy::prelude::*;
+
+/// Fades the points seen by a camera towards `color` as they recede, so that depth reads in
+/// dense clouds. Use the [`ClearColor`] for points to fade into the background.
+///
+/// Points up to `near` from the camera keep their color, and from `far` on they are blended
+/// with `color` by `strength`, linearly in between. Distances are along the view direction.
+/// It is applied before eye dome lighting, which still darkens the edges of faded points.
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct DepthCue {
+    pub near: f32,
+    pub far: f32,
+    pub color: Color,
+    /// From `0.0`, which turns it off, to `1.0`.
+    pub strength: f32,
+}
+
+impl Default for DepthCue {
+    fn default() -> Self {
+        Self {
+            near: 10.0,
+            far: 100.0,
+            color: Color::BLACK,
+            strength: 1.0,
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use bevy::{prelude::*, render::mesh::VertexAttributeValues, utils::HashSet};
+use opd_parser::Frames;
+
+use crate::PointCloudAsset;
+
+impl PointCloudAsset {
+    /// Buckets the points into a grid of `voxel_size` cubes and keeps the first point of every
+    /// cell, for quick previews of huge clouds. Unlike keeping a random fraction of the points,
+    /// sparse regions keep all of their points while dense ones are thinned out.
+    ///
+    /// Animated assets are bucketed by their unanimated positions. Precomputed occlusion is
+    /// dropped, since it refers to the old points; compute it again afterwards if needed.
+    pub fn voxel_downsample(&mut self, voxel_size: f32) {
+        if voxel_size <= 0.0 {
+            return;
+        }
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            self.mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            return;
+        };
+        let min = Vec3::from(self.aabb.min());
+        let mut occupied = HashSet::new();
+        let kept: Vec<u32> = positions
+            .iter()
+            .enumerate()
+            .filter(|(_, &position)| {
+                let cell = ((Vec3::from(position) - min) / voxel_size)
+                    .floor()
+                    .as_ivec3();
+                occupied.insert(cell)
+            })
+            .map(|(index, _)| index as u32)
+            .collect();
+        if kept.len() == positions.len() {
+            return;
+        }
+
+        self.select_points(&kept);
+        self.occlusion = None;
+        if let Some(aabb) = self.mesh.compute_aabb() {
+            self.aabb = aabb;
+        }
+    }
+
+    /// Reorders the points randomly, so that the first points of the asset are spread evenly
+    /// over the whole cloud, as [`PointCloudLod`](crate::PointCloudLod) needs. The order only
+    /// depends on the number of points, so two assets with matching points, like the ends of a
+    /// [`PointCloudMorph`](crate::PointCloudMorph), still match after shuffling both.
+    ///
+    /// Precomputed occlusion is dropped, so compute it afterwards. It keeps the shuffled order
+    /// within every cell.
+    pub fn shuffle_points(&mut self) {
+        let mut order: Vec<u32> = (0..self.num_points() as u32).collect();
+        // Fisher-Yates, with SplitMix64 as the generator.
+        let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+        for i in (1..order.len()).rev() {
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^= z >> 31;
+            order.swap(i, (z % (i as u64 + 1)) as usize);
+        }
+        self.select_points(&order);
+        self.occlusion = None;
+    }
+
+    /// Replaces the points with the points at `indices`, in that order, in every attribute,
+    /// scalar field and animation frame.
+    pub(crate) fn select_points(&mut self, indices: &[u32]) {
+        let num_points = self.num_points();
+        for (_, values) in self.mesh.attributes_mut() {
+            select_attribute(values, num_points, indices);
+        }
+        for values in self.scalar_fields.values_mut() {
+            select(values, num_points, 1, indices);
+        }
+        if let Some(frames) = &mut self.animation {
+            select_frames(frames, num_points, indices);
+        }
+    }
+}
+
+/// Selects groups of `stride` values, leaving `values` alone if it doesn't have a group for
+/// every point.
+fn select<T: Copy>(values: &mut Vec<T>, num_points: usize, stride: usize, indices: &[u32]) {
+    if values.len() == num_points * stride {
+        *values = indices
+            .iter()
+            .flat_map(|&index| {
+                let start = index as usize * stride;
+                values[start..start + stride].iter().copied()
+            })
+            .collect();
+    }
+}
+
+fn select_attribute(values: &mut VertexAttributeValues, num_points: usize, indices: &[u32]) {
+    macro_rules! select_variants {
+        ($($variant:ident),*) => {
+            match values {
+                $(VertexAttributeValues::$variant(values) => select(values, num_points, 1, indices),)*
+            }
+        };
+    }
+    select_variants!(
+        Float32, Sint32, Uint32, Float32x2, Sint32x2, Uint32x2, Float32x3, Sint32x3, Uint32x3,
+        Float32x4, Sint32x4, Uint32x4, Sint16x2, Snorm16x2, Uint16x2, Unorm16x2, Sint16x4,
+        Snorm16x4, Uint16x4, Unorm16x4, Sint8x2, Snorm8x2, Uint8x2, Unorm8x2, Sint8x4, Snorm8x4,
+        Uint8x4, Unorm8x4
+    );
+}
+
+/// Frames store three offsets per point.
+pub(crate) fn select_frames(frames: &mut Frames, num_points: usize, indices: &[u32]) {
+    macro_rules! select_variants {
+        ($($variant:ident),*) => {
+            match frames {
+                $(Frames::$variant(frames) => {
+                    for frame in frames {
+                        select(&mut frame.data, num_points, 3, indices);
+                    }
+                })*
+            }
+        };
+    }
+    select_variants!(I8, I16, I32, I64);
+}
@@ -0,0 +1,1056 @@
+use std::collections::BTreeMap;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    math::{DQuat, DVec3},
+    prelude::*,
+    render::render_resource::PrimitiveTopology,
+    utils::{
+        thiserror::{self, Error},
+        BoxedFuture,
+    },
+};
+use serde::{Deserialize, Serialize};
+use xml::{reader::XmlEvent, EventReader, ParserConfig};
+
+use crate::{color::pack_rgba8, PointCloudAsset, ATTRIBUTE_COLOR_PACKED};
+
+/// Loads `.e57` files, as exported by terrestrial laser scanners.
+///
+/// Every `data3D` section of the file is a scan. Its cartesian or spherical coordinates
+/// become the positions, placed by the pose of the scan, `colorRed`/`colorGreen`/`colorBlue`
+/// the point colors and `normalX`/`normalY`/`normalZ` the point normals. Every other numeric
+/// field, like `intensity`, becomes a scalar field of the same name. Points with invalid
+/// coordinates are skipped. E57 files are Z up, so like with LAS files the Y and Z axes are
+/// swapped. The points are recentered, with the center kept in [`PointCloudAsset::origin`].
+///
+/// Only the `bitPackCodec` of the standard is supported, files with other codecs fail to
+/// load with [`E57LoaderError::UnsupportedCodec`].
+#[derive(Default)]
+pub struct E57Loader;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct E57LoaderSettings {
+    /// Merge every scan of the file into the loaded asset. Fields that only some of the scans
+    /// have are dropped. By default the asset holds the first scan, and the other scans are
+    /// labeled assets named after their index: `building.e57#Scan1` is the second scan.
+    pub merge_scans: bool,
+    /// Keep only one point per cube of this size, see [`PointCloudAsset::voxel_downsample`].
+    pub voxel_size: Option<f32>,
+    /// Shuffle the points, see [`PointCloudAsset::shuffle_points`]. Needed for an even
+    /// [`PointCloudLod`](crate::PointCloudLod).
+    pub shuffle: bool,
+}
+
+/// Possible errors that can be produced by [`E57Loader`]
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum E57LoaderError {
+    #[error("Could not load asset: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Not an E57 file")]
+    BadSignature,
+    #[error("E57 file is truncated")]
+    UnexpectedEof,
+    #[error("Invalid E57 XML: {0}")]
+    Xml(String),
+    #[error("Invalid E57 data: {0}")]
+    Data(String),
+    #[error("Unsupported E57 codec {0}, only bitPackCodec is supported")]
+    UnsupportedCodec(String),
+    #[error("E57 scan has no cartesian or spherical coordinates")]
+    MissingPositions,
+    #[error("E57 file has no scans")]
+    NoScans,
+}
+
+const SIGNATURE: &[u8] = b"ASTM-E57";
+const HEADER_LEN: usize = 48;
+/// Every page ends with a CRC-32C checksum of the rest of the page.
+const CHECKSUM_LEN: usize = 4;
+const COMPRESSED_VECTOR_SECTION: u8 = 1;
+const INDEX_PACKET: u8 = 0;
+const DATA_PACKET: u8 = 1;
+const EMPTY_PACKET: u8 = 2;
+
+/// The logical contents of an E57 file, without the page checksums. Offsets stored in the
+/// file are physical, counting the checksums.
+struct PagedFile {
+    logical: Vec<u8>,
+    page_size: u64,
+}
+
+impl PagedFile {
+    fn new(bytes: &[u8]) -> Result<Self, E57LoaderError> {
+        if !bytes.starts_with(SIGNATURE) {
+            return Err(if SIGNATURE.starts_with(bytes) {
+                E57LoaderError::UnexpectedEof
+            } else {
+                E57LoaderError::BadSignature
+            });
+        }
+        let header = bytes
+            .get(..HEADER_LEN)
+            .ok_or(E57LoaderError::UnexpectedEof)?;
+        let page_size = read_u64(&header[40..]);
+        if page_size <= CHECKSUM_LEN as u64 + HEADER_LEN as u64 {
+            return Err(E57LoaderError::Data(format!(
+                "invalid page size {page_size}"
+            )));
+        }
+        let logical = bytes
+            .chunks(page_size as usize)
+            .flat_map(|page| &page[..page.len().saturating_sub(CHECKSUM_LEN)])
+            .copied()
+            .collect();
+        Ok(Self { logical, page_size })
+    }
+
+    fn logical_offset(&self, physical_offset: u64) -> u64 {
+        let page_len = self.page_size - CHECKSUM_LEN as u64;
+        physical_offset / self.page_size * page_len + physical_offset % self.page_size
+    }
+
+    /// `len` logical bytes, from a logical offset.
+    fn get(&self, offset: u64, len: u64) -> Result<&[u8], E57LoaderError> {
+        let start = usize::try_from(offset).map_err(|_| E57LoaderError::UnexpectedEof)?;
+        let len = usize::try_from(len).map_err(|_| E57LoaderError::UnexpectedEof)?;
+        start
+            .checked_add(len)
+            .and_then(|end| self.logical.get(start..end))
+            .ok_or(E57LoaderError::UnexpectedEof)
+    }
+
+    /// The XML section, which describes the scans and where their points are.
+    fn xml(&self) -> Result<Element, E57LoaderError> {
+        let header = self.get(0, HEADER_LEN as u64)?;
+        let xml_offset = self.logical_offset(read_u64(&header[24..]));
+        let xml_len = read_u64(&header[32..]);
+        Element::parse(self.get(xml_offset, xml_len)?)
+    }
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes(bytes[..2].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+/// An element of the XML section.
+#[derive(Default)]
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Element>,
+    text: String,
+}
+
+impl Element {
+    fn parse(xml: &[u8]) -> Result<Self, E57LoaderError> {
+        let config = ParserConfig::new()
+            .trim_whitespace(true)
+            .cdata_to_characters(true);
+        // The bottom of the stack collects the root element.
+        let mut stack = vec![Element::default()];
+        for event in EventReader::new_with_config(xml, config) {
+            match event.map_err(|err| E57LoaderError::Xml(err.to_string()))? {
+                XmlEvent::StartElement {
+                    name, attributes, ..
+                } => stack.push(Element {
+                    name: name.local_name,
+                    attributes: attributes
+                        .into_iter()
+                        .map(|attribute| (attribute.name.local_name, attribute.value))
+                        .collect(),
+                    ..default()
+                }),
+                XmlEvent::EndElement { .. } if stack.len() > 1 => {
+                    let element = stack.pop().unwrap();
+                    stack.last_mut().unwrap().children.push(element);
+                }
+                XmlEvent::Characters(text) => stack.last_mut().unwrap().text.push_str(&text),
+                _ => {}
+            }
+        }
+        stack
+            .pop()
+            .and_then(|document| document.children.into_iter().next())
+            .ok_or_else(|| E57LoaderError::Xml("missing root element".to_owned()))
+    }
+
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| attribute == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn ty(&self) -> &str {
+        self.attribute("type").unwrap_or_default()
+    }
+
+    fn parse_attribute<T: std::str::FromStr>(
+        &self,
+        name: &str,
+    ) -> Result<Option<T>, E57LoaderError> {
+        self.attribute(name)
+            .map(|value| {
+                value
+                    .trim()
+                    .parse()
+                    .map_err(|_| E57LoaderError::Xml(format!("invalid {name} of {}", self.name)))
+            })
+            .transpose()
+    }
+
+    /// The value of a `Float`, `Integer` or `ScaledInteger` element. Empty elements are zero.
+    fn number(&self) -> Result<f64, E57LoaderError> {
+        let text = self.text.trim();
+        let value = if text.is_empty() {
+            0.0
+        } else {
+            text.parse()
+                .map_err(|_| E57LoaderError::Xml(format!("invalid value of {}", self.name)))?
+        };
+        Ok(if self.ty() == "ScaledInteger" {
+            value * self.parse_attribute("scale")?.unwrap_or(1.0)
+                + self.parse_attribute("offset")?.unwrap_or(0.0)
+        } else {
+            value
+        })
+    }
+
+    fn child_number(&self, name: &str) -> Result<Option<f64>, E57LoaderError> {
+        self.child(name).map(Element::number).transpose()
+    }
+}
+
+/// How the values of a field are stored by the `bitPackCodec`.
+enum Encoding {
+    Float {
+        double: bool,
+    },
+    /// Packed into the fewest bits that hold `maximum - minimum`, least significant bit first.
+    Integer {
+        minimum: i64,
+        bits: u32,
+        scale: f64,
+        offset: f64,
+    },
+    /// Strings have a bytestream like every other field, but there's nothing to draw them with.
+    Unsupported,
+}
+
+/// A field of the points of a scan, in the order of their bytestreams.
+struct Field {
+    name: String,
+    encoding: Encoding,
+    /// The range of the values, if the file declares it.
+    limits: Option<(f64, f64)>,
+}
+
+impl Field {
+    /// The fields of a prototype. Nested structures are flattened.
+    fn parse_prototype(prototype: &Element, fields: &mut Vec<Field>) -> Result<(), E57LoaderError> {
+        for element in &prototype.children {
+            let float_limits = || -> Result<_, E57LoaderError> {
+                Ok(element
+                    .parse_attribute::<f64>("minimum")?
+                    .zip(element.parse_attribute::<f64>("maximum")?))
+            };
+            let field = |encoding, limits| Field {
+                name: element.name.clone(),
+                encoding,
+                limits,
+            };
+            match element.ty() {
+                "Structure" => Self::parse_prototype(element, fields)?,
+                "Float" => fields.push(field(
+                    Encoding::Float {
+                        double: element.attribute("precision") != Some("single"),
+                    },
+                    float_limits()?,
+                )),
+                ty @ ("Integer" | "ScaledInteger") => {
+                    let minimum = element.parse_attribute("minimum")?.unwrap_or(i64::MIN);
+                    let maximum = element.parse_attribute("maximum")?.unwrap_or(i64::MAX);
+                    if maximum < minimum {
+                        return Err(E57LoaderError::Xml(format!(
+                            "invalid limits of {}",
+                            element.name
+                        )));
+                    }
+                    let range = (maximum as i128 - minimum as i128) as u128;
+                    let (scale, offset) = if ty == "ScaledInteger" {
+                        (
+                            element.parse_attribute("scale")?.unwrap_or(1.0),
+                            element.parse_attribute("offset")?.unwrap_or(0.0),
+                        )
+                    } else {
+                        (1.0, 0.0)
+                    };
+                    fields.push(field(
+                        Encoding::Integer {
+                            minimum,
+                            bits: u128::BITS - range.leading_zeros(),
+                            scale,
+                            offset,
+                        },
+                        Some((
+                            minimum as f64 * scale + offset,
+                            maximum as f64 * scale + offset,
+                        )),
+                    ));
+                }
+                _ => fields.push(field(Encoding::Unsupported, None)),
+            }
+        }
+        Ok(())
+    }
+
+    /// The number of bits of a value in the bytestream.
+    fn bits(&self) -> u64 {
+        match self.encoding {
+            Encoding::Float { double: false } => 32,
+            Encoding::Float { double: true } => 64,
+            Encoding::Integer { bits, .. } => bits as u64,
+            Encoding::Unsupported => 0,
+        }
+    }
+
+    /// The number of bytes of the bytestream of `count` values.
+    fn stream_len(&self, count: u64) -> Option<u64> {
+        Some(count.checked_mul(self.bits())?.div_ceil(8))
+    }
+
+    fn decode(&self, stream: &[u8], count: usize) -> Result<Vec<f64>, E57LoaderError> {
+        if self
+            .stream_len(count as u64)
+            .filter(|&len| stream.len() as u64 >= len)
+            .is_none()
+        {
+            return Err(E57LoaderError::Data(format!(
+                "not enough values of {}",
+                self.name
+            )));
+        }
+        Ok(match self.encoding {
+            Encoding::Float { double: false } => stream
+                .chunks_exact(4)
+                .take(count)
+                .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()) as f64)
+                .collect(),
+            Encoding::Float { double: true } => stream
+                .chunks_exact(8)
+                .take(count)
+                .map(|bytes| f64::from_le_bytes(bytes.try_into().unwrap()))
+                .collect(),
+            Encoding::Integer {
+                minimum,
+                bits,
+                scale,
+                offset,
+            } => {
+                // Fields with a single possible value have no bits.
+                let mask = u128::MAX.checked_shr(u128::BITS - bits).unwrap_or(0);
+                (0..count)
+                    .map(|index| {
+                        let bit = index * bits as usize;
+                        // A value spans at most 9 bytes.
+                        let mut window = [0; 16];
+                        let bytes = &stream[(bit / 8).min(stream.len())..];
+                        let len = bytes.len().min(9);
+                        window[..len].copy_from_slice(&bytes[..len]);
+                        let raw = (u128::from_le_bytes(window) >> (bit % 8)) & mask;
+                        (minimum as i128 + raw as i128) as f64 * scale + offset
+                    })
+                    .collect()
+            }
+            Encoding::Unsupported => Vec::new(),
+        })
+    }
+}
+
+/// Reads the bytestream of every field from the packets of a compressed vector section.
+fn read_bytestreams(
+    file: &PagedFile,
+    physical_offset: u64,
+    fields: &[Field],
+    count: u64,
+) -> Result<Vec<Vec<u8>>, E57LoaderError> {
+    let data_error = |message: &str| E57LoaderError::Data(message.to_owned());
+    let section_offset = file.logical_offset(physical_offset);
+    let section = file.get(section_offset, 32)?;
+    if section[0] != COMPRESSED_VECTOR_SECTION {
+        return Err(data_error("points are not a compressed vector section"));
+    }
+    let section_len = read_u64(&section[8..]);
+    let section_end = section_offset
+        .checked_add(section_len)
+        .filter(|&end| end <= file.logical.len() as u64)
+        .ok_or(E57LoaderError::UnexpectedEof)?;
+    // Every record takes at least a bit of the section, even if all its fields are constant,
+    // so that `recordCount` can't ask the decoders for more values than the file holds.
+    let record_bits = fields.iter().map(Field::bits).sum::<u64>().max(1);
+    if count
+        .checked_mul(record_bits)
+        .filter(|&bits| bits <= section_len.saturating_mul(8))
+        .is_none()
+    {
+        return Err(data_error("more records than the section holds"));
+    }
+    let mut offset = file.logical_offset(read_u64(&section[16..]));
+    let lens = fields
+        .iter()
+        .map(|field| field.stream_len(count))
+        .collect::<Option<Vec<u64>>>()
+        .ok_or_else(|| data_error("more records than the section holds"))?;
+    let mut streams = vec![Vec::new(); fields.len()];
+    while offset < section_end
+        && streams
+            .iter()
+            .zip(&lens)
+            .any(|(stream, &len)| (stream.len() as u64) < len)
+    {
+        let header = file.get(offset, 4)?;
+        let packet_len = read_u16(&header[2..]) as u64 + 1;
+        match header[0] {
+            DATA_PACKET => {
+                let packet = file.get(offset, packet_len)?;
+                let stream_count = packet
+                    .get(4..6)
+                    .map(read_u16)
+                    .ok_or_else(|| data_error("truncated data packet"))?
+                    as usize;
+                if stream_count != fields.len() {
+                    return Err(data_error("data packet doesn't match the prototype"));
+                }
+                let mut start = 6 + stream_count * 2;
+                for (index, stream) in streams.iter_mut().enumerate() {
+                    let len = packet
+                        .get(6 + index * 2..)
+                        .map(read_u16)
+                        .ok_or_else(|| data_error("truncated data packet"))?
+                        as usize;
+                    let buffer = packet
+                        .get(start..start + len)
+                        .ok_or_else(|| data_error("truncated data packet"))?;
+                    stream.extend_from_slice(buffer);
+                    start += len;
+                }
+            }
+            INDEX_PACKET | EMPTY_PACKET => {}
+            _ => return Err(data_error("unknown packet type")),
+        }
+        offset += packet_len;
+    }
+    Ok(streams)
+}
+
+/// The points of one `data3D` section, in the file's coordinates.
+struct Scan {
+    positions: Vec<DVec3>,
+    colors: Option<Vec<u32>>,
+    normals: Option<Vec<[f32; 3]>>,
+    scalar_fields: BTreeMap<String, Vec<f32>>,
+}
+
+impl Scan {
+    fn read(file: &PagedFile, data3d: &Element) -> Result<Self, E57LoaderError> {
+        let xml_error = |message: &str| E57LoaderError::Xml(message.to_owned());
+        let points = data3d
+            .child("points")
+            .filter(|points| points.ty() == "CompressedVector")
+            .ok_or_else(|| xml_error("scan has no points"))?;
+        if let Some(codecs) = points.child("codecs") {
+            for codec in &codecs.children {
+                if let Some(codec) = codec.children.iter().find(|child| child.name != "inputs") {
+                    if codec.name != "bitPackCodec" {
+                        return Err(E57LoaderError::UnsupportedCodec(codec.name.clone()));
+                    }
+                }
+            }
+        }
+        let count: u64 = points
+            .parse_attribute("recordCount")?
+            .ok_or_else(|| xml_error("points have no recordCount"))?;
+        let file_offset = points
+            .parse_attribute("fileOffset")?
+            .ok_or_else(|| xml_error("points have no fileOffset"))?;
+        let mut fields = Vec::new();
+        Field::parse_prototype(
+            points
+                .child("prototype")
+                .ok_or_else(|| xml_error("points have no prototype"))?,
+            &mut fields,
+        )?;
+
+        let streams = read_bytestreams(file, file_offset, &fields, count)?;
+        let count = count as usize;
+        let mut values = BTreeMap::new();
+        for (field, stream) in fields.iter().zip(&streams) {
+            if !matches!(field.encoding, Encoding::Unsupported) {
+                values.insert(field.name.as_str(), field.decode(stream, count)?);
+            }
+        }
+        let limits = |name: &str| {
+            fields
+                .iter()
+                .find(|field| field.name == name)
+                .and_then(|field| field.limits)
+        };
+
+        let (positions, invalid_state): (Vec<DVec3>, _) = match (
+            values.get("cartesianX"),
+            values.get("cartesianY"),
+            values.get("cartesianZ"),
+            values.get("sphericalRange"),
+            values.get("sphericalAzimuth"),
+            values.get("sphericalElevation"),
+        ) {
+            (Some(x), Some(y), Some(z), ..) => (
+                (0..count)
+                    .map(|index| DVec3::new(x[index], y[index], z[index]))
+                    .collect(),
+                values.get("cartesianInvalidState"),
+            ),
+            (.., Some(range), Some(azimuth), Some(elevation)) => (
+                (0..count)
+                    .map(|index| {
+                        let (sin_azimuth, cos_azimuth) = azimuth[index].sin_cos();
+                        let (sin_elevation, cos_elevation) = elevation[index].sin_cos();
+                        range[index]
+                            * DVec3::new(
+                                cos_elevation * cos_azimuth,
+                                cos_elevation * sin_azimuth,
+                                sin_elevation,
+                            )
+                    })
+                    .collect(),
+                values.get("sphericalInvalidState"),
+            ),
+            _ => return Err(E57LoaderError::MissingPositions),
+        };
+        // Only `0` is valid, points with other states at most have a direction.
+        let valid: Vec<usize> = (0..count)
+            .filter(|&index| !invalid_state.is_some_and(|state| state[index] != 0.0))
+            .collect();
+
+        let pose = data3d.child("pose");
+        let rotation = match pose.and_then(|pose| pose.child("rotation")) {
+            Some(rotation) => DQuat::from_xyzw(
+                rotation.child_number("x")?.unwrap_or(0.0),
+                rotation.child_number("y")?.unwrap_or(0.0),
+                rotation.child_number("z")?.unwrap_or(0.0),
+                rotation.child_number("w")?.unwrap_or(1.0),
+            )
+            .normalize(),
+            None => DQuat::IDENTITY,
+        };
+        let translation = match pose.and_then(|pose| pose.child("translation")) {
+            Some(translation) => DVec3::new(
+                translation.child_number("x")?.unwrap_or(0.0),
+                translation.child_number("y")?.unwrap_or(0.0),
+                translation.child_number("z")?.unwrap_or(0.0),
+            ),
+            None => DVec3::ZERO,
+        };
+        // Z up to Y up, like the LAS loader.
+        let swizzle = |v: DVec3| DVec3::new(v.x, v.z, v.y);
+        let positions = valid
+            .iter()
+            .map(|&index| swizzle(rotation * positions[index] + translation))
+            .collect();
+
+        let color_names = ["colorRed", "colorGreen", "colorBlue"];
+        let colors = match color_names.map(|name| values.get(name)) {
+            [Some(red), Some(green), Some(blue)] => {
+                // The ranges of the colors are in `colorLimits`, or else in the prototype.
+                let color_limits = data3d.child("colorLimits");
+                let mut channel_limits = [(0.0, 1.0); 3];
+                for (channel, name) in color_names.into_iter().enumerate() {
+                    let suffix = &name["color".len()..];
+                    let from_limits = match color_limits {
+                        Some(color_limits) => color_limits
+                            .child_number(&format!("color{suffix}Minimum"))?
+                            .zip(color_limits.child_number(&format!("color{suffix}Maximum"))?),
+                        None => None,
+                    };
+                    if let Some(limits) = from_limits.or_else(|| limits(name)) {
+                        channel_limits[channel] = limits;
+                    }
+                }
+                let channel = |values: &[f64], channel: usize, index: usize| {
+                    let (min, max) = channel_limits[channel];
+                    let value = (values[index] - min) / (max - min).max(f64::EPSILON);
+                    (value.clamp(0.0, 1.0) * u8::MAX as f64).round() as u8
+                };
+                Some(
+                    valid
+                        .iter()
+                        .map(|&index| {
+                            pack_rgba8([
+                                channel(red, 0, index),
+                                channel(green, 1, index),
+                                channel(blue, 2, index),
+                                u8::MAX,
+                            ])
+                        })
+                        .collect(),
+                )
+            }
+            _ => None,
+        };
+
+        let normal_names = ["normalX", "normalY", "normalZ"];
+        let normals = match normal_names.map(|name| values.get(name)) {
+            [Some(x), Some(y), Some(z)] => Some(
+                valid
+                    .iter()
+                    .map(|&index| {
+                        let normal = rotation * DVec3::new(x[index], y[index], z[index]);
+                        swizzle(normal).as_vec3().to_array()
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        };
+
+        let mut used = vec![
+            "cartesianX",
+            "cartesianY",
+            "cartesianZ",
+            "cartesianInvalidState",
+            "sphericalRange",
+            "sphericalAzimuth",
+            "sphericalElevation",
+            "sphericalInvalidState",
+            "isColorInvalid",
+            "isIntensityInvalid",
+            "isTimeStampInvalid",
+        ];
+        if colors.is_some() {
+            used.extend(color_names);
+        }
+        if normals.is_some() {
+            used.extend(normal_names);
+        }
+        let scalar_fields = values
+            .iter()
+            .filter(|(name, _)| !used.contains(name))
+            .map(|(name, values)| {
+                let values = valid.iter().map(|&index| values[index] as f32).collect();
+                (name.to_string(), values)
+            })
+            .collect();
+
+        Ok(Self {
+            positions,
+            colors,
+            normals,
+            scalar_fields,
+        })
+    }
+
+    /// Appends the points of `other`, dropping the fields that it doesn't have.
+    fn merge(&mut self, other: Scan) {
+        self.positions.extend(other.positions);
+        fn merge_values<T>(values: &mut Option<Vec<T>>, other: Option<Vec<T>>) {
+            match (values.as_mut(), other) {
+                (Some(values), Some(other)) => values.extend(other),
+                _ => *values = None,
+            }
+        }
+        merge_values(&mut self.colors, other.colors);
+        merge_values(&mut self.normals, other.normals);
+        let mut other_fields = other.scalar_fields;
+        self.scalar_fields
+            .retain(|name, values| match other_fields.remove(name) {
+                Some(other) => {
+                    values.extend(other);
+                    true
+                }
+                None => false,
+            });
+    }
+
+    fn into_asset(self) -> PointCloudAsset {
+        // Scans are often geo-referenced, so center them in double precision.
+        let (min, max) = self.positions.iter().fold(
+            (DVec3::splat(f64::MAX), DVec3::splat(f64::MIN)),
+            |(min, max), &position| (min.min(position), max.max(position)),
+        );
+        let origin = if self.positions.is_empty() {
+            DVec3::ZERO
+        } else {
+            (min + max) / 2.0
+        };
+        let positions: Vec<Vec3> = self
+            .positions
+            .into_iter()
+            .map(|position| (position - origin).as_vec3())
+            .collect();
+
+        let mut mesh = Mesh::new(PrimitiveTopology::PointList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        if let Some(colors) = self.colors {
+            mesh.insert_attribute(ATTRIBUTE_COLOR_PACKED, colors);
+        }
+        if let Some(normals) = self.normals {
+            mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        }
+        let mut asset = PointCloudAsset::new(mesh);
+        asset.origin = origin;
+        asset.scalar_fields = self.scalar_fields;
+        asset
+    }
+}
+
+impl E57Loader {
+    /// Reads every scan of an E57 file, in the order of the file.
+    pub fn load_e57(bytes: &[u8]) -> Result<Vec<PointCloudAsset>, E57LoaderError> {
+        Ok(Self::read_scans(bytes)?
+            .into_iter()
+            .map(Scan::into_asset)
+            .collect())
+    }
+
+    fn read_scans(bytes: &[u8]) -> Result<Vec<Scan>, E57LoaderError> {
+        let file = PagedFile::new(bytes)?;
+        let root = file.xml()?;
+        let Some(data3d) = root.child("data3D") else {
+            return Ok(Vec::new());
+        };
+        data3d
+            .children
+            .iter()
+            .map(|scan| Scan::read(&file, scan))
+            .collect()
+    }
+}
+
+impl AssetLoader for E57Loader {
+    type Asset = PointCloudAsset;
+    type Settings = E57LoaderSettings;
+    type Error = E57LoaderError;
+
+    fn extensions(&self) -> &[&str] {
+        &["e57"]
+    }
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        settings: &'a E57LoaderSettings,
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            let mut scans = Self::read_scans(&bytes)?.into_iter();
+            let mut first = scans.next().ok_or(E57LoaderError::NoScans)?;
+            let process = |scan: Scan| {
+                let mut asset = scan.into_asset();
+                if let Some(voxel_size) = settings.voxel_size {
+                    asset.voxel_downsample(voxel_size);
+                }
+                if settings.shuffle {
+                    asset.shuffle_points();
+                }
+                asset
+            };
+            if settings.merge_scans {
+                scans.for_each(|scan| first.merge(scan));
+            } else {
+                for (index, scan) in scans.enumerate() {
+                    load_context.add_labeled_asset(format!("Scan{}", index + 1), process(scan));
+                }
+            }
+            Ok(process(first))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::mesh::VertexAttributeValues;
+
+    use super::*;
+
+    const PAGE_SIZE: usize = 1024;
+    const PAGE_LEN: usize = PAGE_SIZE - CHECKSUM_LEN;
+
+    fn physical_offset(logical_offset: usize) -> usize {
+        logical_offset / PAGE_LEN * PAGE_SIZE + logical_offset % PAGE_LEN
+    }
+
+    fn pad(bytes: &mut Vec<u8>, alignment: usize) {
+        bytes.resize(bytes.len().next_multiple_of(alignment), 0);
+    }
+
+    /// Packs `values` least significant bit first, like the `bitPackCodec`.
+    fn bit_pack(values: impl IntoIterator<Item = u64>, bits: u32) -> Vec<u8> {
+        let (mut packed, mut acc, mut len) = (Vec::new(), 0u128, 0);
+        for value in values {
+            acc |= (value as u128) << len;
+            len += bits;
+            while len >= 8 {
+                packed.push(acc as u8);
+                acc >>= 8;
+                len -= 8;
+            }
+        }
+        if len > 0 {
+            packed.push(acc as u8);
+        }
+        packed
+    }
+
+    fn floats(values: impl IntoIterator<Item = f32>) -> Vec<u8> {
+        values.into_iter().flat_map(f32::to_le_bytes).collect()
+    }
+
+    fn doubles(values: impl IntoIterator<Item = f64>) -> Vec<u8> {
+        values.into_iter().flat_map(f64::to_le_bytes).collect()
+    }
+
+    /// Writes an E57 file with `PAGE_SIZE` pages. Every scan is the XML of its `data3D`
+    /// child, where `FILE_OFFSET` is replaced with the offset of its compressed vector
+    /// section, and the bytestreams of its fields. The streams are split over several data
+    /// packets, and the checksums are left empty since they aren't verified.
+    fn e57_file(scans: &[(&str, Vec<Vec<u8>>)]) -> Vec<u8> {
+        let mut logical = vec![0; HEADER_LEN];
+        let mut scans_xml = String::new();
+        for (xml, streams) in scans {
+            pad(&mut logical, 4);
+            let section_offset = logical.len();
+            let mut packets = Vec::new();
+            let mut read = vec![0; streams.len()];
+            while streams
+                .iter()
+                .zip(&read)
+                .any(|(stream, &read)| read < stream.len())
+            {
+                let buffers: Vec<&[u8]> = streams
+                    .iter()
+                    .zip(&mut read)
+                    .map(|(stream, read)| {
+                        let buffer = &stream[*read..(*read + 200).min(stream.len())];
+                        *read += buffer.len();
+                        buffer
+                    })
+                    .collect();
+                let mut packet = vec![DATA_PACKET, 0, 0, 0];
+                packet.extend((buffers.len() as u16).to_le_bytes());
+                for buffer in &buffers {
+                    packet.extend((buffer.len() as u16).to_le_bytes());
+                }
+                packet.extend(buffers.concat());
+                pad(&mut packet, 4);
+                let len = (packet.len() as u16 - 1).to_le_bytes();
+                packet[2..4].copy_from_slice(&len);
+                packets.extend(packet);
+            }
+            packets.extend([EMPTY_PACKET, 0, 7, 0, 0, 0, 0, 0]);
+
+            logical.extend([COMPRESSED_VECTOR_SECTION, 0, 0, 0, 0, 0, 0, 0]);
+            logical.extend((32 + packets.len() as u64).to_le_bytes());
+            logical.extend((physical_offset(section_offset + 32) as u64).to_le_bytes());
+            logical.extend(0u64.to_le_bytes());
+            logical.extend(packets);
+            scans_xml.push_str(
+                &xml.replace("FILE_OFFSET", &physical_offset(section_offset).to_string()),
+            );
+        }
+
+        let xml = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <e57Root type="Structure" xmlns="http://www.astm.org/COMMIT/E57/2010-e57-v1.0">
+                <formatName type="String"><![CDATA[ASTM E57 3D Imaging Data File]]></formatName>
+                <data3D type="Vector" allowHeterogeneousChildren="1">{scans_xml}</data3D>
+            </e57Root>"#
+        );
+        pad(&mut logical, 4);
+        let xml_offset = logical.len();
+        logical.extend(xml.as_bytes());
+        pad(&mut logical, PAGE_LEN);
+
+        let num_pages = logical.len() / PAGE_LEN;
+        let mut header = SIGNATURE.to_vec();
+        header.extend(1u32.to_le_bytes());
+        header.extend(0u32.to_le_bytes());
+        header.extend(((num_pages * PAGE_SIZE) as u64).to_le_bytes());
+        header.extend((physical_offset(xml_offset) as u64).to_le_bytes());
+        header.extend((xml.len() as u64).to_le_bytes());
+        header.extend((PAGE_SIZE as u64).to_le_bytes());
+        logical[..HEADER_LEN].copy_from_slice(&header);
+        logical
+            .chunks(PAGE_LEN)
+            .flat_map(|page| [page, &[0; CHECKSUM_LEN]].concat())
+            .collect()
+    }
+
+    const CARTESIAN_POINTS: usize = 700;
+
+    /// Cartesian points with colors and an intensity, every tenth of them invalid. Large
+    /// enough to span several pages.
+    fn cartesian_scan() -> (&'static str, Vec<Vec<u8>>) {
+        let indices = 0..CARTESIAN_POINTS as u64;
+        let xml = r#"<vectorChild type="Structure">
+            <points type="CompressedVector" fileOffset="FILE_OFFSET" recordCount="700">
+                <prototype type="Structure">
+                    <cartesianX type="Float" precision="single"/>
+                    <cartesianY type="Float" precision="single"/>
+                    <cartesianZ type="Float" precision="single"/>
+                    <colorRed type="Integer" minimum="0" maximum="255"/>
+                    <colorGreen type="Integer" minimum="0" maximum="255"/>
+                    <colorBlue type="Integer" minimum="0" maximum="255"/>
+                    <intensity type="ScaledInteger" minimum="0" maximum="1000" scale="0.001"/>
+                    <cartesianInvalidState type="Integer" minimum="0" maximum="2"/>
+                </prototype>
+                <codecs type="Vector" allowHeterogeneousChildren="1"/>
+            </points>
+        </vectorChild>"#;
+        let streams = vec![
+            floats(indices.clone().map(|index| index as f32)),
+            floats(indices.clone().map(|_| 1.0)),
+            floats(indices.clone().map(|_| 2.0)),
+            bit_pack(indices.clone().map(|index| index % 256), 8),
+            bit_pack(indices.clone().map(|_| 128), 8),
+            bit_pack(indices.clone().map(|_| 255), 8),
+            bit_pack(indices.clone().map(|index| index % 1000), 10),
+            bit_pack(indices.map(|index| if index % 10 == 0 { 2 } else { 0 }), 2),
+        ];
+        (xml, streams)
+    }
+
+    /// Points one unit ahead of a scanner at x = 10, turned 90 degrees about Z, with a
+    /// constant field that takes no bits.
+    fn spherical_scan() -> (&'static str, Vec<Vec<u8>>) {
+        let xml = r#"<vectorChild type="Structure">
+            <pose type="Structure">
+                <rotation type="Structure">
+                    <w type="Float">0.7071067811865476</w>
+                    <x type="Float">0</x>
+                    <y type="Float">0</y>
+                    <z type="Float">0.7071067811865476</z>
+                </rotation>
+                <translation type="Structure">
+                    <x type="Float">10</x>
+                    <y type="Float">0</y>
+                    <z type="Float">0</z>
+                </translation>
+            </pose>
+            <points type="CompressedVector" fileOffset="FILE_OFFSET" recordCount="50">
+                <prototype type="Structure">
+                    <sphericalRange type="Float"/>
+                    <sphericalAzimuth type="Float"/>
+                    <sphericalElevation type="Float"/>
+                    <rowIndex type="Integer" minimum="7" maximum="7"/>
+                </prototype>
+            </points>
+        </vectorChild>"#;
+        let streams = vec![
+            doubles([1.0; 50]),
+            doubles([0.0; 50]),
+            doubles([0.0; 50]),
+            Vec::new(),
+        ];
+        (xml, streams)
+    }
+
+    fn positions(asset: &PointCloudAsset) -> &[[f32; 3]] {
+        match asset.mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+            Some(VertexAttributeValues::Float32x3(positions)) => positions,
+            _ => panic!("missing positions"),
+        }
+    }
+
+    #[test]
+    fn loads_every_scan_of_a_paged_file() {
+        let file = e57_file(&[cartesian_scan(), spherical_scan()]);
+        assert!(file.len() > 4 * PAGE_SIZE);
+        let scans = E57Loader::load_e57(&file).unwrap();
+        assert_eq!(scans.len(), 2);
+
+        let cartesian = &scans[0];
+        assert_eq!(cartesian.num_points(), CARTESIAN_POINTS * 9 / 10);
+        // The first point is invalid, the second one is at x = 1 and Z up becomes Y up.
+        let first = cartesian.origin + Vec3::from(positions(cartesian)[0]).as_dvec3();
+        assert!(first.abs_diff_eq(DVec3::new(1.0, 2.0, 1.0), 1e-4));
+        let Some(VertexAttributeValues::Uint32(colors)) =
+            cartesian.mesh.attribute(ATTRIBUTE_COLOR_PACKED)
+        else {
+            panic!("missing colors");
+        };
+        assert_eq!(colors[0], pack_rgba8([1, 128, 255, 255]));
+        let intensity = cartesian.scalar_field("intensity").unwrap();
+        assert!((intensity[0] - 0.001).abs() < 1e-6);
+
+        let spherical = &scans[1];
+        assert_eq!(spherical.num_points(), 50);
+        // Along the scanner's X axis, turned to the file's Y axis and swapped to Z.
+        assert!(spherical
+            .origin
+            .abs_diff_eq(DVec3::new(10.0, 0.0, 1.0), 1e-9));
+        assert_eq!(spherical.scalar_field("rowIndex").unwrap(), &[7.0; 50]);
+
+        let mut scans = E57Loader::read_scans(&file).unwrap().into_iter();
+        let mut merged = scans.next().unwrap();
+        scans.for_each(|scan| merged.merge(scan));
+        let merged = merged.into_asset();
+        assert_eq!(merged.num_points(), CARTESIAN_POINTS * 9 / 10 + 50);
+        // Only the first scan has colors and an intensity.
+        assert!(merged.mesh.attribute(ATTRIBUTE_COLOR_PACKED).is_none());
+        assert_eq!(merged.scalar_field_names().count(), 0);
+    }
+
+    #[test]
+    fn rejects_more_records_than_the_section_holds() {
+        let (xml, streams) = cartesian_scan();
+        for count in ["701", "18446744073709551615"] {
+            let xml = xml.replace(r#"recordCount="700""#, &format!(r#"recordCount="{count}""#));
+            let file = e57_file(&[(&xml, streams.clone())]);
+            assert!(matches!(
+                E57Loader::load_e57(&file),
+                Err(E57LoaderError::Data(_))
+            ));
+        }
+
+        // Constant fields take no bits, so their values can't be checked against the streams.
+        let xml = r#"<vectorChild type="Structure">
+            <points type="CompressedVector" fileOffset="FILE_OFFSET" recordCount="1000000000000">
+                <prototype type="Structure">
+                    <cartesianX type="Integer" minimum="1" maximum="1"/>
+                    <cartesianY type="Integer" minimum="1" maximum="1"/>
+                    <cartesianZ type="Integer" minimum="1" maximum="1"/>
+                </prototype>
+            </points>
+        </vectorChild>"#;
+        let file = e57_file(&[(xml, vec![Vec::new(); 3])]);
+        assert!(matches!(
+            E57Loader::load_e57(&file),
+            Err(E57LoaderError::Data(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let mut file = e57_file(&[spherical_scan()]);
+        assert!(matches!(
+            E57Loader::load_e57(&file[..HEADER_LEN / 2]),
+            Err(E57LoaderError::UnexpectedEof)
+        ));
+        file[0] = b'X';
+        assert!(matches!(
+            E57Loader::load_e57(&file),
+            Err(E57LoaderError::BadSignature)
+        ));
+    }
+}
@@ -0,0 +1,274 @@
+use std::io::{self, Write};
+
+use bevy::{prelude::*, render::mesh::VertexAttributeValues};
+
+use crate::{
+    clippling_planes::{GpuClippingPlaneRange, MAX_CLIPPING_PLANES},
+    ClippingPlaneRange, PointCloudAsset, PointCloudSelection, PointConfidence, TimeWindow,
+    ATTRIBUTE_COLOR_PACKED, ATTRIBUTE_COLOR_RGBA16, TIMESTAMP_SCALAR_FIELD,
+};
+
+/// The filters besides the clipping planes that hide points of a cloud, for
+/// [`PointCloudAsset::filtered_points`]. Take them from the components of the cloud's entity
+/// to export what it shows.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PointFilters<'a> {
+    pub time_window: Option<&'a TimeWindow>,
+    pub confidence: Option<&'a PointConfidence>,
+    /// Keeps only the selected points, which are highlighted rather than filtered when drawn.
+    pub selection: Option<&'a PointCloudSelection>,
+}
+
+impl PointCloudAsset {
+    /// Indices of the points that pass the clipping planes and the `filters`, the same way
+    /// they are filtered on the GPU. `transform` places the asset in the world, and the planes
+    /// are given as `(range, plane transform)` pairs like the
+    /// [`ClippingPlaneBundle`](crate::ClippingPlaneBundle) entities they come from.
+    ///
+    /// Animated assets are filtered by their unanimated positions.
+    pub fn filtered_points<'a>(
+        &self,
+        transform: &GlobalTransform,
+        clipping_planes: impl IntoIterator<Item = (&'a ClippingPlaneRange, &'a GlobalTransform)>,
+        filters: PointFilters,
+    ) -> Vec<u32> {
+        let planes: Vec<GpuClippingPlaneRange> = clipping_planes
+            .into_iter()
+            .take(MAX_CLIPPING_PLANES)
+            .map(|(range, plane_transform)| GpuClippingPlaneRange::new(range, plane_transform))
+            .collect();
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            self.mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            return Vec::new();
+        };
+        // Fields that don't have a value for every point are drawn as zeros.
+        let scalar_field = |name: &str| {
+            self.scalar_field(name)
+                .map(|field| (field.len() == positions.len()).then_some(field))
+        };
+        let time_window = filters.time_window.and_then(|time_window| {
+            let times = scalar_field(TIMESTAMP_SCALAR_FIELD)?;
+            let (_, start, end) = time_window.gpu(Some(self));
+            Some((times, start, end))
+        });
+        let confidence = filters
+            .confidence
+            .and_then(|confidence| Some((scalar_field(&confidence.field)?, confidence.threshold)));
+        let selected = filters.selection.map(|selection| {
+            let mut selected = vec![false; positions.len()];
+            for &index in &selection.indices {
+                if let Some(selected) = selected.get_mut(index as usize) {
+                    *selected = true;
+                }
+            }
+            selected
+        });
+        let value = |field: Option<&[f32]>, index: usize| field.map_or(0.0, |field| field[index]);
+        positions
+            .iter()
+            .enumerate()
+            .filter(|&(index, position)| {
+                if selected.as_ref().is_some_and(|selected| !selected[index]) {
+                    return false;
+                }
+                if let Some((times, start, end)) = time_window {
+                    let time = value(times, index);
+                    if time < start || time > end {
+                        return false;
+                    }
+                }
+                if let Some((confidences, threshold)) = confidence {
+                    if value(confidences, index) < threshold {
+                        return false;
+                    }
+                }
+                let world_position = transform.transform_point(Vec3::from(*position));
+                planes.iter().all(|plane| plane.contains(world_position))
+            })
+            .map(|(index, _)| index as u32)
+            .collect()
+    }
+
+    /// Writes the points at `indices` as a binary PLY file, and returns the number of points
+    /// written. Indices are in the current order of the points, which
+    /// [`PointCloudAsset::compute_occlusion`] changes.
+    ///
+    /// Positions are written as doubles in the source data's coordinate system, that is
+    /// offset by [`PointCloudAsset::origin`]. Colors are written as 8 bit RGB, and every
+    /// scalar field as a float property of the same name. Fails with
+    /// [`io::ErrorKind::InvalidInput`] before writing anything if an index is out of range.
+    pub fn write_ply(&self, indices: &[u32], mut writer: impl Write) -> io::Result<usize> {
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            self.mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the point cloud has no positions",
+            ));
+        };
+        if let Some(index) = indices
+            .iter()
+            .find(|&&index| index as usize >= positions.len())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "point {index} is out of range for {} points",
+                    positions.len()
+                ),
+            ));
+        }
+        let colors: Option<Box<dyn Fn(usize) -> [u8; 3]>> =
+            match self.mesh.attribute(ATTRIBUTE_COLOR_RGBA16) {
+                Some(VertexAttributeValues::Uint32x2(colors)) => Some(Box::new(|index| {
+                    let [rg, ba] = colors[index];
+                    [(rg >> 8) as u8, (rg >> 24) as u8, (ba >> 8) as u8]
+                })),
+                _ => match self.mesh.attribute(ATTRIBUTE_COLOR_PACKED) {
+                    Some(VertexAttributeValues::Uint32(colors)) => Some(Box::new(|index| {
+                        let [r, g, b, _] = colors[index].to_le_bytes();
+                        [r, g, b]
+                    })),
+                    _ => None,
+                },
+            };
+        let mut writer = io::BufWriter::new(&mut writer);
+
+        writeln!(writer, "ply")?;
+        writeln!(writer, "format binary_little_endian 1.0")?;
+        writeln!(writer, "element vertex {}", indices.len())?;
+        for axis in ["x", "y", "z"] {
+            writeln!(writer, "property double {axis}")?;
+        }
+        if colors.is_some() {
+            for channel in ["red", "green", "blue"] {
+                writeln!(writer, "property uchar {channel}")?;
+            }
+        }
+        for name in self.scalar_field_names() {
+            writeln!(
+                writer,
+                "property float {}",
+                name.replace(char::is_whitespace, "_")
+            )?;
+        }
+        writeln!(writer, "end_header")?;
+
+        for &index in indices {
+            let index = index as usize;
+            let position = Vec3::from(positions[index]).as_dvec3() + self.origin;
+            for value in position.to_array() {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+            if let Some(colors) = &colors {
+                writer.write_all(&colors(index))?;
+            }
+            for values in self.scalar_fields.values() {
+                writer.write_all(&values[index].to_le_bytes())?;
+            }
+        }
+        writer.flush()?;
+        Ok(indices.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PlyLoader;
+    use bevy::math::DVec3;
+
+    fn asset() -> PointCloudAsset {
+        let mut asset = PointCloudAsset::from_points(
+            vec![Vec3::ZERO, Vec3::new(1.0, 2.0, 3.0)],
+            vec![[10, 20, 30, 255], [40, 50, 60, 255]],
+        )
+        .unwrap();
+        asset.origin = DVec3::new(100.0, 0.0, 0.0);
+        asset
+            .scalar_fields
+            .insert("intensity".to_owned(), vec![0.5, 0.25]);
+        asset
+    }
+
+    #[test]
+    fn writes_points_the_ply_loader_reads_back() {
+        let mut bytes = Vec::new();
+        assert_eq!(asset().write_ply(&[1], &mut bytes).unwrap(), 1);
+        let loaded = PlyLoader::load_ply(&bytes).unwrap();
+        assert_eq!(loaded.num_points(), 1);
+        assert_eq!(loaded.origin, DVec3::new(101.0, 2.0, 3.0));
+        assert_eq!(
+            loaded
+                .mesh
+                .attribute(ATTRIBUTE_COLOR_PACKED)
+                .unwrap()
+                .get_bytes(),
+            [40, 50, 60, 255]
+        );
+        assert_eq!(loaded.scalar_field("intensity").unwrap(), [0.25]);
+    }
+
+    #[test]
+    fn filters_points_like_they_are_drawn() {
+        let mut asset = PointCloudAsset::from_points(
+            (0..4).map(|x| Vec3::new(x as f32, 0.0, 0.0)).collect(),
+            vec![[255; 4]; 4],
+        )
+        .unwrap();
+        asset.timestamp_origin = 100.0;
+        asset
+            .scalar_fields
+            .insert(TIMESTAMP_SCALAR_FIELD.to_owned(), vec![0.0, 1.0, 2.0, 3.0]);
+        asset
+            .scalar_fields
+            .insert("confidence".to_owned(), vec![1.0, 1.0, 0.1, 1.0]);
+        let transform = GlobalTransform::IDENTITY;
+        let plane = ClippingPlaneRange {
+            min_sdist: -10.0,
+            max_sdist: 2.5,
+        };
+        let clipping_planes = [(&plane, &GlobalTransform::IDENTITY)];
+        let time_window = TimeWindow {
+            start: 101.0,
+            end: 103.0,
+        };
+        let confidence = PointConfidence {
+            field: "confidence".to_owned(),
+            threshold: 0.5,
+            fade: false,
+        };
+        let selection = PointCloudSelection {
+            indices: vec![0, 1, 3, 7],
+            color: Color::WHITE,
+        };
+
+        assert_eq!(
+            asset.filtered_points(&transform, clipping_planes, default()),
+            [0, 1, 2]
+        );
+        let filters = PointFilters {
+            time_window: Some(&time_window),
+            confidence: Some(&confidence),
+            selection: None,
+        };
+        assert_eq!(asset.filtered_points(&transform, [], filters), [1, 3]);
+        let filters = PointFilters {
+            selection: Some(&selection),
+            ..filters
+        };
+        assert_eq!(
+            asset.filtered_points(&transform, clipping_planes, filters),
+            [1]
+        );
+    }
+
+    #[test]
+    fn rejects_indices_out_of_range() {
+        let mut bytes = Vec::new();
+        let error = asset().write_ply(&[0, 2], &mut bytes).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(bytes.is_empty());
+    }
+}
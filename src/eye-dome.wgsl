@@ -6,7 +6,24 @@ var input_texture: texture_multisampled_2d<f32>;
 var input_texture: texture_2d<f32>;
 #endif
 
-var<push_constant> edl_strength: f32;
+struct PointCloudView {
+    point_size_multiplier: f32,
+    min_pixel_size: f32,
+    max_pixel_size: f32,
+    eye_dome_strength: f32,
+    // Distance to the neighbors, in pixels.
+    eye_dome_radius: f32,
+    // 4 or 8 neighbors.
+    eye_dome_samples: u32,
+}
+
+@group(1) @binding(2)
+var<uniform> point_cloud_view: PointCloudView;
+
+fn log_depth_at(location: vec2<i32>) -> f32 {
+    let size = vec2<i32>(textureDimensions(input_texture));
+    return log2(textureLoad(input_texture, clamp(location, vec2(0), size - 1), 0).r);
+}
 
 @vertex
 fn vertex(
@@ -21,15 +38,24 @@ fn fragment(
     @builtin(position) position: vec4<f32>,
 ) -> @location(0) vec4<f32> {
     var ilocation = vec2<i32>(position.xy);
-    var log_depth: f32 = log2(textureLoad(input_texture, ilocation, 0).r);
+    var log_depth: f32 = log_depth_at(ilocation);
 
+    let radius = max(i32(round(point_cloud_view.eye_dome_radius)), 1);
     var response: f32 = 0.0;
-    response += max(0.0, log_depth - log2(textureLoad(input_texture, vec2<i32>(ilocation.x + 1, ilocation.y), 0)).r);
-    response += max(0.0, log_depth - log2(textureLoad(input_texture, vec2<i32>(ilocation.x - 1, ilocation.y), 0)).r);
-    response += max(0.0, log_depth - log2(textureLoad(input_texture, vec2<i32>(ilocation.x, ilocation.y + 1), 0)).r);
-    response += max(0.0, log_depth - log2(textureLoad(input_texture, vec2<i32>(ilocation.x, ilocation.y - 1), 0)).r);
-    response /= 4.0;
+    response += max(0.0, log_depth - log_depth_at(vec2<i32>(ilocation.x + radius, ilocation.y)));
+    response += max(0.0, log_depth - log_depth_at(vec2<i32>(ilocation.x - radius, ilocation.y)));
+    response += max(0.0, log_depth - log_depth_at(vec2<i32>(ilocation.x, ilocation.y + radius)));
+    response += max(0.0, log_depth - log_depth_at(vec2<i32>(ilocation.x, ilocation.y - radius)));
+    if (point_cloud_view.eye_dome_samples == 8u) {
+        // The diagonal neighbors, on the same circle as the others.
+        let d = max(i32(round(point_cloud_view.eye_dome_radius * 0.70710678)), 1);
+        response += max(0.0, log_depth - log_depth_at(ilocation + vec2<i32>(d, d)));
+        response += max(0.0, log_depth - log_depth_at(ilocation + vec2<i32>(-d, d)));
+        response += max(0.0, log_depth - log_depth_at(ilocation + vec2<i32>(d, -d)));
+        response += max(0.0, log_depth - log_depth_at(ilocation + vec2<i32>(-d, -d)));
+    }
+    response /= f32(point_cloud_view.eye_dome_samples);
 
-    var shade = exp(-response * 300.0 * edl_strength);
+    var shade = exp(-response * 300.0 * point_cloud_view.eye_dome_strength);
     return vec4<f32>(0.0, 0.0, 0.0, shade);
 }
\ No newline at end of file
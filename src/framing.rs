@@ -0,0 +1,191 @@
+use bevy::{ecs::system::Command, prelude::*, render::primitives::Aabb};
+
+use crate::{PointCloudAsset, PotreePointCloud};
+
+/// Distance from the camera to a point cloud whose bounds have no extent, like a single point.
+pub const DEFAULT_FRAMING_DISTANCE: f32 = 10.0;
+
+/// Moves the first active camera so that this point cloud is fully in view as soon as its
+/// asset has loaded, then removes itself.
+///
+/// The camera keeps its orientation and is moved back along its view direction.
+/// Camera controllers that own the camera [`Transform`] will override the result.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct FramePointCloudWhenReady;
+
+/// Moves this point cloud so that the [`centroid`](PointCloudAsset::centroid) of its points
+/// is at the origin of its parent as soon as its asset has loaded, then removes itself.
+///
+/// Insert it again to recenter a cloud later, for example after changing
+/// [`PotreePointCloud::mesh`]. The rotation and scale of the [`Transform`] are kept.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct CenterPointCloudWhenReady;
+
+/// Computes a transform for a camera that keeps `camera_transform`'s rotation but is moved so
+/// that the `aabb`, placed in the world by `cloud_transform`, is fully in view.
+pub fn framing_transform(
+    camera_transform: &Transform,
+    projection: &Projection,
+    aspect_ratio: f32,
+    aabb: &Aabb,
+    cloud_transform: &GlobalTransform,
+) -> Transform {
+    let center = cloud_transform.transform_point(aabb.center.into());
+    let (min, max) = (Vec3::from(aabb.min()), Vec3::from(aabb.max()));
+    let radius = (0..8)
+        .map(|corner| {
+            let corner = Vec3::select(
+                BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0),
+                max,
+                min,
+            );
+            cloud_transform.transform_point(corner).distance(center)
+        })
+        .fold(0.0, f32::max);
+
+    let distance = if radius <= f32::EPSILON {
+        DEFAULT_FRAMING_DISTANCE
+    } else {
+        match projection {
+            Projection::Perspective(perspective) => {
+                let half_fov_y = perspective.fov * 0.5;
+                let half_fov_x = (half_fov_y.tan() * aspect_ratio).atan();
+                radius / half_fov_y.min(half_fov_x).sin()
+            }
+            // Moving an orthographic camera doesn't change the size of what's visible, so just
+            // keep the bounds in front of the near plane.
+            Projection::Orthographic(_) => radius * 2.0,
+        }
+    };
+
+    Transform {
+        translation: center - camera_transform.forward() * distance,
+        ..*camera_transform
+    }
+}
+
+/// Moves `camera` so that `point_cloud` is fully in view, see [`framing_transform`].
+/// Queue it with `commands.add(fit_camera_to_point_cloud(camera, point_cloud))`.
+///
+/// Does nothing, with a warning, if either entity is missing or the asset hasn't loaded yet.
+/// Use [`FramePointCloudWhenReady`] to wait for the asset instead.
+pub fn fit_camera_to_point_cloud(camera: Entity, point_cloud: Entity) -> FitCameraToPointCloud {
+    FitCameraToPointCloud {
+        camera,
+        point_cloud,
+    }
+}
+
+/// The [`Command`] returned by [`fit_camera_to_point_cloud`].
+#[derive(Clone, Copy, Debug)]
+pub struct FitCameraToPointCloud {
+    pub camera: Entity,
+    pub point_cloud: Entity,
+}
+
+impl Command for FitCameraToPointCloud {
+    fn apply(self, world: &mut World) {
+        let Some((mesh, cloud_transform)) = world.get_entity(self.point_cloud).and_then(|entity| {
+            Some((
+                entity.get::<PotreePointCloud>()?.mesh.clone(),
+                *entity.get::<GlobalTransform>()?,
+            ))
+        }) else {
+            warn!(
+                "Can't fit a camera to {:?}, it isn't a point cloud",
+                self.point_cloud
+            );
+            return;
+        };
+        let Some(aabb) = world
+            .resource::<Assets<PointCloudAsset>>()
+            .get(&mesh)
+            .map(|asset| asset.aabb)
+        else {
+            warn!(
+                "Can't fit a camera to {:?}, its asset hasn't loaded yet",
+                self.point_cloud
+            );
+            return;
+        };
+        let Some(mut camera) = world.get_entity_mut(self.camera) else {
+            warn!("Can't fit the camera {:?}, it doesn't exist", self.camera);
+            return;
+        };
+        let (Some(aspect_ratio), Some(projection)) = (
+            camera.get::<Camera>().map(aspect_ratio),
+            camera.get::<Projection>().cloned(),
+        ) else {
+            warn!("Can't fit {:?}, it isn't a camera", self.camera);
+            return;
+        };
+        if let Some(mut camera_transform) = camera.get_mut::<Transform>() {
+            *camera_transform = framing_transform(
+                &camera_transform,
+                &projection,
+                aspect_ratio,
+                &aabb,
+                &cloud_transform,
+            );
+        }
+    }
+}
+
+/// The aspect ratio of the camera's viewport, or `1.0` before it's known.
+fn aspect_ratio(camera: &Camera) -> f32 {
+    camera
+        .logical_viewport_size()
+        .map(|size| size.x / size.y)
+        .unwrap_or(1.0)
+}
+
+pub(crate) fn frame_point_clouds_when_ready(
+    mut commands: Commands,
+    assets: Res<Assets<PointCloudAsset>>,
+    point_clouds: Query<
+        (Entity, &PotreePointCloud, &GlobalTransform),
+        With<FramePointCloudWhenReady>,
+    >,
+    mut cameras: Query<(&Camera, &Projection, &mut Transform)>,
+) {
+    for (entity, point_cloud, cloud_transform) in &point_clouds {
+        let Some(asset) = assets.get(&point_cloud.mesh) else {
+            continue;
+        };
+        commands.entity(entity).remove::<FramePointCloudWhenReady>();
+
+        let Some((camera, projection, mut camera_transform)) = cameras
+            .iter_mut()
+            .filter(|(camera, _, _)| camera.is_active)
+            .min_by_key(|(camera, _, _)| camera.order)
+        else {
+            continue;
+        };
+        *camera_transform = framing_transform(
+            &camera_transform,
+            projection,
+            aspect_ratio(camera),
+            &asset.aabb,
+            cloud_transform,
+        );
+    }
+}
+
+pub(crate) fn center_point_clouds_when_ready(
+    mut commands: Commands,
+    assets: Res<Assets<PointCloudAsset>>,
+    mut point_clouds: Query<
+        (Entity, &PotreePointCloud, &mut Transform),
+        With<CenterPointCloudWhenReady>,
+    >,
+) {
+    for (entity, point_cloud, mut transform) in &mut point_clouds {
+        let Some(asset) = assets.get(&point_cloud.mesh) else {
+            continue;
+        };
+        commands
+            .entity(entity)
+            .remove::<CenterPointCloudWhenReady>();
+        transform.translation = -(transform.rotation * (transform.scale * asset.centroid()));
+    }
+}
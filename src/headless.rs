@@ -0,0 +1,163 @@
+use std::sync::{Arc, Mutex};
+
+use bevy::{
+    app::PluginsState,
+    prelude::*,
+    render::{
+        camera::RenderTarget,
+        render_asset::RenderAssets,
+        render_resource::{
+            BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Extent3d, ImageCopyBuffer,
+            ImageDataLayout, MapMode, TextureDimension, TextureFormat, TextureUsages,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        Render, RenderApp, RenderSet,
+    },
+    window::ExitCondition,
+};
+
+use crate::PointCloudPlugin;
+
+/// Renders point clouds without a window and reads the frames back to the CPU, for regression
+/// tests of the renderer.
+///
+/// Spawn the scene in [`HeadlessRenderer::world_mut`], with a camera whose
+/// [`Camera::target`] is [`HeadlessRenderer::render_target`]. Pipelined rendering is disabled,
+/// so every [`HeadlessRenderer::render`] call reads back the frame it rendered.
+pub struct HeadlessRenderer {
+    app: App,
+    target: Handle<Image>,
+    readback: HeadlessReadback,
+}
+
+/// The pixels of the last frame rendered to the target, copied out by the render world.
+#[derive(Resource, Clone, Default)]
+struct HeadlessReadback(Arc<Mutex<Option<Vec<u8>>>>);
+
+#[derive(Resource)]
+struct HeadlessTarget(Handle<Image>);
+
+impl HeadlessRenderer {
+    /// Boots an app with the [`DefaultPlugins`], minus windowing, and a [`PointCloudPlugin`],
+    /// rendering to a `size` pixels large [`TextureFormat::Rgba8UnormSrgb`] image.
+    pub fn new(size: UVec2, point_cloud_plugin: PointCloudPlugin) -> Self {
+        let mut app = App::new();
+        app.add_plugins((
+            DefaultPlugins
+                .set(WindowPlugin {
+                    primary_window: None,
+                    exit_condition: ExitCondition::DontExit,
+                    close_when_requested: false,
+                })
+                .disable::<bevy::winit::WinitPlugin>()
+                .disable::<bevy::render::pipelined_rendering::PipelinedRenderingPlugin>(),
+            point_cloud_plugin,
+        ));
+
+        let mut image = Image::new_fill(
+            Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[0; 4],
+            TextureFormat::Rgba8UnormSrgb,
+        );
+        image.texture_descriptor.usage = TextureUsages::RENDER_ATTACHMENT
+            | TextureUsages::TEXTURE_BINDING
+            | TextureUsages::COPY_DST
+            | TextureUsages::COPY_SRC;
+        let target = app.world.resource_mut::<Assets<Image>>().add(image);
+
+        let readback = HeadlessReadback::default();
+        app.sub_app_mut(RenderApp)
+            .insert_resource(readback.clone())
+            .insert_resource(HeadlessTarget(target.clone()))
+            .add_systems(Render, read_back_target.in_set(RenderSet::Cleanup));
+
+        while app.plugins_state() == PluginsState::Adding {
+            bevy::tasks::tick_global_task_pools_on_main_thread();
+        }
+        app.finish();
+        app.cleanup();
+
+        Self {
+            app,
+            target,
+            readback,
+        }
+    }
+
+    pub fn world_mut(&mut self) -> &mut World {
+        &mut self.app.world
+    }
+
+    /// The target for the cameras whose view should be read back.
+    pub fn render_target(&self) -> RenderTarget {
+        RenderTarget::Image(self.target.clone())
+    }
+
+    /// Updates the app `frames` times and returns the last frame as RGBA8 pixels, row by row
+    /// from the top. Assets load and pipelines compile asynchronously, so the first few frames
+    /// may not show the point clouds yet. Returns `None` if nothing was rendered to the target.
+    pub fn render(&mut self, frames: usize) -> Option<Vec<u8>> {
+        for _ in 0..frames {
+            self.app.update();
+        }
+        self.readback.0.lock().unwrap().take()
+    }
+}
+
+fn read_back_target(
+    target: Res<HeadlessTarget>,
+    readback: Res<HeadlessReadback>,
+    images: Res<RenderAssets<Image>>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    let Some(image) = images.get(&target.0) else {
+        return;
+    };
+    let size = image.texture.size();
+    let row_bytes = size.width as usize * 4;
+    let padded_row_bytes = RenderDevice::align_copy_bytes_per_row(row_bytes);
+    let buffer = render_device.create_buffer(&BufferDescriptor {
+        label: Some("headless readback buffer"),
+        size: (padded_row_bytes * size.height as usize) as u64,
+        usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let mut encoder = render_device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("headless readback"),
+    });
+    encoder.copy_texture_to_buffer(
+        image.texture.as_image_copy(),
+        ImageCopyBuffer {
+            buffer: &buffer,
+            layout: ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row_bytes as u32),
+                rows_per_image: None,
+            },
+        },
+        size,
+    );
+    render_queue.submit([encoder.finish()]);
+
+    let slice = buffer.slice(..);
+    render_device.map_buffer(&slice, MapMode::Read, |result| {
+        if let Err(error) = result {
+            error!("Failed to read back the headless render target: {error}");
+        }
+    });
+    render_device.poll(wgpu::Maintain::Wait);
+    let pixels = slice
+        .get_mapped_range()
+        .chunks_exact(padded_row_bytes)
+        .flat_map(|row| &row[..row_bytes])
+        .copied()
+        .collect();
+    buffer.unmap();
+    *readback.0.lock().unwrap() = Some(pixels);
+}
@@ -0,0 +1,153 @@
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssets,
+        render_resource::{BindGroup, BindGroupEntries, BufferInitDescriptor, BufferUsages},
+        renderer::RenderDevice,
+    },
+    utils::HashMap,
+};
+
+use crate::{pipeline::PointCloudPipeline, PointCloudAsset};
+
+/// Draws one [`PointCloudAsset`] at many transforms in a single instanced draw call, which is
+/// far cheaper than spawning an entity per copy.
+///
+/// Each instance is a transform relative to the entity's [`GlobalTransform`], and a tint that
+/// is multiplied with the point colors. Spawn it with a [`SpatialBundle`].
+/// [`PointColorMode`](crate::PointColorMode) and [`PointCloudLighting`](crate::PointCloudLighting)
+/// apply to all instances.
+#[derive(Component, Clone, Debug)]
+pub struct InstancedPointCloud {
+    pub asset: Handle<PointCloudAsset>,
+    pub instances: Vec<(Transform, Color)>,
+    /// See [`PotreePointCloud::point_size`](crate::PotreePointCloud::point_size).
+    pub point_size: f32,
+}
+
+impl InstancedPointCloud {
+    /// Untinted instances of `asset` at `transforms`, with a point size of `1.0`.
+    pub fn from_transforms(
+        asset: Handle<PointCloudAsset>,
+        transforms: impl IntoIterator<Item = Transform>,
+    ) -> Self {
+        Self {
+            asset,
+            instances: transforms
+                .into_iter()
+                .map(|transform| (transform, Color::WHITE))
+                .collect(),
+            point_size: 1.0,
+        }
+    }
+}
+
+/// The instances of an [`InstancedPointCloud`] in the render world.
+#[derive(Component)]
+pub struct ExtractedPointCloudInstances {
+    /// The transform matrix of each instance, followed by its tint.
+    instances: Vec<[f32; 20]>,
+}
+
+impl ExtractedPointCloudInstances {
+    pub(crate) fn new(instances: &[(Transform, Color)]) -> Self {
+        Self {
+            instances: instances
+                .iter()
+                .map(|(transform, tint)| {
+                    let mut instance = [0.0; 20];
+                    instance[..16].copy_from_slice(&transform.compute_matrix().to_cols_array());
+                    instance[16..].copy_from_slice(&tint.as_linear_rgba_f32());
+                    instance
+                })
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+}
+
+#[derive(Component, Clone)]
+pub struct PreparedPointCloudInstances {
+    pub bind_group: BindGroup,
+    pub count: u32,
+}
+
+/// The instance buffers of the entities with an [`InstancedPointCloud`], kept across frames so
+/// that they are only uploaded again when the instances or the number of points change.
+#[derive(Resource, Default)]
+pub(crate) struct PointCloudInstanceBuffers(HashMap<Entity, CachedInstances>);
+
+struct CachedInstances {
+    num_points: u32,
+    instances: Vec<[f32; 20]>,
+    prepared: PreparedPointCloudInstances,
+}
+
+pub(crate) fn prepare_point_cloud_instances(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    pipeline: Res<PointCloudPipeline>,
+    render_assets: Res<RenderAssets<PointCloudAsset>>,
+    mut buffers: ResMut<PointCloudInstanceBuffers>,
+    query: Query<(
+        Entity,
+        &Handle<PointCloudAsset>,
+        &ExtractedPointCloudInstances,
+    )>,
+) {
+    buffers.0.retain(|&entity, _| query.contains(entity));
+    for (entity, handle, extracted) in &query {
+        let Some(asset) = render_assets.get(handle) else {
+            continue;
+        };
+        match buffers.0.get(&entity) {
+            Some(cached)
+                if cached.num_points == asset.num_points
+                    && cached.instances == extracted.instances => {}
+            _ => {
+                if asset
+                    .num_points
+                    .checked_mul(extracted.instances.len() as u32)
+                    .is_none()
+                {
+                    warn!(
+                        "{entity:?} has too many instances of {} points to draw",
+                        asset.num_points
+                    );
+                }
+                // The shader needs the number of points to split the instance index into a
+                // point and an instance. The header is padded to the alignment of the
+                // instances.
+                let mut contents = bytemuck::bytes_of(&[asset.num_points, 0, 0, 0]).to_vec();
+                contents.extend_from_slice(bytemuck::cast_slice(&extracted.instances));
+                let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+                    label: Some("Point cloud instance buffer"),
+                    contents: &contents,
+                    usage: BufferUsages::STORAGE,
+                });
+                let bind_group = render_device.create_bind_group(
+                    "point cloud instance bind group",
+                    &pipeline.instance_layout,
+                    &BindGroupEntries::single(buffer.as_entire_binding()),
+                );
+                buffers.0.insert(
+                    entity,
+                    CachedInstances {
+                        num_points: asset.num_points,
+                        instances: extracted.instances.clone(),
+                        prepared: PreparedPointCloudInstances {
+                            bind_group,
+                            count: extracted.instances.len() as u32,
+                        },
+                    },
+                );
+            }
+        }
+        commands
+            .entity(entity)
+            .insert(buffers.0[&entity].prepared.clone());
+    }
+}
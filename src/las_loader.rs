@@ -1,57 +1,290 @@
 use bevy::{
     asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    math::{DVec3, Vec3A},
     prelude::*,
     reflect::TypePath,
     render::{
-        mesh::MeshVertexAttribute,
+        mesh::{MeshVertexAttribute, VertexAttributeValues},
+        primitives::Aabb,
         render_resource::{PrimitiveTopology, VertexFormat},
     },
-    utils::thiserror::{self, Error},
+    utils::{
+        thiserror::{self, Error},
+        HashSet,
+    },
 };
 use las::Read;
 use opd_parser::Frames;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::color::{pack_rgb_f32, pack_rgba16, pack_rgba8};
+use crate::upload::PointCloudUploads;
+use crate::{OcclusionError, OcclusionSettings, PointCloudOcclusion, TIMESTAMP_SCALAR_FIELD};
 
+/// Colors as three `f32`s per point in `0.0..=1.0`, sRGB encoded like
+/// [`ATTRIBUTE_COLOR_PACKED`]. Easier to fill in by hand than the packed attribute, which it
+/// is converted to once the asset is added, see [`PointCloudAsset::pack_colors`].
 pub const ATTRIBUTE_COLOR: MeshVertexAttribute =
-    MeshVertexAttribute::new("Vertex_Color", 1, VertexFormat::Float32x3);
+    MeshVertexAttribute::new("Vertex_Color", 0x3fc9_0000, VertexFormat::Float32x3);
 
-#[repr(transparent)]
-struct Point {
-    inner: [f32; 3],
-}
-impl From<[f32; 3]> for Point {
-    fn from(inner: [f32; 3]) -> Self {
-        Self { inner }
-    }
-}
-impl Point {
-    pub fn min(&self, other: &Self) -> Self {
-        Point {
-            inner: [
-                self.inner[0].min(other.inner[0]),
-                self.inner[1].min(other.inner[1]),
-                self.inner[2].min(other.inner[2]),
-            ],
-        }
-    }
-    pub fn max(&self, other: &Self) -> Self {
-        Point {
-            inner: [
-                self.inner[0].max(other.inner[0]),
-                self.inner[1].max(other.inner[1]),
-                self.inner[2].max(other.inner[2]),
-            ],
-        }
-    }
-}
+/// 8 bit per channel colors, packed into one `u32` per point as `0xAABBGGRR`.
+///
+/// Colors are sRGB encoded, like [`Color::as_rgba_u8`] and the colors of most point cloud
+/// files, and are converted to linear in the shader before lighting and blending.
+pub const ATTRIBUTE_COLOR_PACKED: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_Color_Packed", 0x3fc9_0001, VertexFormat::Uint32);
+
+/// 16 bit per channel colors, packed into two `u32`s per point as `[0xGGGGRRRR, 0xAAAABBBB]`.
+/// Used instead of [`ATTRIBUTE_COLOR_PACKED`] when the extra precision matters, at twice the
+/// memory. Also sRGB encoded.
+pub const ATTRIBUTE_COLOR_RGBA16: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_Color_Rgba16", 0x3fc9_0002, VertexFormat::Uint32x2);
 
 #[derive(Asset, Clone, TypePath)]
 pub struct PointCloudAsset {
     pub mesh: Mesh,
+    /// Per-point offsets played back over time. Only [`Frames::I8`] frames are played back,
+    /// as loaded from OPD files with a precision of 1, the others are kept but ignored.
     pub animation: Option<Frames>,
     pub animation_scale: Vec3,
+    /// Bounds of the (unanimated) point positions, in the asset's local space. Empty assets
+    /// have an empty box at the origin.
+    pub aabb: Aabb,
+    /// Where the asset's local origin was in the source data's coordinate system.
+    /// Translating the entity by this value places the points at their original coordinates.
+    ///
+    /// The loaders subtract it in double precision, so that the positions uploaded to the GPU
+    /// stay small. Geo-referenced coordinates are too large for an f32 [`Transform`] though, so
+    /// rather than translating by the origin itself, pick a reference point near the data and
+    /// translate every cloud by [`PointCloudAsset::translation_from`] it.
+    pub origin: DVec3,
+    /// Named per-point scalar values, each with one value per point. Fields of any other
+    /// length are drawn as zeros.
+    /// These can be visualized with [`PointColorMode::Scalar`](crate::PointColorMode::Scalar).
+    pub scalar_fields: BTreeMap<String, Vec<f32>>,
+    /// The time that the [`TIMESTAMP_SCALAR_FIELD`](crate::TIMESTAMP_SCALAR_FIELD) values are
+    /// relative to, like [`PointCloudAsset::origin`] for the positions. See
+    /// [`TimeWindow`](crate::TimeWindow).
+    pub timestamp_origin: f64,
+    /// Precomputed chunk occlusion, see [`PointCloudAsset::compute_occlusion`].
+    pub occlusion: Option<PointCloudOcclusion>,
+    /// Points of a streamed asset that haven't been read yet.
+    pub(crate) pending_points: usize,
+    pub(crate) uploads: PointCloudUploads,
+}
+
+/// Homogeneous positions whose `w` is closer to zero than this are treated as points at
+/// infinity. See [`dehomogenize`].
+pub const HOMOGENEOUS_W_EPSILON: f32 = 1e-6;
+
+/// Converts a homogeneous `xyzw` position into Euclidean coordinates by dividing by `w`.
+/// Returns `None` for points at infinity, which should be dropped.
+pub fn dehomogenize(position: Vec4) -> Option<Vec3> {
+    (position.w.abs() >= HOMOGENEOUS_W_EPSILON).then(|| position.truncate() / position.w)
 }
 
 impl PointCloudAsset {
+    /// Creates a static asset from a point list mesh.
+    pub(crate) fn new(mesh: Mesh) -> Self {
+        Self {
+            aabb: mesh.compute_aabb().unwrap_or_default(),
+            mesh,
+            animation: None,
+            animation_scale: Vec3::ZERO,
+            origin: DVec3::ZERO,
+            scalar_fields: default(),
+            timestamp_origin: 0.0,
+            occlusion: None,
+            pending_points: 0,
+            uploads: default(),
+        }
+    }
+
+    /// Creates an uncolored asset from homogeneous `xyzw` positions, as produced by some
+    /// reconstruction pipelines. Each position is divided by its `w`, and points at infinity
+    /// are dropped.
+    pub fn from_homogeneous_positions(positions: impl IntoIterator<Item = Vec4>) -> Self {
+        let positions: Vec<Vec3> = positions.into_iter().filter_map(dehomogenize).collect();
+        let mut mesh = Mesh::new(PrimitiveTopology::PointList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        Self::new(mesh)
+    }
+
+    /// Creates an asset from procedurally generated points, with the same buffers the loaders
+    /// produce. `colors` are 8 bit RGBA, one per position.
+    pub fn from_points(
+        positions: Vec<Vec3>,
+        colors: Vec<[u8; 4]>,
+    ) -> Result<Self, PointCloudAssetError> {
+        if positions.len() != colors.len() {
+            return Err(PointCloudAssetError::LengthMismatch {
+                positions: positions.len(),
+                colors: colors.len(),
+            });
+        }
+        let colors: Vec<u32> = colors.into_iter().map(pack_rgba8).collect();
+        let mut mesh = Mesh::new(PrimitiveTopology::PointList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.insert_attribute(ATTRIBUTE_COLOR_PACKED, colors);
+        Ok(Self::new(mesh))
+    }
+
+    /// Creates an asset from the vertices of a [`Mesh`], usually a
+    /// [`PrimitiveTopology::PointList`], to reuse mesh building code. Indices are ignored, so
+    /// every vertex becomes a point.
+    ///
+    /// Colors are copied from [`ATTRIBUTE_COLOR_PACKED`] or [`ATTRIBUTE_COLOR_RGBA16`] as they
+    /// are, or converted from [`ATTRIBUTE_COLOR`] or bevy's linear [`Mesh::ATTRIBUTE_COLOR`].
+    /// Normals are kept, and other attributes are dropped.
+    pub fn from_mesh(mesh: &Mesh) -> Result<Self, PointCloudAssetError> {
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            return Err(PointCloudAssetError::MissingPositions);
+        };
+        let check_len = |colors: usize| {
+            if colors == positions.len() {
+                Ok(())
+            } else {
+                Err(PointCloudAssetError::LengthMismatch {
+                    positions: positions.len(),
+                    colors,
+                })
+            }
+        };
+        let mut point_mesh = Mesh::new(PrimitiveTopology::PointList);
+        point_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions.clone());
+        match (
+            mesh.attribute(ATTRIBUTE_COLOR_RGBA16),
+            mesh.attribute(ATTRIBUTE_COLOR_PACKED),
+            mesh.attribute(ATTRIBUTE_COLOR),
+            mesh.attribute(Mesh::ATTRIBUTE_COLOR),
+        ) {
+            (Some(colors), ..) => {
+                check_len(colors.len())?;
+                point_mesh.insert_attribute(ATTRIBUTE_COLOR_RGBA16, colors.clone());
+            }
+            (None, Some(colors), ..) => {
+                check_len(colors.len())?;
+                point_mesh.insert_attribute(ATTRIBUTE_COLOR_PACKED, colors.clone());
+            }
+            (None, None, Some(VertexAttributeValues::Float32x3(colors)), _) => {
+                check_len(colors.len())?;
+                let colors: Vec<u32> = colors.iter().copied().map(pack_rgb_f32).collect();
+                point_mesh.insert_attribute(ATTRIBUTE_COLOR_PACKED, colors);
+            }
+            (None, None, _, Some(VertexAttributeValues::Float32x4(colors))) => {
+                check_len(colors.len())?;
+                let colors: Vec<u32> = colors
+                    .iter()
+                    .map(|&[r, g, b, a]| pack_rgba8(Color::rgba_linear(r, g, b, a).as_rgba_u8()))
+                    .collect();
+                point_mesh.insert_attribute(ATTRIBUTE_COLOR_PACKED, colors);
+            }
+            _ => {}
+        }
+        if let Some(normals) = mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+            if normals.len() == positions.len() {
+                point_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals.clone());
+            }
+        }
+        Ok(Self::new(point_mesh))
+    }
+
+    /// Replaces the colors of [`ATTRIBUTE_COLOR`] with [`ATTRIBUTE_COLOR_PACKED`], which is what
+    /// gets drawn. Done for every asset once it's added or modified, so only call it to read
+    /// the packed colors before that. Returns whether there were colors to convert.
+    pub fn pack_colors(&mut self) -> bool {
+        let Some(VertexAttributeValues::Float32x3(colors)) =
+            self.mesh.remove_attribute(ATTRIBUTE_COLOR)
+        else {
+            return false;
+        };
+        if !self.mesh.contains_attribute(ATTRIBUTE_COLOR_PACKED)
+            && !self.mesh.contains_attribute(ATTRIBUTE_COLOR_RGBA16)
+        {
+            let colors: Vec<u32> = colors.into_iter().map(pack_rgb_f32).collect();
+            self.mesh.insert_attribute(ATTRIBUTE_COLOR_PACKED, colors);
+        }
+        true
+    }
+
+    /// The number of points in the asset, available as soon as it has loaded.
+    ///
+    /// Bounds are in [`PointCloudAsset::aabb`].
+    pub fn num_points(&self) -> usize {
+        self.mesh.count_vertices()
+    }
+
+    /// The fraction of the points that have been read, from `0.0` to `1.0`. Only below `1.0`
+    /// while a [`StreamedOpdLoad`](crate::StreamedOpdLoad) is still reading the file.
+    pub fn loaded_fraction(&self) -> f32 {
+        if self.pending_points == 0 {
+            return 1.0;
+        }
+        let loaded = self.num_points();
+        loaded as f32 / (loaded + self.pending_points) as f32
+    }
+
+    /// Names of the scalar fields available for [`PointColorMode::Scalar`](crate::PointColorMode::Scalar).
+    pub fn scalar_field_names(&self) -> impl Iterator<Item = &str> {
+        self.scalar_fields.keys().map(String::as_str)
+    }
+
+    pub fn scalar_field(&self, name: &str) -> Option<&[f32]> {
+        self.scalar_fields.get(name).map(Vec::as_slice)
+    }
+
+    /// The translation that places this asset's points at their original coordinates, relative
+    /// to `reference` in the source data's coordinate system. Clouds translated from the same
+    /// reference line up with each other.
+    pub fn translation_from(&self, reference: DVec3) -> Vec3 {
+        (self.origin - reference).as_vec3()
+    }
+
+    /// The mean of the (unanimated) point positions, in the asset's local space. Unlike the
+    /// center of [`PointCloudAsset::aabb`], it isn't pulled away from the bulk of the points by
+    /// a few outliers. Takes `O(n)` time.
+    pub fn centroid(&self) -> Vec3 {
+        match self.mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+            Some(VertexAttributeValues::Float32x3(positions)) if !positions.is_empty() => {
+                let sum = positions.iter().fold(DVec3::ZERO, |sum, &position| {
+                    sum + Vec3::from(position).as_dvec3()
+                });
+                (sum / positions.len() as f64).as_vec3()
+            }
+            _ => self.aabb.center.into(),
+        }
+    }
+
+    /// Shifts all positions so that the center of the bounding box is at the origin, and adds
+    /// the shift to [`PointCloudAsset::origin`].
+    ///
+    /// Returns the shift, which can be added to the entity's translation to keep the points at
+    /// the same place in the world.
+    pub fn recenter(&mut self) -> Vec3 {
+        let center = Vec3::from(self.aabb.center);
+        if let Some(VertexAttributeValues::Float32x3(positions)) =
+            self.mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION)
+        {
+            for position in positions.iter_mut() {
+                *position = (Vec3::from(*position) - center).into();
+            }
+        }
+        self.aabb.center = Vec3A::ZERO;
+        self.origin += center.as_dvec3();
+        if let Some(occlusion) = &mut self.occlusion {
+            occlusion.translate(-center);
+        }
+        center
+    }
+
+    /// Position of the field in the asset's scalar buffer on the GPU.
+    pub(crate) fn scalar_field_index(&self, name: &str) -> Option<usize> {
+        self.scalar_fields.keys().position(|key| key == name)
+    }
+
     pub fn animation_duration(&self) -> Option<f32> {
         match &self.animation {
             Some(Frames::I8(frames)) => Some(frames.last().unwrap().time / 1000.),
@@ -60,6 +293,16 @@ impl PointCloudAsset {
     }
 }
 
+/// Possible errors when building a [`PointCloudAsset`] from points.
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum PointCloudAssetError {
+    #[error("Got {positions} positions but {colors} colors")]
+    LengthMismatch { positions: usize, colors: usize },
+    #[error("The mesh has no positions")]
+    MissingPositions,
+}
+
 /// Possible errors that can be produced by [`LasLoader`]
 #[non_exhaustive]
 #[derive(Debug, Error)]
@@ -68,13 +311,130 @@ pub enum LasLoaderError {
     Io(#[from] std::io::Error),
     #[error("Could not parse Las: {0}")]
     RonSpannedError(#[from] las::Error),
+    #[error("Could not compute occlusion: {0}")]
+    Occlusion(#[from] OcclusionError),
+}
+
+/// Where [`LasLoader`] takes the colors of the points from.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub enum ColorSource {
+    /// The RGB colors of the points, or their intensity if the file has no colors.
+    #[default]
+    Rgb,
+    /// The intensity of the points, in grayscale.
+    Intensity,
+    /// Looks the classification of every point up in the palette, so that class `n` gets the
+    /// `n`th color. Classes without a palette entry are white.
+    Classification(Vec<Color>),
+}
+
+/// Grayscale for a LAS intensity, saturating at 100.
+fn intensity_color(intensity: f32) -> [u16; 4] {
+    let intensity = ((intensity * 0.01).min(1.0) * u16::MAX as f32) as u16;
+    [intensity, intensity, intensity, u16::MAX]
+}
+
+fn classification_color(palette: &[Color], classification: f32) -> [u16; 4] {
+    let color = palette
+        .get(classification as usize)
+        .copied()
+        .unwrap_or(Color::WHITE);
+    color
+        .as_rgba_f32()
+        .map(|channel| (channel.clamp(0.0, 1.0) * u16::MAX as f32) as u16)
+}
+
+impl PointCloudAsset {
+    /// Rewrites the colors of an asset loaded by [`LasLoader`] from its `intensity` or
+    /// `classification` [scalar field](PointCloudAsset::scalar_field), without reloading the
+    /// file. The colors are uploaded again once the asset is modified.
+    ///
+    /// Returns `false` and leaves the colors unchanged for [`ColorSource::Rgb`], since the
+    /// original colors aren't kept, or when the scalar field is missing, as it is unless
+    /// [`LasLoaderSettings::scalar_fields`] keeps it.
+    pub fn recolor(&mut self, source: &ColorSource) -> bool {
+        let (field, color): (_, &dyn Fn(f32) -> [u16; 4]) = match source {
+            ColorSource::Rgb => return false,
+            ColorSource::Intensity => ("intensity", &intensity_color),
+            ColorSource::Classification(palette) => ("classification", &|classification| {
+                classification_color(palette, classification)
+            }),
+        };
+        let Some(values) = self.scalar_fields.get(field) else {
+            return false;
+        };
+        let colors = values.iter().map(|&value| color(value));
+        if self.mesh.attribute(ATTRIBUTE_COLOR_RGBA16).is_some() {
+            let colors: Vec<[u32; 2]> = colors.map(pack_rgba16).collect();
+            self.mesh.insert_attribute(ATTRIBUTE_COLOR_RGBA16, colors);
+        } else {
+            let colors: Vec<u32> = colors
+                .map(|color| pack_rgba8(color.map(|channel| (channel >> 8) as u8)))
+                .collect();
+            self.mesh.insert_attribute(ATTRIBUTE_COLOR_PACKED, colors);
+        }
+        true
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LasLoaderSettings {
+    /// Where the colors of the points come from.
+    pub color_source: ColorSource,
+    /// Keep the full 16 bit per channel precision of LAS colors in [`ATTRIBUTE_COLOR_RGBA16`]
+    /// instead of truncating them to 8 bits. Doubles the memory used by colors.
+    pub high_precision_color: bool,
+    /// Keep the positions in the file's units, relative to the minimum of the points, which is
+    /// stored in [`PointCloudAsset::origin`]. By default the points are scaled to fit in a unit
+    /// cube instead, and the original coordinates are lost.
+    pub preserve_scale: bool,
+    /// Keep only one point per cube of this size, see [`PointCloudAsset::voxel_downsample`].
+    /// The size is in the file's units with [`Self::preserve_scale`], and relative to the
+    /// normalized unit cube otherwise.
+    pub voxel_size: Option<f32>,
+    /// Shuffle the points, see [`PointCloudAsset::shuffle_points`]. Needed for an even
+    /// [`PointCloudLod`](crate::PointCloudLod).
+    pub shuffle: bool,
+    /// Precompute chunk occlusion for the loaded cloud. Off by default since it's expensive,
+    /// see [`PointCloudAsset::compute_occlusion`].
+    pub occlusion: Option<OcclusionSettings>,
+    /// Which point attributes are kept as [scalar fields](PointCloudAsset::scalar_field). None
+    /// by default, since every field takes an `f32` per point on the CPU and the GPU.
+    pub scalar_fields: LasScalarFields,
+}
+
+/// Selects the point attributes that [`LasLoader`] keeps as scalar fields: `intensity`,
+/// `classification`, `return_number`, [`TIMESTAMP_SCALAR_FIELD`] if the points have GPS
+/// times, and every field described by an extra bytes VLR, by its name. Unnamed extra bytes
+/// fields are named `extra_bytes_<index>`, and names that are already taken get a `_2`, `_3`...
+/// suffix.
+///
+/// [`PointColorMode::Scalar`](crate::PointColorMode::Scalar), [`TimeWindow`](crate::TimeWindow)
+/// and [`PointCloudAsset::recolor`] only work with the fields they use kept.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub enum LasScalarFields {
+    #[default]
+    None,
+    All,
+    /// Only the fields with these names. Names the file doesn't have are ignored.
+    Only(Vec<String>),
+}
+
+impl LasScalarFields {
+    fn keeps(&self, name: &str) -> bool {
+        match self {
+            Self::None => false,
+            Self::All => true,
+            Self::Only(names) => names.iter().any(|kept| kept == name),
+        }
+    }
 }
 
 #[derive(Default)]
 pub struct LasLoader;
 impl AssetLoader for LasLoader {
     type Asset = PointCloudAsset;
-    type Settings = ();
+    type Settings = LasLoaderSettings;
     type Error = LasLoaderError;
 
     fn extensions(&self) -> &[&str] {
@@ -84,65 +444,451 @@ impl AssetLoader for LasLoader {
     fn load<'a>(
         &'a self,
         reader: &'a mut Reader,
-        _settings: &'a (),
+        settings: &'a LasLoaderSettings,
         _load_context: &'a mut LoadContext,
     ) -> bevy::utils::BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
         Box::pin(async move {
             let mut bytes = Vec::new();
             reader.read_to_end(&mut bytes).await?;
-            let mut reader = las::Reader::new(std::io::Cursor::new(bytes))?;
-            let mut mesh = Mesh::new(PrimitiveTopology::PointList);
-            let mut max: Point = [f32::MIN; 3].into();
-            let mut min: Point = [f32::MAX; 3].into();
-            let (mut positions, colors): (Vec<_>, Vec<_>) = reader
-                .points()
-                .map(|a| {
-                    let p = a.unwrap();
-                    let position = {
-                        let p: Point = [p.x as f32, p.z as f32, p.y as f32].into();
-                        min = min.min(&p);
-                        max = max.max(&p);
-                        p.inner
-                    };
-                    let color = {
-                        if let Some(color) = &p.color {
-                            Vec3::new(
-                                color.red as f32 / u16::MAX as f32,
-                                color.green as f32 / u16::MAX as f32,
-                                color.blue as f32 / u16::MAX as f32,
-                            )
-                        } else {
-                            let intensity = p.intensity as f32 * 0.01;
-                            Vec3::new(intensity, intensity, intensity)
-                        }
-                    };
-                    (position, color)
-                })
-                .unzip();
-            let aabb = [
-                max.inner[0] - min.inner[0],
-                max.inner[1] - min.inner[1],
-                max.inner[2] - min.inner[2],
-            ];
-
-            // Normalize the positions
-            let scale = aabb[0].max(aabb[1]).max(aabb[2]);
-            for i in positions.iter_mut() {
-                i[0] -= min.inner[0];
-                i[1] -= min.inner[1];
-                i[2] -= min.inner[2];
-                i[0] /= scale;
-                i[1] /= scale;
-                i[2] /= scale;
+            Self::load_las(bytes, settings)
+        })
+    }
+}
+
+impl LasLoader {
+    /// Loads a whole `.las` or `.laz` file from memory, like the asset loader does.
+    pub fn load_las(
+        bytes: Vec<u8>,
+        settings: &LasLoaderSettings,
+    ) -> Result<PointCloudAsset, LasLoaderError> {
+        let file_len = bytes.len();
+        let mut reader = las::Reader::new(std::io::Cursor::new(bytes))?;
+        let mut extra_bytes_fields = ExtraBytesField::parse_header(reader.header());
+        ExtraBytesField::dedup_names(&mut extra_bytes_fields);
+        let keep_gps_time = reader.header().point_format().has_gps_time
+            && settings.scalar_fields.keeps(TIMESTAMP_SCALAR_FIELD);
+        let extra_bytes_fields: Vec<_> = extra_bytes_fields
+            .into_iter()
+            .filter(|field| settings.scalar_fields.keeps(&field.name))
+            .collect();
+        let mut mesh = Mesh::new(PrimitiveTopology::PointList);
+        let mut max = DVec3::splat(f64::MIN);
+        let mut min = DVec3::splat(f64::MAX);
+        // Don't trust the header to allocate, a record can't be smaller than its length.
+        let record_len = reader.header().point_format().len().max(1) as usize;
+        let num_points = (reader.header().number_of_points() as usize).min(file_len / record_len);
+        let mut scalar_fields: BTreeMap<String, Vec<f32>> = STANDARD_SCALAR_FIELDS
+            .into_iter()
+            .filter(|name| settings.scalar_fields.keeps(name))
+            .chain(keep_gps_time.then_some(TIMESTAMP_SCALAR_FIELD))
+            .map(str::to_owned)
+            .chain(extra_bytes_fields.iter().map(|field| field.name.clone()))
+            .map(|name| (name, Vec::with_capacity(num_points)))
+            .collect();
+        let mut gps_times = Vec::with_capacity(if keep_gps_time { num_points } else { 0 });
+        let mut positions = Vec::with_capacity(num_points);
+        let mut colors = Vec::with_capacity(num_points);
+        for p in reader.points() {
+            // Malformed or unsupported point records are reported instead of panicking.
+            let p = p?;
+            // Survey coordinates don't fit in f32, so only convert them once they are
+            // relative to the minimum.
+            let position = DVec3::new(p.x, p.z, p.y);
+            min = min.min(position);
+            max = max.max(position);
+            let color = match (&settings.color_source, &p.color) {
+                (ColorSource::Rgb, Some(color)) => [color.red, color.green, color.blue, u16::MAX],
+                (ColorSource::Rgb | ColorSource::Intensity, _) => {
+                    intensity_color(p.intensity as f32)
+                }
+                (ColorSource::Classification(palette), _) => {
+                    classification_color(palette, u8::from(p.classification) as f32)
+                }
+            };
+            let mut push_scalar = |name: &str, value: f32| {
+                if let Some(values) = scalar_fields.get_mut(name) {
+                    values.push(value);
+                }
+            };
+            push_scalar("intensity", p.intensity as f32);
+            push_scalar("classification", u8::from(p.classification) as f32);
+            push_scalar("return_number", p.return_number as f32);
+            for field in &extra_bytes_fields {
+                push_scalar(&field.name, field.read(&p.extra_bytes));
             }
-            mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
-            mesh.insert_attribute(ATTRIBUTE_COLOR, colors);
-            let asset = PointCloudAsset {
-                mesh,
-                animation: None,
-                animation_scale: Vec3::default(),
+            if keep_gps_time {
+                gps_times.push(p.gps_time.unwrap_or_default());
+            }
+            positions.push(position);
+            colors.push(color);
+        }
+        let mut timestamp_origin = 0.0;
+        if keep_gps_time && !gps_times.is_empty() {
+            // GPS times are large, so store them relative to the earliest point to keep
+            // them precise in f32.
+            timestamp_origin = gps_times.iter().copied().fold(f64::INFINITY, f64::min);
+            *scalar_fields.get_mut(TIMESTAMP_SCALAR_FIELD).unwrap() = gps_times
+                .iter()
+                .map(|t| (t - timestamp_origin) as f32)
+                .collect();
+        }
+        if positions.is_empty() {
+            (min, max) = (DVec3::ZERO, DVec3::ZERO);
+        }
+        let extent = max - min;
+        let (origin, scale) = if settings.preserve_scale {
+            (min, 1.0)
+        } else {
+            // Normalize the positions. Files with a single point have no extent to
+            // normalize by.
+            let scale = extent.max_element();
+            (DVec3::ZERO, if scale > 0.0 { scale } else { 1.0 })
+        };
+        let positions: Vec<Vec3> = positions
+            .into_iter()
+            .map(|position: DVec3| ((position - min) / scale).as_vec3())
+            .collect();
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        if settings.high_precision_color {
+            let colors: Vec<[u32; 2]> = colors.into_iter().map(pack_rgba16).collect();
+            mesh.insert_attribute(ATTRIBUTE_COLOR_RGBA16, colors);
+        } else {
+            let colors: Vec<u32> = colors
+                .into_iter()
+                .map(|color| pack_rgba8(color.map(|channel| (channel >> 8) as u8)))
+                .collect();
+            mesh.insert_attribute(ATTRIBUTE_COLOR_PACKED, colors);
+        }
+        let mut asset = PointCloudAsset {
+            aabb: mesh.compute_aabb().unwrap_or_default(),
+            origin,
+            mesh,
+            animation: None,
+            animation_scale: Vec3::default(),
+            scalar_fields,
+            timestamp_origin,
+            occlusion: None,
+            pending_points: 0,
+            uploads: default(),
+        };
+        if let Some(voxel_size) = settings.voxel_size {
+            asset.voxel_downsample(voxel_size);
+        }
+        if settings.shuffle {
+            asset.shuffle_points();
+        }
+        if let Some(occlusion) = &settings.occlusion {
+            asset.compute_occlusion(occlusion)?;
+        }
+        Ok(asset)
+    }
+}
+
+/// The scalar fields read from every LAS point record.
+const STANDARD_SCALAR_FIELDS: [&str; 3] = ["intensity", "classification", "return_number"];
+
+/// A field described by the LAS "Extra Bytes" VLR.
+struct ExtraBytesField {
+    name: String,
+    data_type: u8,
+    /// Offset of the field within each point's extra bytes.
+    offset: usize,
+    size: usize,
+    scale: f64,
+    value_offset: f64,
+}
+
+impl ExtraBytesField {
+    const USER_ID: &'static str = "LASF_Spec";
+    const RECORD_ID: u16 = 4;
+    const DESCRIPTOR_LEN: usize = 192;
+
+    /// Reads the scalar extra bytes fields declared in the header.
+    /// Parsing stops at the first field with an unsupported data type, since the offsets
+    /// of the following fields can't be known.
+    fn parse_header(header: &las::Header) -> Vec<Self> {
+        let Some(vlr) = header
+            .all_vlrs()
+            .find(|vlr| vlr.user_id == Self::USER_ID && vlr.record_id == Self::RECORD_ID)
+        else {
+            return Vec::new();
+        };
+        let mut fields = Vec::new();
+        let mut offset = 0;
+        for descriptor in vlr.data.chunks_exact(Self::DESCRIPTOR_LEN) {
+            let data_type = descriptor[2];
+            let options = descriptor[3];
+            let size = match data_type {
+                // Undocumented extra bytes, whose size is stored in the options field
+                0 => {
+                    offset += options as usize;
+                    continue;
+                }
+                1 | 2 => 1,
+                3 | 4 => 2,
+                5 | 6 | 9 => 4,
+                7 | 8 | 10 => 8,
+                _ => break,
             };
-            Ok(asset)
-        })
+            let name = descriptor[4..36]
+                .split(|&byte| byte == 0)
+                .next()
+                .map(|name| String::from_utf8_lossy(name).into_owned())
+                .unwrap_or_default();
+            let read_f64 =
+                |start: usize| f64::from_le_bytes(descriptor[start..start + 8].try_into().unwrap());
+            fields.push(Self {
+                name,
+                data_type,
+                offset,
+                size,
+                scale: if options & 0b01000 != 0 {
+                    read_f64(112)
+                } else {
+                    1.0
+                },
+                value_offset: if options & 0b10000 != 0 {
+                    read_f64(136)
+                } else {
+                    0.0
+                },
+            });
+            offset += size;
+        }
+        fields
+    }
+
+    /// Renames unnamed fields and fields whose name is already taken by a standard field or
+    /// an earlier extra bytes field, so each field gets a scalar field of its own.
+    fn dedup_names(fields: &mut [Self]) {
+        let mut taken: HashSet<String> = STANDARD_SCALAR_FIELDS
+            .into_iter()
+            .chain([TIMESTAMP_SCALAR_FIELD])
+            .map(str::to_owned)
+            .collect();
+        for (index, field) in fields.iter_mut().enumerate() {
+            if field.name.is_empty() {
+                field.name = format!("extra_bytes_{index}");
+            }
+            let mut name = field.name.clone();
+            let mut suffix = 2;
+            while taken.contains(&name) {
+                name = format!("{}_{suffix}", field.name);
+                suffix += 1;
+            }
+            taken.insert(name.clone());
+            field.name = name;
+        }
+    }
+
+    fn read(&self, extra_bytes: &[u8]) -> f32 {
+        let Some(bytes) = extra_bytes.get(self.offset..self.offset + self.size) else {
+            return f32::NAN;
+        };
+        let raw = match self.data_type {
+            1 => bytes[0] as f64,
+            2 => bytes[0] as i8 as f64,
+            3 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            4 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            5 => u32::from_le_bytes(bytes.try_into().unwrap()) as f64,
+            6 => i32::from_le_bytes(bytes.try_into().unwrap()) as f64,
+            7 => u64::from_le_bytes(bytes.try_into().unwrap()) as f64,
+            8 => i64::from_le_bytes(bytes.try_into().unwrap()) as f64,
+            9 => f32::from_le_bytes(bytes.try_into().unwrap()) as f64,
+            10 => f64::from_le_bytes(bytes.try_into().unwrap()),
+            _ => unreachable!(),
+        };
+        (raw * self.scale + self.value_offset) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use las::Write;
+
+    fn las_file(points: &[[f64; 3]]) -> Vec<u8> {
+        let mut builder = las::Builder::from((1, 2));
+        builder.point_format = las::point::Format::new(3).unwrap();
+        let mut writer = las::Writer::new(
+            std::io::Cursor::new(Vec::new()),
+            builder.into_header().unwrap(),
+        )
+        .unwrap();
+        for (index, &[x, y, z]) in points.iter().enumerate() {
+            writer
+                .write(las::Point {
+                    x,
+                    y,
+                    z,
+                    intensity: index as u16 * 10,
+                    gps_time: Some(1e9 + index as f64),
+                    color: Some(las::Color::new(u16::MAX, 0, 0)),
+                    ..default()
+                })
+                .unwrap();
+        }
+        writer.into_inner().unwrap().into_inner()
+    }
+
+    #[test]
+    fn loads_points() {
+        let asset =
+            LasLoader::load_las(las_file(&[[0.0; 3], [1.0, 2.0, 3.0]]), &default()).unwrap();
+        assert_eq!(asset.num_points(), 2);
+    }
+
+    #[test]
+    fn rejects_more_points_than_the_file_holds() {
+        /// Where LAS 1.2 headers store the number of point records.
+        const NUMBER_OF_POINT_RECORDS: usize = 107;
+        let mut file = las_file(&[[0.0; 3]]);
+        file[NUMBER_OF_POINT_RECORDS..NUMBER_OF_POINT_RECORDS + 4]
+            .copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(LasLoader::load_las(file, &default()).is_err());
+    }
+
+    #[test]
+    fn only_keeps_the_selected_scalar_fields() {
+        let file = las_file(&[[0.0; 3], [1.0, 2.0, 3.0]]);
+        let load = |scalar_fields| {
+            let settings = LasLoaderSettings {
+                scalar_fields,
+                ..default()
+            };
+            LasLoader::load_las(file.clone(), &settings).unwrap()
+        };
+
+        assert_eq!(load(LasScalarFields::None).scalar_field_names().count(), 0);
+
+        let all = load(LasScalarFields::All);
+        assert_eq!(
+            all.scalar_field_names().collect::<Vec<_>>(),
+            ["classification", "gps_time", "intensity", "return_number"]
+        );
+        assert_eq!(
+            all.scalar_field(TIMESTAMP_SCALAR_FIELD).unwrap(),
+            [0.0, 1.0]
+        );
+        assert_eq!(all.timestamp_origin, 1e9);
+
+        let some = load(LasScalarFields::Only(vec![
+            "intensity".to_owned(),
+            "missing".to_owned(),
+        ]));
+        assert_eq!(some.scalar_field_names().collect::<Vec<_>>(), ["intensity"]);
+        assert_eq!(some.scalar_field("intensity").unwrap(), [0.0, 10.0]);
+    }
+
+    #[test]
+    fn gives_extra_bytes_fields_unique_names() {
+        // Two unnamed fields and one named like a standard field, one byte each.
+        let names: [&[u8]; 3] = [b"", b"", b"intensity"];
+        let mut builder = las::Builder::from((1, 2));
+        builder.point_format = las::point::Format::new(3).unwrap();
+        builder.point_format.extra_bytes = names.len() as u16;
+        builder.vlrs.push(las::Vlr {
+            user_id: ExtraBytesField::USER_ID.to_owned(),
+            record_id: ExtraBytesField::RECORD_ID,
+            description: String::new(),
+            data: names
+                .iter()
+                .flat_map(|name| {
+                    let mut descriptor = [0; ExtraBytesField::DESCRIPTOR_LEN];
+                    // Unsigned char
+                    descriptor[2] = 1;
+                    descriptor[4..4 + name.len()].copy_from_slice(name);
+                    descriptor
+                })
+                .collect(),
+        });
+        let mut writer = las::Writer::new(
+            std::io::Cursor::new(Vec::new()),
+            builder.into_header().unwrap(),
+        )
+        .unwrap();
+        for index in 0..2 {
+            writer
+                .write(las::Point {
+                    intensity: 100,
+                    color: Some(las::Color::default()),
+                    extra_bytes: vec![index, index + 10, index + 20],
+                    gps_time: Some(0.0),
+                    ..default()
+                })
+                .unwrap();
+        }
+        let file = writer.into_inner().unwrap().into_inner();
+
+        let settings = LasLoaderSettings {
+            scalar_fields: LasScalarFields::All,
+            ..default()
+        };
+        let asset = LasLoader::load_las(file, &settings).unwrap();
+        assert_eq!(
+            asset.scalar_field_names().collect::<Vec<_>>(),
+            [
+                "classification",
+                "extra_bytes_0",
+                "extra_bytes_1",
+                "gps_time",
+                "intensity",
+                "intensity_2",
+                "return_number"
+            ]
+        );
+        assert_eq!(asset.scalar_field("extra_bytes_0").unwrap(), [0.0, 1.0]);
+        assert_eq!(asset.scalar_field("extra_bytes_1").unwrap(), [10.0, 11.0]);
+        assert_eq!(asset.scalar_field("intensity").unwrap(), [100.0, 100.0]);
+        assert_eq!(asset.scalar_field("intensity_2").unwrap(), [20.0, 21.0]);
+    }
+
+    #[test]
+    fn packs_float_colors() {
+        let mut mesh = Mesh::new(PrimitiveTopology::PointList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vec![Vec3::ZERO; 2]);
+        mesh.insert_attribute(ATTRIBUTE_COLOR, vec![[1.0, 0.5, 0.0], [0.0, 2.0, -1.0]]);
+        let mut asset = PointCloudAsset::new(mesh);
+        assert!(asset.pack_colors());
+        assert!(!asset.mesh.contains_attribute(ATTRIBUTE_COLOR));
+        assert_eq!(
+            asset
+                .mesh
+                .attribute(ATTRIBUTE_COLOR_PACKED)
+                .unwrap()
+                .get_bytes(),
+            [255, 128, 0, 255, 0, 255, 0, 255]
+        );
+        assert!(!asset.pack_colors());
+    }
+
+    #[test]
+    fn keeps_16_bit_colors_only_when_asked_to() {
+        let file = las_file(&[[0.0; 3]]);
+        let asset = LasLoader::load_las(file.clone(), &default()).unwrap();
+        assert_eq!(
+            asset
+                .mesh
+                .attribute(ATTRIBUTE_COLOR_PACKED)
+                .unwrap()
+                .get_bytes(),
+            [u8::MAX, 0, 0, u8::MAX]
+        );
+        assert!(asset.mesh.attribute(ATTRIBUTE_COLOR_RGBA16).is_none());
+
+        let settings = LasLoaderSettings {
+            high_precision_color: true,
+            ..default()
+        };
+        let asset = LasLoader::load_las(file, &settings).unwrap();
+        assert_eq!(
+            asset
+                .mesh
+                .attribute(ATTRIBUTE_COLOR_RGBA16)
+                .unwrap()
+                .get_bytes(),
+            bytemuck::cast_slice::<_, u8>(&pack_rgba16([u16::MAX, 0, 0, u16::MAX]))
+        );
+        assert!(asset.mesh.attribute(ATTRIBUTE_COLOR_PACKED).is_none());
     }
 }
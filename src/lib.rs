@@ -1,37 +1,216 @@
+mod batching;
 mod clippling_planes;
+mod color;
+mod composite;
+mod compute;
+mod debug;
+mod depth_cue;
+mod downsample;
+#[cfg(feature = "e57")]
+mod e57_loader;
+mod export;
+mod framing;
+#[cfg(feature = "headless")]
+mod headless;
+mod instancing;
 #[cfg(feature = "las")]
 mod las_loader;
+mod lighting;
+mod loading;
+mod lod;
+mod memory;
+mod morph;
+mod occlusion;
+#[cfg(feature = "potree")]
+mod octree;
 #[cfg(feature = "opd")]
 mod opd_loader;
+#[cfg(feature = "pcd")]
+mod pcd_loader;
+mod picking;
 mod pipeline;
 mod playback;
+#[cfg(feature = "ply")]
+mod ply_loader;
+mod point_size;
+mod radii;
 mod render;
 mod render_graph;
+mod selection;
+mod sorting;
+mod spawn;
+mod time_window;
+mod transparency;
+mod upload;
+mod view_state;
+mod visibility;
+#[cfg(feature = "xyz")]
+mod xyz_loader;
+pub use batching::{BatchedPointCloud, PointCloudBatch};
 use bevy::{
     asset::load_internal_asset,
     core_pipeline::core_3d::CORE_3D,
     prelude::*,
     render::{
-        extract_component::UniformComponentPlugin,
+        extract_component::{ExtractComponentPlugin, UniformComponentPlugin},
         extract_resource::ExtractResourcePlugin,
         render_asset::RenderAssetPlugin,
-        render_graph::{RenderGraphApp, ViewNodeRunner},
-        render_resource::{ShaderStage, SpecializedRenderPipelines},
+        render_graph::{RenderGraph, RenderGraphApp, ViewNodeRunner},
+        render_resource::{ShaderRef, ShaderStage, SpecializedRenderPipelines},
+        renderer::RenderDevice,
         Render, RenderApp, RenderSet,
     },
 };
 pub use clippling_planes::{ClippingPlaneBundle, ClippingPlaneRange};
+pub use color::{transfer_function_image, ColorRamp, PointColorMode, MAX_COLOR_RAMP_STOPS};
+pub use composite::{FrameComposite, FrameCompositeMode};
+pub use compute::{
+    PrePointCompute, PrePointComputeNode, PrePointComputePipeline, PRE_POINT_COMPUTE_WORKGROUP_SIZE,
+};
+pub use debug::PointCloudDebug;
+pub use depth_cue::DepthCue;
+#[cfg(feature = "e57")]
+pub use e57_loader::*;
+pub use export::PointFilters;
+pub use framing::{
+    fit_camera_to_point_cloud, framing_transform, CenterPointCloudWhenReady, FitCameraToPointCloud,
+    FramePointCloudWhenReady, DEFAULT_FRAMING_DISTANCE,
+};
+#[cfg(feature = "headless")]
+pub use headless::HeadlessRenderer;
+pub use instancing::InstancedPointCloud;
 #[cfg(feature = "las")]
 pub use las_loader::*;
+pub use lighting::{PointCloudLighting, MAX_NORMAL_DIRECTIONAL_LIGHTS};
+pub use loading::{
+    LoadPriority, PointCloudLoadQueue, PointCloudLoadState, PointCloudReady, QueuedPointCloudLoad,
+};
+pub use lod::PointCloudLod;
+pub use memory::{PointCloudMemoryBudget, PointCloudMemoryUsage};
+pub use morph::PointCloudMorph;
+pub use occlusion::{OcclusionError, OcclusionSettings, PointCloudOcclusion};
+#[cfg(feature = "potree")]
+pub use octree::{
+    PotreeOctree, PotreeOctreeAsset, PotreeOctreeError, PotreeOctreeNode, PotreeOctreeNodeIndex,
+};
 #[cfg(feature = "opd")]
 pub use opd_loader::*;
+#[cfg(feature = "pcd")]
+pub use pcd_loader::*;
+pub use picking::PointCloudRaycast;
 pub use pipeline::*;
 pub use playback::*;
+#[cfg(feature = "ply")]
+pub use ply_loader::*;
+pub use point_size::{
+    AutoPointSize, PointPixelSize, PointShape, PointSizeMode, PointSizeMultiplier,
+};
+pub use radii::{DEFAULT_RADIUS_NEIGHBORS, RADIUS_SCALAR_FIELD};
 pub use render::*;
 pub use render_graph::*;
+pub use selection::PointCloudSelection;
+pub use sorting::PointDepthSort;
+pub use spawn::SpawnPointCloudExt;
+pub use time_window::{TimeWindow, TIMESTAMP_SCALAR_FIELD};
+pub use transparency::{PointBlendMode, PointCloudTransparency, PointConfidence};
+pub use view_state::{ViewState, ViewStateError, ViewStates};
+pub use visibility::{PointCloudCullingStats, PointCloudStats, VisiblePointClouds};
+#[cfg(feature = "xyz")]
+pub use xyz_loader::*;
+
+/// Loads and renders [`PotreePointCloud`]s.
+///
+/// Points are read from storage buffers in the vertex shader. WebGL2 lacks them, so there the
+/// positions and colors of the points are read from vertex buffers instead, and the features
+/// that need more data per point or compute shaders are left out:
+///
+/// - Scalar fields aren't read, so [`PointColorMode::Scalar`] and
+///   [`PointColorMode::TransferFunction`] fall back to the colors of the points, and the
+///   [`TimeWindow`], [`PointConfidence`] and per-point radii are ignored.
+/// - Normals aren't read, so clouds are shaded as if normal lighting was off.
+/// - [`PointCloudSelection`]s aren't highlighted.
+/// - Animations aren't played, [`PointDepthSort`]s and [`PrePointCompute`] aren't applied.
+/// - [`InstancedPointCloud`]s, [`PointCloudBatch`]es and [`PointCloudMorph`]s aren't drawn.
+///
+/// Eye dome lighting works everywhere. In the browser, the WebGPU backend draws everything.
+pub struct PointCloudPlugin {
+    /// Darken the edges of point clouds with eye dome lighting. When `false`, the extra depth
+    /// texture and the fullscreen pass it needs are never created, and points are drawn with
+    /// plain depth testing. Use [`EyeDomeSettings`] to turn it off for single cameras instead.
+    pub eye_dome_lighting: bool,
+    /// Replaces the GLSL fragment shader of the points, for example to grade their colors,
+    /// while keeping the vertex shader that expands them into quads.
+    ///
+    /// The replacement receives the vertex outputs of `shader.vert` and gets the same bind
+    /// groups and shader defs as the built-in `shader.frag`, which is the best starting point.
+    /// It writes the color to location 0, and with the `EYE_DOME_LIGHTING` def, the view space
+    /// depth to location 1.
+    pub fragment_shader: ShaderRef,
+    /// Also draw opaque points into the depth prepass of cameras with a
+    /// [`DepthPrepass`](bevy::core_pipeline::prepass::DepthPrepass), so that effects reading
+    /// the prepass depth, like SSAO or fog, react to them. Off by default since it draws the
+    /// points twice. Points don't write the normal or motion vector prepass textures.
+    pub depth_prepass: bool,
+    /// Where the points are drawn relative to the transparent meshes of the main pass.
+    pub render_order: PointCloudRenderOrder,
+    /// Draw round points, those with a [`PointShape::Circle`] or a positive
+    /// [`edge_softness`](PotreePointCloud::edge_softness), as polygons with this many vertices,
+    /// up to 64, instead of as quads. A hexagon or an octagon covers fewer pixels outside the
+    /// disc that the fragment shader discards, which pays off for large points. Square points
+    /// are always quads. `4` by default, which draws round points as quads too.
+    pub round_point_vertices: u32,
+    /// Read the points from storage buffers where the device supports them. When `false`,
+    /// they are read from vertex buffers as on WebGL2, for example to check how a scene looks
+    /// in the browser. `true` by default.
+    pub storage_buffers: bool,
+}
+
+impl Default for PointCloudPlugin {
+    fn default() -> Self {
+        Self {
+            eye_dome_lighting: true,
+            fragment_shader: ShaderRef::Default,
+            depth_prepass: false,
+            render_order: PointCloudRenderOrder::default(),
+            round_point_vertices: 4,
+            storage_buffers: true,
+        }
+    }
+}
 
-#[derive(Default)]
-pub struct PointCloudPlugin;
+/// When the [`PointCloudNode`] runs in the 3d render graph, see
+/// [`PointCloudPlugin::render_order`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PointCloudRenderOrder {
+    /// Draw the points after the whole main pass. Opaque meshes hide points behind them, but
+    /// transparent meshes don't write depth, so points behind a transparent mesh are drawn over
+    /// it, as if they were in front.
+    #[default]
+    AfterTransparent,
+    /// Draw the points after the opaque meshes, before the transmissive and transparent ones.
+    /// Opaque points write depth, so transparent meshes in front of them blend over them and
+    /// the ones behind them are hidden. Transmissive materials refract the points too. Eye dome
+    /// lighting is applied before the transparent meshes are drawn, so it doesn't darken their
+    /// edges. Blended points don't write depth and are still drawn under transparent meshes
+    /// behind them.
+    BeforeTransparent,
+}
+
+/// The systems of the [`PointCloudPlugin`] in the render app, to order custom render systems
+/// against. Each set runs within the [`RenderSet`] of the same stage.
+#[derive(SystemSet, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PointCloudSet {
+    /// Extracts point clouds, cameras and clipping planes into the render world, in the
+    /// [`ExtractSchedule`](bevy::render::ExtractSchedule). Assets and the components extracted
+    /// as they are, like [`PointCloudLod`], are extracted by Bevy's plugins instead.
+    Extract,
+    /// Builds the [`PointCloudDrawList`] of every view.
+    Queue,
+    /// Uploads per-frame data and creates the bind groups, from after the
+    /// [`PointCloudAsset`]s are prepared to the end of [`RenderSet::Prepare`]. Run after it to
+    /// read the prepared buffers and bind groups.
+    Prepare,
+}
 
 impl Plugin for PointCloudPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
@@ -40,15 +219,98 @@ impl Plugin for PointCloudPlugin {
         #[cfg(feature = "las")]
         app.init_asset_loader::<LasLoader>();
         #[cfg(feature = "opd")]
-        app.init_asset_loader::<OpdLoader>();
+        app.init_asset_loader::<OpdLoader>().add_systems(
+            PreUpdate,
+            opd_loader::stream_opd_point_clouds.before(loading::update_point_cloud_load_states),
+        );
+        #[cfg(feature = "ply")]
+        app.init_asset_loader::<PlyLoader>();
+        #[cfg(feature = "pcd")]
+        app.init_asset_loader::<PcdLoader>();
+        #[cfg(feature = "e57")]
+        app.init_asset_loader::<E57Loader>();
+        #[cfg(feature = "xyz")]
+        app.init_asset_loader::<XyzLoader>();
+        #[cfg(feature = "potree")]
+        app.init_asset::<PotreeOctreeAsset>().add_systems(
+            PostUpdate,
+            octree::stream_potree_octrees
+                .after(bevy::render::view::VisibilitySystems::UpdatePerspectiveFrusta)
+                .after(bevy::render::view::VisibilitySystems::UpdateOrthographicFrusta)
+                .after(bevy::transform::TransformSystem::TransformPropagate),
+        );
+        #[cfg(feature = "potree")]
+        app.add_systems(
+            PostUpdate,
+            debug::draw_octree_node_bounds
+                .run_if(resource_exists::<bevy::gizmos::GizmoConfig>())
+                .after(bevy::transform::TransformSystem::TransformPropagate),
+        );
+
+        app.add_systems(
+            PostUpdate,
+            (
+                PointCloudPlaybackControls::playback_system,
+                playback::play_point_cloud_animations,
+                point_size::auto_point_size_system
+                    .after(bevy::transform::TransformSystem::TransformPropagate),
+                composite::build_frame_composites,
+                color::pack_point_cloud_colors,
+                morph::validate_point_cloud_morphs,
+                framing::center_point_clouds_when_ready
+                    .before(bevy::transform::TransformSystem::TransformPropagate),
+                framing::frame_point_clouds_when_ready
+                    .after(bevy::transform::TransformSystem::TransformPropagate),
+                debug::draw_point_cloud_bounds
+                    .run_if(resource_exists::<bevy::gizmos::GizmoConfig>())
+                    .after(bevy::transform::TransformSystem::TransformPropagate),
+                batching::batch_point_clouds
+                    .after(bevy::render::view::VisibilitySystems::VisibilityPropagate),
+            ),
+        )
+        .add_systems(
+            PreUpdate,
+            (
+                loading::process_point_cloud_load_queue,
+                loading::update_point_cloud_load_states,
+            )
+                .chain(),
+        )
+        .add_systems(PreUpdate, picking::invalidate_point_cloud_picking_trees)
+        .add_event::<PointCloudReady>()
+        .init_resource::<PointCloudPlaybackControls>()
+        .init_resource::<PointCloudLoadQueue>()
+        .init_resource::<PointCloudDebug>()
+        .init_resource::<picking::PointCloudPickingTrees>()
+        .init_resource::<PrePointCompute>();
 
+        app.init_resource::<VisiblePointClouds>()
+            .init_resource::<PointCloudCullingStats>()
+            .init_resource::<PointCloudStats>()
+            .init_resource::<visibility::DrawnPointCloudsChannel>()
+            .add_systems(First, visibility::sync_visible_point_clouds);
+
+        // Added here rather than in `finish`, so that their own `finish` runs.
         app.add_plugins((
             RenderAssetPlugin::<PointCloudAsset>::default(),
             UniformComponentPlugin::<PointCloudUniform>::default(),
+            UniformComponentPlugin::<PointCloudViewUniform>::default(),
+            ExtractComponentPlugin::<EyeDomeSettings>::default(),
+            ExtractComponentPlugin::<PointDepthSort>::default(),
+            ExtractComponentPlugin::<PointCloudLod>::default(),
             ExtractResourcePlugin::<PointCloudPlaybackControls>::default(),
-        ))
-        .add_systems(PostUpdate, PointCloudPlaybackControls::playback_system)
-        .init_resource::<PointCloudPlaybackControls>();
+            ExtractResourcePlugin::<PrePointCompute>::default(),
+            ExtractResourcePlugin::<PointCloudMemoryBudget>::default(),
+        ));
+
+        app.init_resource::<PointCloudMemoryBudget>()
+            .init_resource::<PointCloudMemoryUsage>()
+            .init_resource::<memory::PointCloudMemoryChannel>()
+            .add_systems(
+                PostUpdate,
+                memory::reload_evicted_point_cloud_assets
+                    .after(bevy::render::view::VisibilitySystems::CheckVisibility),
+            );
 
         load_internal_asset!(
             app,
@@ -68,6 +330,64 @@ impl Plugin for PointCloudPlugin {
             "eye-dome.wgsl",
             Shader::from_wgsl
         );
+        load_internal_asset!(
+            app,
+            sorting::DEPTH_SORT_SHADER_HANDLE,
+            "depth-sort.wgsl",
+            Shader::from_wgsl
+        );
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Ok(render_app) = app.get_sub_app(RenderApp) else {
+            return;
+        };
+        let limits = render_app.world.resource::<RenderDevice>().limits();
+        let storage_buffers =
+            self.storage_buffers && limits.max_storage_buffers_per_shader_stage > 0;
+        if !storage_buffers {
+            info!("Reading points from vertex buffers, since storage buffers aren't used");
+        }
+        self.build_render_app(app, storage_buffers);
+
+        let fragment_shader = match &self.fragment_shader {
+            ShaderRef::Default => POINT_CLOUD_FRAG_SHADER_HANDLE,
+            ShaderRef::Handle(handle) => handle.clone(),
+            ShaderRef::Path(path) => app.world.resource::<AssetServer>().load(path.clone()),
+        };
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app
+            .insert_resource(PointCloudFragmentShader(fragment_shader))
+            .insert_resource(PointCloudRoundPointVertices(self.round_point_vertices))
+            .insert_resource(PointCloudStorageBuffers(storage_buffers))
+            .init_resource::<PointCloudPipeline>()
+            .init_resource::<SpecializedRenderPipelines<PointCloudPipeline>>()
+            .init_resource::<EyeDomePipeline>()
+            .init_resource::<SpecializedRenderPipelines<EyeDomePipeline>>();
+        if storage_buffers {
+            render_app
+                .init_resource::<PrePointComputePipeline>()
+                .init_resource::<sorting::DepthSortPipeline>();
+        }
+
+        render_app
+            .insert_resource(EyeDomeLightingEnabled(self.eye_dome_lighting))
+            .insert_resource(PointCloudDepthPrepassEnabled(self.depth_prepass));
+    }
+}
+
+impl PointCloudPlugin {
+    /// Adds the systems of the render world, once the render device is known. Those of the
+    /// features that need storage buffers are left out without them.
+    fn build_render_app(&self, app: &mut App, storage_buffers: bool) {
+        let drawn_point_clouds = app
+            .world
+            .resource::<visibility::DrawnPointCloudsChannel>()
+            .clone();
+        let memory = app
+            .world
+            .resource::<memory::PointCloudMemoryChannel>()
+            .clone();
         let render_app = app.sub_app_mut(RenderApp);
 
         render_app
@@ -75,44 +395,145 @@ impl Plugin for PointCloudPlugin {
                 ExtractSchedule,
                 (
                     extract_point_cloud,
+                    extract_point_cloud_views,
                     clippling_planes::extract_clipping_planes,
-                ),
+                )
+                    .in_set(PointCloudSet::Extract),
             )
             .add_systems(
                 Render,
-                (clippling_planes::prepare_clipping_planes,).in_set(RenderSet::Prepare),
+                (clippling_planes::prepare_clipping_planes,)
+                    .in_set(RenderSet::PrepareResources)
+                    .in_set(PointCloudSet::Prepare),
             )
             .add_systems(
                 Render,
                 (
-                    queue_point_cloud_bind_group,
-                    queue_view_targets,
+                    queue_view_targets.run_if(|enabled: Res<EyeDomeLightingEnabled>| enabled.0),
                     queue_point_cloud,
                 )
-                    .in_set(RenderSet::Queue),
+                    .in_set(RenderSet::Queue)
+                    .in_set(PointCloudSet::Queue),
+            )
+            .add_systems(
+                Render,
+                (
+                    // The view and model uniform buffers are (re)allocated while preparing
+                    // resources, so their bind groups must be created after that. Otherwise
+                    // they would point at the previous frame's buffers, which are too small
+                    // once another point cloud is spawned.
+                    prepare_point_cloud_bind_group,
+                )
+                    .in_set(RenderSet::PrepareBindGroups)
+                    .in_set(PointCloudSet::Prepare),
+            )
+            .add_systems(
+                Render,
+                memory::evict_point_cloud_assets
+                    .in_set(RenderSet::PrepareAssets)
+                    .in_set(PointCloudSet::Prepare)
+                    .after(bevy::render::render_asset::prepare_assets::<PointCloudAsset>)
+                    .before(RenderSet::Queue),
+            )
+            .add_systems(
+                Render,
+                memory::record_drawn_point_cloud_assets
+                    .in_set(RenderSet::PrepareResources)
+                    .in_set(PointCloudSet::Prepare),
+            )
+            .add_systems(
+                Render,
+                visibility::finish_drawn_point_clouds.in_set(RenderSet::Cleanup),
             )
             .init_resource::<clippling_planes::UniformBufferOfGpuClippingPlaneRanges>()
-            .init_resource::<PointCloudBindGroup>();
+            .init_resource::<PointCloudBindGroup>()
+            .init_resource::<memory::PointCloudAssetsLastDrawn>()
+            .insert_resource(drawn_point_clouds)
+            .insert_resource(memory);
 
         render_app
-            .add_systems(Render, prepare_animated_assets.in_set(RenderSet::Prepare))
+            .add_systems(
+                Render,
+                prepare_animated_assets
+                    .in_set(RenderSet::Prepare)
+                    .in_set(PointCloudSet::Prepare),
+            )
             .init_resource::<PointCloudPlaybackControls>();
 
-        render_app
-            .add_render_graph_node::<ViewNodeRunner<PointCloudNode>>(CORE_3D, PointCloudNode::NAME)
-            .add_render_graph_edge(
-                CORE_3D,
-                bevy::core_pipeline::core_3d::graph::node::END_MAIN_PASS,
-                PointCloudNode::NAME,
+        if storage_buffers {
+            render_app
+                .add_systems(
+                    ExtractSchedule,
+                    selection::extract_point_cloud_selections.in_set(PointCloudSet::Extract),
+                )
+                .add_systems(
+                    Render,
+                    (
+                        instancing::prepare_point_cloud_instances,
+                        batching::prepare_point_cloud_batches,
+                        compute::prepare_pre_point_computes,
+                        morph::prepare_point_cloud_morphs,
+                        color::prepare_transfer_functions,
+                        sorting::prepare_depth_sorts,
+                    )
+                        .in_set(RenderSet::PrepareBindGroups)
+                        .in_set(PointCloudSet::Prepare),
+                )
+                .add_systems(
+                    Render,
+                    selection::prepare_point_cloud_selections
+                        .in_set(RenderSet::PrepareAssets)
+                        .in_set(PointCloudSet::Prepare)
+                        .after(bevy::render::render_asset::prepare_assets::<PointCloudAsset>),
+                )
+                .init_resource::<compute::PreparedPrePointComputes>()
+                .init_resource::<sorting::DepthSortBuffers>()
+                .init_resource::<selection::PointCloudSelectionMasks>()
+                .init_resource::<instancing::PointCloudInstanceBuffers>();
+
+            let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+            render_graph.add_node(PrePointComputeNode::NAME, PrePointComputeNode);
+            render_graph.add_node_edge(
+                PrePointComputeNode::NAME,
+                bevy::render::main_graph::node::CAMERA_DRIVER,
             );
-    }
+        }
 
-    fn finish(&self, app: &mut App) {
-        let render_app = app.sub_app_mut(RenderApp);
         render_app
-            .init_resource::<PointCloudPipeline>()
-            .init_resource::<SpecializedRenderPipelines<PointCloudPipeline>>()
-            .init_resource::<EyeDomePipeline>()
-            .init_resource::<SpecializedRenderPipelines<EyeDomePipeline>>();
+            .add_render_graph_node::<ViewNodeRunner<PointCloudNode>>(CORE_3D, PointCloudNode::NAME);
+        match self.render_order {
+            PointCloudRenderOrder::AfterTransparent => {
+                render_app.add_render_graph_edge(
+                    CORE_3D,
+                    bevy::core_pipeline::core_3d::graph::node::END_MAIN_PASS,
+                    PointCloudNode::NAME,
+                );
+            }
+            PointCloudRenderOrder::BeforeTransparent => {
+                render_app.add_render_graph_edges(
+                    CORE_3D,
+                    &[
+                        bevy::core_pipeline::core_3d::graph::node::MAIN_OPAQUE_PASS,
+                        PointCloudNode::NAME,
+                        bevy::core_pipeline::core_3d::graph::node::MAIN_TRANSMISSIVE_PASS,
+                    ],
+                );
+            }
+        }
+        if self.depth_prepass {
+            render_app
+                .add_render_graph_node::<ViewNodeRunner<PointCloudPrepassNode>>(
+                    CORE_3D,
+                    PointCloudPrepassNode::NAME,
+                )
+                .add_render_graph_edges(
+                    CORE_3D,
+                    &[
+                        bevy::core_pipeline::core_3d::graph::node::PREPASS,
+                        PointCloudPrepassNode::NAME,
+                        bevy::core_pipeline::core_3d::graph::node::DEFERRED_PREPASS,
+                    ],
+                );
+        }
     }
 }
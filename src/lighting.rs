@@ -0,0 +1,108 @@
+use bevy::prelude::*;
+
+/// Makes a [`PotreePointCloud`](crate::PotreePointCloud) respond to the scene's
+/// [`AmbientLight`] and [`DirectionalLight`]s, so that it doesn't look unlit next to PBR meshes.
+///
+/// Without normals every point receives the same amount of light: the ambient light plus the
+/// color of each directional light, scaled by its illuminance relative to
+/// [`PointCloudLighting::REFERENCE_ILLUMINANCE`]. Assets with [`Mesh::ATTRIBUTE_NORMAL`], like
+/// PLY and PCD files with normals, are shaded per point with Lambert's cosine law instead, from
+/// up to [`MAX_NORMAL_DIRECTIONAL_LIGHTS`] directional lights. Eye dome lighting darkens the lit
+/// color the same way it darkens the unlit one; both are multiplicative, so their order doesn't
+/// matter.
+#[derive(Component, Clone, Debug)]
+pub struct PointCloudLighting {
+    pub respond_to_lighting: bool,
+    /// Blends between the unlit color at `0.0` and the fully lit color at `1.0`.
+    pub intensity: f32,
+    /// Shades points by their normals when the asset has them. Set to `false` to light every
+    /// point the same way, which suits noisy normals.
+    pub normals: bool,
+}
+
+/// Number of directional lights that points with normals are shaded with. Further lights are
+/// ignored.
+pub const MAX_NORMAL_DIRECTIONAL_LIGHTS: usize = 4;
+
+impl PointCloudLighting {
+    /// The directional light illuminance, in lux, that lights points at their original color.
+    /// This is the default illuminance of a [`DirectionalLight`].
+    pub const REFERENCE_ILLUMINANCE: f32 = 100_000.0;
+}
+
+impl Default for PointCloudLighting {
+    fn default() -> Self {
+        Self {
+            respond_to_lighting: true,
+            intensity: 1.0,
+            normals: true,
+        }
+    }
+}
+
+/// The ambient light of the scene, in linear RGB.
+pub(crate) fn ambient_light(ambient_light: Option<&AmbientLight>) -> Vec3 {
+    ambient_light
+        .map(|light| Vec3::from_slice(&light.color.as_linear_rgba_f32()) * light.brightness)
+        .unwrap_or_default()
+}
+
+/// The light a directional light adds to the points facing it, in linear RGB.
+pub(crate) fn directional_light(light: &DirectionalLight) -> Vec3 {
+    Vec3::from_slice(&light.color.as_linear_rgba_f32())
+        * (light.illuminance / PointCloudLighting::REFERENCE_ILLUMINANCE)
+}
+
+/// The light received by every point in the scene, in linear RGB.
+pub(crate) fn scene_light<'a>(
+    ambient_light: Option<&AmbientLight>,
+    directional_lights: impl Iterator<Item = &'a DirectionalLight>,
+) -> Vec3 {
+    directional_lights.fold(self::ambient_light(ambient_light), |total, light| {
+        total + directional_light(light)
+    })
+}
+
+/// The directional lights that points with normals are shaded with, as world space directions
+/// towards each light and their colors, padded to [`MAX_NORMAL_DIRECTIONAL_LIGHTS`].
+pub(crate) fn normal_lights<'a>(
+    directional_lights: impl Iterator<Item = (&'a DirectionalLight, &'a GlobalTransform)>,
+) -> (
+    u32,
+    [Vec4; MAX_NORMAL_DIRECTIONAL_LIGHTS],
+    [Vec4; MAX_NORMAL_DIRECTIONAL_LIGHTS],
+) {
+    let mut count = 0;
+    let mut directions = [Vec4::ZERO; MAX_NORMAL_DIRECTIONAL_LIGHTS];
+    let mut colors = [Vec4::ZERO; MAX_NORMAL_DIRECTIONAL_LIGHTS];
+    for (light, transform) in directional_lights.take(MAX_NORMAL_DIRECTIONAL_LIGHTS) {
+        // Directional lights shine along their forward axis.
+        directions[count] = transform.back().extend(0.0);
+        colors[count] = directional_light(light).extend(1.0);
+        count += 1;
+    }
+    (count as u32, directions, colors)
+}
+
+/// How much of the normal based shading points get, or `0.0` to use [`light_factor`].
+pub(crate) fn normal_light_intensity(
+    lighting: Option<&PointCloudLighting>,
+    has_normals: bool,
+) -> f32 {
+    match lighting {
+        Some(lighting) if lighting.respond_to_lighting && lighting.normals && has_normals => {
+            lighting.intensity
+        }
+        _ => 0.0,
+    }
+}
+
+/// The factor the point colors get multiplied with.
+pub(crate) fn light_factor(lighting: Option<&PointCloudLighting>, scene_light: Vec3) -> Vec3 {
+    match lighting {
+        Some(lighting) if lighting.respond_to_lighting => {
+            Vec3::ONE.lerp(scene_light, lighting.intensity)
+        }
+        _ => Vec3::ONE,
+    }
+}
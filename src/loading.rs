@@ -0,0 +1,151 @@
+use bevy::{
+    asset::{AssetPath, LoadState},
+    prelude::*,
+};
+
+use crate::{PointCloudAsset, PotreePointCloud};
+
+/// Limits how many point clouds queued with [`QueuedPointCloudLoad`] load at the same time.
+#[derive(Resource, Clone, Debug)]
+pub struct PointCloudLoadQueue {
+    pub max_concurrent_loads: usize,
+}
+
+impl Default for PointCloudLoadQueue {
+    fn default() -> Self {
+        Self {
+            max_concurrent_loads: 4,
+        }
+    }
+}
+
+/// Loads `path` into the [`PotreePointCloud`] of this entity once the [`PointCloudLoadQueue`]
+/// has a free slot. Queued clouds with a higher [`LoadPriority`] start loading first.
+#[derive(Component, Clone, Debug)]
+pub struct QueuedPointCloudLoad {
+    pub path: AssetPath<'static>,
+}
+
+/// Orders the [`QueuedPointCloudLoad`]s, higher priorities load first. Clouds without it
+/// have a priority of `0`. Changing it only has an effect while the cloud is still queued.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LoadPriority(pub i32);
+
+/// Marks point clouds dequeued from the [`PointCloudLoadQueue`] whose asset is still loading.
+#[derive(Component)]
+pub(crate) struct LoadingPointCloud;
+
+pub(crate) fn process_point_cloud_load_queue(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    queue: Res<PointCloudLoadQueue>,
+    loading: Query<(Entity, &PotreePointCloud), With<LoadingPointCloud>>,
+    mut queued: Query<
+        (
+            Entity,
+            &QueuedPointCloudLoad,
+            Option<&LoadPriority>,
+            &mut PotreePointCloud,
+        ),
+        Without<LoadingPointCloud>,
+    >,
+) {
+    let mut in_flight = 0;
+    for (entity, point_cloud) in &loading {
+        match asset_server.load_state(&point_cloud.mesh) {
+            LoadState::Loaded | LoadState::Failed | LoadState::NotLoaded => {
+                commands.entity(entity).remove::<LoadingPointCloud>();
+            }
+            LoadState::Loading => in_flight += 1,
+        }
+    }
+
+    let free_slots = queue.max_concurrent_loads.saturating_sub(in_flight);
+    if free_slots == 0 || queued.is_empty() {
+        return;
+    }
+    let mut candidates: Vec<(LoadPriority, Entity)> = queued
+        .iter()
+        .map(|(entity, _, priority, _)| (priority.copied().unwrap_or_default(), entity))
+        .collect();
+    // Highest priority first, then in spawn order.
+    candidates.sort_by_key(|&(priority, entity)| (std::cmp::Reverse(priority), entity));
+    for (_, entity) in candidates.into_iter().take(free_slots) {
+        let (_, load, _, mut point_cloud) = queued.get_mut(entity).unwrap();
+        let mesh: Handle<PointCloudAsset> = asset_server.load(load.path.clone());
+        point_cloud.mesh = mesh;
+        commands
+            .entity(entity)
+            .remove::<QueuedPointCloudLoad>()
+            .insert(LoadingPointCloud);
+    }
+}
+
+/// How far the asset of a [`PotreePointCloud`] has loaded. Inserted on every point cloud and
+/// kept up to date in [`PreUpdate`].
+///
+/// Clouds start drawing on their own once their asset is available, there is no need to
+/// respawn them. Use this, or the [`PointCloudReady`] event, to react when that happens.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PointCloudLoadState {
+    /// Waiting in the [`PointCloudLoadQueue`].
+    Queued,
+    /// The asset is loading, or hasn't been added to the [`Assets`] yet.
+    Loading,
+    /// The asset is available and the cloud can be drawn. Streamed assets may still be
+    /// receiving points, see [`PointCloudAsset::loaded_fraction`].
+    Ready,
+    /// The asset failed to load, or a [`StreamedOpdLoad`](crate::StreamedOpdLoad) failed to
+    /// read its file.
+    Failed,
+}
+
+/// Marks point clouds whose asset failed to load without the asset server knowing, like a
+/// failed [`StreamedOpdLoad`](crate::StreamedOpdLoad).
+#[derive(Component)]
+pub(crate) struct FailedPointCloudLoad;
+
+/// Sent when the asset of a point cloud becomes available, including after
+/// [`PotreePointCloud::mesh`] changed to another asset that then loaded.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct PointCloudReady {
+    pub entity: Entity,
+}
+
+type LoadingPointCloudItem = (
+    Entity,
+    &'static PotreePointCloud,
+    Has<QueuedPointCloudLoad>,
+    Has<FailedPointCloudLoad>,
+    Option<&'static mut PointCloudLoadState>,
+);
+
+pub(crate) fn update_point_cloud_load_states(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    assets: Res<Assets<PointCloudAsset>>,
+    mut ready: EventWriter<PointCloudReady>,
+    mut point_clouds: Query<LoadingPointCloudItem>,
+) {
+    for (entity, point_cloud, queued, failed, load_state) in &mut point_clouds {
+        let state = if queued {
+            PointCloudLoadState::Queued
+        } else if assets.contains(&point_cloud.mesh) {
+            PointCloudLoadState::Ready
+        } else if failed || asset_server.load_state(&point_cloud.mesh) == LoadState::Failed {
+            PointCloudLoadState::Failed
+        } else {
+            PointCloudLoadState::Loading
+        };
+        match load_state {
+            Some(load_state) if *load_state == state => continue,
+            Some(mut load_state) => *load_state = state,
+            None => {
+                commands.entity(entity).insert(state);
+            }
+        }
+        if state == PointCloudLoadState::Ready {
+            ready.send(PointCloudReady { entity });
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use bevy::{prelude::*, render::extract_component::ExtractComponent};
+
+/// Draws only a fraction of the points of a cloud when it is far from the camera, a cheap
+/// level of detail for clouds that aren't streamed as a [`PotreeOctree`](crate::PotreeOctree).
+///
+/// The fraction is picked for every view by the distance from the camera to the center of the
+/// cloud's bounds. It is `1.0` up to `near`, `min_fraction` from `far` on, and interpolated
+/// linearly in between. The first points of the asset are drawn, so load it with the points
+/// shuffled, see [`PointCloudAsset::shuffle_points`](crate::PointCloudAsset::shuffle_points),
+/// to thin it out evenly. Files are usually ordered by scan line or by space, and would lose
+/// whole regions instead.
+///
+/// Ignored for [`InstancedPointCloud`](crate::InstancedPointCloud)s and clouds with a
+/// [`PointDepthSort`](crate::PointDepthSort).
+#[derive(Component, Clone, Copy, Debug, PartialEq, ExtractComponent)]
+pub struct PointCloudLod {
+    pub near: f32,
+    pub far: f32,
+    /// From `0.0` to `1.0`.
+    pub min_fraction: f32,
+}
+
+impl Default for PointCloudLod {
+    fn default() -> Self {
+        Self {
+            near: 10.0,
+            far: 100.0,
+            min_fraction: 0.1,
+        }
+    }
+}
+
+impl PointCloudLod {
+    /// The fraction of the points that is drawn at `distance` from the camera.
+    pub fn fraction(&self, distance: f32) -> f32 {
+        let t = if self.far > self.near {
+            ((distance - self.near) / (self.far - self.near)).clamp(0.0, 1.0)
+        } else if distance >= self.far {
+            1.0
+        } else {
+            0.0
+        };
+        1.0 + (self.min_fraction.clamp(0.0, 1.0) - 1.0) * t
+    }
+}
@@ -0,0 +1,193 @@
+use std::sync::{Arc, Mutex};
+
+use bevy::{
+    prelude::*,
+    render::{extract_resource::ExtractResource, render_asset::RenderAssets},
+    utils::{HashMap, HashSet},
+};
+
+use crate::{
+    morph::ExtractedPointCloudMorph, InstancedPointCloud, PointCloudAnimation, PointCloudAsset,
+    PointCloudBatch, PointCloudDrawList, PointCloudMorph, PotreePointCloud,
+};
+
+/// Limits the GPU memory used by the buffers of [`PointCloudAsset`]s. While they use more than
+/// `max_bytes`, the buffers of the assets that were drawn least recently are freed. Evicted
+/// assets keep their points on the CPU, and are uploaded again once a point cloud using them
+/// is visible, which takes a frame or two during which it isn't drawn.
+///
+/// Assets drawn in the previous frame are never evicted, so the budget is exceeded when the
+/// visible clouds alone need more than that. Unlimited by default, see
+/// [`PointCloudMemoryUsage`] for the current usage.
+#[derive(Resource, Clone, Copy, Debug, ExtractResource)]
+pub struct PointCloudMemoryBudget {
+    pub max_bytes: u64,
+}
+
+impl Default for PointCloudMemoryBudget {
+    fn default() -> Self {
+        Self {
+            max_bytes: u64::MAX,
+        }
+    }
+}
+
+/// The GPU memory used by [`PointCloudAsset`]s in the most recently rendered frame, for
+/// memory overlays. Lags one frame behind the main world with pipelined rendering, like
+/// [`VisiblePointClouds`](crate::VisiblePointClouds).
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PointCloudMemoryUsage {
+    /// The size of the buffers of the assets on the GPU. Per-view and per-entity buffers,
+    /// like those of a [`PointDepthSort`](crate::PointDepthSort), aren't included.
+    pub gpu_bytes: u64,
+    pub resident_assets: usize,
+    /// Assets evicted to stay within the [`PointCloudMemoryBudget`], that haven't been
+    /// uploaded again yet.
+    pub evicted_assets: usize,
+}
+
+#[derive(Default)]
+struct PointCloudMemory {
+    usage: PointCloudMemoryUsage,
+    /// Evicted by the render world, and not yet marked for uploading again by the main world.
+    evicted: HashSet<AssetId<PointCloudAsset>>,
+}
+
+/// Carries the evicted assets from the render world to the main world, which reloads them.
+/// The same channel is inserted into both worlds.
+#[derive(Resource, Clone, Default)]
+pub(crate) struct PointCloudMemoryChannel(Arc<Mutex<PointCloudMemory>>);
+
+/// The last frame in which every asset on the GPU was queued for drawing.
+#[derive(Resource, Default)]
+pub(crate) struct PointCloudAssetsLastDrawn {
+    frame: u64,
+    last_drawn: HashMap<AssetId<PointCloudAsset>, u64>,
+}
+
+/// Runs in the render world once the draw lists of the views have been queued.
+pub(crate) fn record_drawn_point_cloud_assets(
+    mut last_drawn: ResMut<PointCloudAssetsLastDrawn>,
+    views: Query<&PointCloudDrawList>,
+    point_clouds: Query<(&Handle<PointCloudAsset>, Option<&ExtractedPointCloudMorph>)>,
+) {
+    let last_drawn = &mut *last_drawn;
+    for draw_list in &views {
+        for draw_data in &draw_list.list {
+            let Ok((handle, morph)) = point_clouds.get(draw_data.entity) else {
+                continue;
+            };
+            last_drawn.last_drawn.insert(handle.id(), last_drawn.frame);
+            if let Some(morph) = morph {
+                last_drawn
+                    .last_drawn
+                    .insert(morph.to.id(), last_drawn.frame);
+            }
+        }
+    }
+}
+
+/// Runs in the render world once the assets of this frame have been prepared, and before the
+/// views are queued, so evicted assets are simply not drawn.
+pub(crate) fn evict_point_cloud_assets(
+    budget: Res<PointCloudMemoryBudget>,
+    channel: Res<PointCloudMemoryChannel>,
+    mut last_drawn: ResMut<PointCloudAssetsLastDrawn>,
+    mut render_assets: ResMut<RenderAssets<PointCloudAsset>>,
+) {
+    let last_drawn = &mut *last_drawn;
+    last_drawn.frame += 1;
+    let frame = last_drawn.frame;
+    last_drawn
+        .last_drawn
+        .retain(|&id, _| render_assets.get(id).is_some());
+    // Assets that were just uploaded count as drawn, so they get a chance to be.
+    let mut assets: Vec<_> = render_assets
+        .iter()
+        .map(|(id, asset)| {
+            let drawn = *last_drawn.last_drawn.entry(id).or_insert(frame);
+            (id, asset.gpu_bytes(), drawn)
+        })
+        .collect();
+    let mut gpu_bytes: u64 = assets.iter().map(|&(_, bytes, _)| bytes).sum();
+
+    let mut memory = channel.0.lock().unwrap();
+    memory.evicted.retain(|&id| render_assets.get(id).is_none());
+    if gpu_bytes > budget.max_bytes {
+        assets.sort_by_key(|&(_, _, drawn)| drawn);
+        for (id, bytes, drawn) in assets {
+            if gpu_bytes <= budget.max_bytes || drawn + 1 >= frame {
+                break;
+            }
+            if let Some(asset) = render_assets.remove(id) {
+                asset.forget_uploads();
+            }
+            last_drawn.last_drawn.remove(&id);
+            memory.evicted.insert(id);
+            gpu_bytes -= bytes;
+        }
+    }
+    memory.usage = PointCloudMemoryUsage {
+        gpu_bytes,
+        resident_assets: last_drawn.last_drawn.len(),
+        evicted_assets: memory.evicted.len(),
+    };
+}
+
+/// Marks the evicted assets of visible point clouds as modified, so that they are extracted
+/// and uploaded again.
+pub(crate) fn reload_evicted_point_cloud_assets(
+    channel: Res<PointCloudMemoryChannel>,
+    mut usage: ResMut<PointCloudMemoryUsage>,
+    mut assets: ResMut<Assets<PointCloudAsset>>,
+    point_clouds: Query<(
+        &PotreePointCloud,
+        Option<&PointCloudAnimation>,
+        &ViewVisibility,
+    )>,
+    instanced: Query<(&InstancedPointCloud, &ViewVisibility)>,
+    morphs: Query<(&PointCloudMorph, &ViewVisibility)>,
+    batches: Query<(&PointCloudBatch, &ViewVisibility)>,
+) {
+    let mut memory = channel.0.lock().unwrap();
+    usage.set_if_neq(memory.usage);
+    if memory.evicted.is_empty() {
+        return;
+    }
+    memory.evicted.retain(|&id| assets.contains(id));
+    let visible_handles = point_clouds
+        .iter()
+        .filter(|(_, _, visibility)| visibility.get())
+        .flat_map(|(point_cloud, animation, _)| {
+            // The frame being blended towards is drawn too.
+            let next = animation
+                .and_then(|animation| animation.interpolation_target(&point_cloud.mesh, &assets));
+            [Some(&point_cloud.mesh), next.map(|(next, _)| next)]
+        })
+        .flatten()
+        .chain(
+            instanced
+                .iter()
+                .filter(|(_, visibility)| visibility.get())
+                .map(|(instanced, _)| &instanced.asset),
+        )
+        .chain(
+            morphs
+                .iter()
+                .filter(|(_, visibility)| visibility.get())
+                .flat_map(|(morph, _)| [&morph.from, &morph.to]),
+        )
+        .chain(
+            batches
+                .iter()
+                .filter(|(_, visibility)| visibility.get())
+                .map(|(batch, _)| &batch.asset),
+        );
+    let reloaded: Vec<_> = visible_handles
+        .filter(|handle| memory.evicted.remove(&handle.id()))
+        .cloned()
+        .collect();
+    for handle in reloaded {
+        assets.get_mut(&handle);
+    }
+}
@@ -0,0 +1,121 @@
+use bevy::{
+    prelude::*,
+    render::{
+        primitives::Aabb,
+        render_asset::RenderAssets,
+        render_resource::{BindGroup, BindGroupEntries},
+        renderer::RenderDevice,
+    },
+    utils::HashSet,
+};
+
+use crate::{
+    pipeline::PointCloudPipeline, PointCloudAsset, ATTRIBUTE_COLOR_PACKED, ATTRIBUTE_COLOR_RGBA16,
+};
+
+/// Cross-fades between two aligned point cloud assets, for example to compare scans of the
+/// same site before and after a change.
+///
+/// Point `i` of `from` is blended with point `i` of `to`, so both assets must have the same
+/// number of points in the same order. A warning is logged otherwise, and only `from` is drawn.
+/// Colors are blended in [`PointColorMode::Rgb`](crate::PointColorMode::Rgb); other color
+/// modes and the animation only use `from`. Spawn it with a [`SpatialBundle`].
+#[derive(Component, Clone, Debug)]
+pub struct PointCloudMorph {
+    pub from: Handle<PointCloudAsset>,
+    pub to: Handle<PointCloudAsset>,
+    /// `0.0` draws `from`, `1.0` draws `to`.
+    pub mix: f32,
+    /// Also interpolate the point positions, rather than only cross-fading the colors.
+    pub interpolate_positions: bool,
+    /// See [`PotreePointCloud::point_size`](crate::PotreePointCloud::point_size).
+    pub point_size: f32,
+}
+
+impl PointCloudMorph {
+    /// Whether the two assets can be blended. `None` until both have loaded.
+    pub(crate) fn is_compatible(&self, assets: &Assets<PointCloudAsset>) -> Option<bool> {
+        let from = assets.get(&self.from)?;
+        let to = assets.get(&self.to)?;
+        Some(from.num_points() == to.num_points())
+    }
+}
+
+/// Color formats of the `to` asset, as laid out in
+/// [`PointCloudUniform::morph_color_format`](crate::PointCloudUniform::morph_color_format).
+pub(crate) fn gpu_morph_color_format(asset: &PointCloudAsset) -> u32 {
+    if asset.mesh.contains_attribute(ATTRIBUTE_COLOR_RGBA16) {
+        2
+    } else if asset.mesh.contains_attribute(ATTRIBUTE_COLOR_PACKED) {
+        1
+    } else {
+        0
+    }
+}
+
+pub(crate) fn validate_point_cloud_morphs(
+    mut validated: Local<HashSet<Entity>>,
+    assets: Res<Assets<PointCloudAsset>>,
+    morphs: Query<(Entity, Ref<PointCloudMorph>)>,
+) {
+    for (entity, morph) in &morphs {
+        if morph.is_changed() {
+            validated.remove(&entity);
+        }
+        if validated.contains(&entity) {
+            continue;
+        }
+        let Some(compatible) = morph.is_compatible(&assets) else {
+            continue;
+        };
+        if !compatible {
+            warn!(
+                "Can't morph point cloud {:?}, its assets have different numbers of points",
+                entity
+            );
+        }
+        validated.insert(entity);
+    }
+}
+
+/// The `to` asset of a [`PointCloudMorph`] in the render world.
+#[derive(Component)]
+pub struct ExtractedPointCloudMorph {
+    pub to: Handle<PointCloudAsset>,
+}
+
+#[derive(Component)]
+pub struct PreparedPointCloudMorph {
+    pub bind_group: BindGroup,
+    /// Bounds of the target asset, see [`PointCloudAsset::aabb`].
+    pub to_aabb: Aabb,
+}
+
+pub(crate) fn prepare_point_cloud_morphs(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    pipeline: Res<PointCloudPipeline>,
+    render_assets: Res<RenderAssets<PointCloudAsset>>,
+    query: Query<(Entity, &ExtractedPointCloudMorph)>,
+) {
+    for (entity, morph) in &query {
+        let Some(to) = render_assets.get(&morph.to) else {
+            continue;
+        };
+        let bind_group = render_device.create_bind_group(
+            "point cloud morph bind group",
+            &pipeline.morph_layout,
+            &BindGroupEntries::sequential((
+                to.position_buffer.as_entire_binding(),
+                to.color_buffer
+                    .as_ref()
+                    .unwrap_or(&pipeline.placeholder_buffer)
+                    .as_entire_binding(),
+            )),
+        );
+        commands.entity(entity).insert(PreparedPointCloudMorph {
+            bind_group,
+            to_aabb: to.aabb,
+        });
+    }
+}
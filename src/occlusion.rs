@@ -0,0 +1,312 @@
+use std::ops::Range;
+
+use bevy::{prelude::*, render::mesh::VertexAttributeValues, utils::thiserror::Error};
+use serde::{Deserialize, Serialize};
+
+use crate::PointCloudAsset;
+
+/// Controls the precomputation done by [`PointCloudAsset::compute_occlusion`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OcclusionSettings {
+    /// Number of chunks along the longest side of the bounding box.
+    ///
+    /// Preprocessing time grows with the sixth power of the resolution, and memory with the
+    /// sixth power divided by eight, so keep this low.
+    pub resolution: u32,
+    /// Chunks holding at least this many times the average number of points of the non-empty
+    /// chunks are treated as solid, like the inside of a wall.
+    pub occluder_density: f32,
+}
+
+impl Default for OcclusionSettings {
+    fn default() -> Self {
+        Self {
+            resolution: 8,
+            occluder_density: 1.0,
+        }
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum OcclusionError {
+    #[error("Occlusion can't be computed for animated point clouds")]
+    Animated,
+    #[error("Occlusion resolution must be at least 1")]
+    ZeroResolution,
+}
+
+/// A coarse potentially visible set: the points of a [`PointCloudAsset`] grouped into a grid of
+/// chunks, and for every chunk, which chunks can be seen from inside it.
+///
+/// Used by the renderer to skip chunks hidden behind dense walls when the camera is inside the
+/// bounds of the cloud, in addition to the frustum culling of whole entities.
+#[derive(Clone, Debug)]
+pub struct PointCloudOcclusion {
+    /// Corner of the grid, in the asset's local space.
+    min: Vec3,
+    cell_size: f32,
+    dims: UVec3,
+    /// Range of points in each cell. Points are sorted by cell.
+    chunks: Vec<Range<u32>>,
+    /// One row of bits per cell, with a bit set for every cell visible from it.
+    visibility: Vec<u64>,
+    row_len: usize,
+    /// The index every point had before the points were sorted by cell.
+    source_indices: Vec<u32>,
+}
+
+impl PointCloudOcclusion {
+    /// Ranges of the points that may be visible from `position`, in the asset's local space.
+    ///
+    /// Returns `None` if `position` is outside of the grid, in which case every point may be
+    /// visible.
+    pub fn visible_points(&self, position: Vec3) -> Option<Vec<Range<u32>>> {
+        let cell = self.cell_index(self.grid_position(position))?;
+        let row = &self.visibility[cell * self.row_len..(cell + 1) * self.row_len];
+        let mut ranges: Vec<Range<u32>> = Vec::new();
+        for (target, chunk) in self.chunks.iter().enumerate() {
+            if chunk.is_empty() || row[target / 64] & (1 << (target % 64)) == 0 {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some(last) if last.end == chunk.start => last.end = chunk.end,
+                _ => ranges.push(chunk.clone()),
+            }
+        }
+        Some(ranges)
+    }
+
+    /// The index that the point at `index` had before
+    /// [`PointCloudAsset::compute_occlusion`] reordered the points, for example its index in
+    /// the loaded file. Indices past the last point are returned as they are.
+    pub fn source_index(&self, index: u32) -> u32 {
+        self.source_indices
+            .get(index as usize)
+            .copied()
+            .unwrap_or(index)
+    }
+
+    /// Moves the grid along with the points, see [`PointCloudAsset::recenter`].
+    pub(crate) fn translate(&mut self, offset: Vec3) {
+        self.min += offset;
+    }
+
+    fn grid_position(&self, position: Vec3) -> Vec3 {
+        (position - self.min) / self.cell_size
+    }
+
+    fn cell_index(&self, grid_position: Vec3) -> Option<usize> {
+        let cell = grid_position.floor();
+        if cell.cmplt(Vec3::ZERO).any() || cell.cmpge(self.dims.as_vec3()).any() {
+            return None;
+        }
+        let cell = cell.as_uvec3();
+        Some((cell.x + self.dims.x * (cell.y + self.dims.y * cell.z)) as usize)
+    }
+
+    /// Walks the cells crossed by the segment between two grid positions and returns whether
+    /// any of them, apart from the first and the last, is solid.
+    fn segment_blocked(&self, solid: &[bool], from: Vec3, to: Vec3) -> bool {
+        let direction = to - from;
+        let step = IVec3::select(direction.cmplt(Vec3::ZERO), IVec3::NEG_ONE, IVec3::ONE);
+        let t_delta = direction.recip().abs();
+        let mut cell = from.floor().as_ivec3();
+        let end = to.floor().as_ivec3();
+        let next_boundary = cell.as_vec3() + step.max(IVec3::ZERO).as_vec3();
+        let mut t_max = (next_boundary - from) / direction;
+        loop {
+            let axis = if t_max.x < t_max.y && t_max.x < t_max.z {
+                0
+            } else if t_max.y < t_max.z {
+                1
+            } else {
+                2
+            };
+            if t_max[axis] > 1.0 {
+                return false;
+            }
+            cell[axis] += step[axis];
+            t_max[axis] += t_delta[axis];
+            if cell == end {
+                return false;
+            }
+            if let Some(index) = self.cell_index(cell.as_vec3()) {
+                if solid[index] {
+                    return true;
+                }
+            }
+        }
+    }
+}
+
+impl PointCloudAsset {
+    /// Groups the points into chunks and precomputes which chunks occlude each other, so that
+    /// chunks hidden behind dense walls are skipped while the camera is inside the cloud.
+    /// Mostly useful for scans of building interiors.
+    ///
+    /// This reorders the points, and can take a long time for large resolutions, see
+    /// [`OcclusionSettings::resolution`]. Point indices, like those of
+    /// [`PointCloudRaycast`](crate::PointCloudRaycast) hits, a
+    /// [`PointCloudSelection`](crate::PointCloudSelection) or
+    /// [`PointCloudAsset::write_ply`], refer to the new order afterwards. Indices taken before,
+    /// or from the source file, have to be mapped with
+    /// [`PointCloudOcclusion::source_index`]. Animated assets are rejected, since their frames
+    /// would have to be reordered too.
+    pub fn compute_occlusion(
+        &mut self,
+        settings: &OcclusionSettings,
+    ) -> Result<(), OcclusionError> {
+        if self.animation.is_some() {
+            return Err(OcclusionError::Animated);
+        }
+        if settings.resolution == 0 {
+            return Err(OcclusionError::ZeroResolution);
+        }
+        let min = Vec3::from(self.aabb.min());
+        let size = Vec3::from(self.aabb.half_extents * 2.0);
+        let cell_size = (size.max_element() / settings.resolution as f32).max(f32::EPSILON);
+        let dims = (size / cell_size)
+            .ceil()
+            .as_uvec3()
+            .clamp(UVec3::ONE, UVec3::splat(settings.resolution));
+        let num_cells = (dims.x * dims.y * dims.z) as usize;
+        let row_len = num_cells.div_ceil(64);
+        let mut occlusion = PointCloudOcclusion {
+            min,
+            cell_size,
+            dims,
+            chunks: Vec::new(),
+            visibility: Vec::new(),
+            row_len,
+            source_indices: Vec::new(),
+        };
+
+        // Sort the points by cell.
+        let cells: Vec<usize> = match self.mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+            Some(VertexAttributeValues::Float32x3(positions)) => positions
+                .iter()
+                .map(|position| {
+                    let grid_position = occlusion
+                        .grid_position(Vec3::from(*position))
+                        .clamp(Vec3::ZERO, dims.as_vec3() - 0.5);
+                    occlusion.cell_index(grid_position).unwrap()
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        let mut order: Vec<u32> = (0..cells.len() as u32).collect();
+        order.sort_by_key(|&point| cells[point as usize]);
+        self.select_points(&order);
+        // Points that were already reordered by an earlier call keep their original index.
+        occlusion.source_indices = match &self.occlusion {
+            Some(previous) => order
+                .iter()
+                .map(|&index| previous.source_index(index))
+                .collect(),
+            None => order,
+        };
+
+        let mut counts = vec![0u32; num_cells];
+        for &cell in &cells {
+            counts[cell] += 1;
+        }
+        let mut start = 0;
+        occlusion.chunks = counts
+            .iter()
+            .map(|&count| {
+                start += count;
+                start - count..start
+            })
+            .collect();
+
+        let non_empty: Vec<usize> = (0..num_cells).filter(|&cell| counts[cell] > 0).collect();
+        let average = cells.len() as f32 / non_empty.len().max(1) as f32;
+        let solid: Vec<bool> = counts
+            .iter()
+            .map(|&count| count > 0 && count as f32 >= average * settings.occluder_density)
+            .collect();
+
+        // A chunk is visible from a cell if any of a few parallel rays between them gets
+        // through, which makes the result less sensitive to where in the cell the camera is.
+        let sample_offsets: Vec<Vec3> = std::iter::once(Vec3::ZERO)
+            .chain((0..8).map(|corner| {
+                Vec3::select(
+                    BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0),
+                    Vec3::splat(0.35),
+                    Vec3::splat(-0.35),
+                )
+            }))
+            .collect();
+        let cell_center = |cell: usize| {
+            let cell = cell as u32;
+            let x = cell % dims.x;
+            let y = cell / dims.x % dims.y;
+            let z = cell / (dims.x * dims.y);
+            UVec3::new(x, y, z).as_vec3() + 0.5
+        };
+        let mut visibility = vec![0; row_len * num_cells];
+        for source in 0..num_cells {
+            let row = &mut visibility[source * row_len..(source + 1) * row_len];
+            let from = cell_center(source);
+            for &target in &non_empty {
+                let to = cell_center(target);
+                let visible = source == target
+                    || sample_offsets.iter().any(|offset| {
+                        !occlusion.segment_blocked(&solid, from + *offset, to + *offset)
+                    });
+                if visible {
+                    row[target / 64] |= 1 << (target % 64);
+                }
+            }
+        }
+
+        occlusion.visibility = visibility;
+        self.occlusion = Some(occlusion);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(asset: &PointCloudAsset) -> Vec<[f32; 3]> {
+        asset
+            .mesh
+            .attribute(Mesh::ATTRIBUTE_POSITION)
+            .and_then(VertexAttributeValues::as_float3)
+            .unwrap()
+            .to_vec()
+    }
+
+    #[test]
+    fn maps_points_back_to_their_source_index() {
+        let source: Vec<Vec3> = [3.0, 0.0, 2.0, 1.0, 3.5, 0.5].map(|x| Vec3::X * x).into();
+        let colors = vec![[u8::MAX; 4]; source.len()];
+        let mut asset = PointCloudAsset::from_points(source.clone(), colors).unwrap();
+        let settings = OcclusionSettings {
+            resolution: 4,
+            ..default()
+        };
+        asset.compute_occlusion(&settings).unwrap();
+        // Again with a different grid, which reorders the points another time.
+        asset
+            .compute_occlusion(&OcclusionSettings {
+                resolution: 2,
+                ..settings
+            })
+            .unwrap();
+
+        let occlusion = asset.occlusion.as_ref().unwrap();
+        let positions = positions(&asset);
+        assert_ne!(
+            positions,
+            source.iter().map(|p| p.to_array()).collect::<Vec<_>>()
+        );
+        for (index, position) in positions.iter().enumerate() {
+            let source_index = occlusion.source_index(index as u32) as usize;
+            assert_eq!(*position, source[source_index].to_array());
+        }
+    }
+}
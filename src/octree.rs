@@ -0,0 +1,863 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, HashSet},
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use bevy::{
+    math::DVec3,
+    prelude::*,
+    reflect::TypePath,
+    render::{
+        mesh::Mesh,
+        primitives::{Aabb, Frustum},
+        render_resource::PrimitiveTopology,
+    },
+    tasks::{block_on, AsyncComputeTaskPool, Task},
+    utils::thiserror::{self, Error},
+};
+use serde::Deserialize;
+
+use crate::{
+    color::pack_rgba8, PointCloudAsset, PointCloudBundle, PointCloudLoadQueue, PotreePointCloud,
+    ATTRIBUTE_COLOR_PACKED,
+};
+
+/// Size of one node record in `hierarchy.bin`.
+const HIERARCHY_RECORD_SIZE: usize = 22;
+
+/// Node type of a `hierarchy.bin` record whose subtree is stored in another chunk.
+const NODE_TYPE_PROXY: u8 = 2;
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum PotreeOctreeError {
+    #[error("Could not read the octree files: {0}")]
+    Io(#[from] io::Error),
+    #[error("Could not parse metadata.json: {0}")]
+    Metadata(#[from] serde_json::Error),
+    #[error("Unsupported octree encoding {0:?}, only DEFAULT is supported")]
+    UnsupportedEncoding(String),
+    #[error("The octree has no int32 position attribute")]
+    MissingPositions,
+    #[error("Malformed hierarchy.bin: {0}")]
+    Hierarchy(&'static str),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Metadata {
+    points: u64,
+    hierarchy: HierarchyMetadata,
+    offset: [f64; 3],
+    scale: [f64; 3],
+    spacing: f64,
+    bounding_box: BoundingBoxMetadata,
+    #[serde(default = "default_encoding")]
+    encoding: String,
+    attributes: Vec<AttributeMetadata>,
+}
+
+fn default_encoding() -> String {
+    "DEFAULT".to_owned()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HierarchyMetadata {
+    first_chunk_size: u64,
+}
+
+#[derive(Deserialize)]
+struct BoundingBoxMetadata {
+    min: [f64; 3],
+    max: [f64; 3],
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AttributeMetadata {
+    name: String,
+    size: usize,
+    num_elements: usize,
+    #[serde(rename = "type")]
+    ty: String,
+    #[serde(default)]
+    min: Vec<f64>,
+}
+
+/// How to decode an attribute value from a point record.
+#[derive(Clone, Copy)]
+enum AttributeType {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+}
+
+impl AttributeType {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "int8" => Self::I8,
+            "int16" => Self::I16,
+            "int32" => Self::I32,
+            "int64" => Self::I64,
+            "uint8" => Self::U8,
+            "uint16" => Self::U16,
+            "uint32" => Self::U32,
+            "uint64" => Self::U64,
+            "float" => Self::F32,
+            "double" => Self::F64,
+            _ => return None,
+        })
+    }
+
+    /// Number of bytes of a value.
+    fn size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::I64 | Self::U64 | Self::F64 => 8,
+        }
+    }
+
+    fn read(self, bytes: &[u8]) -> f64 {
+        macro_rules! read {
+            ($ty:ty) => {
+                <$ty>::from_le_bytes(bytes[..std::mem::size_of::<$ty>()].try_into().unwrap()) as f64
+            };
+        }
+        match self {
+            Self::I8 => read!(i8),
+            Self::I16 => read!(i16),
+            Self::I32 => read!(i32),
+            Self::I64 => read!(i64),
+            Self::U8 => read!(u8),
+            Self::U16 => read!(u16),
+            Self::U32 => read!(u32),
+            Self::U64 => read!(u64),
+            Self::F32 => read!(f32),
+            Self::F64 => read!(f64),
+        }
+    }
+}
+
+/// A single valued attribute that is loaded as a scalar field.
+struct ScalarAttribute {
+    name: String,
+    offset: usize,
+    ty: AttributeType,
+    /// Subtracted from every value to keep large doubles, like GPS times, precise in f32.
+    value_offset: f64,
+}
+
+/// Everything needed to decode the points of a node, shared with the loading tasks.
+struct PointLayout {
+    octree_path: PathBuf,
+    point_size: usize,
+    position_offset: usize,
+    color_offset: Option<usize>,
+    scalars: Vec<ScalarAttribute>,
+    scale: DVec3,
+    /// Added to the scaled positions to make them relative to `origin`.
+    offset: DVec3,
+    /// Minimum of the octree's bounds, in the file's coordinate system.
+    origin: DVec3,
+}
+
+impl PointLayout {
+    fn read_node(&self, num_points: u32, byte_offset: u64) -> Result<PointCloudAsset, io::Error> {
+        let num_points = num_points as usize;
+        let mut file = File::open(&self.octree_path)?;
+        // Check the node against the file before allocating, the hierarchy can claim any size.
+        let file_len = file.metadata()?.len();
+        let len = num_points
+            .checked_mul(self.point_size)
+            .filter(|&len| {
+                byte_offset
+                    .checked_add(len as u64)
+                    .is_some_and(|end| end <= file_len)
+            })
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "node extends past the end of octree.bin",
+                )
+            })?;
+        let mut bytes = vec![0; len];
+        file.seek(SeekFrom::Start(byte_offset))?;
+        file.read_exact(&mut bytes)?;
+
+        let mut positions = Vec::with_capacity(num_points);
+        let mut colors = Vec::with_capacity(if self.color_offset.is_some() {
+            num_points
+        } else {
+            0
+        });
+        let mut scalar_fields: Vec<Vec<f32>> = self
+            .scalars
+            .iter()
+            .map(|_| Vec::with_capacity(num_points))
+            .collect();
+        for point in bytes.chunks_exact(self.point_size) {
+            let coordinate = |axis: usize| {
+                let start = self.position_offset + axis * 4;
+                i32::from_le_bytes(point[start..start + 4].try_into().unwrap()) as f64
+            };
+            let position =
+                DVec3::new(coordinate(0), coordinate(1), coordinate(2)) * self.scale + self.offset;
+            // Potree data is Z up, swap it to Y up like the LAS loader.
+            positions.push([position.x as f32, position.z as f32, position.y as f32]);
+            if let Some(offset) = self.color_offset {
+                let channel = |index: usize| {
+                    let start = offset + index * 2;
+                    let value = u16::from_le_bytes([point[start], point[start + 1]]);
+                    // Some converters store 8 bit colors in the 16 bit attribute.
+                    if value > u8::MAX as u16 {
+                        (value >> 8) as u8
+                    } else {
+                        value as u8
+                    }
+                };
+                colors.push(pack_rgba8([channel(0), channel(1), channel(2), u8::MAX]));
+            }
+            for (values, scalar) in scalar_fields.iter_mut().zip(&self.scalars) {
+                values.push((scalar.ty.read(&point[scalar.offset..]) - scalar.value_offset) as f32);
+            }
+        }
+
+        let mut mesh = Mesh::new(PrimitiveTopology::PointList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        if self.color_offset.is_some() {
+            mesh.insert_attribute(ATTRIBUTE_COLOR_PACKED, colors);
+        }
+        let mut asset = PointCloudAsset::new(mesh);
+        asset.origin = swap_yz(self.origin);
+        asset.scalar_fields = self
+            .scalars
+            .iter()
+            .map(|scalar| scalar.name.clone())
+            .zip(scalar_fields)
+            .collect();
+        Ok(asset)
+    }
+}
+
+fn swap_yz(v: DVec3) -> DVec3 {
+    DVec3::new(v.x, v.z, v.y)
+}
+
+/// A node of a [`PotreeOctreeAsset`].
+#[derive(Clone, Debug)]
+pub struct PotreeOctreeNode {
+    /// Potree's name for the node, `r` followed by the child index at every level.
+    pub name: String,
+    /// Depth of the node, `0` for the root.
+    pub level: u32,
+    /// Bounds of the node in the octree's local space.
+    pub aabb: Aabb,
+    pub num_points: u32,
+    /// Indices of the existing children in [`PotreeOctreeAsset::nodes`].
+    pub children: Vec<usize>,
+    byte_offset: u64,
+}
+
+/// The hierarchy of a Potree 2.0 octree, as written by PotreeConverter 2.
+///
+/// Only `metadata.json` and `hierarchy.bin` are read by [`PotreeOctreeAsset::open`]. The
+/// points stay in `octree.bin` and are streamed in per node by [`PotreeOctree`] entities.
+/// The files are read from the filesystem directly rather than through the asset server,
+/// since nodes are loaded from byte ranges of a file that can be far too large to read
+/// at once.
+#[derive(Asset, Clone, TypePath)]
+pub struct PotreeOctreeAsset {
+    /// Total number of points in all nodes.
+    pub num_points: u64,
+    /// Average distance between the points of the root node. Halves at every level.
+    pub spacing: f64,
+    /// Where the octree's local origin is in the source data's coordinate system,
+    /// see [`PointCloudAsset::origin`].
+    pub origin: DVec3,
+    nodes: Vec<PotreeOctreeNode>,
+    layout: Arc<PointLayout>,
+}
+
+impl PotreeOctreeAsset {
+    /// Reads the octree in `directory`, which has the `metadata.json`, `hierarchy.bin` and
+    /// `octree.bin` files written by PotreeConverter 2.
+    pub fn open(directory: impl AsRef<Path>) -> Result<Self, PotreeOctreeError> {
+        let directory = directory.as_ref();
+        let metadata: Metadata =
+            serde_json::from_slice(&std::fs::read(directory.join("metadata.json"))?)?;
+        if metadata.encoding != "DEFAULT" {
+            return Err(PotreeOctreeError::UnsupportedEncoding(metadata.encoding));
+        }
+
+        let mut point_size = 0;
+        let mut position_offset = None;
+        let mut color_offset = None;
+        let mut scalars = Vec::new();
+        for attribute in &metadata.attributes {
+            // Bytes read from the attribute, which must fit in its declared size so that
+            // decoding a point never reads past its record.
+            let mut used = 0;
+            match (attribute.name.as_str(), attribute.ty.as_str()) {
+                ("position", "int32") if attribute.num_elements == 3 => {
+                    position_offset = Some(point_size);
+                    used = 12;
+                }
+                ("rgb", "uint16") if attribute.num_elements == 3 => {
+                    color_offset = Some(point_size);
+                    used = 6;
+                }
+                (name, ty) if attribute.num_elements == 1 => {
+                    if let Some(ty) = AttributeType::parse(ty) {
+                        used = ty.size();
+                        scalars.push(ScalarAttribute {
+                            name: name.replace([' ', '-'], "_"),
+                            offset: point_size,
+                            ty,
+                            value_offset: match ty {
+                                AttributeType::F64 => {
+                                    attribute.min.first().copied().unwrap_or_default()
+                                }
+                                _ => 0.0,
+                            },
+                        });
+                    }
+                }
+                _ => {}
+            }
+            if attribute.size < used {
+                return Err(invalid_layout(format!(
+                    "attribute {:?} is smaller than its type",
+                    attribute.name
+                )));
+            }
+            point_size = point_size
+                .checked_add(attribute.size)
+                .ok_or_else(|| invalid_layout("attributes are too large".to_owned()))?;
+        }
+        let position_offset = position_offset.ok_or(PotreeOctreeError::MissingPositions)?;
+
+        let min = DVec3::from(metadata.bounding_box.min);
+        let max = DVec3::from(metadata.bounding_box.max);
+        let hierarchy = std::fs::read(directory.join("hierarchy.bin"))?;
+        let nodes = parse_hierarchy(&hierarchy, metadata.hierarchy.first_chunk_size, max - min)?;
+
+        Ok(Self {
+            num_points: metadata.points,
+            spacing: metadata.spacing,
+            origin: swap_yz(min),
+            nodes,
+            layout: Arc::new(PointLayout {
+                octree_path: directory.join("octree.bin"),
+                point_size,
+                position_offset,
+                color_offset,
+                scalars,
+                scale: DVec3::from(metadata.scale),
+                offset: DVec3::from(metadata.offset) - min,
+                origin: min,
+            }),
+        })
+    }
+
+    /// All nodes of the octree. The root is the first one.
+    pub fn nodes(&self) -> &[PotreeOctreeNode] {
+        &self.nodes
+    }
+
+    /// Reads the points of the node at `index` from `octree.bin`.
+    pub fn load_node(&self, index: usize) -> Result<PointCloudAsset, io::Error> {
+        let node = &self.nodes[index];
+        self.layout.read_node(node.num_points, node.byte_offset)
+    }
+}
+
+fn invalid_layout(message: String) -> PotreeOctreeError {
+    io::Error::new(io::ErrorKind::InvalidData, message).into()
+}
+
+/// Parses all chunks of `hierarchy.bin`. `size` is the extent of the root node in the file's
+/// coordinate system.
+fn parse_hierarchy(
+    bytes: &[u8],
+    first_chunk_size: u64,
+    size: DVec3,
+) -> Result<Vec<PotreeOctreeNode>, PotreeOctreeError> {
+    // Node bounds are kept in f64 and in the file's coordinate system while parsing.
+    let mut bounds = vec![(DVec3::ZERO, size)];
+    let mut nodes = vec![PotreeOctreeNode {
+        name: "r".to_owned(),
+        level: 0,
+        aabb: Aabb::default(),
+        num_points: 0,
+        children: Vec::new(),
+        byte_offset: 0,
+    }];
+    let mut chunks = vec![(0, 0, first_chunk_size)];
+    // A proxy pointing back at a chunk that was already parsed would loop forever.
+    let mut visited_chunks = HashSet::new();
+    while let Some((chunk_root, chunk_offset, chunk_size)) = chunks.pop() {
+        if !visited_chunks.insert((chunk_offset, chunk_size)) {
+            return Err(PotreeOctreeError::Hierarchy("chunk referenced twice"));
+        }
+        let chunk = usize::try_from(chunk_offset)
+            .ok()
+            .zip(usize::try_from(chunk_size).ok())
+            .and_then(|(start, len)| bytes.get(start..start.checked_add(len)?))
+            .ok_or(PotreeOctreeError::Hierarchy("chunk out of bounds"))?;
+        // Records are in breadth first order, starting with the chunk's root.
+        let mut chunk_nodes = vec![chunk_root];
+        for (i, record) in chunk.chunks_exact(HIERARCHY_RECORD_SIZE).enumerate() {
+            let current = *chunk_nodes
+                .get(i)
+                .ok_or(PotreeOctreeError::Hierarchy("record without a node"))?;
+            let node_type = record[0];
+            let child_mask = record[1];
+            let num_points = u32::from_le_bytes(record[2..6].try_into().unwrap());
+            let byte_offset = u64::from_le_bytes(record[6..14].try_into().unwrap());
+            let byte_size = u64::from_le_bytes(record[14..22].try_into().unwrap());
+            nodes[current].num_points = num_points;
+            if node_type == NODE_TYPE_PROXY {
+                // The node's own record and its subtree are in another chunk.
+                chunks.push((current, byte_offset, byte_size));
+                continue;
+            }
+            nodes[current].byte_offset = byte_offset;
+
+            let (min, max) = bounds[current];
+            let half = (max - min) / 2.0;
+            for child_index in 0..8 {
+                if child_mask & (1 << child_index) == 0 {
+                    continue;
+                }
+                let upper = DVec3::new(
+                    (child_index & 0b100 != 0) as u8 as f64,
+                    (child_index & 0b010 != 0) as u8 as f64,
+                    (child_index & 0b001 != 0) as u8 as f64,
+                );
+                let child_min = min + half * upper;
+                let child = nodes.len();
+                bounds.push((child_min, child_min + half));
+                nodes.push(PotreeOctreeNode {
+                    name: format!("{}{child_index}", nodes[current].name),
+                    level: nodes[current].level + 1,
+                    aabb: Aabb::default(),
+                    num_points: 0,
+                    children: Vec::new(),
+                    byte_offset: 0,
+                });
+                nodes[current].children.push(child);
+                chunk_nodes.push(child);
+            }
+        }
+    }
+    for (node, (min, max)) in nodes.iter_mut().zip(bounds) {
+        node.aabb = Aabb::from_min_max(swap_yz(min).as_vec3(), swap_yz(max).as_vec3());
+    }
+    Ok(nodes)
+}
+
+/// Streams the nodes of a [`PotreeOctreeAsset`] in and out as the cameras move.
+///
+/// Starting from the root, the children of a node are loaded while the node's point spacing
+/// is larger than `max_screen_space_error` pixels on any active camera, and the node is in
+/// its view. Nodes that no longer need to be shown are despawned, which frees their assets
+/// and GPU buffers.
+///
+/// Every loaded node is a child entity with its own [`PotreePointCloud`], so it is culled and
+/// drawn like any other point cloud. The entity needs a [`SpatialBundle`]. At most
+/// [`PointCloudLoadQueue::max_concurrent_loads`] nodes per octree load at the same time.
+#[derive(Component, Clone, Debug)]
+pub struct PotreeOctree {
+    pub octree: Handle<PotreeOctreeAsset>,
+    /// Point spacing, in pixels, above which the children of a node are loaded.
+    pub max_screen_space_error: f32,
+    /// Nodes are loaded by priority until their points add up to this budget. The root node
+    /// is always loaded.
+    pub max_resident_points: u64,
+    /// [`PotreePointCloud::point_size`] of the nodes.
+    pub point_size: f32,
+    /// [`PotreePointCloud::color`] of the nodes.
+    pub color: Color,
+}
+
+impl Default for PotreeOctree {
+    fn default() -> Self {
+        Self {
+            octree: default(),
+            max_screen_space_error: 2.0,
+            max_resident_points: 5_000_000,
+            point_size: 1.0,
+            color: Color::WHITE,
+        }
+    }
+}
+
+/// Marks the entities of the nodes spawned by a [`PotreeOctree`].
+#[derive(Component, Clone, Copy, Debug)]
+pub struct PotreeOctreeNodeIndex(pub usize);
+
+/// Which nodes of a [`PotreeOctree`] are spawned or loading.
+#[derive(Component, Default)]
+pub(crate) struct PotreeOctreeStreaming {
+    octree: AssetId<PotreeOctreeAsset>,
+    resident: HashMap<usize, Entity>,
+    loading: HashMap<usize, Task<io::Result<PointCloudAsset>>>,
+    failed: HashSet<usize>,
+}
+
+/// A node waiting to be selected, ordered by its screen space error.
+struct Candidate {
+    error: f32,
+    index: usize,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Candidate {}
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.error.total_cmp(&other.error)
+    }
+}
+
+struct StreamingView<'a> {
+    position: Vec3,
+    /// Pixels covered by one world unit at a distance of one, or at any distance for
+    /// orthographic cameras.
+    pixels_per_unit: f32,
+    perspective: bool,
+    frustum: &'a Frustum,
+}
+
+/// Largest screen space error of `node` over all `views` it is visible in.
+fn screen_space_error(
+    node: &PotreeOctreeNode,
+    spacing: f32,
+    transform: &GlobalTransform,
+    scale: f32,
+    views: &[StreamingView],
+) -> Option<f32> {
+    let spacing = spacing / (1u64 << node.level.min(63)) as f32 * scale;
+    let center = transform.transform_point(node.aabb.center.into());
+    let radius = Vec3::from(node.aabb.half_extents).length() * scale;
+    views
+        .iter()
+        .filter(|view| {
+            view.frustum
+                .intersects_obb(&node.aabb, &transform.affine(), true, false)
+        })
+        .map(|view| {
+            let distance = if view.perspective {
+                (center.distance(view.position) - radius).max(1e-3)
+            } else {
+                1.0
+            };
+            spacing * view.pixels_per_unit / distance
+        })
+        .reduce(f32::max)
+}
+
+pub(crate) fn stream_potree_octrees(
+    mut commands: Commands,
+    octree_assets: Res<Assets<PotreeOctreeAsset>>,
+    mut point_cloud_assets: ResMut<Assets<PointCloudAsset>>,
+    queue: Res<PointCloudLoadQueue>,
+    cameras: Query<(&Camera, &GlobalTransform, &Frustum)>,
+    mut octrees: Query<(
+        Entity,
+        Ref<PotreeOctree>,
+        &GlobalTransform,
+        Option<&mut PotreeOctreeStreaming>,
+    )>,
+    mut nodes: Query<&mut PotreePointCloud, With<PotreeOctreeNodeIndex>>,
+) {
+    let views: Vec<StreamingView> = cameras
+        .iter()
+        .filter(|(camera, ..)| camera.is_active)
+        .filter_map(|(camera, transform, frustum)| {
+            let viewport = camera.physical_viewport_size()?;
+            let projection = camera.projection_matrix();
+            Some(StreamingView {
+                position: transform.translation(),
+                pixels_per_unit: projection.y_axis.y * viewport.y as f32 * 0.5,
+                perspective: projection.z_axis.w != 0.0,
+                frustum,
+            })
+        })
+        .collect();
+
+    for (entity, octree, transform, streaming) in &mut octrees {
+        let Some(mut streaming) = streaming else {
+            commands
+                .entity(entity)
+                .insert(PotreeOctreeStreaming::default());
+            continue;
+        };
+        if streaming.octree != octree.octree.id() {
+            for (_, node) in streaming.resident.drain() {
+                commands.entity(node).despawn_recursive();
+            }
+            *streaming = PotreeOctreeStreaming {
+                octree: octree.octree.id(),
+                ..default()
+            };
+        }
+        let Some(asset) = octree_assets.get(&octree.octree) else {
+            continue;
+        };
+        if octree.is_changed() {
+            for &node in streaming.resident.values() {
+                if let Ok(mut point_cloud) = nodes.get_mut(node) {
+                    point_cloud.point_size = octree.point_size;
+                    point_cloud.color = octree.color;
+                }
+            }
+        }
+
+        // Select the nodes to show, most important first, until the budget is used up.
+        let scale = transform.to_scale_rotation_translation().0.max_element();
+        let spacing = asset.spacing as f32;
+        let error = |index: usize| {
+            screen_space_error(&asset.nodes[index], spacing, transform, scale, &views)
+        };
+        let mut wanted = Vec::new();
+        let mut heap = BinaryHeap::new();
+        // The root is always refined if it is visible at all.
+        if error(0).is_some() {
+            heap.push(Candidate {
+                error: f32::INFINITY,
+                index: 0,
+            });
+        }
+        let mut points = 0;
+        while let Some(Candidate {
+            error: node_error,
+            index,
+        }) = heap.pop()
+        {
+            let node = &asset.nodes[index];
+            if index != 0 && points + node.num_points as u64 > octree.max_resident_points {
+                break;
+            }
+            points += node.num_points as u64;
+            wanted.push(index);
+            if node_error > octree.max_screen_space_error {
+                heap.extend(node.children.iter().filter_map(|&child| {
+                    Some(Candidate {
+                        error: error(child)?,
+                        index: child,
+                    })
+                }));
+            }
+        }
+        let wanted_set: HashSet<usize> = wanted.iter().copied().collect();
+
+        // Release what is no longer needed.
+        streaming.resident.retain(|index, node| {
+            let keep = wanted_set.contains(index);
+            if !keep {
+                commands.entity(*node).despawn_recursive();
+            }
+            keep
+        });
+        streaming
+            .loading
+            .retain(|index, _| wanted_set.contains(index));
+
+        // Spawn the nodes that finished loading.
+        let streaming = &mut *streaming;
+        let finished: Vec<usize> = streaming
+            .loading
+            .iter()
+            .filter(|(_, task)| task.is_finished())
+            .map(|(&index, _)| index)
+            .collect();
+        for index in finished {
+            let task = streaming.loading.remove(&index).unwrap();
+            match block_on(task) {
+                Ok(point_cloud) => {
+                    let node = commands
+                        .spawn((
+                            PointCloudBundle {
+                                point_cloud: PotreePointCloud {
+                                    mesh: point_cloud_assets.add(point_cloud),
+                                    point_size: octree.point_size,
+                                    color: octree.color,
+                                    ..default()
+                                },
+                                // The node has no offset from the octree, so this avoids
+                                // drawing it at the origin until transforms propagate.
+                                global_transform: *transform,
+                                ..default()
+                            },
+                            PotreeOctreeNodeIndex(index),
+                        ))
+                        .id();
+                    commands.entity(entity).add_child(node);
+                    streaming.resident.insert(index, node);
+                }
+                Err(err) => {
+                    warn!(
+                        "Failed to load octree node {}: {err}",
+                        asset.nodes[index].name
+                    );
+                    streaming.failed.insert(index);
+                }
+            }
+        }
+
+        // Start loading the missing nodes, most important first.
+        let task_pool = AsyncComputeTaskPool::get();
+        for index in wanted {
+            if streaming.loading.len() >= queue.max_concurrent_loads {
+                break;
+            }
+            let node = &asset.nodes[index];
+            if node.num_points == 0
+                || streaming.resident.contains_key(&index)
+                || streaming.loading.contains_key(&index)
+                || streaming.failed.contains(&index)
+            {
+                continue;
+            }
+            let layout = asset.layout.clone();
+            let (num_points, byte_offset) = (node.num_points, node.byte_offset);
+            streaming.loading.insert(
+                index,
+                task_pool.spawn(async move { layout.read_node(num_points, byte_offset) }),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::mesh::VertexAttributeValues;
+
+    use super::*;
+
+    /// Writes an octree with a single node of `num_points` points to a new directory.
+    /// `attributes` is the attribute list of `metadata.json`.
+    fn octree_dir(name: &str, attributes: &str, num_points: u32, octree: &[u8]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("bevy_potree_octree_{name}_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("metadata.json"),
+            format!(
+                r#"{{
+                    "points": {num_points},
+                    "hierarchy": {{ "firstChunkSize": {HIERARCHY_RECORD_SIZE} }},
+                    "offset": [0, 0, 0],
+                    "scale": [0.5, 0.5, 0.5],
+                    "spacing": 1,
+                    "boundingBox": {{ "min": [0, 0, 0], "max": [4, 4, 4] }},
+                    "attributes": {attributes}
+                }}"#
+            ),
+        )
+        .unwrap();
+        let mut record = vec![0, 0];
+        record.extend_from_slice(&num_points.to_le_bytes());
+        record.extend_from_slice(&0u64.to_le_bytes());
+        record.extend_from_slice(&(octree.len() as u64).to_le_bytes());
+        std::fs::write(dir.join("hierarchy.bin"), record).unwrap();
+        std::fs::write(dir.join("octree.bin"), octree).unwrap();
+        dir
+    }
+
+    const ATTRIBUTES: &str = r#"[
+        { "name": "position", "size": 12, "numElements": 3, "type": "int32" },
+        { "name": "rgb", "size": 6, "numElements": 3, "type": "uint16" },
+        { "name": "intensity", "size": 2, "numElements": 1, "type": "uint16" }
+    ]"#;
+
+    fn point(position: [i32; 3], rgb: [u16; 3], intensity: u16) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for value in position {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in rgb {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&intensity.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn loads_nodes() {
+        let mut octree = point([2, 4, 6], [255, 0, 0], 7);
+        octree.extend(point([0, 0, 0], [0, 65535, 0], 9));
+        let dir = octree_dir("loads", ATTRIBUTES, 2, &octree);
+        let asset = PotreeOctreeAsset::open(&dir).unwrap();
+        let node = asset.load_node(0).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(node.num_points(), 2);
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            node.mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            panic!("missing positions");
+        };
+        assert_eq!(positions[0], [1.0, 3.0, 2.0]);
+        assert_eq!(node.scalar_field("intensity").unwrap(), &[7.0, 9.0]);
+    }
+
+    #[test]
+    fn rejects_attributes_smaller_than_their_type() {
+        let attributes = r#"[
+            { "name": "position", "size": 4, "numElements": 3, "type": "int32" }
+        ]"#;
+        let dir = octree_dir("small", attributes, 1, &[0; 4]);
+        let result = PotreeOctreeAsset::open(&dir);
+        std::fs::remove_dir_all(dir).unwrap();
+
+        assert!(matches!(
+            result,
+            Err(PotreeOctreeError::Io(error)) if error.kind() == io::ErrorKind::InvalidData
+        ));
+    }
+
+    #[test]
+    fn rejects_proxies_to_their_own_chunk() {
+        let mut record = vec![NODE_TYPE_PROXY, 0];
+        record.extend_from_slice(&1u32.to_le_bytes());
+        record.extend_from_slice(&0u64.to_le_bytes());
+        record.extend_from_slice(&(HIERARCHY_RECORD_SIZE as u64).to_le_bytes());
+        let result = parse_hierarchy(&record, HIERARCHY_RECORD_SIZE as u64, DVec3::ONE);
+
+        assert!(matches!(result, Err(PotreeOctreeError::Hierarchy(_))));
+    }
+
+    #[test]
+    fn rejects_nodes_past_the_end_of_the_file() {
+        let dir = octree_dir("past_end", ATTRIBUTES, u32::MAX, &point([0; 3], [0; 3], 0));
+        let asset = PotreeOctreeAsset::open(&dir).unwrap();
+        let result = asset.load_node(0);
+        std::fs::remove_dir_all(dir).unwrap();
+
+        assert!(matches!(result, Err(error) if error.kind() == io::ErrorKind::UnexpectedEof));
+    }
+}
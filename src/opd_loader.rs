@@ -1,36 +1,111 @@
-use crate::PointCloudAsset;
+use crate::{
+    downsample::select_frames, loading::FailedPointCloudLoad, PointCloudAsset, PotreePointCloud,
+    DEFAULT_RADIUS_NEIGHBORS,
+};
 use bevy::{
-    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
-    math::Vec3A,
+    asset::{
+        io::{AssetReaderError, MissingAssetSourceError, Reader},
+        AssetLoader, AssetPath, AsyncReadExt, LoadContext,
+    },
+    math::{DVec3, Vec3A},
     prelude::*,
-    render::render_resource::PrimitiveTopology,
+    render::{mesh::VertexAttributeValues, primitives::Aabb, render_resource::PrimitiveTopology},
+    tasks::{block_on, IoTaskPool, Task},
     utils::{
         thiserror::{self, Error},
         BoxedFuture,
     },
 };
+use opd_parser::{Frame, FrameMeta, Frames, OpdFile, OpdHeader};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct OpdLoaderSettings {
+    /// Size every point by the spacing to its nearest neighbors, see
+    /// [`PointCloudAsset::compute_radii`]. Off by default since it's costly for large clouds.
+    pub compute_radii: bool,
+    /// Keep only one point per cube of this size, see [`PointCloudAsset::voxel_downsample`].
+    pub voxel_size: Option<f32>,
+    /// Shuffle the points, see [`PointCloudAsset::shuffle_points`]. Needed for an even
+    /// [`PointCloudLod`](crate::PointCloudLod).
+    pub shuffle: bool,
+    /// Only keep the points inside this box, in the coordinates of the file, relative to the
+    /// origin in its header. OPD files have no spatial index, so the whole file is still read,
+    /// but cropped points are dropped before anything else is done with them. The asset is
+    /// centered on the remaining points.
+    #[serde(default, with = "serde_crop")]
+    pub crop: Option<Aabb>,
+}
+
+/// [`Aabb`] doesn't implement serde, so crops are stored as their min and max corners.
+mod serde_crop {
+    use bevy::{math::Vec3, render::primitives::Aabb};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(crop: &Option<Aabb>, serializer: S) -> Result<S::Ok, S::Error> {
+        crop.map(|aabb| (Vec3::from(aabb.min()), Vec3::from(aabb.max())))
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Aabb>, D::Error> {
+        let corners = Option::<(Vec3, Vec3)>::deserialize(deserializer)?;
+        Ok(corners.map(|(min, max)| Aabb::from_min_max(min, max)))
+    }
+}
 
 #[derive(Default)]
 pub struct OpdLoader;
 
 impl OpdLoader {
-    pub async fn load_opd(
-        bytes: &[u8],
-    ) -> Result<PointCloudAsset, nom::Err<nom::error::Error<Vec<u8>>>> {
-        let file = opd_parser::parse(bytes).map_err(|e| e.to_owned())?.1;
+    /// See [`PointCloudAsset::from_opd_bytes`], which doesn't need an executor.
+    pub async fn load_opd(bytes: &[u8]) -> Result<PointCloudAsset, OpdLoaderError> {
+        PointCloudAsset::from_opd_bytes(bytes)
+    }
+}
+
+impl PointCloudAsset {
+    /// Parses a whole OPD file from memory, like the [`OpdLoader`] does, without the loader
+    /// settings. Useful for tests and procedural pipelines that don't go through the asset
+    /// server.
+    pub fn from_opd_bytes(bytes: &[u8]) -> Result<Self, OpdLoaderError> {
+        Self::from_cropped_opd_bytes(bytes, None)
+    }
+
+    /// See [`OpdLoaderSettings::crop`].
+    fn from_cropped_opd_bytes(bytes: &[u8], crop: Option<&Aabb>) -> Result<Self, OpdLoaderError> {
+        let mut file = parse_opd(bytes)?;
         let mut positions: Vec<Vec3A> = Vec::new();
 
         let mut max_position = Vec3A::splat(f32::MIN);
         let mut min_position = Vec3A::splat(f32::MAX);
 
-        for i in file.centroids {
-            max_position = max_position.max(i.offset.into());
-            min_position = min_position.min(i.offset.into());
-            positions.push(i.offset.into());
+        let num_centroids = file.centroids.len();
+        let mut kept = Vec::new();
+        for (index, i) in file.centroids.into_iter().enumerate() {
+            let position = Vec3A::from(i.offset);
+            if let Some(crop) = crop {
+                if position.cmplt(crop.min()).any() || position.cmpgt(crop.max()).any() {
+                    continue;
+                }
+                kept.push(index as u32);
+            }
+            max_position = max_position.max(position);
+            min_position = min_position.min(position);
+            positions.push(position);
+        }
+        if positions.len() != num_centroids {
+            select_frames(&mut file.frames, num_centroids, &kept);
         }
 
         let size = max_position - min_position;
-        let position_offset: Vec3A = min_position + size / 2.0;
+        let position_offset: Vec3A = if positions.is_empty() {
+            Vec3A::ZERO
+        } else {
+            min_position + size / 2.0
+        };
         for position in positions.iter_mut() {
             *position -= position_offset;
         }
@@ -39,9 +114,17 @@ impl OpdLoader {
         mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
 
         Ok(PointCloudAsset {
+            aabb: mesh.compute_aabb().unwrap_or_default(),
+            origin: Vec3::from(<[f32; 3]>::from(file.header.directive.origin)).as_dvec3()
+                + Vec3::from(position_offset).as_dvec3(),
             mesh,
-            animation: Some(file.frames),
+            animation: animation(file.frames),
             animation_scale: file.header.directive.scale.into(),
+            scalar_fields: default(),
+            timestamp_origin: 0.0,
+            occlusion: None,
+            pending_points: 0,
+            uploads: default(),
         })
     }
 }
@@ -54,11 +137,125 @@ pub enum OpdLoaderError {
     Io(#[from] std::io::Error),
     #[error("Could not parse Opd: {0}")]
     OpdParseError(#[from] nom::Err<nom::error::Error<Vec<u8>>>),
+    #[error("Could not parse Opd header: {0}")]
+    Header(String),
+    #[error("Not an Opd file")]
+    BadMagic,
+    #[error("Opd file is truncated")]
+    UnexpectedEof,
+    #[error("Unsupported Opd frame precision of {0} bytes")]
+    UnsupportedPrecision(usize),
+    #[error("Could not read Opd file: {0}")]
+    AssetReader(#[from] AssetReaderError),
+    #[error(transparent)]
+    MissingAssetSource(#[from] MissingAssetSourceError),
+}
+
+/// Parses a whole OPD file. The header is validated first, since the parser panics on
+/// headers it doesn't expect.
+fn parse_opd(bytes: &[u8]) -> Result<OpdFile, OpdLoaderError> {
+    const MAGIC: &[u8] = b".opd";
+    if !bytes.starts_with(MAGIC) {
+        return Err(if MAGIC.starts_with(bytes) {
+            OpdLoaderError::UnexpectedEof
+        } else {
+            OpdLoaderError::BadMagic
+        });
+    }
+    let header = bytes
+        .get(4..8)
+        .and_then(|len| {
+            let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+            bytes.get(8..8usize.checked_add(len)?)
+        })
+        .ok_or(OpdLoaderError::UnexpectedEof)?;
+    let header_len = header.len();
+    let header: OpdHeader =
+        serde_json::from_slice(header).map_err(|err| OpdLoaderError::Header(err.to_string()))?;
+    validate_header(&header, bytes.len() - 8 - header_len)?;
+
+    match opd_parser::parse(bytes) {
+        Ok((_, file)) => Ok(file),
+        Err(nom::Err::Error(err) | nom::Err::Failure(err))
+            if err.code == nom::error::ErrorKind::Eof =>
+        {
+            Err(OpdLoaderError::UnexpectedEof)
+        }
+        Err(err) => Err(err.to_owned().into()),
+    }
+}
+
+/// Checks that a file with `header`, followed by `body_len` bytes, holds all its centroids and
+/// frames, and returns the number of centroids.
+fn validate_header(header: &OpdHeader, body_len: usize) -> Result<usize, OpdLoaderError> {
+    let num_centroids = header
+        .directive
+        .num_centroids
+        .ok_or_else(|| OpdLoaderError::Header("missing numCentroids".to_owned()))?;
+    // The parser reads 2, 4 and 8 byte frames too, but only 8 bit frames can be played back.
+    let precision = header.directive.precision;
+    if precision != 1 {
+        return Err(OpdLoaderError::UnsupportedPrecision(precision));
+    }
+    // Frame offsets count from the start of the centroids.
+    let centroids_len = num_centroids
+        .checked_mul(OPD_CENTROID_SIZE)
+        .ok_or(OpdLoaderError::UnexpectedEof)?;
+    if body_len < centroids_len {
+        return Err(OpdLoaderError::UnexpectedEof);
+    }
+    let mut offset = centroids_len;
+    for frame in &header.directive.frames {
+        if frame.offset < offset {
+            return Err(OpdLoaderError::Header(
+                "frame offsets are out of order".to_owned(),
+            ));
+        }
+        offset = frame.offset;
+    }
+    // The length of the last frame isn't stored, but every frame has an offset per centroid.
+    if !header.directive.frames.is_empty() {
+        offset = num_centroids
+            .checked_mul(3 * precision)
+            .and_then(|frame_len| offset.checked_add(frame_len))
+            .ok_or(OpdLoaderError::UnexpectedEof)?;
+    }
+    if body_len < offset {
+        return Err(OpdLoaderError::UnexpectedEof);
+    }
+    Ok(num_centroids)
+}
+
+/// Splits the bytes following the centroids into the 8 bit frames of `frames`, like the
+/// parser does. The last frame takes the rest of the bytes.
+fn split_frames(frames: &[FrameMeta], mut bytes: &[u8]) -> Frames {
+    let mut split = Vec::with_capacity(frames.len());
+    for (index, frame) in frames.iter().enumerate() {
+        let len = frames
+            .get(index + 1)
+            .map_or(bytes.len(), |next| next.offset - frame.offset)
+            .min(bytes.len());
+        let (data, rest) = bytes.split_at(len);
+        split.push(Frame {
+            time: frame.time,
+            data: data.iter().map(|&value| value as i8).collect(),
+        });
+        bytes = rest;
+    }
+    Frames::I8(split)
+}
+
+/// Files without frames are static.
+fn animation(frames: Frames) -> Option<Frames> {
+    match &frames {
+        Frames::I8(frames) if frames.is_empty() => None,
+        _ => Some(frames),
+    }
 }
 
 impl AssetLoader for OpdLoader {
     type Asset = PointCloudAsset;
-    type Settings = ();
+    type Settings = OpdLoaderSettings;
     type Error = OpdLoaderError;
 
     fn extensions(&self) -> &[&str] {
@@ -68,14 +265,553 @@ impl AssetLoader for OpdLoader {
     fn load<'a>(
         &'a self,
         reader: &'a mut Reader,
-        _settings: &'a (),
+        settings: &'a OpdLoaderSettings,
         _load_context: &'a mut LoadContext,
     ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
         Box::pin(async move {
             let mut bytes = Vec::new();
             reader.read_to_end(&mut bytes).await?;
-            let asset = Self::load_opd(bytes.as_slice()).await?;
+            let mut asset =
+                PointCloudAsset::from_cropped_opd_bytes(&bytes, settings.crop.as_ref())?;
+            if let Some(voxel_size) = settings.voxel_size {
+                asset.voxel_downsample(voxel_size);
+            }
+            if settings.shuffle {
+                asset.shuffle_points();
+            }
+            if settings.compute_radii {
+                asset.compute_radii(DEFAULT_RADIUS_NEIGHBORS);
+            }
             Ok(asset)
         })
     }
 }
+
+/// Streams an OPD file into the [`PotreePointCloud`] of this entity. Unlike loading it with
+/// the asset server, the points show up while the file is still being read, and
+/// [`PointCloudAsset::loaded_fraction`] tells how far along it is.
+///
+/// The file is read through the [`AssetServer`], but not loaded as an asset of it. The points
+/// are static until the whole file has been read, since the animation frames follow the points
+/// in the file. The GPU buffer is allocated for all the points up front, and each of the about
+/// [`StreamedOpdLoad::UPDATES`] chunks is written into it on its own. The component is removed
+/// once the file is loaded. Files that fail to load leave the cloud without an asset, in the
+/// [`PointCloudLoadState::Failed`](crate::PointCloudLoadState::Failed) state.
+#[derive(Component, Clone, Debug)]
+pub struct StreamedOpdLoad {
+    pub path: AssetPath<'static>,
+}
+
+impl StreamedOpdLoad {
+    pub const UPDATES: usize = 16;
+    /// Files are never streamed in chunks smaller than this many points.
+    pub const MIN_CHUNK_POINTS: usize = 1 << 16;
+}
+
+/// What the task reading a [`StreamedOpdLoad`] has read so far.
+#[derive(Default)]
+struct OpdStreamProgress {
+    /// The number of points in the file, once the header has been read.
+    num_points: usize,
+    origin: DVec3,
+    /// Positions read since the asset was last updated, relative to `origin`.
+    positions: Vec<[f32; 3]>,
+}
+
+#[derive(Component)]
+pub(crate) struct OpdStream {
+    progress: Arc<Mutex<OpdStreamProgress>>,
+    /// The animation frames and their scale.
+    task: Task<Result<(Frames, Vec3), OpdLoaderError>>,
+    /// Subtracted from all positions, so that the points are centered around the origin.
+    /// Taken from the first chunk, since the bounds of the cloud aren't known in advance.
+    offset: Option<Vec3>,
+    /// Created with the first chunk, since empty buffers can't be bound.
+    asset: Option<Handle<PointCloudAsset>>,
+}
+
+const OPD_CENTROID_SIZE: usize = 16;
+
+/// Reads `len` bytes of `reader` into `buffer`, replacing its contents, and returns them.
+/// The buffer grows as the bytes come in, so that a corrupt length can't allocate more than
+/// the file holds.
+async fn read_bytes<'a>(
+    reader: &mut Reader<'_>,
+    buffer: &'a mut Vec<u8>,
+    len: usize,
+) -> Result<&'a [u8], OpdLoaderError> {
+    buffer.clear();
+    reader.take(len as u64).read_to_end(buffer).await?;
+    if buffer.len() < len {
+        return Err(OpdLoaderError::UnexpectedEof);
+    }
+    Ok(buffer)
+}
+
+async fn read_streamed_opd(
+    reader: &mut Reader<'_>,
+    progress: &Mutex<OpdStreamProgress>,
+) -> Result<(Frames, Vec3), OpdLoaderError> {
+    let mut buffer = Vec::new();
+    let prefix = read_bytes(reader, &mut buffer, 8).await?;
+    if &prefix[..4] != b".opd" {
+        return Err(OpdLoaderError::BadMagic);
+    }
+    let header_len = u32::from_be_bytes(prefix[4..].try_into().unwrap()) as usize;
+    let header: OpdHeader =
+        serde_json::from_slice(read_bytes(reader, &mut buffer, header_len).await?)
+            .map_err(|err| OpdLoaderError::Header(err.to_string()))?;
+    // The length of the file isn't known up front, so the lengths in the header are checked
+    // against it once the frames have been read.
+    let num_points = validate_header(&header, usize::MAX)?;
+    {
+        let mut progress = progress.lock().unwrap();
+        progress.num_points = num_points;
+        let origin = &header.directive.origin;
+        progress.origin = Vec3::new(origin.x, origin.y, origin.z).as_dvec3();
+    }
+
+    let chunk_points =
+        (num_points / StreamedOpdLoad::UPDATES).max(StreamedOpdLoad::MIN_CHUNK_POINTS);
+    let mut read_points = 0;
+    while read_points < num_points {
+        let chunk_len = chunk_points.min(num_points - read_points);
+        let chunk = read_bytes(reader, &mut buffer, chunk_len * OPD_CENTROID_SIZE).await?;
+        // Each centroid is a big endian parent id followed by its position.
+        let positions = chunk.chunks_exact(OPD_CENTROID_SIZE).map(|centroid| {
+            let coordinate =
+                |i: usize| f32::from_be_bytes(centroid[4 + i * 4..8 + i * 4].try_into().unwrap());
+            [coordinate(0), coordinate(1), coordinate(2)]
+        });
+        progress.lock().unwrap().positions.extend(positions);
+        read_points += chunk_len;
+    }
+
+    // The points have been handed over already, only the frames are left to read.
+    buffer.clear();
+    reader.read_to_end(&mut buffer).await?;
+    validate_header(&header, num_points * OPD_CENTROID_SIZE + buffer.len())?;
+    Ok((
+        split_frames(&header.directive.frames, &buffer),
+        header.directive.scale.into(),
+    ))
+}
+
+pub(crate) fn stream_opd_point_clouds(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut assets: ResMut<Assets<PointCloudAsset>>,
+    mut loads: Query<(
+        Entity,
+        &StreamedOpdLoad,
+        &mut PotreePointCloud,
+        Option<&mut OpdStream>,
+    )>,
+) {
+    for (entity, load, mut point_cloud, stream) in &mut loads {
+        let Some(mut stream) = stream else {
+            let progress: Arc<Mutex<OpdStreamProgress>> = default();
+            let task = IoTaskPool::get().spawn({
+                let progress = progress.clone();
+                let asset_server = asset_server.clone();
+                let path = load.path.clone();
+                async move {
+                    let source = asset_server.get_source(path.source().clone())?;
+                    let mut reader = source.reader().read(path.path()).await?;
+                    read_streamed_opd(&mut reader, &progress).await
+                }
+            });
+            commands
+                .entity(entity)
+                .insert(OpdStream {
+                    progress,
+                    task,
+                    offset: None,
+                    asset: None,
+                })
+                .remove::<FailedPointCloudLoad>();
+            continue;
+        };
+        // Checked first, so that no positions are pushed after they have been taken.
+        let finished = stream.task.is_finished();
+        let (positions, num_points, origin) = {
+            let mut progress = stream.progress.lock().unwrap();
+            let positions = std::mem::take(&mut progress.positions);
+            (positions, progress.num_points, progress.origin)
+        };
+        if !positions.is_empty() {
+            let offset = *stream.offset.get_or_insert_with(|| {
+                let aabb = Aabb::enclosing(positions.iter().copied().map(Vec3::from)).unwrap();
+                aabb.center.into()
+            });
+            let positions: Vec<_> = positions
+                .into_iter()
+                .map(|position| (Vec3::from(position) - offset).to_array())
+                .collect();
+            if let Some(asset) = stream
+                .asset
+                .as_ref()
+                .and_then(|asset| assets.get_mut(asset))
+            {
+                let from = asset.num_points();
+                let chunk = Aabb::enclosing(positions.iter().copied().map(Vec3::from)).unwrap();
+                if let Some(VertexAttributeValues::Float32x3(loaded)) =
+                    asset.mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION)
+                {
+                    loaded.extend(positions);
+                }
+                asset.aabb = Aabb::from_min_max(
+                    Vec3::from(asset.aabb.min().min(chunk.min())),
+                    Vec3::from(asset.aabb.max().max(chunk.max())),
+                );
+                asset.pending_points = num_points.saturating_sub(asset.num_points());
+                asset.upload_appended_points(from);
+            } else {
+                let mut mesh = Mesh::new(PrimitiveTopology::PointList);
+                mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+                let mut asset = PointCloudAsset::new(mesh);
+                asset.origin = origin + offset.as_dvec3();
+                asset.pending_points = num_points.saturating_sub(asset.num_points());
+                let asset = assets.add(asset);
+                point_cloud.mesh = asset.clone();
+                stream.asset = Some(asset);
+            }
+        }
+        if !finished {
+            continue;
+        }
+        match block_on(&mut stream.task) {
+            Ok((frames, scale)) => {
+                if let Some(asset) = stream
+                    .asset
+                    .as_ref()
+                    .and_then(|asset| assets.get_mut(asset))
+                {
+                    asset.animation = animation(frames);
+                    asset.animation_scale = scale;
+                    asset.pending_points = 0;
+                    // All the points have been uploaded, but animated assets need the
+                    // buffers for their frames.
+                    if asset.animation.is_none() {
+                        let num_points = asset.num_points();
+                        asset.upload_appended_points(num_points);
+                    }
+                }
+            }
+            Err(err) => {
+                warn!("Failed to stream {}: {err}", load.path);
+                // The points read so far would look like a loaded cloud.
+                point_cloud.mesh = default();
+                commands.entity(entity).insert(FailedPointCloudLoad);
+            }
+        }
+        commands
+            .entity(entity)
+            .remove::<(StreamedOpdLoad, OpdStream)>();
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{ATTRIBUTE_COLOR_PACKED, ATTRIBUTE_COLOR_RGBA16};
+    use bevy::asset::LoadState;
+
+    /// Writes an OPD file with a precision of 1, and frames made of their time in
+    /// milliseconds and one offset per point.
+    pub(crate) fn opd_file(positions: &[[f32; 3]], frames: &[(f32, Vec<[i8; 3]>)]) -> Vec<u8> {
+        opd_file_with_precision(positions, frames, 1)
+    }
+
+    fn opd_file_with_precision(
+        positions: &[[f32; 3]],
+        frames: &[(f32, Vec<[i8; 3]>)],
+        precision: usize,
+    ) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (index, position) in positions.iter().enumerate() {
+            body.extend((index as u32).to_be_bytes());
+            for coordinate in position {
+                body.extend(coordinate.to_be_bytes());
+            }
+        }
+        let mut frame_meta = Vec::new();
+        for (time, offsets) in frames {
+            frame_meta.push(serde_json::json!({ "time": time, "offset": body.len() }));
+            body.extend(offsets.iter().flatten().map(|&offset| offset as u8));
+        }
+        let header = serde_json::json!({
+            "version": "1",
+            "type": "points",
+            "directive": {
+                "version": "1",
+                "meta": { "projectId": "test", "projectName": "test" },
+                "numCentroids": positions.len(),
+                "origin": { "x": 10.0, "y": 20.0, "z": 30.0 },
+                "precision": precision,
+                "scale": [1.0, 1.0, 1.0],
+                "frames": frame_meta,
+            },
+        })
+        .to_string();
+
+        let mut bytes = b".opd".to_vec();
+        bytes.extend((header.len() as u32).to_be_bytes());
+        bytes.extend(header.as_bytes());
+        bytes.extend(body);
+        bytes
+    }
+
+    fn two_points() -> Vec<u8> {
+        opd_file(
+            &[[0.0, 0.0, 0.0], [2.0, 4.0, 6.0]],
+            &[(100.0, vec![[1, 2, 3], [4, 5, 6]])],
+        )
+    }
+
+    #[test]
+    fn loads_points_and_frames() {
+        let asset = PointCloudAsset::from_opd_bytes(&two_points()).unwrap();
+        assert_eq!(asset.num_points(), 2);
+        assert_eq!(
+            Vec3::from(asset.aabb.half_extents),
+            Vec3::new(1.0, 2.0, 3.0)
+        );
+        assert_eq!(asset.origin, DVec3::new(11.0, 22.0, 33.0));
+        let Some(Frames::I8(frames)) = &asset.animation else {
+            panic!("expected 8 bit frames");
+        };
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].data, [1, 2, 3, 4, 5, 6]);
+        // OPD files have no colors, the points are drawn with the fallback color.
+        assert!(asset.mesh.attribute(ATTRIBUTE_COLOR_PACKED).is_none());
+        assert!(asset.mesh.attribute(ATTRIBUTE_COLOR_RGBA16).is_none());
+    }
+
+    #[test]
+    fn loads_many_files_at_once() {
+        const FILES: usize = 50;
+        let dir = std::env::temp_dir().join(format!("bevy_potree_opd_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for file in 0..FILES {
+            let num_points = file + 1;
+            let positions: Vec<[f32; 3]> = (0..num_points).map(|i| [i as f32, 0.0, 0.0]).collect();
+            let offsets = vec![[1, 1, 1]; num_points];
+            std::fs::write(
+                dir.join(format!("{file}.opd")),
+                opd_file(&positions, &[(100.0, offsets)]),
+            )
+            .unwrap();
+        }
+
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            AssetPlugin {
+                file_path: dir.to_string_lossy().into_owned(),
+                ..default()
+            },
+        ))
+        .init_asset::<PointCloudAsset>()
+        .init_asset_loader::<OpdLoader>();
+        let handles: Vec<Handle<PointCloudAsset>> = (0..FILES)
+            .map(|file| {
+                app.world
+                    .resource::<AssetServer>()
+                    .load(format!("{file}.opd"))
+            })
+            .collect();
+        let started = std::time::Instant::now();
+        while handles.iter().any(|handle| {
+            app.world.resource::<AssetServer>().load_state(handle) == LoadState::Loading
+        }) {
+            assert!(started.elapsed().as_secs() < 60, "loading timed out");
+            app.update();
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let assets = app.world.resource::<Assets<PointCloudAsset>>();
+        for (file, handle) in handles.iter().enumerate() {
+            let asset = assets.get(handle).expect("the file failed to load");
+            assert_eq!(asset.num_points(), file + 1);
+        }
+    }
+
+    #[test]
+    fn files_without_frames_are_static() {
+        let asset = PointCloudAsset::from_opd_bytes(&opd_file(&[[1.0, 2.0, 3.0]], &[])).unwrap();
+        assert_eq!(asset.num_points(), 1);
+        assert!(asset.animation.is_none());
+    }
+
+    #[test]
+    fn truncated_files_are_unexpected_eof() {
+        let file = two_points();
+        for len in 0..file.len() {
+            let result = PointCloudAsset::from_opd_bytes(&file[..len]);
+            assert!(
+                matches!(result, Err(OpdLoaderError::UnexpectedEof)),
+                "truncated to {len} bytes: {:?}",
+                result.err()
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(matches!(
+            PointCloudAsset::from_opd_bytes(b"ply\nformat ascii 1.0\n"),
+            Err(OpdLoaderError::BadMagic)
+        ));
+
+        let mut file = two_points();
+        file[8] = b'[';
+        assert!(matches!(
+            PointCloudAsset::from_opd_bytes(&file),
+            Err(OpdLoaderError::Header(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_frames_that_cant_be_played_back() {
+        let file = opd_file_with_precision(&[[0.0; 3]], &[(100.0, vec![[1, 2, 3]])], 2);
+        assert!(matches!(
+            PointCloudAsset::from_opd_bytes(&file),
+            Err(OpdLoaderError::UnsupportedPrecision(2))
+        ));
+    }
+
+    #[test]
+    fn rejects_more_centroids_than_the_file_holds() {
+        let file = two_points();
+        let header_len = u32::from_be_bytes(file[4..8].try_into().unwrap()) as usize;
+        let header = std::str::from_utf8(&file[8..8 + header_len]).unwrap();
+        for num_centroids in [3, usize::MAX / 2] {
+            let header = header.replace(
+                "\"numCentroids\":2",
+                &format!("\"numCentroids\":{num_centroids}"),
+            );
+            let mut bytes = b".opd".to_vec();
+            bytes.extend((header.len() as u32).to_be_bytes());
+            bytes.extend(header.as_bytes());
+            bytes.extend(&file[8 + header_len..]);
+            assert!(matches!(
+                PointCloudAsset::from_opd_bytes(&bytes),
+                Err(OpdLoaderError::UnexpectedEof)
+            ));
+        }
+    }
+
+    /// Streams `file` from memory, and returns what was read.
+    fn stream(file: &[u8]) -> (OpdStreamProgress, Result<(Frames, Vec3), OpdLoaderError>) {
+        let progress = Mutex::default();
+        let mut reader = file;
+        let result = block_on(read_streamed_opd(&mut reader, &progress));
+        (progress.into_inner().unwrap(), result)
+    }
+
+    #[test]
+    fn streams_points_and_frames() {
+        let file = opd_file(
+            &[[0.0, 0.0, 0.0], [2.0, 4.0, 6.0]],
+            &[
+                (100.0, vec![[1, 2, 3], [4, 5, 6]]),
+                (200.0, vec![[-1, -2, -3], [-4, -5, -6]]),
+            ],
+        );
+        let (progress, result) = stream(&file);
+        let (Frames::I8(frames), scale) = result.unwrap() else {
+            panic!("expected 8 bit frames");
+        };
+        assert_eq!(progress.num_points, 2);
+        assert_eq!(progress.origin, DVec3::new(10.0, 20.0, 30.0));
+        assert_eq!(progress.positions, [[0.0, 0.0, 0.0], [2.0, 4.0, 6.0]]);
+        assert_eq!(scale, Vec3::ONE);
+
+        let Some(Frames::I8(loaded)) = PointCloudAsset::from_opd_bytes(&file).unwrap().animation
+        else {
+            panic!("expected 8 bit frames");
+        };
+        assert_eq!(frames.len(), loaded.len());
+        for (frame, loaded) in frames.iter().zip(&loaded) {
+            assert_eq!(frame.time, loaded.time);
+            assert_eq!(frame.data, loaded.data);
+        }
+    }
+
+    #[test]
+    fn streaming_rejects_lengths_past_the_end_of_the_file() {
+        let mut file = two_points();
+        file[4..8].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(
+            stream(&file).1,
+            Err(OpdLoaderError::UnexpectedEof)
+        ));
+
+        let file = two_points();
+        let (_, result) = stream(&file[..file.len() - 1]);
+        assert!(matches!(result, Err(OpdLoaderError::UnexpectedEof)));
+    }
+
+    #[test]
+    fn streamed_clouds_become_ready_or_failed() {
+        let dir =
+            std::env::temp_dir().join(format!("bevy_potree_opd_stream_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("points.opd"), two_points()).unwrap();
+        let file = two_points();
+        std::fs::write(dir.join("truncated.opd"), &file[..file.len() - 1]).unwrap();
+
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            AssetPlugin {
+                file_path: dir.to_string_lossy().into_owned(),
+                ..default()
+            },
+        ))
+        .init_asset::<PointCloudAsset>()
+        .add_event::<crate::PointCloudReady>()
+        .add_systems(
+            Update,
+            (
+                stream_opd_point_clouds,
+                crate::loading::update_point_cloud_load_states,
+            )
+                .chain(),
+        );
+        let spawn = |app: &mut App, path: &'static str| {
+            app.world
+                .spawn((
+                    PotreePointCloud::default(),
+                    StreamedOpdLoad { path: path.into() },
+                ))
+                .id()
+        };
+        let points = spawn(&mut app, "points.opd");
+        let truncated = spawn(&mut app, "truncated.opd");
+        let missing = spawn(&mut app, "missing.opd");
+        let started = std::time::Instant::now();
+        while app
+            .world
+            .query_filtered::<(), With<StreamedOpdLoad>>()
+            .iter(&app.world)
+            .next()
+            .is_some()
+        {
+            assert!(started.elapsed().as_secs() < 60, "streaming timed out");
+            app.update();
+        }
+        app.update();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let state = |entity| *app.world.get::<crate::PointCloudLoadState>(entity).unwrap();
+        assert_eq!(state(points), crate::PointCloudLoadState::Ready);
+        assert_eq!(state(truncated), crate::PointCloudLoadState::Failed);
+        assert_eq!(state(missing), crate::PointCloudLoadState::Failed);
+        let mesh = &app.world.get::<PotreePointCloud>(points).unwrap().mesh;
+        let asset = app.world.resource::<Assets<PointCloudAsset>>().get(mesh);
+        assert_eq!(asset.unwrap().num_points(), 2);
+        assert_eq!(asset.unwrap().loaded_fraction(), 1.0);
+    }
+}
@@ -0,0 +1,561 @@
+use std::collections::BTreeMap;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    math::DVec3,
+    prelude::*,
+    render::render_resource::PrimitiveTopology,
+    utils::{
+        thiserror::{self, Error},
+        BoxedFuture,
+    },
+};
+
+use crate::{color::pack_rgba8, PointCloudAsset, ATTRIBUTE_COLOR_PACKED};
+
+/// Loads `.pcd` files written by PCL and ROS, with `ascii`, `binary` or `binary_compressed`
+/// data.
+///
+/// The `x`/`y`/`z` fields become the positions and a packed `rgb` or `rgba` field the point
+/// colors, and `normal_x`/`normal_y`/`normal_z` the point normals. Every other single valued
+/// field becomes a scalar field of the same name. Points
+/// with non-finite positions, like the holes of organized clouds, are skipped. The points are
+/// recentered, with the center kept in [`PointCloudAsset::origin`].
+#[derive(Default)]
+pub struct PcdLoader;
+
+/// Possible errors that can be produced by [`PcdLoader`]
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum PcdLoaderError {
+    #[error("Could not load asset: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid PCD header: {0}")]
+    Header(String),
+    #[error("Invalid PCD data: {0}")]
+    Data(String),
+    #[error("PCD file has no x, y and z fields")]
+    MissingPositions,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum DataFormat {
+    Ascii,
+    Binary,
+    BinaryCompressed,
+}
+
+#[derive(Clone, Copy)]
+enum ScalarType {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn parse(ty: &str, size: usize) -> Option<Self> {
+        Some(match (ty, size) {
+            ("I", 1) => Self::I8,
+            ("I", 2) => Self::I16,
+            ("I", 4) => Self::I32,
+            ("I", 8) => Self::I64,
+            ("U", 1) => Self::U8,
+            ("U", 2) => Self::U16,
+            ("U", 4) => Self::U32,
+            ("U", 8) => Self::U64,
+            ("F", 4) => Self::F32,
+            ("F", 8) => Self::F64,
+            _ => return None,
+        })
+    }
+
+    fn read(self, bytes: &[u8]) -> f64 {
+        macro_rules! read {
+            ($ty:ty) => {
+                <$ty>::from_le_bytes(bytes[..std::mem::size_of::<$ty>()].try_into().unwrap()) as f64
+            };
+        }
+        match self {
+            Self::I8 => read!(i8),
+            Self::I16 => read!(i16),
+            Self::I32 => read!(i32),
+            Self::I64 => read!(i64),
+            Self::U8 => read!(u8),
+            Self::U16 => read!(u16),
+            Self::U32 => read!(u32),
+            Self::U64 => read!(u64),
+            Self::F32 => read!(f32),
+            Self::F64 => read!(f64),
+        }
+    }
+}
+
+struct Field {
+    name: String,
+    ty: ScalarType,
+    size: usize,
+    count: usize,
+}
+
+impl Field {
+    /// Packed colors are stored in the bits of a float or unsigned int.
+    fn is_packed_color(&self) -> bool {
+        matches!(self.name.as_str(), "rgb" | "rgba")
+            && self.count == 1
+            && matches!(self.ty, ScalarType::F32 | ScalarType::U32)
+    }
+}
+
+struct Header {
+    fields: Vec<Field>,
+    points: usize,
+    format: DataFormat,
+}
+
+impl Header {
+    /// Parses the header, and returns it with the length of the header in bytes.
+    fn parse(bytes: &[u8]) -> Result<(Self, usize), PcdLoaderError> {
+        let header_error = |message: &str| PcdLoaderError::Header(message.to_owned());
+        let mut names: Vec<String> = Vec::new();
+        let mut sizes: Vec<usize> = Vec::new();
+        let mut types: Vec<String> = Vec::new();
+        let mut counts: Option<Vec<usize>> = None;
+        let mut width = None;
+        let mut height = 1;
+        let mut points = None;
+        let mut offset = 0;
+        loop {
+            let end = bytes[offset..]
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map(|position| offset + position + 1)
+                .ok_or_else(|| header_error("missing DATA line"))?;
+            let line = std::str::from_utf8(&bytes[offset..end])
+                .map_err(|_| header_error("header is not valid UTF-8"))?;
+            offset = end;
+            let mut words = line.split_whitespace();
+            let Some(keyword) = words.next() else {
+                continue;
+            };
+            let parse_numbers = |words: std::str::SplitWhitespace| {
+                words
+                    .map(|word| word.parse::<usize>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| PcdLoaderError::Header(format!("invalid {keyword} line")))
+            };
+            let parse_number = |words: std::str::SplitWhitespace| {
+                parse_numbers(words)?
+                    .first()
+                    .copied()
+                    .ok_or_else(|| PcdLoaderError::Header(format!("invalid {keyword} line")))
+            };
+            match keyword {
+                keyword if keyword.starts_with('#') => {}
+                "FIELDS" => names = words.map(str::to_owned).collect(),
+                "SIZE" => sizes = parse_numbers(words)?,
+                "TYPE" => types = words.map(str::to_owned).collect(),
+                "COUNT" => {
+                    let line_counts = parse_numbers(words)?;
+                    if line_counts.contains(&0) {
+                        return Err(header_error("COUNT of 0"));
+                    }
+                    counts = Some(line_counts);
+                }
+                "WIDTH" => width = Some(parse_number(words)?),
+                "HEIGHT" => height = parse_number(words)?,
+                "POINTS" => points = Some(parse_number(words)?),
+                "DATA" => {
+                    let format = match words.next() {
+                        Some("ascii") => DataFormat::Ascii,
+                        Some("binary") => DataFormat::Binary,
+                        Some("binary_compressed") => DataFormat::BinaryCompressed,
+                        _ => return Err(header_error("unsupported DATA format")),
+                    };
+                    let counts = counts.unwrap_or_else(|| vec![1; names.len()]);
+                    if sizes.len() != names.len()
+                        || types.len() != names.len()
+                        || counts.len() != names.len()
+                    {
+                        return Err(header_error(
+                            "FIELDS, SIZE, TYPE and COUNT have different lengths",
+                        ));
+                    }
+                    let fields = names
+                        .into_iter()
+                        .zip(sizes)
+                        .zip(types)
+                        .zip(counts)
+                        .map(|(((name, size), ty), count)| {
+                            let ty = ScalarType::parse(&ty, size).ok_or_else(|| {
+                                PcdLoaderError::Header(format!("unsupported type of {name}"))
+                            })?;
+                            Ok(Field {
+                                name,
+                                ty,
+                                size,
+                                count,
+                            })
+                        })
+                        .collect::<Result<Vec<_>, PcdLoaderError>>()?;
+                    let points = points
+                        .or(width.map(|width| width * height))
+                        .ok_or_else(|| header_error("missing POINTS"))?;
+                    return Ok((
+                        Self {
+                            fields,
+                            points,
+                            format,
+                        },
+                        offset,
+                    ));
+                }
+                // VERSION and VIEWPOINT
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Reads the values of the point at an index.
+type PointReader<'a> = dyn Fn(usize, &mut [f64]) -> Result<(), PcdLoaderError> + 'a;
+
+/// The most bytes LZF expands a byte of input to: a 2 byte back reference copies up to 264.
+const MAX_LZF_EXPANSION: usize = 132;
+
+/// Decompresses LZF data, as used by `binary_compressed` PCD files.
+fn lzf_decompress(input: &[u8], output_len: usize) -> Option<Vec<u8>> {
+    if output_len > input.len().saturating_mul(MAX_LZF_EXPANSION) {
+        return None;
+    }
+    let mut output = Vec::with_capacity(output_len);
+    let mut i = 0;
+    while i < input.len() {
+        let control = input[i] as usize;
+        i += 1;
+        if control < 32 {
+            // A run of literal bytes.
+            let len = control + 1;
+            output.extend_from_slice(input.get(i..i + len)?);
+            i += len;
+        } else {
+            // A back reference into the output.
+            let mut len = control >> 5;
+            if len == 7 {
+                len += *input.get(i)? as usize;
+                i += 1;
+            }
+            let distance = ((control & 0x1f) << 8) + *input.get(i)? as usize + 1;
+            i += 1;
+            let start = output.len().checked_sub(distance)?;
+            for j in 0..len + 2 {
+                output.push(output[start + j]);
+            }
+        }
+        if output.len() > output_len {
+            return None;
+        }
+    }
+    (output.len() == output_len).then_some(output)
+}
+
+impl PcdLoader {
+    pub fn load_pcd(bytes: &[u8]) -> Result<PointCloudAsset, PcdLoaderError> {
+        let data_error = |message: &str| PcdLoaderError::Data(message.to_owned());
+        let (header, header_len) = Header::parse(bytes)?;
+        let body = &bytes[header_len..];
+        let field_index = |name: &str| header.fields.iter().position(|field| field.name == name);
+        let (Some(x), Some(y), Some(z)) = (field_index("x"), field_index("y"), field_index("z"))
+        else {
+            return Err(PcdLoaderError::MissingPositions);
+        };
+        let color = header
+            .fields
+            .iter()
+            .position(|field| field.is_packed_color());
+        let normal = match [
+            field_index("normal_x"),
+            field_index("normal_y"),
+            field_index("normal_z"),
+        ] {
+            [Some(x), Some(y), Some(z)] => Some([x, y, z]),
+            _ => None,
+        };
+        let is_scalar_field = |index: usize| {
+            index != x
+                && index != y
+                && index != z
+                && Some(index) != color
+                && !normal.is_some_and(|normal| normal.contains(&index))
+                && header.fields[index].count == 1
+        };
+
+        // Reads the first element of every field of a point. Packed colors are read as their
+        // raw bits.
+        let point_len = header
+            .fields
+            .iter()
+            .try_fold(0usize, |len, field| {
+                len.checked_add(field.size.checked_mul(field.count)?)
+            })
+            .ok_or_else(|| data_error("fields are too large"))?;
+        let data_len = header
+            .points
+            .checked_mul(point_len)
+            .ok_or_else(|| data_error("too many points"))?;
+        let decompressed;
+        let lines: Vec<&str>;
+        // Also returns how many points the data can hold at most, to allocate for them.
+        let (read_point, max_points) = match header.format {
+            DataFormat::Ascii => {
+                lines = std::str::from_utf8(body)
+                    .map_err(|_| data_error("data is not valid UTF-8"))?
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .collect();
+                let read_point: Box<PointReader> = Box::new(|point, values| {
+                    let line = lines
+                        .get(point)
+                        .ok_or_else(|| data_error("unexpected end of file"))?;
+                    let mut words = line.split_whitespace();
+                    for (value, field) in values.iter_mut().zip(&header.fields) {
+                        let word = words.next().ok_or_else(|| data_error("missing value"))?;
+                        *value = if field.is_packed_color() {
+                            let bits = match field.ty {
+                                ScalarType::F32 => word.parse::<f32>().ok().map(f32::to_bits),
+                                _ => word.parse::<u32>().ok(),
+                            };
+                            bits.ok_or_else(|| data_error("invalid color"))? as f64
+                        } else {
+                            word.parse::<f64>()
+                                .map_err(|_| data_error("invalid number"))?
+                        };
+                        // Skip the remaining elements of the field.
+                        for _ in 1..field.count {
+                            words.next();
+                        }
+                    }
+                    Ok(())
+                });
+                (read_point, lines.len())
+            }
+            DataFormat::Binary => {
+                if body.len() < data_len {
+                    return Err(data_error("unexpected end of file"));
+                }
+                let read_point: Box<PointReader> = Box::new(|point, values| {
+                    let mut offset = point * point_len;
+                    for (value, field) in values.iter_mut().zip(&header.fields) {
+                        *value = read_value(field, &body[offset..]);
+                        offset += field.size * field.count;
+                    }
+                    Ok(())
+                });
+                (read_point, body.len() / point_len)
+            }
+            DataFormat::BinaryCompressed => {
+                let sizes = body
+                    .get(..8)
+                    .ok_or_else(|| data_error("unexpected end of file"))?;
+                let compressed_len = u32::from_le_bytes(sizes[..4].try_into().unwrap());
+                let len = u32::from_le_bytes(sizes[4..].try_into().unwrap()) as usize;
+                let compressed = body
+                    .get(8..8 + compressed_len as usize)
+                    .ok_or_else(|| data_error("unexpected end of file"))?;
+                decompressed = lzf_decompress(compressed, len)
+                    .ok_or_else(|| data_error("invalid compressed data"))?;
+                if decompressed.len() < data_len {
+                    return Err(data_error("not enough decompressed data"));
+                }
+                // The fields are stored one after another rather than interleaved.
+                let read_point: Box<PointReader> = Box::new(|point, values| {
+                    let mut field_offset = 0;
+                    for (value, field) in values.iter_mut().zip(&header.fields) {
+                        let stride = field.size * field.count;
+                        *value = read_value(field, &decompressed[field_offset + point * stride..]);
+                        field_offset += header.points * stride;
+                    }
+                    Ok(())
+                });
+                (read_point, decompressed.len() / point_len)
+            }
+        };
+
+        let mut positions = Vec::with_capacity(header.points.min(max_points));
+        let mut colors = Vec::new();
+        let mut normals = Vec::new();
+        let mut scalar_fields: BTreeMap<String, Vec<f32>> = (0..header.fields.len())
+            .filter(|&index| is_scalar_field(index))
+            .map(|index| (header.fields[index].name.clone(), Vec::new()))
+            .collect();
+        let mut values = vec![0.0; header.fields.len()];
+        for point in 0..header.points {
+            read_point(point, &mut values)?;
+            let position = DVec3::new(values[x], values[y], values[z]);
+            if !position.is_finite() {
+                continue;
+            }
+            positions.push(position);
+            if let Some(color) = color {
+                let [b, g, r, _] = (values[color] as u32).to_le_bytes();
+                colors.push(pack_rgba8([r, g, b, u8::MAX]));
+            }
+            if let Some(normal) = normal {
+                normals.push(normal.map(|index| values[index] as f32));
+            }
+            for (index, field) in header.fields.iter().enumerate() {
+                if is_scalar_field(index) {
+                    scalar_fields
+                        .get_mut(&field.name)
+                        .unwrap()
+                        .push(values[index] as f32);
+                }
+            }
+        }
+
+        // Positions can be far from the origin, so center them in double precision.
+        let (min, max) = positions.iter().fold(
+            (DVec3::splat(f64::MAX), DVec3::splat(f64::MIN)),
+            |(min, max), &position| (min.min(position), max.max(position)),
+        );
+        let origin = if positions.is_empty() {
+            DVec3::ZERO
+        } else {
+            (min + max) / 2.0
+        };
+        let positions: Vec<Vec3> = positions
+            .into_iter()
+            .map(|position| (position - origin).as_vec3())
+            .collect();
+
+        let mut mesh = Mesh::new(PrimitiveTopology::PointList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        if color.is_some() {
+            mesh.insert_attribute(ATTRIBUTE_COLOR_PACKED, colors);
+        }
+        if normal.is_some() {
+            mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        }
+        let mut asset = PointCloudAsset::new(mesh);
+        asset.origin = origin;
+        asset.scalar_fields = scalar_fields;
+        Ok(asset)
+    }
+}
+
+/// Reads the first element of `field` from the start of `bytes`. Packed colors are read as
+/// their raw bits.
+fn read_value(field: &Field, bytes: &[u8]) -> f64 {
+    if field.is_packed_color() {
+        u32::from_le_bytes(bytes[..4].try_into().unwrap()) as f64
+    } else {
+        field.ty.read(bytes)
+    }
+}
+
+impl AssetLoader for PcdLoader {
+    type Asset = PointCloudAsset;
+    type Settings = ();
+    type Error = PcdLoaderError;
+
+    fn extensions(&self) -> &[&str] {
+        &["pcd"]
+    }
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            Self::load_pcd(&bytes)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(fields: &str, count: &str, points: usize, data: &str) -> Vec<u8> {
+        let n = fields.split_whitespace().count();
+        format!(
+            "# .PCD v0.7\nVERSION 0.7\nFIELDS {fields}\nSIZE {}\nTYPE {}\nCOUNT {count}\n\
+             WIDTH {points}\nHEIGHT 1\nPOINTS {points}\nDATA {data}\n",
+            vec!["4"; n].join(" "),
+            vec!["F"; n].join(" "),
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn loads_ascii_and_binary_points() {
+        let mut ascii = header("x y z intensity", "1 1 1 1", 2, "ascii");
+        ascii.extend(b"0 0 0 1\n2 4 6 3\n");
+        let mut binary = header("x y z intensity", "1 1 1 1", 2, "binary");
+        for value in [0.0f32, 0.0, 0.0, 1.0, 2.0, 4.0, 6.0, 3.0] {
+            binary.extend(value.to_le_bytes());
+        }
+        for file in [ascii, binary] {
+            let asset = PcdLoader::load_pcd(&file).unwrap();
+            assert_eq!(asset.num_points(), 2);
+            assert_eq!(asset.origin, DVec3::new(1.0, 2.0, 3.0));
+            assert_eq!(asset.scalar_fields["intensity"], [1.0, 3.0]);
+        }
+    }
+
+    #[test]
+    fn rejects_count_of_zero() {
+        let mut file = header("x y z", "1 0 1", 1, "ascii");
+        file.extend(b"0 0 0\n");
+        assert!(matches!(
+            PcdLoader::load_pcd(&file),
+            Err(PcdLoaderError::Header(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_more_points_than_the_data_holds() {
+        for (points, data) in [
+            (usize::MAX / 4, "binary"),
+            (usize::MAX, "binary"),
+            (1 << 40, "ascii"),
+            (1 << 40, "binary_compressed"),
+        ] {
+            let mut file = header("x y z", "1 1 1", points, data);
+            file.extend([0; 64]);
+            assert!(
+                matches!(PcdLoader::load_pcd(&file), Err(PcdLoaderError::Data(_))),
+                "{points} {data} points"
+            );
+        }
+
+        // Fields so large that a single point overflows.
+        let mut file = header("x y z", &format!("1 1 {}", usize::MAX / 2), 1, "binary");
+        file.extend([0; 64]);
+        assert!(matches!(
+            PcdLoader::load_pcd(&file),
+            Err(PcdLoaderError::Data(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_compressed_data_that_cant_expand_to_its_length() {
+        // 1 byte of compressed data claiming to expand to 4 GiB.
+        assert!(lzf_decompress(&[0], u32::MAX as usize).is_none());
+        // A literal run of 3 bytes, and a back reference repeating them.
+        assert_eq!(
+            lzf_decompress(&[2, 1, 2, 3, 0x20, 2], 6).unwrap(),
+            [1, 2, 3, 1, 2, 3]
+        );
+    }
+}
@@ -0,0 +1,267 @@
+use std::sync::{Arc, Mutex};
+
+use bevy::{
+    ecs::system::SystemParam, math::Ray, prelude::*, render::mesh::VertexAttributeValues,
+    utils::HashMap,
+};
+
+use crate::{
+    clippling_planes::{GpuClippingPlaneRange, MAX_CLIPPING_PLANES},
+    radii::KdTree,
+    ClippingPlaneRange, PointCloudAsset, PotreePointCloud,
+};
+
+/// Finds the points of visible [`PotreePointCloud`]s under a ray or the cursor, on the CPU.
+///
+/// Hits are `(entity, point index, world position)`. The index is in the current order of the
+/// asset's points, which [`PointCloudAsset::compute_occlusion`] changes. Points removed by
+/// clipping planes are skipped, and animated clouds are tested with their unanimated
+/// positions.
+///
+/// The first query of an asset builds a kd-tree of its points in O(n log n), which is kept until
+/// the asset is modified. Queries then only test the points near the ray, so they are cheap
+/// enough to run every frame, like for hover highlights. Assets modified in the same frame are
+/// only picked up by the tree once their [`AssetEvent`] was sent, unless their number of points
+/// changed.
+#[derive(SystemParam)]
+pub struct PointCloudRaycast<'w, 's> {
+    assets: Res<'w, Assets<PointCloudAsset>>,
+    trees: Res<'w, PointCloudPickingTrees>,
+    point_clouds: Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static PotreePointCloud,
+            &'static GlobalTransform,
+            &'static InheritedVisibility,
+        ),
+    >,
+    cameras: Query<'w, 's, (&'static Camera, &'static GlobalTransform)>,
+    clipping_planes: Query<'w, 's, (&'static ClippingPlaneRange, &'static GlobalTransform)>,
+}
+
+impl<'w, 's> PointCloudRaycast<'w, 's> {
+    /// The point closest to the ray's origin among those at most `radius` away from the ray.
+    pub fn cast_ray(&self, ray: Ray, radius: f32) -> Option<(Entity, u32, Vec3)> {
+        let radius_squared = radius * radius;
+        // A square prism around the ray, in front of its origin.
+        let side = ray.direction.any_orthonormal_vector();
+        let up = ray.direction.cross(side);
+        let plane = |normal: Vec3, offset: f32| normal.extend(offset - normal.dot(ray.origin));
+        let region = [
+            plane(ray.direction, 0.0),
+            plane(side, radius),
+            plane(-side, radius),
+            plane(up, radius),
+            plane(-up, radius),
+        ];
+        self.closest_point(ray.origin, &region, |position| {
+            let along = (position - ray.origin).dot(ray.direction);
+            along >= 0.0
+                && (position - ray.origin).length_squared() - along * along <= radius_squared
+        })
+    }
+
+    /// The point closest to `camera` among those within `pixel_tolerance` logical pixels of
+    /// `viewport_position`, for example the cursor position of
+    /// [`Camera::viewport_to_world`].
+    pub fn cast_from_camera(
+        &self,
+        camera: Entity,
+        viewport_position: Vec2,
+        pixel_tolerance: f32,
+    ) -> Option<(Entity, u32, Vec3)> {
+        let (camera, camera_transform) = self.cameras.get(camera).ok()?;
+        let viewport_size = camera.logical_viewport_size()?;
+        let view_proj = camera.projection_matrix() * camera_transform.compute_matrix().inverse();
+        let tolerance_squared = pixel_tolerance * pixel_tolerance;
+        // The part of the view frustum within the tolerance, as planes in clip space.
+        let [x, y, z, w] = [0, 1, 2, 3].map(|row| view_proj.row(row));
+        let ndc_min = (viewport_position - pixel_tolerance) / viewport_size * 2.0 - 1.0;
+        let ndc_max = (viewport_position + pixel_tolerance) / viewport_size * 2.0 - 1.0;
+        let region = [
+            w,
+            z,
+            w - z,
+            x - ndc_min.x * w,
+            ndc_max.x * w - x,
+            // The viewport's y points down.
+            -ndc_min.y * w - y,
+            y + ndc_max.y * w,
+        ];
+        self.closest_point(camera_transform.translation(), &region, |position| {
+            let clip = view_proj * position.extend(1.0);
+            if clip.w <= 0.0 {
+                return false;
+            }
+            let ndc = clip.truncate() / clip.w;
+            if !(0.0..=1.0).contains(&ndc.z) {
+                return false;
+            }
+            let pixel = Vec2::new(ndc.x + 1.0, 1.0 - ndc.y) * 0.5 * viewport_size;
+            pixel.distance_squared(viewport_position) <= tolerance_squared
+        })
+    }
+
+    /// The hit point that passes `is_hit` and is closest to `origin`. Only the points inside
+    /// all the `region` planes, in world space, are tested, so it must hold every hit.
+    fn closest_point(
+        &self,
+        origin: Vec3,
+        region: &[Vec4],
+        is_hit: impl Fn(Vec3) -> bool,
+    ) -> Option<(Entity, u32, Vec3)> {
+        let planes: Vec<GpuClippingPlaneRange> = self
+            .clipping_planes
+            .iter()
+            .take(MAX_CLIPPING_PLANES)
+            .map(|(range, transform)| GpuClippingPlaneRange::new(range, transform))
+            .collect();
+        let mut closest: Option<(f32, (Entity, u32, Vec3))> = None;
+        for (entity, point_cloud, transform, visibility) in &self.point_clouds {
+            if !visibility.get() {
+                continue;
+            }
+            let Some(asset) = self.assets.get(&point_cloud.mesh) else {
+                continue;
+            };
+            let Some(VertexAttributeValues::Float32x3(positions)) =
+                asset.mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+            else {
+                continue;
+            };
+            let tree = self.trees.get(point_cloud.mesh.id(), positions);
+            let kd_tree = KdTree::from_indices(positions, &tree.indices);
+            // Planes are moved into the asset's space by the transpose of its transform.
+            let to_local = transform.compute_matrix().transpose();
+            let region: Vec<Vec4> = region.iter().map(|&plane| to_local * plane).collect();
+            let affine = transform.affine();
+            kd_tree.visit(
+                &tree.indices,
+                0,
+                tree.bounds,
+                &|min, max| {
+                    region.iter().all(|plane| {
+                        let normal = plane.truncate();
+                        let corner = Vec3::select(normal.cmpgt(Vec3::ZERO), max, min);
+                        normal.dot(corner) + plane.w >= 0.0
+                    })
+                },
+                &mut |index| {
+                    let position = affine.transform_point3(Vec3::from(positions[index as usize]));
+                    let distance = position.distance_squared(origin);
+                    if closest.is_some_and(|(closest, _)| closest <= distance)
+                        || !is_hit(position)
+                        || !planes.iter().all(|plane| plane.contains(position))
+                    {
+                        return;
+                    }
+                    closest = Some((distance, (entity, index, position)));
+                },
+            );
+        }
+        closest.map(|(_, hit)| hit)
+    }
+}
+
+/// The kd-trees of the assets queried by [`PointCloudRaycast`].
+#[derive(Resource, Default)]
+pub(crate) struct PointCloudPickingTrees(
+    Mutex<HashMap<AssetId<PointCloudAsset>, Arc<PickingTree>>>,
+);
+
+pub(crate) struct PickingTree {
+    /// See [`KdTree::from_indices`].
+    indices: Vec<u32>,
+    /// The bounds of the positions the tree was built from, for [`KdTree::visit`].
+    bounds: (Vec3, Vec3),
+}
+
+impl PointCloudPickingTrees {
+    /// The tree of the asset, built if it has none yet.
+    fn get(&self, id: AssetId<PointCloudAsset>, positions: &[[f32; 3]]) -> Arc<PickingTree> {
+        let mut trees = self.0.lock().unwrap();
+        match trees.get(&id) {
+            Some(tree) if tree.indices.len() == positions.len() => tree.clone(),
+            _ => {
+                let bounds = positions.iter().fold(
+                    (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+                    |(min, max), &position| (min.min(position.into()), max.max(position.into())),
+                );
+                let tree = Arc::new(PickingTree {
+                    indices: KdTree::new(positions).indices.into_owned(),
+                    bounds,
+                });
+                trees.insert(id, tree.clone());
+                tree
+            }
+        }
+    }
+}
+
+/// Drops the trees of modified and removed assets, to be built again on their next query.
+pub(crate) fn invalidate_point_cloud_picking_trees(
+    mut events: EventReader<AssetEvent<PointCloudAsset>>,
+    mut trees: ResMut<PointCloudPickingTrees>,
+) {
+    let trees = trees.0.get_mut().unwrap();
+    for event in events.read() {
+        match *event {
+            AssetEvent::Modified { id } | AssetEvent::Removed { id } => {
+                trees.remove(&id);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::SystemState;
+
+    #[test]
+    fn rays_hit_the_closest_point_near_them() {
+        let mut world = World::new();
+        world.init_resource::<Assets<PointCloudAsset>>();
+        world.init_resource::<PointCloudPickingTrees>();
+        let positions: Vec<Vec3> = (0..1000)
+            .map(|i| Vec3::new((i % 10) as f32, (i / 10 % 10) as f32, (i / 100) as f32))
+            .collect();
+        let colors = vec![[u8::MAX; 4]; positions.len()];
+        let mesh = world
+            .resource_mut::<Assets<PointCloudAsset>>()
+            .add(PointCloudAsset::from_points(positions, colors).unwrap());
+        let transform = Transform::from_xyz(1.0, 2.0, 3.0)
+            .with_rotation(Quat::from_rotation_y(1.0))
+            .with_scale(Vec3::new(1.0, 2.0, 0.5));
+        let entity = world
+            .spawn((
+                PotreePointCloud { mesh, ..default() },
+                GlobalTransform::from(transform),
+                InheritedVisibility::VISIBLE,
+            ))
+            .id();
+
+        let mut raycast = SystemState::<PointCloudRaycast>::new(&mut world);
+        let raycast = raycast.get(&world);
+        // Along the row of points at a local y and z of 4, towards increasing x.
+        let start = transform.transform_point(Vec3::new(-5.0, 4.0, 4.0));
+        let end = transform.transform_point(Vec3::new(9.0, 4.0, 4.0));
+        let ray = Ray {
+            origin: start,
+            direction: (end - start).normalize(),
+        };
+        let (hit_entity, index, position) = raycast.cast_ray(ray, 0.1).unwrap();
+        assert_eq!(hit_entity, entity);
+        assert_eq!(index, 440);
+        assert!(position.distance(transform.transform_point(Vec3::new(0.0, 4.0, 4.0))) < 1e-4);
+
+        let behind = Ray {
+            origin: start,
+            direction: -ray.direction,
+        };
+        assert!(raycast.cast_ray(behind, 0.1).is_none());
+    }
+}
@@ -1,8 +1,10 @@
+use std::ops::Range;
+
 use bevy::{
     prelude::*,
     render::{
         camera::ExtractedCamera,
-        extract_component::ComponentUniforms,
+        extract_component::{ComponentUniforms, ExtractComponent},
         extract_resource::ExtractResource,
         render_asset::RenderAssets,
         render_resource::*,
@@ -14,8 +16,9 @@ use bevy::{
 };
 
 use crate::{
-    clippling_planes::UniformBufferOfGpuClippingPlaneRanges, PointCloudAsset,
-    PointCloudPlaybackControls, PointCloudUniform,
+    clippling_planes::UniformBufferOfGpuClippingPlaneRanges, selection::PointCloudSelectionMasks,
+    PointBlendMode, PointCloudAsset, PointCloudPlaybackControls, PointCloudUniform,
+    PointCloudViewUniform,
 };
 
 pub(crate) const POINT_CLOUD_VERT_SHADER_HANDLE: Handle<Shader> =
@@ -31,23 +34,83 @@ pub struct PointCloudPipeline {
     pub entity_layout: BindGroupLayout,
     pub animated_entity_layout: BindGroupLayout,
     pub model_layout: BindGroupLayout,
+    /// Per-instance transforms and tints of an [`InstancedPointCloud`](crate::InstancedPointCloud).
+    pub instance_layout: BindGroupLayout,
+    /// Positions and colors of the target asset of a [`PointCloudMorph`](crate::PointCloudMorph).
+    pub morph_layout: BindGroupLayout,
+    /// The image of a [`PointColorMode::TransferFunction`](crate::PointColorMode::TransferFunction).
+    pub transfer_function_layout: BindGroupLayout,
 
+    /// The 4 vertices of the point quad, followed by those of the polygon of round points, see
+    /// [`Self::point_vertices`].
     pub instanced_point_quad: Buffer,
+    /// The range of the polygon in [`Self::instanced_point_quad`], the quad itself unless
+    /// [`PointCloudPlugin::round_point_vertices`](crate::PointCloudPlugin::round_point_vertices)
+    /// is above 4.
+    pub round_point_vertices: Range<u32>,
+    pub placeholder_buffer: Buffer,
+    /// See [`PointCloudPlugin::fragment_shader`](crate::PointCloudPlugin::fragment_shader).
+    pub fragment_shader: Handle<Shader>,
+    /// Whether the points are read from storage buffers. Otherwise, as on WebGL2, their
+    /// positions and colors are read from instance rate vertex buffers, see
+    /// [`Self::point_stream_layouts`], and the bind groups of the point streams are empty.
+    pub storage_buffers: bool,
 }
 
+/// Whether [`PointCloudPipeline::storage_buffers`] are used, see
+/// [`PointCloudPlugin::storage_buffers`](crate::PointCloudPlugin::storage_buffers).
+#[derive(Resource)]
+pub(crate) struct PointCloudStorageBuffers(pub bool);
+
+/// See [`PointCloudPlugin::round_point_vertices`](crate::PointCloudPlugin::round_point_vertices).
+#[derive(Resource)]
+pub(crate) struct PointCloudRoundPointVertices(pub u32);
+
+/// The resolved [`PointCloudPlugin::fragment_shader`](crate::PointCloudPlugin::fragment_shader).
+#[derive(Resource)]
+pub(crate) struct PointCloudFragmentShader(pub Handle<Shader>);
+
 #[derive(PartialEq, Eq, Hash, Clone)]
 pub struct PointCloudPipelineKey {
     pub colored: bool,
+    pub high_precision_color: bool,
     pub animated: bool,
+    pub instanced: bool,
+    /// Instanced, with one instance per cloud of a [`PointCloudBatch`](crate::PointCloudBatch)
+    /// instead of one per copy of the whole asset.
+    pub batched: bool,
+    pub morph: bool,
+    pub transfer_function: bool,
+    /// Read the point indices from the instance rate vertex buffer of a
+    /// [`PointDepthSort`](crate::PointDepthSort).
+    pub depth_sorted: bool,
+    /// Also write the depth for [`EyeDomeViewTarget`] to a second color target.
+    pub eye_dome_lighting: bool,
+    pub blend_mode: PointBlendMode,
     pub msaa: u32,
+    /// Only write depth, in the depth prepass of views with a
+    /// [`DepthPrepass`](bevy::core_pipeline::prepass::DepthPrepass).
+    pub depth_prepass: bool,
 }
 
 #[derive(Resource)]
 pub struct EyeDomePipeline {
     pub eye_dome_image_layout: BindGroupLayout,
     pub multisampled_eye_dome_image_layout: BindGroupLayout,
+    /// The [`PointCloudPipeline::view_layout`], for the per-view settings.
+    pub view_layout: BindGroupLayout,
 }
 
+/// Whether views get an [`EyeDomeViewTarget`]: eye dome lighting is enabled in the
+/// [`PointCloudPlugin`](crate::PointCloudPlugin).
+#[derive(Resource, Clone, Copy)]
+pub struct EyeDomeLightingEnabled(pub bool);
+
+/// Whether opaque points are also drawn into the depth prepass, see
+/// [`PointCloudPlugin::depth_prepass`](crate::PointCloudPlugin::depth_prepass).
+#[derive(Resource, Clone, Copy)]
+pub struct PointCloudDepthPrepassEnabled(pub bool);
+
 #[derive(PartialEq, Eq, Hash, Clone)]
 pub struct EyeDomePipelineKey {
     pub msaa: u32,
@@ -58,22 +121,45 @@ pub struct PointCloudBindGroup {
     pub bind_group: Option<BindGroup>,
     pub model_bind_group: Option<BindGroup>,
 }
-pub(crate) fn queue_point_cloud_bind_group(
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn prepare_point_cloud_bind_group(
     render_device: Res<RenderDevice>,
     pipeline: Res<PointCloudPipeline>,
     view_uniform: Res<ViewUniforms>,
     clipping_planes_uniform: Res<UniformBufferOfGpuClippingPlaneRanges>,
+    view_settings_uniform: Res<ComponentUniforms<PointCloudViewUniform>>,
     model_uniform: Res<ComponentUniforms<PointCloudUniform>>,
+    selection_masks: Option<Res<PointCloudSelectionMasks>>,
     mut bind_groups: ResMut<PointCloudBindGroup>,
 ) {
-    if let (Some(view_uniform_resource), Some(clipping_plane_resource)) = (
+    // Without storage buffers, there are no selection masks to bind.
+    let selection_resource = match &selection_masks {
+        Some(selection_masks) => selection_masks.buffer.binding().map(Some),
+        None => Some(None),
+    };
+    if let (
+        Some(view_uniform_resource),
+        Some(clipping_plane_resource),
+        Some(view_settings_resource),
+        Some(selection_resource),
+    ) = (
         view_uniform.uniforms.binding(),
         clipping_planes_uniform.0.binding(),
+        view_settings_uniform.uniforms().binding(),
+        selection_resource,
     ) {
+        let mut entries = DynamicBindGroupEntries::sequential((
+            view_uniform_resource,
+            clipping_plane_resource,
+            view_settings_resource,
+        ));
+        if let Some(selection_resource) = selection_resource {
+            entries = entries.extend_sequential((selection_resource,));
+        }
         let bind_group = render_device.create_bind_group(
             "point_cloud_bind_group",
             &pipeline.view_layout,
-            &BindGroupEntries::sequential((view_uniform_resource, clipping_plane_resource)),
+            &entries,
         );
         bind_groups.bind_group = Some(bind_group);
     }
@@ -87,110 +173,300 @@ pub(crate) fn queue_point_cloud_bind_group(
     }
 }
 
+/// A read-only storage buffer holding one stream of per-point data.
+fn point_stream_layout_entry(binding: u32) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::VERTEX,
+        ty: BindingType::Buffer {
+            ty: BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
 const QUAD_VERTEX_BUF: &[f32] = &[0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0, 0.0];
 
+/// The most vertices round points can be drawn with.
+const MAX_ROUND_POINT_VERTICES: u32 = 64;
+
+/// The corners of a regular polygon with `vertices` corners around the disc inscribed in the
+/// quad, in the same coordinates as `QUAD_VERTEX_BUF`. They zigzag between both sides of the
+/// polygon, so that it can be drawn as a triangle strip.
+fn round_point_polygon(vertices: u32) -> Vec<[f32; 2]> {
+    let radius = 0.5 / (std::f32::consts::PI / vertices as f32).cos();
+    (0..vertices)
+        .map(|i| {
+            let corner = if i % 2 == 0 {
+                (vertices - i / 2) % vertices
+            } else {
+                i / 2 + 1
+            };
+            let angle = std::f32::consts::TAU * corner as f32 / vertices as f32;
+            [0.5 + radius * angle.cos(), 0.5 + radius * angle.sin()]
+        })
+        .collect()
+}
+
+impl PointCloudPipeline {
+    /// The corner of the point quad, from `0.0` to `1.0` on both axes, at location 0.
+    pub const POINT_QUAD_ATTRIBUTE: VertexAttribute = VertexAttribute {
+        format: VertexFormat::Float32x2,
+        offset: 0,
+        shader_location: 0,
+    };
+    /// The index of the drawn point, at location 1.
+    pub const SORTED_POINT_INDEX_ATTRIBUTE: VertexAttribute = VertexAttribute {
+        format: VertexFormat::Uint32,
+        offset: 0,
+        shader_location: 1,
+    };
+    /// The position of the drawn point, at location 2, without storage buffers.
+    pub const POINT_POSITION_ATTRIBUTE: VertexAttribute = VertexAttribute {
+        format: VertexFormat::Float32x3,
+        offset: 0,
+        shader_location: 2,
+    };
+    /// The packed color of the drawn point, at location 3, without storage buffers. Two
+    /// `u32`s with [`PointCloudPipelineKey::high_precision_color`].
+    pub const POINT_COLOR_ATTRIBUTE: VertexAttribute = VertexAttribute {
+        format: VertexFormat::Uint32,
+        offset: 0,
+        shader_location: 3,
+    };
+
+    /// The layout of [`Self::instanced_point_quad`], bound at slot 0 of every point cloud
+    /// pipeline. Points are drawn as instances of its vertices in [`Self::point_vertices`], as
+    /// a triangle strip, and read their data from the storage buffers of
+    /// [`PreparedPointCloudAsset`] at the instance index.
+    ///
+    /// [`PreparedPointCloudAsset`]: crate::PreparedPointCloudAsset
+    pub fn point_quad_layout() -> VertexBufferLayout {
+        VertexBufferLayout {
+            array_stride: Self::POINT_QUAD_ATTRIBUTE.format.size(),
+            step_mode: VertexStepMode::Vertex,
+            attributes: vec![Self::POINT_QUAD_ATTRIBUTE],
+        }
+    }
+
+    /// The vertices a point is drawn with, the polygon of [`Self::round_point_vertices`] if it
+    /// is `round`, and the quad otherwise.
+    pub fn point_vertices(&self, round: bool) -> Range<u32> {
+        if round {
+            self.round_point_vertices.clone()
+        } else {
+            0..4
+        }
+    }
+
+    /// The layout of the sorted point indices of a [`PointDepthSort`](crate::PointDepthSort),
+    /// bound at slot 1 of [`PointCloudPipelineKey::depth_sorted`] pipelines. Every instance
+    /// reads its point index from it instead of using the instance index.
+    pub fn sorted_point_index_layout() -> VertexBufferLayout {
+        VertexBufferLayout {
+            array_stride: Self::SORTED_POINT_INDEX_ATTRIBUTE.format.size(),
+            step_mode: VertexStepMode::Instance,
+            attributes: vec![Self::SORTED_POINT_INDEX_ATTRIBUTE],
+        }
+    }
+
+    /// The layouts of the positions and, if `colored`, the colors of the points, bound from
+    /// slot 1 on when the pipeline doesn't use [`Self::storage_buffers`]. Every instance reads
+    /// its point from them.
+    pub fn point_stream_layouts(
+        colored: bool,
+        high_precision_color: bool,
+    ) -> Vec<VertexBufferLayout> {
+        let mut layouts = vec![VertexBufferLayout {
+            array_stride: Self::POINT_POSITION_ATTRIBUTE.format.size(),
+            step_mode: VertexStepMode::Instance,
+            attributes: vec![Self::POINT_POSITION_ATTRIBUTE],
+        }];
+        if colored {
+            let color = VertexAttribute {
+                format: if high_precision_color {
+                    VertexFormat::Uint32x2
+                } else {
+                    VertexFormat::Uint32
+                },
+                ..Self::POINT_COLOR_ATTRIBUTE
+            };
+            layouts.push(VertexBufferLayout {
+                array_stride: color.format.size(),
+                step_mode: VertexStepMode::Instance,
+                attributes: vec![color],
+            });
+        }
+        layouts
+    }
+
+    /// The usage of the buffers of per-point data, which are bound as storage or vertex
+    /// buffers depending on [`Self::storage_buffers`].
+    pub fn point_stream_usage(&self) -> BufferUsages {
+        if self.storage_buffers {
+            BufferUsages::STORAGE
+        } else {
+            BufferUsages::VERTEX
+        }
+    }
+}
+
 impl FromWorld for PointCloudPipeline {
     fn from_world(world: &mut World) -> Self {
         let render_device = world.resource::<RenderDevice>();
+        let storage_buffers = world
+            .get_resource::<PointCloudStorageBuffers>()
+            .is_none_or(|storage_buffers| storage_buffers.0);
+        // Without storage buffers, the layouts of the point streams are empty.
+        let point_streams = |bindings: &[u32]| -> Vec<_> {
+            if storage_buffers {
+                bindings
+                    .iter()
+                    .copied()
+                    .map(point_stream_layout_entry)
+                    .collect()
+            } else {
+                Vec::new()
+            }
+        };
+        let round_point_vertices = world
+            .get_resource::<PointCloudRoundPointVertices>()
+            .map_or(4, |vertices| vertices.0.min(MAX_ROUND_POINT_VERTICES));
+        let mut vertices = QUAD_VERTEX_BUF.to_vec();
+        let round_point_vertices = if round_point_vertices > 4 {
+            vertices.extend(round_point_polygon(round_point_vertices).iter().flatten());
+            4..4 + round_point_vertices
+        } else {
+            0..4
+        };
         let instanced_point_quad = render_device.create_buffer_with_data(&BufferInitDescriptor {
             label: "instanced point quad".into(),
-            contents: bytemuck::cast_slice(QUAD_VERTEX_BUF),
+            contents: bytemuck::cast_slice(&vertices),
             usage: BufferUsages::VERTEX,
         });
-        let view_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("PointCloudViewLabel"),
-            entries: &[
-                BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: true,
-                        min_binding_size: None,
-                    },
-                    count: None,
+        let mut view_entries = vec![
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: None,
                 },
-                BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: ShaderStages::VERTEX,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
                 },
-            ],
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ];
+        // Selection masks
+        view_entries.extend(point_streams(&[3]));
+        let view_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("PointCloudViewLabel"),
+            entries: &view_entries,
         });
+        // Positions, colors, scalar fields and normals
         let entity_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
             label: Some("PointCloudViewLayout"),
+            entries: &point_streams(&[0, 1, 4, 5]),
+        });
+        // Like the `entity_layout`, with the previous and next animation frames at 2 and 3
+        let animated_entity_layout =
+            render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("PointCloudViewLayout"),
+                entries: &point_streams(&[0, 1, 2, 3, 4, 5]),
+            });
+        let model_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("PointCloudModelLayout"),
             entries: &[BindGroupLayoutEntry {
                 binding: 0,
-                visibility: ShaderStages::VERTEX,
+                visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
                 ty: BindingType::Buffer {
-                    ty: BufferBindingType::Storage { read_only: true },
-                    has_dynamic_offset: false,
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
                     min_binding_size: None,
                 },
                 count: None,
             }],
         });
-        let animated_entity_layout =
+
+        let instance_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("PointCloudInstanceLayout"),
+            entries: &point_streams(&[0]),
+        });
+        let morph_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("PointCloudMorphLayout"),
+            // Positions and colors
+            entries: &point_streams(&[0, 1]),
+        });
+
+        let transfer_function_layout =
             render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: Some("PointCloudViewLayout"),
+                label: Some("PointCloudTransferFunctionLayout"),
                 entries: &[
                     BindGroupLayoutEntry {
                         binding: 0,
                         visibility: ShaderStages::VERTEX,
-                        ty: BindingType::Buffer {
-                            ty: BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: true },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
                         },
                         count: None,
                     },
                     BindGroupLayoutEntry {
                         binding: 1,
                         visibility: ShaderStages::VERTEX,
-                        ty: BindingType::Buffer {
-                            ty: BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: ShaderStages::VERTEX,
-                        ty: BindingType::Buffer {
-                            ty: BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
+                        ty: BindingType::Sampler(SamplerBindingType::Filtering),
                         count: None,
                     },
                 ],
             });
-        let model_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("PointCloudModelLayout"),
-            entries: &[BindGroupLayoutEntry {
-                binding: 0,
-                visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
-                ty: BindingType::Buffer {
-                    ty: BufferBindingType::Uniform,
-                    has_dynamic_offset: true,
-                    min_binding_size: None,
-                },
-                count: None,
-            }],
+
+        // Bound in place of optional point streams that an asset doesn't have.
+        let placeholder_buffer = render_device.create_buffer(&BufferDescriptor {
+            label: Some("point cloud placeholder buffer"),
+            size: 16,
+            usage: if storage_buffers {
+                BufferUsages::STORAGE
+            } else {
+                BufferUsages::VERTEX
+            },
+            mapped_at_creation: false,
         });
 
         Self {
             view_layout,
             model_layout,
+            instance_layout,
+            morph_layout,
+            transfer_function_layout,
             entity_layout,
             animated_entity_layout,
             instanced_point_quad,
+            round_point_vertices,
+            placeholder_buffer,
+            fragment_shader: world.resource::<PointCloudFragmentShader>().0.clone(),
+            storage_buffers,
         }
     }
 }
@@ -201,21 +477,47 @@ impl SpecializedRenderPipeline for PointCloudPipeline {
     fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
         let PointCloudPipelineKey {
             colored,
+            high_precision_color,
             animated,
+            instanced,
+            batched,
+            morph,
+            transfer_function,
+            depth_sorted,
+            eye_dome_lighting,
+            blend_mode,
             msaa,
+            depth_prepass,
         } = key;
+        let blended = blend_mode != PointBlendMode::Opaque;
+
+        let mut layout = vec![
+            self.view_layout.clone(),
+            if animated {
+                self.animated_entity_layout.clone()
+            } else {
+                self.entity_layout.clone()
+            },
+            self.model_layout.clone(),
+        ];
+        // Instanced and morphing point clouds are separate kinds of entities, and transfer
+        // functions are only used by plain point clouds, so they never need group 3 at the
+        // same time.
+        if instanced {
+            layout.push(self.instance_layout.clone());
+        } else if morph {
+            layout.push(self.morph_layout.clone());
+        } else if transfer_function {
+            layout.push(self.transfer_function_layout.clone());
+        }
 
         RenderPipelineDescriptor {
-            label: Some("point_cloud_pipeline".into()),
-            layout: vec![
-                self.view_layout.clone(),
-                if animated {
-                    self.animated_entity_layout.clone()
-                } else {
-                    self.entity_layout.clone()
-                },
-                self.model_layout.clone(),
-            ],
+            label: Some(if depth_prepass {
+                "point_cloud_prepass_pipeline".into()
+            } else {
+                "point_cloud_pipeline".into()
+            }),
+            layout,
             vertex: VertexState {
                 shader: POINT_CLOUD_VERT_SHADER_HANDLE,
                 shader_defs: {
@@ -223,24 +525,44 @@ impl SpecializedRenderPipeline for PointCloudPipeline {
                     if colored {
                         defs.push("COLORED".into());
                     }
+                    if high_precision_color {
+                        defs.push("HIGH_PRECISION_COLOR".into());
+                    }
                     if animated {
                         defs.push("ANIMATED".into());
                     }
+                    if instanced {
+                        defs.push("INSTANCED".into());
+                        if batched {
+                            defs.push("BATCHED".into());
+                        }
+                    } else if morph {
+                        defs.push("MORPH".into());
+                    } else if transfer_function {
+                        defs.push("TRANSFER_FUNCTION".into());
+                    }
+                    if depth_sorted {
+                        defs.push("DEPTH_SORTED".into());
+                    }
+                    if !self.storage_buffers {
+                        defs.push("NO_STORAGE_BUFFERS".into());
+                    }
                     defs
                 },
                 entry_point: "main".into(),
-                buffers: vec![VertexBufferLayout {
-                    array_stride: 8,
-                    step_mode: VertexStepMode::Vertex,
-                    attributes: vec![VertexAttribute {
-                        format: VertexFormat::Float32x2,
-                        offset: 0,
-                        shader_location: 0,
-                    }],
-                }],
+                buffers: {
+                    let mut buffers = vec![Self::point_quad_layout()];
+                    if depth_sorted {
+                        buffers.push(Self::sorted_point_index_layout());
+                    }
+                    if !self.storage_buffers {
+                        buffers.extend(Self::point_stream_layouts(colored, high_precision_color));
+                    }
+                    buffers
+                },
             },
             fragment: Some(FragmentState {
-                shader: POINT_CLOUD_FRAG_SHADER_HANDLE,
+                shader: self.fragment_shader.clone(),
                 shader_defs: {
                     let mut defs = Vec::new();
                     if colored {
@@ -249,21 +571,54 @@ impl SpecializedRenderPipeline for PointCloudPipeline {
                     if animated {
                         defs.push("ANIMATED".into());
                     }
+                    // Without color targets there is no alpha to coverage, so the prepass
+                    // dithers translucent edges instead.
+                    if msaa > 1 && !depth_prepass {
+                        defs.push("MULTISAMPLED".into());
+                    }
+                    if eye_dome_lighting {
+                        defs.push("EYE_DOME_LIGHTING".into());
+                    }
+                    if blended {
+                        defs.push("BLENDED".into());
+                    }
                     defs
                 },
                 entry_point: "main".into(),
-                targets: vec![
-                    Some(ColorTargetState {
+                // Fragment outputs without a target are ignored, so the prepass shares the
+                // fragment shader and writes the same depth as the main pass.
+                targets: if depth_prepass {
+                    Vec::new()
+                } else {
+                    let mut targets = vec![Some(ColorTargetState {
                         format: TextureFormat::Rgba8UnormSrgb,
-                        blend: Some(BlendState::REPLACE),
+                        blend: Some(match blend_mode {
+                            PointBlendMode::Opaque => BlendState::REPLACE,
+                            PointBlendMode::Additive => BlendState {
+                                color: BlendComponent {
+                                    src_factor: BlendFactor::SrcAlpha,
+                                    dst_factor: BlendFactor::One,
+                                    operation: BlendOperation::Add,
+                                },
+                                alpha: BlendComponent {
+                                    src_factor: BlendFactor::Zero,
+                                    dst_factor: BlendFactor::One,
+                                    operation: BlendOperation::Add,
+                                },
+                            },
+                            PointBlendMode::AlphaBlend => BlendState::ALPHA_BLENDING,
+                        }),
                         write_mask: ColorWrites::ALL,
-                    }),
-                    Some(ColorTargetState {
-                        format: TextureFormat::R32Float,
-                        blend: Some(BlendState::REPLACE),
-                        write_mask: ColorWrites::RED,
-                    }),
-                ],
+                    })];
+                    if eye_dome_lighting {
+                        targets.push(Some(ColorTargetState {
+                            format: TextureFormat::R32Float,
+                            blend: Some(BlendState::REPLACE),
+                            write_mask: ColorWrites::RED,
+                        }));
+                    }
+                    targets
+                },
             }),
             primitive: PrimitiveState {
                 front_face: FrontFace::Ccw,
@@ -276,8 +631,9 @@ impl SpecializedRenderPipeline for PointCloudPipeline {
             },
             depth_stencil: Some(DepthStencilState {
                 format: TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: CompareFunction::Greater,
+                depth_write_enabled: !blended,
+                // Points drawn in the depth prepass are drawn again at the same depth.
+                depth_compare: CompareFunction::GreaterEqual,
                 stencil: StencilState {
                     front: StencilFaceState::IGNORE,
                     back: StencilFaceState::IGNORE,
@@ -293,7 +649,10 @@ impl SpecializedRenderPipeline for PointCloudPipeline {
             multisample: MultisampleState {
                 count: msaa,
                 mask: !0,
-                alpha_to_coverage_enabled: false,
+                // Softens point edges, see `PotreePointCloud::edge_softness`, and blends
+                // translucent points of transfer functions. Blended points use their alpha
+                // directly instead.
+                alpha_to_coverage_enabled: msaa > 1 && !blended && !depth_prepass,
             },
             push_constant_ranges: default(),
         }
@@ -336,6 +695,7 @@ impl FromWorld for EyeDomePipeline {
         Self {
             eye_dome_image_layout,
             multisampled_eye_dome_image_layout,
+            view_layout: world.resource::<PointCloudPipeline>().view_layout.clone(),
         }
     }
 }
@@ -348,11 +708,14 @@ impl SpecializedRenderPipeline for EyeDomePipeline {
 
         RenderPipelineDescriptor {
             label: Some("EyeDomeLightingPipeline".into()),
-            layout: vec![if msaa > 1 {
-                self.multisampled_eye_dome_image_layout.clone()
-            } else {
-                self.eye_dome_image_layout.clone()
-            }],
+            layout: vec![
+                if msaa > 1 {
+                    self.multisampled_eye_dome_image_layout.clone()
+                } else {
+                    self.eye_dome_image_layout.clone()
+                },
+                self.view_layout.clone(),
+            ],
             vertex: VertexState {
                 shader: EYE_DOME_LIGHTING_SHADER_HANDLE,
                 shader_defs: if msaa > 1 {
@@ -411,16 +774,89 @@ impl SpecializedRenderPipeline for EyeDomePipeline {
                     write_mask: ColorWrites::COLOR,
                 })],
             }),
-            push_constant_ranges: vec![PushConstantRange {
-                stages: ShaderStages::FRAGMENT,
-                range: 0..std::mem::size_of::<f32>() as u32,
-            }],
+            push_constant_ranges: default(),
+        }
+    }
+}
+
+/// Tunes the eye dome lighting of the camera it's inserted on. Cameras without it use the
+/// defaults.
+#[derive(Component, Clone, Copy, Debug, ExtractComponent)]
+pub struct EyeDomeSettings {
+    /// How strongly edges are darkened.
+    pub strength: f32,
+    /// Distance to the neighboring pixels that depths are compared with, in pixels. Larger
+    /// radii give thicker outlines, which suit sparse clouds and high DPI displays.
+    pub radius: f32,
+    pub samples: EyeDomeSamples,
+    /// What eye dome lighting does to clouds with a blended
+    /// [`PointBlendMode`](crate::PointBlendMode).
+    pub blended_clouds: EyeDomeBlendedClouds,
+    /// Skips the eye dome lighting pass entirely when `false`.
+    pub enabled: bool,
+}
+
+impl Default for EyeDomeSettings {
+    fn default() -> Self {
+        Self {
+            strength: 1.0,
+            radius: 1.0,
+            samples: EyeDomeSamples::Four,
+            blended_clouds: EyeDomeBlendedClouds::Unlit,
+            enabled: true,
         }
     }
 }
 
+/// Which neighbors of a pixel [`EyeDomeSettings`] compares its depth with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EyeDomeSamples {
+    /// The pixels left, right, above and below.
+    #[default]
+    Four,
+    /// The four pixels of [`EyeDomeSamples::Four`] and the four diagonal ones, all at the same
+    /// distance. Smoother outlines, at twice the texture reads.
+    Eight,
+}
+
+impl EyeDomeSamples {
+    pub(crate) fn count(self) -> u32 {
+        match self {
+            Self::Four => 4,
+            Self::Eight => 8,
+        }
+    }
+}
+
+/// How [`EyeDomeSettings`] treats clouds with a blended [`PointBlendMode`](crate::PointBlendMode).
+///
+/// Eye dome lighting is a screen space pass over the depth of the opaque points. Blended points
+/// don't write depth, so it can't outline them, and either leaves them alone or darkens them by
+/// the edges of the opaque points behind them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EyeDomeBlendedClouds {
+    /// Lights the opaque clouds before the blended ones are drawn over them, unlit.
+    #[default]
+    Unlit,
+    /// Lights the view after the blended clouds are drawn, so they are darkened along the
+    /// outlines of the opaque points behind them, as if they were a tinted window.
+    LitByOpaqueDepth,
+    /// Skips eye dome lighting in views that draw any blended cloud.
+    DisableEyeDome,
+}
+
+/// Only inserted on views while [`EyeDomeLightingEnabled`].
+///
+/// Custom render graph nodes ordered after [`PointCloudNode`](crate::PointCloudNode) can read
+/// the textures of the view: the shaded points are in the main texture of its [`ViewTarget`],
+/// and this holds their depth alone, without the meshes of the depth buffer.
 #[derive(Clone, Component)]
 pub struct EyeDomeViewTarget {
+    /// The reverse-z depth of the opaque points, like in the depth buffer, as
+    /// [`TextureFormat::R32Float`]. `0.0`, the far plane, where there are no points. It has the
+    /// sample count of the [`Msaa`] setting, so bind it as a multisampled texture when that's
+    /// above one. Views with the same render target share it, and it's overwritten by the
+    /// next view drawn to that target.
     pub depth_texture: Texture,
     pub depth_texture_view: TextureView,
     pub bind_group: BindGroup,
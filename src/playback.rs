@@ -1,6 +1,6 @@
 use bevy::{prelude::*, utils::HashMap};
 
-use crate::PointCloudAsset;
+use crate::{PointCloudAsset, PotreePointCloud};
 
 #[derive(Resource, Clone, Default)]
 pub struct PointCloudPlaybackControls {
@@ -57,6 +57,10 @@ impl PointCloudPlaybackControls {
                     changed |= true;
                     controls.time += controls.speed * time.delta_seconds();
                     controls.time = controls.time.rem_euclid(animation_duration);
+                } else if controls.time > animation_duration {
+                    // The asset was reloaded with a shorter animation.
+                    changed |= true;
+                    controls.time = animation_duration;
                 }
 
                 true
@@ -67,3 +71,111 @@ impl PointCloudPlaybackControls {
         }
     }
 }
+
+/// Plays a sequence of point clouds like an animation, by switching the
+/// [`PotreePointCloud::mesh`] of this entity. Useful for replays stored as one file per frame.
+///
+/// Frames that haven't loaded yet are skipped, and the last loaded frame before them stays
+/// visible until they have.
+///
+/// With `interpolate`, the positions and colors of the visible frame are blended on the GPU
+/// towards the next one as playback moves ahead, like a [`PointCloudMorph`](crate::PointCloudMorph).
+/// Point `i` of one frame moves to point `i` of the next, so it only looks right when the points
+/// keep their order across frames. Frames are swapped without blending when the next frame
+/// has a different number of points or hasn't loaded yet, and for clouds colored with a
+/// [`PointColorMode::TransferFunction`](crate::PointColorMode::TransferFunction).
+#[derive(Component, Clone, Debug)]
+pub struct PointCloudAnimation {
+    pub frames: Vec<Handle<PointCloudAsset>>,
+    pub fps: f32,
+    pub playing: bool,
+    /// Start over after the last frame, instead of stopping on it.
+    pub looping: bool,
+    /// Seconds since the start of the first frame. Can be set to seek.
+    pub time: f32,
+    /// Blend each frame towards the next one, instead of swapping them.
+    pub interpolate: bool,
+}
+
+impl PointCloudAnimation {
+    /// A looping animation that starts playing right away.
+    pub fn new(frames: Vec<Handle<PointCloudAsset>>, fps: f32) -> Self {
+        Self {
+            frames,
+            fps,
+            playing: true,
+            looping: true,
+            time: 0.0,
+            interpolate: false,
+        }
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.frames.len() as f32 / self.fps
+    }
+
+    /// The index of the frame that should be visible at [`PointCloudAnimation::time`].
+    pub fn current_frame(&self) -> usize {
+        ((self.time * self.fps).max(0.0) as usize).min(self.frames.len().saturating_sub(1))
+    }
+
+    /// The frame that `current`, the visible frame, is blended towards, and how far, while
+    /// interpolating.
+    pub(crate) fn interpolation_target(
+        &self,
+        current: &Handle<PointCloudAsset>,
+        assets: &Assets<PointCloudAsset>,
+    ) -> Option<(&Handle<PointCloudAsset>, f32)> {
+        if !self.interpolate || self.fps <= 0.0 {
+            return None;
+        }
+        let index = self.current_frame();
+        if self.frames.get(index) != Some(current) {
+            // The current frame hasn't loaded yet.
+            return None;
+        }
+        let next = match self.frames.get(index + 1) {
+            Some(next) => next,
+            None if self.looping => &self.frames[0],
+            None => return None,
+        };
+        let num_points = assets.get(current)?.num_points();
+        if next == current || assets.get(next)?.num_points() != num_points {
+            return None;
+        }
+        Some((next, (self.time * self.fps).max(0.0).fract()))
+    }
+}
+
+pub(crate) fn play_point_cloud_animations(
+    time: Res<Time>,
+    assets: Res<Assets<PointCloudAsset>>,
+    mut animations: Query<(&mut PointCloudAnimation, &mut PotreePointCloud)>,
+) {
+    for (mut animation, mut point_cloud) in &mut animations {
+        if animation.frames.is_empty() || animation.fps <= 0.0 {
+            continue;
+        }
+        if animation.playing {
+            let duration = animation.duration();
+            let mut elapsed = animation.time + time.delta_seconds();
+            if animation.looping {
+                elapsed = elapsed.rem_euclid(duration);
+            } else if elapsed >= duration {
+                elapsed = duration;
+                animation.playing = false;
+            }
+            animation.time = elapsed;
+        }
+        let Some(frame) = animation.frames[..=animation.current_frame()]
+            .iter()
+            .rev()
+            .find(|frame| assets.contains(*frame))
+        else {
+            continue;
+        };
+        if point_cloud.mesh != *frame {
+            point_cloud.mesh = frame.clone();
+        }
+    }
+}
@@ -0,0 +1,491 @@
+use std::collections::BTreeMap;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    math::DVec3,
+    prelude::*,
+    render::render_resource::PrimitiveTopology,
+    utils::{
+        thiserror::{self, Error},
+        BoxedFuture,
+    },
+};
+
+use crate::{color::pack_rgba8, PointCloudAsset, ATTRIBUTE_COLOR_PACKED};
+
+/// Loads the `vertex` element of ASCII and binary `.ply` files, as exported by MeshLab or
+/// Open3D. Faces and other elements are ignored.
+///
+/// `red`/`green`/`blue` (or `diffuse_red`/`diffuse_green`/`diffuse_blue`) properties become
+/// the point colors, `nx`/`ny`/`nz` the point normals, and every other vertex property except
+/// `x`/`y`/`z` becomes a scalar field of the same name. The points are recentered, with the center kept in
+/// [`PointCloudAsset::origin`].
+#[derive(Default)]
+pub struct PlyLoader;
+
+/// Possible errors that can be produced by [`PlyLoader`]
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum PlyLoaderError {
+    #[error("Could not load asset: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid PLY header: {0}")]
+    Header(String),
+    #[error("Invalid PLY data: {0}")]
+    Data(String),
+    #[error("PLY file has no vertex element with x, y and z properties")]
+    MissingPositions,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "char" | "int8" => Self::I8,
+            "uchar" | "uint8" => Self::U8,
+            "short" | "int16" => Self::I16,
+            "ushort" | "uint16" => Self::U16,
+            "int" | "int32" => Self::I32,
+            "uint" | "uint32" => Self::U32,
+            "float" | "float32" => Self::F32,
+            "double" | "float64" => Self::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+
+    /// The factor that maps the full range of the type to `0.0..=1.0`, for colors.
+    fn color_scale(self) -> f64 {
+        match self {
+            Self::U8 | Self::I8 => 1.0 / u8::MAX as f64,
+            Self::U16 | Self::I16 => 1.0 / u16::MAX as f64,
+            Self::U32 | Self::I32 => 1.0 / u32::MAX as f64,
+            Self::F32 | Self::F64 => 1.0,
+        }
+    }
+}
+
+enum PropertyType {
+    Scalar(ScalarType),
+    List { len: ScalarType, item: ScalarType },
+}
+
+struct Property {
+    name: String,
+    ty: PropertyType,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+}
+
+impl Header {
+    /// Parses the header, and returns it with the length of the header in bytes.
+    fn parse(bytes: &[u8]) -> Result<(Self, usize), PlyLoaderError> {
+        let header_error = |message: &str| PlyLoaderError::Header(message.to_owned());
+        let mut format = None;
+        let mut elements: Vec<Element> = Vec::new();
+        let mut offset = 0;
+        let mut lines = bytes.split_inclusive(|&byte| byte == b'\n');
+        let magic = lines.next().ok_or_else(|| header_error("empty file"))?;
+        offset += magic.len();
+        if magic.trim_ascii() != b"ply" {
+            return Err(header_error("missing `ply` magic number"));
+        }
+        loop {
+            let line = lines
+                .next()
+                .ok_or_else(|| header_error("missing `end_header`"))?;
+            offset += line.len();
+            let line = std::str::from_utf8(line).map_err(|_| header_error("not UTF-8"))?;
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("format") => {
+                    format = Some(match words.next() {
+                        Some("ascii") => Format::Ascii,
+                        Some("binary_little_endian") => Format::BinaryLittleEndian,
+                        Some("binary_big_endian") => Format::BinaryBigEndian,
+                        _ => return Err(header_error("unknown format")),
+                    });
+                }
+                Some("element") => {
+                    let (Some(name), Some(count)) = (words.next(), words.next()) else {
+                        return Err(header_error("incomplete element"));
+                    };
+                    elements.push(Element {
+                        name: name.to_owned(),
+                        count: count
+                            .parse()
+                            .map_err(|_| header_error("invalid element count"))?,
+                        properties: Vec::new(),
+                    });
+                }
+                Some("property") => {
+                    let element = elements
+                        .last_mut()
+                        .ok_or_else(|| header_error("property outside of an element"))?;
+                    let words: Vec<&str> = words.collect();
+                    let parse_type = |name: &str| {
+                        ScalarType::parse(name)
+                            .ok_or_else(|| PlyLoaderError::Header(format!("unknown type `{name}`")))
+                    };
+                    let (ty, name) = match words.as_slice() {
+                        ["list", len, item, name] => (
+                            PropertyType::List {
+                                len: parse_type(len)?,
+                                item: parse_type(item)?,
+                            },
+                            name,
+                        ),
+                        [ty, name] => (PropertyType::Scalar(parse_type(ty)?), name),
+                        _ => return Err(header_error("invalid property")),
+                    };
+                    element.properties.push(Property {
+                        name: (*name).to_owned(),
+                        ty,
+                    });
+                }
+                Some("end_header") => break,
+                Some("comment" | "obj_info") | None => {}
+                Some(keyword) => {
+                    return Err(PlyLoaderError::Header(format!(
+                        "unknown keyword `{keyword}`"
+                    )));
+                }
+            }
+        }
+        let format = format.ok_or_else(|| header_error("missing format"))?;
+        Ok((Self { format, elements }, offset))
+    }
+}
+
+/// Reads the values of the body one after another, in any of the formats.
+struct BodyReader<'a> {
+    format: Format,
+    bytes: &'a [u8],
+    /// The remaining words of the body, for ASCII files.
+    words: std::str::SplitAsciiWhitespace<'a>,
+}
+
+impl<'a> BodyReader<'a> {
+    fn new(format: Format, bytes: &'a [u8]) -> Result<Self, PlyLoaderError> {
+        let words = if format == Format::Ascii {
+            std::str::from_utf8(bytes)
+                .map_err(|_| PlyLoaderError::Data("not UTF-8".to_owned()))?
+                .split_ascii_whitespace()
+        } else {
+            "".split_ascii_whitespace()
+        };
+        Ok(Self {
+            format,
+            bytes,
+            words,
+        })
+    }
+
+    fn read(&mut self, ty: ScalarType) -> Result<f64, PlyLoaderError> {
+        let end_of_data = || PlyLoaderError::Data("unexpected end of data".to_owned());
+        if self.format == Format::Ascii {
+            let word = self.words.next().ok_or_else(end_of_data)?;
+            return word
+                .parse()
+                .map_err(|_| PlyLoaderError::Data(format!("invalid number `{word}`")));
+        }
+        if self.bytes.len() < ty.size() {
+            return Err(end_of_data());
+        }
+        let (value, rest) = self.bytes.split_at(ty.size());
+        self.bytes = rest;
+        let mut buf = [0; 8];
+        buf[..value.len()].copy_from_slice(value);
+        if self.format == Format::BinaryBigEndian {
+            buf[..value.len()].reverse();
+        }
+        Ok(match ty {
+            ScalarType::I8 => buf[0] as i8 as f64,
+            ScalarType::U8 => buf[0] as f64,
+            ScalarType::I16 => i16::from_le_bytes([buf[0], buf[1]]) as f64,
+            ScalarType::U16 => u16::from_le_bytes([buf[0], buf[1]]) as f64,
+            ScalarType::I32 => i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            ScalarType::U32 => u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            ScalarType::F32 => f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            ScalarType::F64 => f64::from_le_bytes(buf),
+        })
+    }
+
+    /// How many of the `element`s to allocate for: as many as the remaining binary data can
+    /// hold at most, and none for ASCII files, whose values have no fixed size.
+    fn capacity(&self, element: &Element) -> usize {
+        if self.format == Format::Ascii {
+            return 0;
+        }
+        let min_len: usize = element
+            .properties
+            .iter()
+            .map(|property| match property.ty {
+                PropertyType::Scalar(ty) => ty.size(),
+                PropertyType::List { len, .. } => len.size(),
+            })
+            .sum();
+        element.count.min(self.bytes.len() / min_len.max(1))
+    }
+
+    /// Reads a property, and returns its value if it's a scalar.
+    fn read_property(&mut self, ty: &PropertyType) -> Result<Option<f64>, PlyLoaderError> {
+        match *ty {
+            PropertyType::Scalar(ty) => Ok(Some(self.read(ty)?)),
+            PropertyType::List { len, item } => {
+                for _ in 0..self.read(len)? as usize {
+                    self.read(item)?;
+                }
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl PlyLoader {
+    pub fn load_ply(bytes: &[u8]) -> Result<PointCloudAsset, PlyLoaderError> {
+        let (header, header_len) = Header::parse(bytes)?;
+        let mut body = BodyReader::new(header.format, &bytes[header_len..])?;
+
+        for element in &header.elements {
+            if element.name != "vertex" {
+                // Skip elements in front of the vertices. Those after them are never read.
+                if element.properties.is_empty() {
+                    continue;
+                }
+                for _ in 0..element.count {
+                    for property in &element.properties {
+                        body.read_property(&property.ty)?;
+                    }
+                }
+                continue;
+            }
+
+            let index_of = |names: &[&str]| {
+                element
+                    .properties
+                    .iter()
+                    .position(|property| names.contains(&property.name.as_str()))
+            };
+            let (Some(x), Some(y), Some(z)) =
+                (index_of(&["x"]), index_of(&["y"]), index_of(&["z"]))
+            else {
+                return Err(PlyLoaderError::MissingPositions);
+            };
+            let color_channels = [
+                index_of(&["red", "diffuse_red"]),
+                index_of(&["green", "diffuse_green"]),
+                index_of(&["blue", "diffuse_blue"]),
+            ];
+            let color_channels = match color_channels {
+                [Some(r), Some(g), Some(b)] => Some([r, g, b]),
+                _ => None,
+            };
+            let normal_channels = match [index_of(&["nx"]), index_of(&["ny"]), index_of(&["nz"])] {
+                [Some(x), Some(y), Some(z)] => Some([x, y, z]),
+                _ => None,
+            };
+            let is_scalar_field = |index: usize| {
+                ![x, y, z].contains(&index)
+                    && !color_channels.is_some_and(|channels| channels.contains(&index))
+                    && !normal_channels.is_some_and(|channels| channels.contains(&index))
+                    && matches!(element.properties[index].ty, PropertyType::Scalar(_))
+            };
+
+            let capacity = body.capacity(element);
+            let mut positions = Vec::with_capacity(capacity);
+            let mut colors = Vec::with_capacity(color_channels.map_or(0, |_| capacity));
+            let mut normals = Vec::with_capacity(normal_channels.map_or(0, |_| capacity));
+            let mut scalar_fields: BTreeMap<String, Vec<f32>> = (0..element.properties.len())
+                .filter(|&index| is_scalar_field(index))
+                .map(|index| (element.properties[index].name.clone(), Vec::new()))
+                .collect();
+            let mut values = vec![0.0; element.properties.len()];
+            for _ in 0..element.count {
+                for (value, property) in values.iter_mut().zip(&element.properties) {
+                    *value = body.read_property(&property.ty)?.unwrap_or_default();
+                }
+                positions.push(DVec3::new(values[x], values[y], values[z]));
+                if let Some(channels) = color_channels {
+                    let color = channels.map(|index| {
+                        let PropertyType::Scalar(ty) = element.properties[index].ty else {
+                            return 0;
+                        };
+                        (values[index] * ty.color_scale() * 255.0).clamp(0.0, 255.0) as u8
+                    });
+                    colors.push(pack_rgba8([color[0], color[1], color[2], u8::MAX]));
+                }
+                if let Some(channels) = normal_channels {
+                    normals.push(channels.map(|index| values[index] as f32));
+                }
+                for (index, property) in element.properties.iter().enumerate() {
+                    if is_scalar_field(index) {
+                        scalar_fields
+                            .get_mut(&property.name)
+                            .unwrap()
+                            .push(values[index] as f32);
+                    }
+                }
+            }
+
+            // Positions can be far from the origin, so center them in double precision.
+            let (min, max) = positions.iter().fold(
+                (DVec3::splat(f64::MAX), DVec3::splat(f64::MIN)),
+                |(min, max), &position| (min.min(position), max.max(position)),
+            );
+            let origin = if positions.is_empty() {
+                DVec3::ZERO
+            } else {
+                (min + max) / 2.0
+            };
+            let positions: Vec<Vec3> = positions
+                .into_iter()
+                .map(|position| (position - origin).as_vec3())
+                .collect();
+
+            let mut mesh = Mesh::new(PrimitiveTopology::PointList);
+            mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+            if color_channels.is_some() {
+                mesh.insert_attribute(ATTRIBUTE_COLOR_PACKED, colors);
+            }
+            if normal_channels.is_some() {
+                mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+            }
+            let mut asset = PointCloudAsset::new(mesh);
+            asset.origin = origin;
+            asset.scalar_fields = scalar_fields;
+            return Ok(asset);
+        }
+        Err(PlyLoaderError::MissingPositions)
+    }
+}
+
+impl AssetLoader for PlyLoader {
+    type Asset = PointCloudAsset;
+    type Settings = ();
+    type Error = PlyLoaderError;
+
+    fn extensions(&self) -> &[&str] {
+        &["ply"]
+    }
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            Self::load_ply(&bytes)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::mesh::VertexAttributeValues;
+
+    #[test]
+    fn loads_empty_files() {
+        for format in ["ascii", "binary_little_endian"] {
+            let file = format!(
+                "ply\nformat {format} 1.0\nelement vertex 0\nproperty float x\n\
+                 property float y\nproperty float z\nend_header\n"
+            );
+            let asset = PlyLoader::load_ply(file.as_bytes()).unwrap();
+            assert_eq!(asset.num_points(), 0);
+            assert!(asset.aabb.center.is_finite());
+            assert!(asset.aabb.half_extents.is_finite());
+            assert_eq!(asset.origin, DVec3::ZERO);
+        }
+    }
+
+    #[test]
+    fn rejects_more_vertices_than_the_body_holds() {
+        for format in ["ascii", "binary_little_endian", "binary_big_endian"] {
+            let file = format!(
+                "ply\nformat {format} 1.0\nelement face {}\nelement vertex {}\n\
+                 property float x\nproperty float y\nproperty float z\nend_header\n1 2 3 4",
+                usize::MAX,
+                usize::MAX
+            );
+            assert!(
+                matches!(
+                    PlyLoader::load_ply(file.as_bytes()),
+                    Err(PlyLoaderError::Data(_))
+                ),
+                "{format}"
+            );
+        }
+    }
+
+    #[test]
+    fn converts_16_bit_colors_to_8_bits() {
+        let mut file = b"ply\nformat binary_little_endian 1.0\nelement vertex 2\n\
+            property float x\nproperty float y\nproperty float z\n\
+            property ushort red\nproperty ushort green\nproperty ushort blue\nend_header\n"
+            .to_vec();
+        for (position, color) in [
+            ([0.0f32, 0.0, 0.0], [u16::MAX, 0x8080, 0]),
+            ([1.0, 1.0, 1.0], [0x0101, 0xfefe, 0x4040]),
+        ] {
+            file.extend(
+                position
+                    .iter()
+                    .flat_map(|coordinate| coordinate.to_le_bytes()),
+            );
+            file.extend(color.iter().flat_map(|channel| channel.to_le_bytes()));
+        }
+        let asset = PlyLoader::load_ply(&file).unwrap();
+        let Some(VertexAttributeValues::Uint32(colors)) =
+            asset.mesh.attribute(ATTRIBUTE_COLOR_PACKED)
+        else {
+            panic!("expected 8 bit colors");
+        };
+        assert_eq!(
+            colors,
+            &[
+                pack_rgba8([u8::MAX, 0x80, 0, u8::MAX]),
+                pack_rgba8([0x01, 0xfe, 0x40, u8::MAX]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,332 @@
+use bevy::{prelude::*, render::primitives::Aabb};
+
+use crate::{PointCloudAsset, PointCloudViewUniform, PotreePointCloud};
+
+/// Automatically picks [`PotreePointCloud::point_size`] every frame based on the distance
+/// between the closest active camera and the cloud's bounding box.
+///
+/// The target size grows linearly with distance so that the cloud looks reasonably dense both
+/// up close and from afar, and is clamped to `min_size..=max_size`. If `min_size` is larger
+/// than `max_size`, `max_size` wins.
+#[derive(Component, Clone, Debug)]
+pub struct AutoPointSize {
+    /// World space point size per unit of distance between the camera and the cloud.
+    pub size_per_distance: f32,
+    /// The smallest point size that will be picked.
+    pub min_size: f32,
+    /// The largest point size that will be picked.
+    pub max_size: f32,
+    /// How quickly the point size approaches its target, in 1/seconds.
+    /// Use `f32::INFINITY` to snap to the target immediately.
+    pub responsiveness: f32,
+}
+
+impl Default for AutoPointSize {
+    fn default() -> Self {
+        Self {
+            size_per_distance: 0.002,
+            min_size: 0.001,
+            max_size: 1.0,
+            responsiveness: 8.0,
+        }
+    }
+}
+
+impl AutoPointSize {
+    fn target_size(&self, distance: f32) -> f32 {
+        (distance * self.size_per_distance)
+            .max(self.min_size)
+            .min(self.max_size)
+    }
+}
+
+/// Scales the size of every point drawn by the camera it's inserted on.
+///
+/// Useful when the same cloud is drawn into views of very different resolutions, like a main
+/// view and a thumbnail, where the small view needs larger points to stay legible.
+/// Cameras without it use a multiplier of `1.0`.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct PointSizeMultiplier(pub f32);
+
+impl Default for PointSizeMultiplier {
+    fn default() -> Self {
+        Self(1.0)
+    }
+}
+
+/// Clamps the on-screen size of every point drawn by the camera it's inserted on, in physical
+/// pixels.
+///
+/// The clamp is applied last, after perspective attenuation, [`AutoPointSize`] and
+/// [`PointSizeMultiplier`], so it always holds. `max` caps the fill rate when the camera gets
+/// close to a cloud, and `min` keeps far points from disappearing. If `min` is larger than
+/// `max`, `max` wins. Cameras without it don't clamp point sizes.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct PointPixelSize {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Default for PointPixelSize {
+    fn default() -> Self {
+        Self {
+            min: 0.0,
+            max: f32::INFINITY,
+        }
+    }
+}
+
+/// The shape of the points of a [`PotreePointCloud`]. Points with a positive
+/// [`edge_softness`](PotreePointCloud::edge_softness) are always round.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PointShape {
+    #[default]
+    Square,
+    /// Discs as wide as the square would be.
+    Circle,
+}
+
+impl PointShape {
+    /// The shape in the model uniform.
+    pub(crate) fn gpu(self) -> u32 {
+        match self {
+            Self::Square => 0,
+            Self::Circle => 1,
+        }
+    }
+}
+
+/// How [`PotreePointCloud::point_size`] is interpreted.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PointSizeMode {
+    /// The point size is in world units, so points get smaller with distance.
+    #[default]
+    Fixed,
+    /// The point size is in physical pixels, regardless of the distance to the camera.
+    Screen,
+    /// Points closer than `distance` to a perspective camera have the world space size, and
+    /// farther points keep the pixel size they would have at `distance`. Behaves like
+    /// [`PointSizeMode::Fixed`] for orthographic cameras.
+    Adaptive { distance: f32 },
+}
+
+pub(crate) const GPU_POINT_SIZE_MODE_FIXED: u32 = 0;
+pub(crate) const GPU_POINT_SIZE_MODE_SCREEN: u32 = 1;
+pub(crate) const GPU_POINT_SIZE_MODE_ADAPTIVE: u32 = 2;
+
+impl PointSizeMode {
+    /// The mode and adaptive distance in the model uniform.
+    pub(crate) fn gpu(self) -> (u32, f32) {
+        match self {
+            Self::Fixed => (GPU_POINT_SIZE_MODE_FIXED, 0.0),
+            Self::Screen => (GPU_POINT_SIZE_MODE_SCREEN, 0.0),
+            Self::Adaptive { distance } => {
+                (GPU_POINT_SIZE_MODE_ADAPTIVE, distance.max(f32::EPSILON))
+            }
+        }
+    }
+}
+
+/// World space distance from `point` to `aabb`, which is transformed by `transform`.
+/// Zero if the point is inside the box.
+fn distance_to_aabb(aabb: &Aabb, transform: &GlobalTransform, point: Vec3) -> f32 {
+    let affine = transform.affine();
+    let local_point = affine.inverse().transform_point3(point);
+    let closest = Vec3::from(aabb.min()).max(local_point.min(aabb.max().into()));
+    affine.transform_point3(closest).distance(point)
+}
+
+/// World space distance from `point` to the farthest corner of `aabb`, which is transformed by
+/// `transform`.
+pub(crate) fn farthest_distance_to_aabb(aabb: &Aabb, transform: &Mat4, point: Vec3) -> f32 {
+    let (min, max) = (Vec3::from(aabb.min()), Vec3::from(aabb.max()));
+    (0..8)
+        .map(|corner| {
+            let corner = Vec3::select(
+                BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0),
+                max,
+                min,
+            );
+            transform.transform_point3(corner).distance(point)
+        })
+        .fold(0.0, f32::max)
+}
+
+/// An upper bound of the world space size of the points of a cloud drawn with `size` in the
+/// GPU `mode`, for points up to `distance` from the camera of a view with `projection` and
+/// `viewport` (width and height in physical pixels). Mirrors the size math of the vertex shader.
+pub(crate) fn world_point_size(
+    size: f32,
+    (mode, adaptive_distance): (u32, f32),
+    view_settings: &PointCloudViewUniform,
+    projection: &Mat4,
+    viewport: Vec2,
+    distance: f32,
+) -> f32 {
+    let size = size * view_settings.point_size_multiplier;
+    let perspective = projection.z_axis.w == -1.0;
+    // The clip space size of the points, which is added before the perspective divide, and
+    // the clip space w of the farthest ones.
+    let (clip_size, w) = if perspective {
+        (0.5 * size * projection.y_axis.y, distance)
+    } else {
+        let max_scale = (2.0 / projection.x_axis.x)
+            .abs()
+            .max((2.0 / projection.y_axis.y).abs());
+        (size / max_scale, 1.0)
+    };
+    let pixel_size = clip_size / w * viewport.x * 0.5;
+    if !pixel_size.is_finite() || pixel_size <= 0.0 || viewport.y <= 0.0 {
+        return size;
+    }
+    let mode_scale = match mode {
+        GPU_POINT_SIZE_MODE_SCREEN => size / pixel_size,
+        GPU_POINT_SIZE_MODE_ADAPTIVE if perspective => (w / adaptive_distance).max(1.0),
+        _ => 1.0,
+    };
+    let clamped_pixel_size = (pixel_size * mode_scale)
+        .max(view_settings.min_pixel_size)
+        .min(view_settings.max_pixel_size);
+    let clip_size = clip_size * clamped_pixel_size / pixel_size;
+    // Back to world space, on both axes since the quad is stretched by the aspect ratio.
+    let width = clip_size / projection.x_axis.x;
+    let height = clip_size * viewport.x / viewport.y / projection.y_axis.y;
+    width.abs().max(height.abs())
+}
+
+pub(crate) fn auto_point_size_system(
+    time: Res<Time>,
+    assets: Res<Assets<PointCloudAsset>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    mut point_clouds: Query<(&mut PotreePointCloud, &AutoPointSize, &GlobalTransform)>,
+) {
+    for (mut point_cloud, auto_size, transform) in &mut point_clouds {
+        let Some(asset) = assets.get(&point_cloud.mesh) else {
+            continue;
+        };
+        let Some(distance) = cameras
+            .iter()
+            .filter(|(camera, _)| camera.is_active)
+            .map(|(_, camera_transform)| {
+                distance_to_aabb(&asset.aabb, transform, camera_transform.translation())
+            })
+            .reduce(f32::min)
+        else {
+            continue;
+        };
+
+        let target = auto_size.target_size(distance);
+        let blend = if auto_size.responsiveness.is_finite() {
+            1.0 - (-auto_size.responsiveness * time.delta_seconds()).exp()
+        } else {
+            1.0
+        };
+        let point_size = point_cloud.point_size + (target - point_cloud.point_size) * blend;
+        if point_size != point_cloud.point_size {
+            point_cloud.point_size = point_size;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::camera::CameraProjection;
+
+    use super::*;
+
+    const VIEWPORT: Vec2 = Vec2::new(800.0, 400.0);
+
+    fn view_settings(min_pixel_size: f32) -> PointCloudViewUniform {
+        PointCloudViewUniform {
+            point_size_multiplier: 1.0,
+            min_pixel_size,
+            max_pixel_size: f32::INFINITY,
+            eye_dome_strength: 0.0,
+            eye_dome_radius: 0.0,
+            eye_dome_samples: 0,
+            depth_cue_near: 0.0,
+            depth_cue_far: 0.0,
+            depth_cue_strength: 0.0,
+            depth_cue_color: Vec4::ZERO,
+        }
+    }
+
+    /// A 90 degree field of view, so that a pixel is `distance / 200` wide.
+    fn perspective() -> Mat4 {
+        PerspectiveProjection {
+            fov: std::f32::consts::FRAC_PI_2,
+            aspect_ratio: VIEWPORT.x / VIEWPORT.y,
+            ..default()
+        }
+        .get_projection_matrix()
+    }
+
+    fn size(size: f32, mode: PointSizeMode, min_pixel_size: f32, distance: f32) -> f32 {
+        world_point_size(
+            size,
+            mode.gpu(),
+            &view_settings(min_pixel_size),
+            &perspective(),
+            VIEWPORT,
+            distance,
+        )
+    }
+
+    #[test]
+    fn fixed_points_keep_their_world_size() {
+        assert!((size(1.0, PointSizeMode::Fixed, 0.0, 10.0) - 1.0).abs() < 1e-5);
+        assert!((size(1.0, PointSizeMode::Fixed, 0.0, 1000.0) - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn screen_points_grow_with_distance() {
+        assert!((size(10.0, PointSizeMode::Screen, 0.0, 10.0) - 0.5).abs() < 1e-5);
+        assert!((size(10.0, PointSizeMode::Screen, 0.0, 20.0) - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn adaptive_points_grow_past_their_distance() {
+        let mode = PointSizeMode::Adaptive { distance: 5.0 };
+        assert!((size(1.0, mode, 0.0, 2.0) - 1.0).abs() < 1e-5);
+        assert!((size(1.0, mode, 0.0, 10.0) - 2.0).abs() < 1e-5);
+
+        let orthographic = OrthographicProjection::default().get_projection_matrix();
+        let fixed = |mode: PointSizeMode| {
+            world_point_size(
+                1.0,
+                mode.gpu(),
+                &view_settings(0.0),
+                &orthographic,
+                VIEWPORT,
+                10.0,
+            )
+        };
+        assert_eq!(fixed(mode), fixed(PointSizeMode::Fixed));
+    }
+
+    #[test]
+    fn far_points_grow_to_the_min_pixel_size() {
+        // Points are 0.2 pixels wide 1000 units away, so 10 pixels are 50 times as wide.
+        assert!((size(1.0, PointSizeMode::Fixed, 10.0, 1000.0) - 50.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn auto_size_prefers_the_max_size_over_the_min_size() {
+        let auto_size = AutoPointSize {
+            size_per_distance: 1.0,
+            min_size: 2.0,
+            max_size: 1.0,
+            ..default()
+        };
+        assert_eq!(auto_size.target_size(0.5), 1.0);
+        assert_eq!(auto_size.target_size(5.0), 1.0);
+    }
+
+    #[test]
+    fn farthest_distance_is_to_the_opposite_corner() {
+        let aabb = Aabb::from_min_max(Vec3::ZERO, Vec3::ONE);
+        let transform = Mat4::from_translation(Vec3::X);
+        let distance = farthest_distance_to_aabb(&aabb, &transform, Vec3::ZERO);
+        assert!((distance - Vec3::new(2.0, 1.0, 1.0).length()).abs() < 1e-5);
+    }
+}
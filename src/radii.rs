@@ -0,0 +1,220 @@
+use std::{borrow::Cow, collections::BinaryHeap};
+
+use bevy::{prelude::*, render::mesh::VertexAttributeValues};
+
+use crate::PointCloudAsset;
+
+/// Name of the scalar field holding per-point radii. When an asset has it,
+/// [`PotreePointCloud::point_size`](crate::PotreePointCloud::point_size) is multiplied by the
+/// radius of every point, except in [`PointSizeMode::Screen`](crate::PointSizeMode::Screen).
+pub const RADIUS_SCALAR_FIELD: &str = "radius";
+
+/// Number of neighbors averaged by the loaders that compute radii.
+pub const DEFAULT_RADIUS_NEIGHBORS: usize = 8;
+
+/// A balanced kd-tree stored implicitly in an index array. The root of a range is the middle
+/// element, the left half is below it on the split axis and the right half above.
+///
+/// The index array can be kept and borrowed again, as long as the positions don't change.
+pub(crate) struct KdTree<'a> {
+    positions: &'a [[f32; 3]],
+    pub(crate) indices: Cow<'a, [u32]>,
+}
+
+impl<'a> KdTree<'a> {
+    pub(crate) fn new(positions: &'a [[f32; 3]]) -> Self {
+        let mut indices: Vec<u32> = (0..positions.len() as u32).collect();
+        Self::build(positions, &mut indices, 0);
+        Self {
+            positions,
+            indices: indices.into(),
+        }
+    }
+
+    /// A tree over `positions` from the indices of a tree built over them before.
+    pub(crate) fn from_indices(positions: &'a [[f32; 3]], indices: &'a [u32]) -> Self {
+        Self {
+            positions,
+            indices: indices.into(),
+        }
+    }
+
+    fn build(positions: &[[f32; 3]], indices: &mut [u32], axis: usize) {
+        if indices.len() <= 1 {
+            return;
+        }
+        let mid = indices.len() / 2;
+        indices.select_nth_unstable_by(mid, |&a, &b| {
+            positions[a as usize][axis].total_cmp(&positions[b as usize][axis])
+        });
+        let (left, right) = indices.split_at_mut(mid);
+        Self::build(positions, left, (axis + 1) % 3);
+        Self::build(positions, &mut right[1..], (axis + 1) % 3);
+    }
+
+    /// Collects the `k` nearest neighbors of the point at `index` into `heap`, as
+    /// `(squared distance bits, index)`. Non-negative floats order like their bits.
+    fn nearest(
+        &self,
+        range: &[u32],
+        axis: usize,
+        index: u32,
+        k: usize,
+        heap: &mut BinaryHeap<(u32, u32)>,
+    ) {
+        if range.is_empty() {
+            return;
+        }
+        let mid = range.len() / 2;
+        let node = range[mid];
+        let point = Vec3::from(self.positions[index as usize]);
+        let node_position = Vec3::from(self.positions[node as usize]);
+        if node != index {
+            let distance = point.distance_squared(node_position).to_bits();
+            if heap.len() < k {
+                heap.push((distance, node));
+            } else if distance < heap.peek().unwrap().0 {
+                heap.pop();
+                heap.push((distance, node));
+            }
+        }
+        let diff = point[axis] - node_position[axis];
+        let (near, far) = if diff < 0.0 {
+            (&range[..mid], &range[mid + 1..])
+        } else {
+            (&range[mid + 1..], &range[..mid])
+        };
+        let next_axis = (axis + 1) % 3;
+        self.nearest(near, next_axis, index, k, heap);
+        if heap.len() < k || (diff * diff).to_bits() < heap.peek().unwrap().0 {
+            self.nearest(far, next_axis, index, k, heap);
+        }
+    }
+
+    /// Calls `visit` with the indices of the points in the boxes, from `min` to `max`, that
+    /// `may_contain` accepts. Subtrees whose box is rejected are skipped, so `may_contain`
+    /// must accept every box that holds a point of interest. The boxes of a range are its
+    /// parent's, split at the parent on its axis, so `min` and `max` must bound all points.
+    pub(crate) fn visit(
+        &self,
+        range: &[u32],
+        axis: usize,
+        (min, max): (Vec3, Vec3),
+        may_contain: &impl Fn(Vec3, Vec3) -> bool,
+        visit: &mut impl FnMut(u32),
+    ) {
+        if range.is_empty() || !may_contain(min, max) {
+            return;
+        }
+        let mid = range.len() / 2;
+        let node = range[mid];
+        visit(node);
+        let split = self.positions[node as usize][axis];
+        let next_axis = (axis + 1) % 3;
+        let (mut left_max, mut right_min) = (max, min);
+        left_max[axis] = split;
+        right_min[axis] = split;
+        self.visit(
+            &range[..mid],
+            next_axis,
+            (min, left_max),
+            may_contain,
+            visit,
+        );
+        self.visit(
+            &range[mid + 1..],
+            next_axis,
+            (right_min, max),
+            may_contain,
+            visit,
+        );
+    }
+}
+
+impl PointCloudAsset {
+    /// Stores the mean distance from every point to its `neighbors` nearest neighbors in the
+    /// [`RADIUS_SCALAR_FIELD`] scalar field, so that points in sparse regions are drawn larger
+    /// than points in dense ones. Takes `O(n log n)` time. At least one neighbor is used.
+    ///
+    /// Animated assets use their unanimated positions.
+    pub fn compute_radii(&mut self, neighbors: usize) {
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            self.mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            return;
+        };
+        let neighbors = neighbors.max(1);
+        let tree = KdTree::new(positions);
+        let mut heap = BinaryHeap::with_capacity(neighbors + 1);
+        let radii = (0..positions.len() as u32)
+            .map(|index| {
+                heap.clear();
+                tree.nearest(&tree.indices, 0, index, neighbors, &mut heap);
+                if heap.is_empty() {
+                    return 1.0;
+                }
+                let sum: f32 = heap
+                    .iter()
+                    .map(|&(distance, _)| f32::from_bits(distance).sqrt())
+                    .sum();
+                sum / heap.len() as f32
+            })
+            .collect();
+        self.scalar_fields
+            .insert(RADIUS_SCALAR_FIELD.to_owned(), radii);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn radii(positions: Vec<Vec3>, neighbors: usize) -> Vec<f32> {
+        let colors = vec![[u8::MAX; 4]; positions.len()];
+        let mut asset = PointCloudAsset::from_points(positions, colors).unwrap();
+        asset.compute_radii(neighbors);
+        asset.scalar_field(RADIUS_SCALAR_FIELD).unwrap().to_vec()
+    }
+
+    #[test]
+    fn averages_the_distance_to_the_nearest_neighbors() {
+        let positions: Vec<Vec3> = [0.0, 1.0, 3.0, 7.0].map(|x| Vec3::X * x).into();
+        assert_eq!(radii(positions.clone(), 1), [1.0, 1.0, 2.0, 4.0]);
+        assert_eq!(radii(positions, 2), [2.0, 1.5, 2.5, 5.0]);
+    }
+
+    #[test]
+    fn visits_the_points_in_the_accepted_boxes() {
+        let positions: Vec<[f32; 3]> = (0..1000)
+            .map(|i| [(i % 10) as f32, (i / 10 % 10) as f32, (i / 100) as f32])
+            .collect();
+        let tree = KdTree::new(&positions);
+        let bounds = (Vec3::ZERO, Vec3::splat(9.0));
+        let in_region = |position: Vec3| position.cmpge(Vec3::splat(2.5)).all() && position.x < 4.0;
+        let mut visited = Vec::new();
+        tree.visit(
+            &tree.indices,
+            0,
+            bounds,
+            &|min, max| max.cmpge(Vec3::splat(2.5)).all() && min.x < 4.0,
+            &mut |index| visited.push(index),
+        );
+        assert!(
+            visited.len() < positions.len() / 2,
+            "no subtree was skipped"
+        );
+        visited.retain(|&index| in_region(Vec3::from(positions[index as usize])));
+        visited.sort();
+        let expected: Vec<u32> = (0..positions.len() as u32)
+            .filter(|&index| in_region(Vec3::from(positions[index as usize])))
+            .collect();
+        assert_eq!(visited, expected);
+    }
+
+    #[test]
+    fn uses_at_least_one_neighbor() {
+        assert_eq!(radii(vec![Vec3::ZERO, Vec3::Y * 2.0], 0), [2.0, 2.0]);
+        // Without neighbors the points keep their size.
+        assert_eq!(radii(vec![Vec3::ZERO], 0), [1.0]);
+    }
+}
@@ -1,12 +1,36 @@
-use crate::{pipeline::PointCloudPipeline, PointCloudAsset};
-use crate::{PointCloudPipelineKey, ATTRIBUTE_COLOR};
+use crate::{
+    batching::{ExtractedPointCloudBatch, PointCloudBatch},
+    color::{ExtractedTransferFunction, GpuPointColorMode, GPU_COLOR_MODE_TRANSFER_FUNCTION},
+    instancing::ExtractedPointCloudInstances,
+    lighting::{
+        ambient_light, light_factor, normal_light_intensity, normal_lights, scene_light,
+        MAX_NORMAL_DIRECTIONAL_LIGHTS,
+    },
+    morph::{gpu_morph_color_format, ExtractedPointCloudMorph},
+    point_size::GPU_POINT_SIZE_MODE_FIXED,
+    CenterPointCloudWhenReady, DepthCue, InstancedPointCloud, PointBlendMode, PointCloudAnimation,
+    PointCloudLighting, PointCloudLod, PointCloudMorph, PointCloudOcclusion, PointCloudPipelineKey,
+    PointCloudTransparency, PointColorMode, PointConfidence, PointDepthSort, PointPixelSize,
+    PointShape, PointSizeMode, PointSizeMultiplier, TimeWindow, ATTRIBUTE_COLOR_PACKED,
+    ATTRIBUTE_COLOR_RGBA16, MAX_COLOR_RAMP_STOPS, RADIUS_SCALAR_FIELD,
+};
+use crate::{
+    pipeline::{
+        EyeDomeLightingEnabled, EyeDomeSettings, PointCloudDepthPrepassEnabled, PointCloudPipeline,
+    },
+    upload::WeakUploads,
+    PointCloudAsset,
+};
+use bevy::core_pipeline::prepass::DepthPrepass;
+use bevy::render::primitives::Aabb;
 use bevy::render::render_asset::RenderAssets;
 use bevy::render::render_resource::{
     BufferDescriptor, CachedRenderPipelineId, DynamicBindGroupEntries, PipelineCache,
     SpecializedRenderPipelines,
 };
 use bevy::render::renderer::RenderQueue;
-use bevy::render::view::VisibleEntities;
+use bevy::render::view::{ExtractedView, VisibleEntities};
+use bevy::utils::HashSet;
 use bevy::{
     ecs::system::{lifetimeless::SRes, SystemParamItem},
     prelude::*,
@@ -18,38 +42,521 @@ use bevy::{
     },
 };
 use opd_parser::Frames;
+use std::borrow::Cow;
 #[derive(Component, Clone)]
 pub struct PotreePointCloud {
+    /// Point clouds can share an asset. It is uploaded to the GPU once, and stays there until
+    /// the last handle to it is dropped. Shared animated assets play back in sync, since
+    /// [`PointCloudPlaybackControls`](crate::PointCloudPlaybackControls) are per asset.
     pub mesh: Handle<PointCloudAsset>,
+    /// Copied into the per-frame uniform of the cloud, so it can be animated freely without
+    /// respawning or re-specializing anything.
     pub point_size: f32,
+    /// Points closer than this distance to the camera's near plane are faded out with a
+    /// dither pattern instead of popping when the camera flies through the cloud.
+    /// Only applies to perspective cameras. `0.0` disables the fade.
+    pub near_fade_distance: f32,
+    /// Fraction of the point radius over which the edge of a point fades out, from `0.0` to
+    /// `1.0`. Points are drawn as discs when this is positive, and as squares otherwise.
+    ///
+    /// The edge only softens against what is behind the point. The depth test keeps it crisp
+    /// where nearer geometry covers it. Softening is smoothest with MSAA, where it uses alpha
+    /// to coverage. Without MSAA it is dithered.
+    pub edge_softness: f32,
+    /// Multiplied with the color of every point, for example to tell overlaid clouds apart.
+    /// The alpha is only used by blended [`PointBlendMode`]s.
+    pub color: Color,
+    /// Whether `point_size` is in world units or pixels.
+    pub size_mode: PointSizeMode,
+    pub shape: PointShape,
+    pub blend_mode: PointBlendMode,
+}
+
+impl Default for PotreePointCloud {
+    fn default() -> Self {
+        Self {
+            mesh: default(),
+            point_size: 1.0,
+            near_fade_distance: 0.0,
+            edge_softness: 0.0,
+            color: Color::WHITE,
+            size_mode: PointSizeMode::Fixed,
+            shape: PointShape::Square,
+            blend_mode: PointBlendMode::Opaque,
+        }
+    }
 }
+
+/// A [`PotreePointCloud`] with the components needed to place it in the world, like a
+/// [`SpatialBundle`].
+///
+/// Like meshes, the cloud is hidden by [`Visibility::Hidden`] and only drawn by the cameras
+/// whose [`RenderLayers`](bevy::render::view::RenderLayers) intersect its own.
+#[derive(Bundle, Clone, Default)]
+pub struct PointCloudBundle {
+    pub point_cloud: PotreePointCloud,
+    pub transform: Transform,
+    pub global_transform: GlobalTransform,
+    pub visibility: Visibility,
+    pub inherited_visibility: InheritedVisibility,
+    pub view_visibility: ViewVisibility,
+}
+
+impl PointCloudBundle {
+    /// A default cloud drawing `mesh`, moved so that its points are centered on the origin
+    /// once the asset has loaded, see [`CenterPointCloudWhenReady`].
+    pub fn centered(mesh: Handle<PointCloudAsset>) -> (Self, CenterPointCloudWhenReady) {
+        (
+            Self {
+                point_cloud: PotreePointCloud { mesh, ..default() },
+                ..default()
+            },
+            CenterPointCloudWhenReady,
+        )
+    }
+}
+
 #[derive(Component, Clone, ShaderType)]
 pub struct PointCloudUniform {
     pub transform: Mat4,
     pub point_size: f32,
+    pub near_fade_distance: f32,
+    pub edge_softness: f32,
+    /// See [`PotreePointCloud::shape`].
+    pub point_shape: u32,
+    /// See [`PotreePointCloud::color`], in linear RGB.
+    pub tint: Vec4,
+    /// See [`PointCloudSelection::color`](crate::PointCloudSelection::color), in linear RGB.
+    pub selection_color: Vec4,
+    pub color_mode: u32,
+    /// Index of the first value of the selected scalar field in the asset's scalar buffer.
+    pub scalar_offset: u32,
+    pub scalar_min: f32,
+    pub scalar_max: f32,
+    pub color_ramp_len: u32,
+    pub color_ramp: [Vec4; MAX_COLOR_RAMP_STOPS],
+    /// Multiplied with the point colors, see [`PointCloudLighting`].
+    pub light_factor: Vec3,
+    /// See [`PointCloudMorph::mix`].
+    pub morph_mix: f32,
+    /// See [`PointCloudMorph::interpolate_positions`].
+    pub morph_interpolate_positions: u32,
+    /// 0 if the morph target has no colors, 1 for [`ATTRIBUTE_COLOR_PACKED`], 2 for
+    /// [`ATTRIBUTE_COLOR_RGBA16`].
+    pub morph_color_format: u32,
+    /// See [`PointCloudTransparency`].
+    pub opacity: f32,
+    /// See [`PotreePointCloud::size_mode`].
+    pub point_size_mode: u32,
+    /// See [`PointSizeMode::Adaptive`].
+    pub adaptive_distance: f32,
+    /// Index of the first [`RADIUS_SCALAR_FIELD`] value in the
+    /// asset's scalar buffer, or `u32::MAX` if the asset has no radii.
+    pub radius_offset: u32,
+    /// How much points are shaded by their normals instead of [`Self::light_factor`], see
+    /// [`PointCloudLighting::normals`].
+    pub normal_light_intensity: f32,
+    pub num_directional_lights: u32,
+    /// The ambient light received by points with normals, in linear RGB.
+    pub ambient_light: Vec3,
+    /// World space directions towards the directional lights.
+    pub directional_light_directions: [Vec4; MAX_NORMAL_DIRECTIONAL_LIGHTS],
+    /// Colors of the directional lights, scaled by their illuminance.
+    pub directional_light_colors: [Vec4; MAX_NORMAL_DIRECTIONAL_LIGHTS],
+    /// Index of the first [`PointConfidence::field`] value in the asset's scalar buffer, or
+    /// `u32::MAX` without [`PointConfidence`].
+    pub confidence_offset: u32,
+    /// See [`PointConfidence::threshold`].
+    pub confidence_threshold: f32,
+    /// See [`PointConfidence::fade`].
+    pub confidence_fade: u32,
+    /// Index of the first word of the [`PointCloudSelection`](crate::PointCloudSelection)
+    /// mask in the selection buffer, or `u32::MAX` if no points are selected.
+    pub selection_offset: u32,
+    /// Index of the first [`TIMESTAMP_SCALAR_FIELD`](crate::TIMESTAMP_SCALAR_FIELD) value in
+    /// the asset's scalar buffer, or `u32::MAX` without [`TimeWindow`].
+    pub time_offset: u32,
+    /// See [`TimeWindow`], relative to [`PointCloudAsset::timestamp_origin`].
+    pub time_start: f32,
+    pub time_end: f32,
+}
+
+/// Per-view settings, bound next to the view uniform.
+#[derive(Component, Clone, ShaderType)]
+pub struct PointCloudViewUniform {
+    /// See [`PointSizeMultiplier`].
+    pub point_size_multiplier: f32,
+    /// See [`PointPixelSize`].
+    pub min_pixel_size: f32,
+    pub max_pixel_size: f32,
+    /// See [`EyeDomeSettings`], scaled for the projection.
+    pub eye_dome_strength: f32,
+    pub eye_dome_radius: f32,
+    /// See [`EyeDomeSamples`](crate::EyeDomeSamples).
+    pub eye_dome_samples: u32,
+    /// See [`DepthCue`], with a strength of `0.0` without one.
+    pub depth_cue_near: f32,
+    pub depth_cue_far: f32,
+    pub depth_cue_strength: f32,
+    /// In linear RGB.
+    pub depth_cue_color: Vec4,
+}
+
+type ExtractedPointCloudView = (
+    Entity,
+    &'static Camera,
+    Option<&'static PointSizeMultiplier>,
+    Option<&'static PointPixelSize>,
+    Option<&'static EyeDomeSettings>,
+    Option<&'static DepthCue>,
+);
+
+pub(crate) fn extract_point_cloud_views(
+    mut commands: Commands,
+    mut previous_len: Local<usize>,
+    cameras: Extract<Query<ExtractedPointCloudView>>,
+) {
+    let mut values = Vec::with_capacity(*previous_len);
+    for (entity, camera, point_size_multiplier, pixel_size, eye_dome_settings, depth_cue) in
+        &cameras
+    {
+        if !camera.is_active {
+            continue;
+        }
+        let pixel_size = pixel_size.copied().unwrap_or_default();
+        let eye_dome_settings = eye_dome_settings.copied().unwrap_or_default();
+        let depth_cue = depth_cue.copied().unwrap_or(DepthCue {
+            strength: 0.0,
+            ..default()
+        });
+        let projection = camera.projection_matrix();
+        let eye_dome_strength = if projection.z_axis.w == -1.0 {
+            // perspective projection
+            // See https://github.com/bitshifter/glam-rs/blob/a35030d130c0464cbb07d6404df6843240182803/src/f32/scalar/mat4.rs#L843
+            1.0
+        } else {
+            // orthographic projection
+            // See https://github.com/bitshifter/glam-rs/blob/a35030d130c0464cbb07d6404df6843240182803/src/f32/scalar/mat4.rs#L924
+            1.0 / projection.z_axis.z // near - far
+        };
+        values.push((
+            entity,
+            PointCloudViewUniform {
+                point_size_multiplier: point_size_multiplier.copied().unwrap_or_default().0,
+                min_pixel_size: pixel_size.min.max(0.0),
+                max_pixel_size: pixel_size.max,
+                eye_dome_strength: eye_dome_strength * eye_dome_settings.strength,
+                eye_dome_radius: eye_dome_settings.radius,
+                eye_dome_samples: eye_dome_settings.samples.count(),
+                depth_cue_near: depth_cue.near,
+                depth_cue_far: depth_cue.far,
+                depth_cue_strength: depth_cue.strength.clamp(0.0, 1.0),
+                depth_cue_color: Vec4::from(depth_cue.color.as_linear_rgba_f32()),
+            },
+        ));
+    }
+    *previous_len = values.len();
+    commands.insert_or_spawn_batch(values);
 }
 
+type ExtractedPointCloud = (
+    Entity,
+    &'static PotreePointCloud,
+    &'static GlobalTransform,
+    Option<&'static PointColorMode>,
+    Option<&'static PointCloudLighting>,
+    Option<&'static PointCloudTransparency>,
+    Option<&'static PointConfidence>,
+    Option<&'static PointCloudAnimation>,
+    Option<&'static TimeWindow>,
+);
+
+type ExtractedPointCloudMorphQuery = (
+    Entity,
+    &'static PointCloudMorph,
+    &'static GlobalTransform,
+    Option<&'static PointColorMode>,
+    Option<&'static PointCloudLighting>,
+    Option<&'static PointCloudTransparency>,
+);
+
+type ExtractedInstancedPointCloud = (
+    Entity,
+    &'static InstancedPointCloud,
+    &'static GlobalTransform,
+    Option<&'static PointColorMode>,
+    Option<&'static PointCloudLighting>,
+    Option<&'static PointCloudTransparency>,
+);
+
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn extract_point_cloud(
     mut commands: Commands,
     mut previous_len: Local<usize>,
-    query: Extract<Query<(Entity, &PotreePointCloud, &GlobalTransform)>>,
+    mut previous_instanced_len: Local<usize>,
+    mut previous_morph_len: Local<usize>,
+    mut previous_batch_len: Local<usize>,
+    query: Extract<Query<ExtractedPointCloud>>,
+    instanced_query: Extract<Query<ExtractedInstancedPointCloud>>,
+    morph_query: Extract<Query<ExtractedPointCloudMorphQuery>>,
+    batch_query: Extract<Query<(Entity, &PointCloudBatch, &GlobalTransform)>>,
+    assets: Extract<Res<Assets<PointCloudAsset>>>,
+    ambient_light: Extract<Option<Res<AmbientLight>>>,
+    directional_lights: Extract<Query<(&DirectionalLight, &GlobalTransform, &ViewVisibility)>>,
 ) {
     let mut values = Vec::with_capacity(*previous_len);
+    let visible_lights = || {
+        directional_lights
+            .iter()
+            .filter(|(_, _, visibility)| visibility.get())
+    };
+    let scene_light = scene_light(
+        ambient_light.as_deref(),
+        visible_lights().map(|(light, _, _)| light),
+    );
+    let ambient_light = self::ambient_light(ambient_light.as_deref());
+    let (num_directional_lights, directional_light_directions, directional_light_colors) =
+        normal_lights(visible_lights().map(|(light, transform, _)| (light, transform)));
 
-    for (entity, point_cloud, transform) in query.iter() {
+    let uniform = |transform: &GlobalTransform,
+                   point_size: f32,
+                   near_fade_distance: f32,
+                   edge_softness: f32,
+                   tint: Color,
+                   mesh: &Handle<PointCloudAsset>,
+                   color_mode: Option<&PointColorMode>,
+                   lighting: Option<&PointCloudLighting>,
+                   transparency: Option<&PointCloudTransparency>| {
+        let color_mode = GpuPointColorMode::new(color_mode, assets.get(mesh));
+        PointCloudUniform {
+            transform: transform.compute_matrix(),
+            point_size,
+            near_fade_distance,
+            edge_softness: edge_softness.clamp(0.0, 1.0),
+            point_shape: PointShape::Square.gpu(),
+            tint: Vec4::from(tint.as_linear_rgba_f32()),
+            selection_color: Vec4::ZERO,
+            color_mode: color_mode.mode,
+            scalar_offset: color_mode.scalar_offset,
+            scalar_min: color_mode.scalar_min,
+            scalar_max: color_mode.scalar_max,
+            color_ramp_len: color_mode.ramp_len,
+            color_ramp: color_mode.ramp,
+            light_factor: light_factor(lighting, scene_light),
+            morph_mix: 0.0,
+            morph_interpolate_positions: 0,
+            morph_color_format: 0,
+            opacity: PointCloudTransparency::opacity(transparency),
+            point_size_mode: GPU_POINT_SIZE_MODE_FIXED,
+            adaptive_distance: 0.0,
+            radius_offset: assets
+                .get(mesh)
+                .and_then(|asset| {
+                    let index = asset.scalar_field_index(RADIUS_SCALAR_FIELD)?;
+                    Some((index * asset.num_points()) as u32)
+                })
+                .unwrap_or(u32::MAX),
+            normal_light_intensity: normal_light_intensity(
+                lighting,
+                assets
+                    .get(mesh)
+                    .is_some_and(|asset| asset.mesh.contains_attribute(Mesh::ATTRIBUTE_NORMAL)),
+            ),
+            num_directional_lights,
+            ambient_light,
+            directional_light_directions,
+            directional_light_colors,
+            confidence_offset: u32::MAX,
+            confidence_threshold: 0.0,
+            confidence_fade: 0,
+            selection_offset: u32::MAX,
+            time_offset: u32::MAX,
+            time_start: 0.0,
+            time_end: 0.0,
+        }
+    };
+
+    // Batched clouds are drawn by their batch.
+    let batched: HashSet<Entity> = batch_query
+        .iter()
+        .flat_map(|(_, batch, _)| batch.clouds())
+        .collect();
+    for (
+        entity,
+        point_cloud,
+        transform,
+        color_mode,
+        lighting,
+        transparency,
+        confidence,
+        animation,
+        time_window,
+    ) in query.iter()
+    {
+        if batched.contains(&entity) {
+            continue;
+        }
+        let mut uniform = uniform(
+            transform,
+            point_cloud.point_size,
+            point_cloud.near_fade_distance,
+            point_cloud.edge_softness,
+            point_cloud.color,
+            &point_cloud.mesh,
+            color_mode,
+            lighting,
+            transparency,
+        );
+        (uniform.point_size_mode, uniform.adaptive_distance) = point_cloud.size_mode.gpu();
+        uniform.point_shape = point_cloud.shape.gpu();
+        if let Some(confidence) = confidence {
+            uniform.confidence_offset = confidence.scalar_offset(assets.get(&point_cloud.mesh));
+            uniform.confidence_threshold = confidence.threshold;
+            uniform.confidence_fade = confidence.fade.into();
+        }
+        if let Some(time_window) = time_window {
+            (uniform.time_offset, uniform.time_start, uniform.time_end) =
+                time_window.gpu(assets.get(&point_cloud.mesh));
+        }
+        if let Some(PointColorMode::TransferFunction { texture, .. }) = color_mode {
+            if uniform.color_mode == GPU_COLOR_MODE_TRANSFER_FUNCTION {
+                commands
+                    .get_or_spawn(entity)
+                    .insert(ExtractedTransferFunction {
+                        texture: texture.clone(),
+                    });
+            }
+        }
+        // Interpolated frames are drawn like a morph, whose bind group takes the slot of the
+        // transfer function.
+        let interpolation = animation
+            .filter(|_| uniform.color_mode != GPU_COLOR_MODE_TRANSFER_FUNCTION)
+            .and_then(|animation| animation.interpolation_target(&point_cloud.mesh, &assets));
+        if let Some((next, mix)) = interpolation {
+            uniform.morph_mix = mix;
+            uniform.morph_interpolate_positions = 1;
+            uniform.morph_color_format = assets.get(next).map_or(0, gpu_morph_color_format);
+            commands
+                .get_or_spawn(entity)
+                .insert(ExtractedPointCloudMorph { to: next.clone() });
+        }
         values.push((
+            entity,
+            (uniform, point_cloud.mesh.clone(), point_cloud.blend_mode),
+        ));
+    }
+    *previous_len = values.len();
+    commands.insert_or_spawn_batch(values);
+
+    let mut instanced_values = Vec::with_capacity(*previous_instanced_len);
+    for (entity, point_cloud, transform, color_mode, lighting, transparency) in
+        instanced_query.iter()
+    {
+        instanced_values.push((
             entity,
             (
-                PointCloudUniform {
-                    transform: transform.compute_matrix(),
-                    point_size: point_cloud.point_size,
+                uniform(
+                    transform,
+                    point_cloud.point_size,
+                    0.0,
+                    0.0,
+                    Color::WHITE,
+                    &point_cloud.asset,
+                    color_mode,
+                    lighting,
+                    transparency,
+                ),
+                point_cloud.asset.clone(),
+                ExtractedPointCloudInstances::new(&point_cloud.instances),
+            ),
+        ));
+    }
+    *previous_instanced_len = instanced_values.len();
+    commands.insert_or_spawn_batch(instanced_values);
+
+    let mut morph_values = Vec::with_capacity(*previous_morph_len);
+    for (entity, morph, transform, color_mode, lighting, transparency) in morph_query.iter() {
+        let mut uniform = uniform(
+            transform,
+            morph.point_size,
+            0.0,
+            0.0,
+            Color::WHITE,
+            &morph.from,
+            color_mode,
+            lighting,
+            transparency,
+        );
+        if morph.is_compatible(&assets) != Some(true) {
+            // Only `from` is drawn until both assets have loaded, or if they can't be blended.
+            commands
+                .get_or_spawn(entity)
+                .insert((uniform, morph.from.clone()));
+            continue;
+        }
+        uniform.morph_mix = morph.mix.clamp(0.0, 1.0);
+        uniform.morph_interpolate_positions = morph.interpolate_positions.into();
+        uniform.morph_color_format = assets.get(&morph.to).map_or(0, gpu_morph_color_format);
+        morph_values.push((
+            entity,
+            (
+                uniform,
+                morph.from.clone(),
+                ExtractedPointCloudMorph {
+                    to: morph.to.clone(),
                 },
-                point_cloud.mesh.clone(),
             ),
         ));
     }
-    *previous_len = values.len();
-    commands.insert_or_spawn_batch(values);
+    *previous_morph_len = morph_values.len();
+    commands.insert_or_spawn_batch(morph_values);
+
+    let mut batch_values = Vec::with_capacity(*previous_batch_len);
+    for (entity, batch, transform) in batch_query.iter() {
+        let Some(Ok((_, first, _, color_mode, lighting, transparency, ..))) =
+            batch.clouds().next().map(|first| query.get(first))
+        else {
+            continue;
+        };
+        let mut uniform = uniform(
+            transform,
+            first.point_size,
+            first.near_fade_distance,
+            first.edge_softness,
+            Color::WHITE,
+            &batch.asset,
+            color_mode,
+            lighting,
+            transparency,
+        );
+        (uniform.point_size_mode, uniform.adaptive_distance) = first.size_mode.gpu();
+        uniform.point_shape = first.shape.gpu();
+        // One instance per cloud, in the order of their points. A zero transform collapses the
+        // points of clouds that were despawned since the batch was built.
+        let instances = batch
+            .clouds()
+            .map(|cloud| {
+                let Ok((_, point_cloud, transform, ..)) = query.get(cloud) else {
+                    return [0.0; 20];
+                };
+                let mut instance = [0.0; 20];
+                instance[..16].copy_from_slice(&transform.compute_matrix().to_cols_array());
+                instance[16..].copy_from_slice(&point_cloud.color.as_linear_rgba_f32());
+                instance
+            })
+            .collect();
+        batch_values.push((
+            entity,
+            (
+                uniform,
+                batch.asset.clone(),
+                first.blend_mode,
+                ExtractedPointCloudBatch::new(batch, instances),
+            ),
+        ));
+    }
+    *previous_batch_len = batch_values.len();
+    commands.insert_or_spawn_batch(batch_values);
 }
 
 #[derive(Component)]
@@ -60,37 +567,153 @@ pub struct PointCloudDrawList {
 pub struct PointCloudDrawData {
     pub entity: Entity,
     pub pipeline_id: CachedRenderPipelineId,
+    /// The depth only variant of the pipeline, for the views' depth prepass.
+    pub prepass_pipeline_id: Option<CachedRenderPipelineId>,
+    pub blend_mode: PointBlendMode,
+    /// Drawn in the order of its [`PointDepthSort`].
+    pub depth_sorted: bool,
+    /// The fraction of the points that is drawn, see [`PointCloudLod`].
+    pub point_fraction: f32,
 }
 
+type QueuedPointCloud = (
+    &'static Handle<PointCloudAsset>,
+    Option<&'static ExtractedPointCloudInstances>,
+    Option<&'static ExtractedPointCloudMorph>,
+    Option<&'static ExtractedTransferFunction>,
+    Option<&'static PointBlendMode>,
+    Has<PointDepthSort>,
+    &'static PointCloudUniform,
+    Option<&'static PointCloudLod>,
+    Option<&'static ExtractedPointCloudBatch>,
+);
+
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn queue_point_cloud(
     pipeline: Res<PointCloudPipeline>,
     mut pipelines: ResMut<SpecializedRenderPipelines<PointCloudPipeline>>,
     cache: Res<PipelineCache>,
-    views: Query<(Entity, &VisibleEntities)>,
-    items: Query<&Handle<PointCloudAsset>>,
+    views: Query<(Entity, &ExtractedView, &VisibleEntities, Has<DepthPrepass>)>,
+    items: Query<QueuedPointCloud>,
+    images: Res<RenderAssets<Image>>,
     point_clouds: Res<RenderAssets<PointCloudAsset>>,
     msaa: Option<Res<Msaa>>,
+    eye_dome_lighting: Res<EyeDomeLightingEnabled>,
+    depth_prepass: Res<PointCloudDepthPrepassEnabled>,
+    mut warned_storage_buffers: Local<bool>,
     mut commands: Commands,
 ) {
     let msaa = msaa.map(|a| a.samples()).unwrap_or(1);
-    for (view_entity, entities) in &views {
+    for (view_entity, view, entities, has_depth_prepass) in &views {
         let mut list = vec![];
         for &entity in &entities.entities {
-            if let Some(asset) = items
-                .get(entity)
-                .ok()
-                .and_then(|handle| point_clouds.get(handle))
-            {
+            if let Some((
+                asset,
+                instances,
+                morph,
+                transfer_function,
+                blend_mode,
+                depth_sort,
+                uniform,
+                lod,
+                batch,
+            )) = items.get(entity).ok().and_then(
+                |(
+                    handle,
+                    instances,
+                    morph,
+                    transfer_function,
+                    blend_mode,
+                    depth_sort,
+                    uniform,
+                    lod,
+                    batch,
+                )| {
+                    Some((
+                        point_clouds.get(handle)?,
+                        instances,
+                        morph,
+                        transfer_function,
+                        blend_mode.copied().unwrap_or_default(),
+                        depth_sort,
+                        uniform,
+                        lod,
+                        batch,
+                    ))
+                },
+            ) {
+                if morph.is_some_and(|morph| point_clouds.get(&morph.to).is_none()) {
+                    continue;
+                }
+                if instances.is_some_and(ExtractedPointCloudInstances::is_empty)
+                    || batch.is_some_and(ExtractedPointCloudBatch::is_empty)
+                {
+                    continue;
+                }
+                // Batches are drawn through the instanced path, with one instance per cloud.
+                let instanced = instances.is_some() || batch.is_some();
+                if !pipeline.storage_buffers && (instanced || morph.is_some()) {
+                    if !std::mem::replace(&mut *warned_storage_buffers, true) {
+                        warn!(
+                            "Instanced, batched and morphing point clouds need storage buffers, \
+                             they aren't drawn"
+                        );
+                    }
+                    continue;
+                }
+                // The order of additive points doesn't matter, and opaque points are depth tested.
+                // The sorted indices are written by a compute shader.
+                let depth_sorted = depth_sort
+                    && blend_mode == PointBlendMode::AlphaBlend
+                    && !instanced
+                    && pipeline.storage_buffers;
+                // Instances and sorted points can't be thinned out by drawing fewer of them.
+                let point_fraction = match lod {
+                    Some(lod) if !instanced && !depth_sorted => {
+                        let center = uniform.transform.transform_point3(asset.aabb.center.into());
+                        lod.fraction(center.distance(view.transform.translation()))
+                    }
+                    _ => 1.0,
+                };
                 let key = PointCloudPipelineKey {
                     colored: asset.colored,
+                    high_precision_color: asset.high_precision_color,
                     animated: asset.animation_buffer.is_some(),
+                    instanced,
+                    batched: batch.is_some(),
+                    morph: morph.is_some(),
+                    // Until the image has loaded, the shader falls back to the asset colors.
+                    // The scalars it maps are read from storage buffers.
+                    transfer_function: pipeline.storage_buffers
+                        && transfer_function.is_some_and(|transfer_function| {
+                            images.get(&transfer_function.texture).is_some()
+                        }),
+                    depth_sorted,
+                    // Blended clouds are drawn after eye dome lighting.
+                    eye_dome_lighting: eye_dome_lighting.0 && blend_mode == PointBlendMode::Opaque,
+                    blend_mode,
                     msaa,
+                    depth_prepass: false,
                 };
 
+                let prepass_pipeline_id =
+                    (depth_prepass.0 && has_depth_prepass && blend_mode == PointBlendMode::Opaque)
+                        .then(|| {
+                            let key = PointCloudPipelineKey {
+                                eye_dome_lighting: false,
+                                depth_prepass: true,
+                                ..key.clone()
+                            };
+                            pipelines.specialize(&cache, &pipeline, key)
+                        });
                 let pipeline_id = pipelines.specialize(&cache, &pipeline, key);
                 list.push(PointCloudDrawData {
                     entity,
                     pipeline_id,
+                    prepass_pipeline_id,
+                    blend_mode,
+                    depth_sorted,
+                    point_fraction,
                 });
             }
         }
@@ -102,10 +725,24 @@ pub(crate) fn queue_point_cloud(
     }
 }
 
+#[derive(Clone)]
 pub struct PreparedPointCloudAsset {
-    pub buffer: Buffer,
+    /// Point positions, tightly packed as three `f32`s per point. Streamed assets reserve room
+    /// for the points that haven't been read yet.
+    pub position_buffer: Buffer,
+    /// Point colors, uploaded as a separate stream so positions and colors never
+    /// have to be interleaved on the CPU.
+    pub color_buffer: Option<Buffer>,
+    /// All scalar fields of the asset, one after another.
+    pub scalar_buffer: Option<Buffer>,
+    /// Point normals, three `f32`s per point like the positions.
+    pub normal_buffer: Option<Buffer>,
     pub num_points: u32,
     pub bind_group: Option<BindGroup>,
+    /// Used to skip occluded chunks when the camera is inside the cloud.
+    pub occlusion: Option<PointCloudOcclusion>,
+    /// See [`PointCloudAsset::aabb`].
+    pub aabb: Aabb,
 
     pub animation_buffer: Option<(Buffer, Buffer)>,
     pub frames: Option<Frames>,
@@ -115,9 +752,48 @@ pub struct PreparedPointCloudAsset {
     pub animation_scale: Vec3,
 
     pub colored: bool,
+    /// Whether the colors are stored as [`ATTRIBUTE_COLOR_RGBA16`] rather than
+    /// [`ATTRIBUTE_COLOR_PACKED`].
+    pub high_precision_color: bool,
+    pub(crate) uploads: WeakUploads,
 }
 
 impl PreparedPointCloudAsset {
+    /// The bytes per point of [`Self::position_buffer`], three `f32`s.
+    pub const POSITION_STRIDE: u64 = 12;
+    /// The bytes per point of [`Self::normal_buffer`], three `f32`s.
+    pub const NORMAL_STRIDE: u64 = 12;
+
+    /// The bytes per point of [`Self::color_buffer`]: one packed RGBA8 `u32`, or two `u32`s of
+    /// packed RGBA16 with [`Self::high_precision_color`].
+    pub fn color_stride(&self) -> u64 {
+        if self.high_precision_color {
+            8
+        } else {
+            4
+        }
+    }
+
+    /// The size of the buffers of the asset.
+    pub fn gpu_bytes(&self) -> u64 {
+        let (previous_frame, next_frame) = match &self.animation_buffer {
+            Some((previous_frame, next_frame)) => (Some(previous_frame), Some(next_frame)),
+            None => (None, None),
+        };
+        [
+            Some(&self.position_buffer),
+            self.color_buffer.as_ref(),
+            self.scalar_buffer.as_ref(),
+            self.normal_buffer.as_ref(),
+            previous_frame,
+            next_frame,
+        ]
+        .into_iter()
+        .flatten()
+        .map(|buffer| buffer.size())
+        .sum()
+    }
+
     pub fn seek(
         &mut self,
         seek_to: f32, // time from the start of the animation to seek to
@@ -128,11 +804,13 @@ impl PreparedPointCloudAsset {
         let (prev_animation_buffer, next_animation_buffer) = self.animation_buffer.as_mut().expect(
             "Cannot call PreparedPointCloudAsset::seek on an instance without an animation",
         );
-        let frames = match self.frames.as_ref().unwrap() {
-            Frames::I8(frames) => frames,
-            _ => todo!(), // make some kinda trait abstraction
+        // Only 8 bit frames are played back, see `PointCloudAsset::animation`.
+        let Some(Frames::I8(frames)) = self.frames.as_ref() else {
+            return;
         };
 
+        // The controls can be ahead of a reloaded asset for a frame.
+        let seek_to = seek_to.clamp(0.0, frames.last().unwrap().time / 1000.);
         self.animation_time = seek_to;
 
         // If we're already in the correct frame, adjust interpolation and exit
@@ -242,14 +920,44 @@ impl PreparedPointCloudAsset {
         render_device: &RenderDevice,
         pipeline: &PointCloudPipeline,
     ) {
-        let mut bind_group_entries =
-            DynamicBindGroupEntries::sequential((self.buffer.as_entire_binding(),));
+        // The points are bound as vertex buffers when drawn instead.
+        if !pipeline.storage_buffers {
+            self.bind_group = Some(render_device.create_bind_group(
+                "point cloud buffer bind group",
+                &pipeline.entity_layout,
+                &[],
+            ));
+            return;
+        }
+        let mut bind_group_entries = DynamicBindGroupEntries::sequential((
+            self.position_buffer.as_entire_binding(),
+            self.color_buffer
+                .as_ref()
+                .unwrap_or(&pipeline.placeholder_buffer)
+                .as_entire_binding(),
+        ));
         if let Some((animation_buffer, next)) = self.animation_buffer.as_ref() {
             bind_group_entries = bind_group_entries.extend_sequential((
                 animation_buffer.as_entire_binding(),
                 next.as_entire_binding(),
             ));
         }
+        bind_group_entries = bind_group_entries.extend_with_indices((
+            (
+                4,
+                self.scalar_buffer
+                    .as_ref()
+                    .unwrap_or(&pipeline.placeholder_buffer)
+                    .as_entire_binding(),
+            ),
+            (
+                5,
+                self.normal_buffer
+                    .as_ref()
+                    .unwrap_or(&pipeline.placeholder_buffer)
+                    .as_entire_binding(),
+            ),
+        ));
         let bind_group = render_device.create_bind_group(
             "point cloud buffer bind group",
             if self.animation_buffer.is_some() {
@@ -263,36 +971,142 @@ impl PreparedPointCloudAsset {
     }
 }
 
+/// Buffers can't be bound with a size of zero, so the buffers of empty assets hold `len`
+/// zeroed bytes instead, enough for one point. Empty assets are never drawn.
+fn non_empty(contents: &[u8], len: usize) -> Cow<'_, [u8]> {
+    if contents.is_empty() {
+        vec![0; len].into()
+    } else {
+        contents.into()
+    }
+}
+
 impl RenderAsset for PointCloudAsset {
     type ExtractedAsset = Self;
 
     type PreparedAsset = PreparedPointCloudAsset;
 
-    type Param = (SRes<RenderDevice>, SRes<PointCloudPipeline>);
+    type Param = (
+        SRes<RenderDevice>,
+        SRes<RenderQueue>,
+        SRes<PointCloudPipeline>,
+    );
 
     fn extract_asset(&self) -> Self::ExtractedAsset {
-        self.clone()
+        if let Some(asset) = self.extract_partial() {
+            return asset;
+        }
+        let mut asset = self.clone();
+        self.share_uploads(&mut asset);
+        // In case the main world didn't get to it before the asset was extracted.
+        asset.pack_colors();
+        asset
     }
 
     fn prepare_asset(
         extracted_asset: Self::ExtractedAsset,
-        (render_device, pipeline): &mut SystemParamItem<Self::Param>,
+        (render_device, render_queue, pipeline): &mut SystemParamItem<Self::Param>,
     ) -> Result<
         Self::PreparedAsset,
         bevy::render::render_asset::PrepareAssetError<Self::ExtractedAsset>,
     > {
-        let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
-            usage: BufferUsages::STORAGE,
-            label: Some("Point cloud vertex buffer"),
-            contents: extracted_asset.mesh.get_vertex_buffer_data().as_slice(),
+        if let Some(asset) =
+            PreparedPointCloudAsset::prepare_partial(&extracted_asset, render_queue)
+        {
+            return Ok(asset);
+        }
+        // Each attribute is stored as its own contiguous stream in the mesh, so it can be
+        // uploaded as-is without interleaving.
+        let positions = extracted_asset
+            .mesh
+            .attribute(Mesh::ATTRIBUTE_POSITION)
+            .map(|values| values.get_bytes())
+            .unwrap_or_default();
+        // Streamed points are written in as they are read, see `PointCloudUploads`.
+        let position_buffer = render_device.create_buffer(&BufferDescriptor {
+            label: Some("Point cloud position buffer"),
+            size: ((extracted_asset.num_points() + extracted_asset.pending_points).max(1) as u64)
+                * PreparedPointCloudAsset::POSITION_STRIDE,
+            usage: pipeline.point_stream_usage() | BufferUsages::COPY_DST,
+            mapped_at_creation: true,
         });
+        if !positions.is_empty() {
+            position_buffer
+                .slice(..positions.len() as u64)
+                .get_mapped_range_mut()
+                .copy_from_slice(positions);
+        }
+        position_buffer.unmap();
+        let high_precision_color = extracted_asset
+            .mesh
+            .contains_attribute(ATTRIBUTE_COLOR_RGBA16);
+        let color_buffer = extracted_asset
+            .mesh
+            .attribute(ATTRIBUTE_COLOR_RGBA16)
+            .or_else(|| extracted_asset.mesh.attribute(ATTRIBUTE_COLOR_PACKED))
+            .map(|values| {
+                render_device.create_buffer_with_data(&BufferInitDescriptor {
+                    // Written by partial uploads, see `PointCloudAsset::update_colors`.
+                    usage: pipeline.point_stream_usage() | BufferUsages::COPY_DST,
+                    label: Some("Point cloud color buffer"),
+                    contents: &non_empty(values.get_bytes(), 8),
+                })
+            });
+        // Scalar fields, normals and animations are only read from storage buffers.
+        let scalar_buffer = (pipeline.storage_buffers
+            && !extracted_asset.scalar_fields.is_empty()
+            && extracted_asset.num_points() > 0)
+            .then(|| {
+                let field_size = extracted_asset.num_points() * std::mem::size_of::<f32>();
+                let buffer = render_device.create_buffer(&BufferDescriptor {
+                    label: Some("Point cloud scalar buffer"),
+                    size: (field_size * extracted_asset.scalar_fields.len()) as u64,
+                    usage: BufferUsages::STORAGE,
+                    mapped_at_creation: true,
+                });
+                {
+                    let mut view = buffer.slice(..).get_mapped_range_mut();
+                    for (chunk, (name, field)) in view
+                        .chunks_exact_mut(field_size)
+                        .zip(&extracted_asset.scalar_fields)
+                    {
+                        // Scalar fields are public, so they can be out of sync with the
+                        // points. Zero them rather than shifting the following fields.
+                        if field.len() == extracted_asset.num_points() {
+                            chunk.copy_from_slice(bytemuck::cast_slice(field));
+                        } else {
+                            warn!(
+                                "Scalar field {name:?} has {} values but the point cloud has {} \
+                                 points, it is drawn as zeros",
+                                field.len(),
+                                extracted_asset.num_points()
+                            );
+                            chunk.fill(0);
+                        }
+                    }
+                }
+                buffer.unmap();
+                buffer
+            });
+        let normal_buffer = extracted_asset
+            .mesh
+            .attribute(Mesh::ATTRIBUTE_NORMAL)
+            .filter(|_| pipeline.storage_buffers)
+            .map(|values| {
+                render_device.create_buffer_with_data(&BufferInitDescriptor {
+                    usage: BufferUsages::STORAGE,
+                    label: Some("Point cloud normal buffer"),
+                    contents: &non_empty(values.get_bytes(), 12),
+                })
+            });
 
-        let animation_buffer = if extracted_asset.animation.is_some() {
+        let animation_buffer = if extracted_asset.animation.is_some() && pipeline.storage_buffers {
             let size = extracted_asset
                 .mesh
                 .attribute(Mesh::ATTRIBUTE_POSITION)
                 .unwrap()
-                .len() as u64
+                .len()
+                .max(1) as u64
                 * std::mem::size_of::<f32>() as u64
                 * 3
                 + std::mem::size_of::<f32>() as u64;
@@ -313,18 +1127,26 @@ impl RenderAsset for PointCloudAsset {
             None
         };
         let mut asset = PreparedPointCloudAsset {
-            buffer,
-            num_points: extracted_asset.mesh.count_vertices() as u32,
+            position_buffer,
+            colored: color_buffer.is_some(),
+            high_precision_color,
+            color_buffer,
+            scalar_buffer,
+            normal_buffer,
+            num_points: extracted_asset.num_points() as u32,
             bind_group: None,
+            occlusion: extracted_asset.occlusion,
+            aabb: extracted_asset.aabb,
             animation_buffer,
             frames: extracted_asset.animation,
             current_animation_frame: 0,
             animation_time: 0.0,
             animation_frame_start_time: 0.0,
             animation_scale: extracted_asset.animation_scale,
-            colored: extracted_asset.mesh.contains_attribute(ATTRIBUTE_COLOR),
+            uploads: default(),
         };
         asset.update_bind_group(render_device, pipeline);
+        asset.cache_for_uploads(&extracted_asset.uploads);
         Ok(asset)
     }
 }
@@ -1,32 +1,262 @@
-use crate::pipeline::{EyeDomeViewTarget, PointCloudBindGroup, PointCloudPipeline};
-use crate::{PointCloudAsset, PointCloudDrawList, PointCloudUniform};
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::color::PreparedTransferFunction;
+use crate::instancing::PreparedPointCloudInstances;
+use crate::morph::PreparedPointCloudMorph;
+use crate::pipeline::{
+    EyeDomeBlendedClouds, EyeDomeSettings, EyeDomeViewTarget, PointCloudBindGroup,
+    PointCloudPipeline,
+};
+use crate::point_size::{farthest_distance_to_aabb, world_point_size};
+use crate::sorting::PreparedDepthSorts;
+use crate::visibility::{is_frustum_culled, DrawnPointCloudsChannel};
+use crate::{
+    PointBlendMode, PointCloudAsset, PointCloudDrawData, PointCloudDrawList, PointCloudUniform,
+    PointCloudViewUniform, PointShape, PrePointCompute,
+};
+use bevy::core_pipeline::prepass::{DeferredPrepass, ViewPrepassTextures};
 use bevy::ecs::query::QueryItem;
+use bevy::math::Vec3A;
 use bevy::prelude::*;
 use bevy::render::camera::ExtractedCamera;
 use bevy::render::extract_component::DynamicUniformIndex;
+use bevy::render::primitives::{Aabb, Frustum};
 use bevy::render::render_asset::RenderAssets;
 use bevy::render::render_graph::ViewNode;
+use bevy::render::render_phase::TrackedRenderPass;
 use bevy::render::render_resource::{
-    LoadOp, Operations, PipelineCache, RenderPassColorAttachment, RenderPassDepthStencilAttachment,
-    RenderPassDescriptor, ShaderStages,
+    CachedPipelineState, CachedRenderPipelineId, LoadOp, Operations, PipelineCache,
+    RenderPassColorAttachment, RenderPassDepthStencilAttachment, RenderPassDescriptor,
 };
 use bevy::render::view::{ExtractedView, ViewDepthTexture, ViewTarget, ViewUniformOffset};
 
+type PointCloudNodeItem = (
+    &'static Handle<PointCloudAsset>,
+    &'static DynamicUniformIndex<PointCloudUniform>,
+    &'static PointCloudUniform,
+    Option<&'static PreparedPointCloudInstances>,
+    Option<&'static PreparedPointCloudMorph>,
+    Option<&'static PreparedTransferFunction>,
+);
+
+/// Draws the points of a view into the main texture of its [`ViewTarget`], and shades
+/// them with eye dome lighting. Runs in the 3d render graph, see
+/// [`PointCloudRenderOrder`](crate::PointCloudRenderOrder).
+///
+/// Camera render graphs pass nothing between nodes but the view entity, so the node has no
+/// output slots. To post-process the points, add an edge from [`PointCloudNode::NAME`] to a
+/// custom node and read the [`ViewTarget`] and [`EyeDomeViewTarget`] of the view there.
 pub struct PointCloudNode {
-    entity_query: QueryState<(
-        &'static Handle<PointCloudAsset>,
-        &'static DynamicUniformIndex<PointCloudUniform>,
-    )>,
+    entity_query: QueryState<PointCloudNodeItem>,
+    diagnostics: NodeDiagnostics,
+}
+
+/// Reasons for not drawing point clouds, each logged only once so they don't flood the log
+/// every frame.
+#[derive(Default)]
+struct NodeDiagnostics {
+    pipeline_compiling: AtomicBool,
+    pipeline_failed: AtomicBool,
+    missing_bind_group: AtomicBool,
+}
+
+impl NodeDiagnostics {
+    /// Whether `flag` is logged for the first time.
+    fn first(flag: &AtomicBool) -> bool {
+        !flag.swap(true, Ordering::Relaxed)
+    }
+
+    fn missing_bind_group(&self, which: &str) {
+        if Self::first(&self.missing_bind_group) {
+            warn!("Point clouds can't be drawn without the {which} bind group");
+        }
+    }
+}
+
+enum DrawOutcome {
+    /// With the number of points drawn.
+    Drawn(u64),
+    Culled,
+    Skipped,
+}
+
+struct DrawStats {
+    drawn: Vec<Entity>,
+    drawn_points: u64,
+    culled: usize,
+}
+
+impl DrawStats {
+    fn record(&mut self, entity: Entity, outcome: DrawOutcome) {
+        match outcome {
+            DrawOutcome::Drawn(points) => {
+                self.drawn.push(entity);
+                self.drawn_points += points;
+            }
+            DrawOutcome::Culled => self.culled += 1,
+            DrawOutcome::Skipped => {}
+        }
+    }
 }
 
 impl PointCloudNode {
     pub const NAME: &'static str = "point_cloud_node";
+
+    /// Draws one point cloud of the draw list into `tracked_pass` with `pipeline_id`, one of the
+    /// pipelines of `draw_data`. The view bind group and vertex buffer must already be set.
+    /// `depth_sorts` are the view's dispatched sorts.
+    #[allow(clippy::too_many_arguments)]
+    fn draw_point_cloud<'w>(
+        &'w self,
+        tracked_pass: &mut TrackedRenderPass<'w>,
+        draw_data: &PointCloudDrawData,
+        pipeline_id: CachedRenderPipelineId,
+        world: &'w World,
+        view: &ExtractedView,
+        view_settings: &PointCloudViewUniform,
+        frustum: &Frustum,
+        depth_sorts: Option<&'w PreparedDepthSorts>,
+    ) -> DrawOutcome {
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let render_assets = world.resource::<RenderAssets<PointCloudAsset>>();
+        let pre_point_compute = world.resource::<PrePointCompute>();
+        let model_bind_group = world
+            .resource::<PointCloudBindGroup>()
+            .model_bind_group
+            .as_ref()
+            .unwrap();
+        let Some(pipeline) = pipeline_cache.get_render_pipeline(pipeline_id) else {
+            if let CachedPipelineState::Err(err) =
+                pipeline_cache.get_render_pipeline_state(pipeline_id)
+            {
+                if NodeDiagnostics::first(&self.diagnostics.pipeline_failed) {
+                    warn!("Point cloud pipeline failed to compile, its clouds aren't drawn: {err}");
+                }
+            } else if NodeDiagnostics::first(&self.diagnostics.pipeline_compiling) {
+                debug!(
+                    "Point cloud pipeline still compiling, its clouds are drawn once it is ready"
+                );
+            }
+            return DrawOutcome::Skipped;
+        };
+        let sorted_indices = depth_sorts.and_then(|sorts| sorts.indices(draw_data.entity));
+        if draw_data.depth_sorted && sorted_indices.is_none() {
+            return DrawOutcome::Skipped;
+        }
+        let Ok((handle, dynamic_index, uniform, instances, morph, transfer_function)) =
+            self.entity_query.get_manual(world, draw_data.entity)
+        else {
+            return DrawOutcome::Skipped;
+        };
+        let Some(point_cloud_asset) = render_assets.get(handle) else {
+            return DrawOutcome::Skipped;
+        };
+        // Some backends reject draws without instances.
+        if point_cloud_asset.num_points == 0 {
+            return DrawOutcome::Skipped;
+        }
+        let point_cloud_pipeline = world.resource::<PointCloudPipeline>();
+        let vertices = point_cloud_pipeline.point_vertices(
+            uniform.edge_softness > 0.0 || uniform.point_shape == PointShape::Circle.gpu(),
+        );
+        // Instanced clouds and clouds whose points move on the GPU have no fixed bounds.
+        if instances.is_none()
+            && point_cloud_asset.animation_buffer.is_none()
+            && !pre_point_compute.modifies(handle)
+        {
+            let mut aabb = match morph {
+                Some(morph) => Aabb::from_min_max(
+                    point_cloud_asset.aabb.min().min(morph.to_aabb.min()).into(),
+                    point_cloud_asset.aabb.max().max(morph.to_aabb.max()).into(),
+                ),
+                None => point_cloud_asset.aabb,
+            };
+            // The bounds are of the point centers, so pad them by the size of the farthest
+            // points, the largest in world space, so that points at the edge don't pop out
+            // early.
+            let distance =
+                farthest_distance_to_aabb(&aabb, &uniform.transform, view.transform.translation());
+            aabb.half_extents += Vec3A::splat(world_point_size(
+                uniform.point_size,
+                (uniform.point_size_mode, uniform.adaptive_distance),
+                view_settings,
+                &view.projection,
+                view.viewport.zw().as_vec2(),
+                distance,
+            ));
+            if is_frustum_culled(frustum, &aabb, &uniform.transform) {
+                return DrawOutcome::Culled;
+            }
+        }
+        let Some(asset_bind_group) = point_cloud_asset.bind_group.as_ref() else {
+            self.diagnostics.missing_bind_group("asset");
+            return DrawOutcome::Skipped;
+        };
+
+        tracked_pass.set_render_pipeline(pipeline);
+        tracked_pass.set_bind_group(1, asset_bind_group, &[]);
+        tracked_pass.set_bind_group(2, model_bind_group, &[dynamic_index.index()]);
+        if !point_cloud_pipeline.storage_buffers {
+            // See `PointCloudPipeline::point_stream_layouts`.
+            tracked_pass.set_vertex_buffer(1, point_cloud_asset.position_buffer.slice(..));
+            if let Some(color_buffer) = &point_cloud_asset.color_buffer {
+                tracked_pass.set_vertex_buffer(2, color_buffer.slice(..));
+            }
+        }
+        if let Some(morph) = morph {
+            tracked_pass.set_bind_group(3, &morph.bind_group, &[]);
+        }
+        if let Some(transfer_function) = transfer_function {
+            tracked_pass.set_bind_group(3, &transfer_function.bind_group, &[]);
+        }
+        if let Some(instances) = instances {
+            // Warned about when the instances are prepared.
+            let Some(points) = point_cloud_asset.num_points.checked_mul(instances.count) else {
+                return DrawOutcome::Skipped;
+            };
+            tracked_pass.set_bind_group(3, &instances.bind_group, &[]);
+            tracked_pass.draw(vertices, 0..points);
+            return DrawOutcome::Drawn(points.into());
+        }
+        if let Some(sorted_indices) = sorted_indices {
+            // The occlusion ranges index the unsorted points.
+            tracked_pass.set_vertex_buffer(1, sorted_indices.slice(..));
+            tracked_pass.draw(vertices, 0..point_cloud_asset.num_points);
+            return DrawOutcome::Drawn(point_cloud_asset.num_points.into());
+        }
+        let visible_points = point_cloud_asset.occlusion.as_ref().and_then(|occlusion| {
+            let camera_position = uniform
+                .transform
+                .inverse()
+                .transform_point3(view.transform.translation());
+            occlusion.visible_points(camera_position)
+        });
+        // Keep the first points of every range, which are spread over all of it when the
+        // points are shuffled.
+        let thin_out = |range: Range<u32>| {
+            let len = range.len() as f32 * draw_data.point_fraction;
+            range.start..range.end.min(range.start + len.ceil() as u32)
+        };
+        let mut points = 0;
+        let mut draw = |range: Range<u32>| {
+            let range = thin_out(range);
+            points += u64::from(range.end - range.start);
+            tracked_pass.draw(vertices.clone(), range);
+        };
+        match visible_points {
+            Some(ranges) => ranges.into_iter().for_each(draw),
+            None => draw(0..point_cloud_asset.num_points),
+        }
+        DrawOutcome::Drawn(points)
+    }
 }
 
 impl FromWorld for PointCloudNode {
     fn from_world(world: &mut World) -> Self {
         Self {
             entity_query: world.query_filtered(),
+            diagnostics: default(),
         }
     }
 }
@@ -38,8 +268,13 @@ impl ViewNode for PointCloudNode {
         &'static ViewTarget,
         &'static ViewDepthTexture,
         &'static ViewUniformOffset,
-        &'static EyeDomeViewTarget,
+        Option<&'static EyeDomeViewTarget>,
         &'static PointCloudDrawList,
+        &'static DynamicUniformIndex<PointCloudViewUniform>,
+        &'static PointCloudViewUniform,
+        &'static Frustum,
+        Option<&'static EyeDomeSettings>,
+        Option<&'static PreparedDepthSorts>,
     );
 
     fn update(&mut self, world: &mut World) {
@@ -50,13 +285,36 @@ impl ViewNode for PointCloudNode {
         &self,
         _graph: &mut bevy::render::render_graph::RenderGraphContext,
         render_context: &mut bevy::render::renderer::RenderContext,
-        (view, camera, target, depth, view_uniform_offset, eye_dome_view_target, draw_list): QueryItem<Self::ViewQuery>,
+        (
+            view,
+            camera,
+            target,
+            depth,
+            view_uniform_offset,
+            eye_dome_view_target,
+            draw_list,
+            view_settings_index,
+            view_settings,
+            frustum,
+            eye_dome_settings,
+            depth_sorts,
+        ): QueryItem<Self::ViewQuery>,
         world: &World,
     ) -> Result<(), bevy::render::render_graph::NodeRunError> {
+        // Spans time the recording of the commands on the CPU, not their execution on the GPU.
+        #[cfg(feature = "trace")]
+        let span = info_span!(
+            "point_cloud_draw",
+            clouds = bevy::utils::tracing::field::Empty,
+            points = bevy::utils::tracing::field::Empty,
+        )
+        .entered();
         let point_cloud_pipeline = world.resource::<PointCloudPipeline>();
         let pipeline_cache = world.resource::<PipelineCache>();
         let render_assets = world.resource::<RenderAssets<PointCloudAsset>>();
 
+        // Without eye dome lighting, points only write the view target and its depth.
+        let color_attachment_count = if eye_dome_view_target.is_some() { 2 } else { 1 };
         let mut tracked_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
             label: Some("point_cloud"),
             // NOTE: The opaque pass loads the color
@@ -66,7 +324,7 @@ impl ViewNode for PointCloudNode {
                     load: LoadOp::Load,
                     store: true,
                 })),
-                Some(RenderPassColorAttachment {
+                eye_dome_view_target.map(|eye_dome_view_target| RenderPassColorAttachment {
                     view: &eye_dome_view_target.depth_texture_view,
                     resolve_target: None,
                     ops: Operations {
@@ -74,12 +332,13 @@ impl ViewNode for PointCloudNode {
                         store: true,
                     },
                 }),
-            ],
+            ][..color_attachment_count],
             depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
                 view: &depth.view,
                 // NOTE: The opaque main pass loads the depth buffer and possibly overwrites it
                 depth_ops: Some(Operations {
-                    // NOTE: 0.0 is the far plane due to bevy's use of reverse-z projections.
+                    // NOTE: 0.0 is the far plane, since both perspective and orthographic projections
+                    // are reverse-z in bevy.
                     load: LoadOp::Load,
                     store: true,
                 }),
@@ -91,79 +350,297 @@ impl ViewNode for PointCloudNode {
         }
 
         let bind_groups = world.resource::<PointCloudBindGroup>();
-        if bind_groups.bind_group.is_none() || bind_groups.model_bind_group.is_none() {
+        let (Some(view_bind_group), Some(_)) = (
+            bind_groups.bind_group.as_ref(),
+            bind_groups.model_bind_group.as_ref(),
+        ) else {
+            self.diagnostics.missing_bind_group("view or model");
             return Ok(());
-        }
+        };
+        let mut stats = DrawStats {
+            drawn: Vec::with_capacity(draw_list.list.len()),
+            drawn_points: 0,
+            culled: 0,
+        };
+
         tracked_pass.set_bind_group(
             0,
-            bind_groups.bind_group.as_ref().unwrap(),
-            &[view_uniform_offset.offset],
+            view_bind_group,
+            &[view_uniform_offset.offset, view_settings_index.index()],
         );
-        tracked_pass.set_vertex_buffer(0, point_cloud_pipeline.instanced_point_quad.slice(0..32));
+        tracked_pass.set_vertex_buffer(0, point_cloud_pipeline.instanced_point_quad.slice(..));
         for draw_data in &draw_list.list {
-            let Some(pipeline) = pipeline_cache.get_render_pipeline(draw_data.pipeline_id) else {
-                continue;
-            };
-            let Ok((point_cloud_asset, dynamic_index)) =
-                self.entity_query.get_manual(world, draw_data.entity)
-            else {
-                continue;
-            };
-            let Some(point_cloud_asset) = render_assets.get(point_cloud_asset) else {
-                continue;
+            if draw_data.blend_mode == PointBlendMode::Opaque {
+                stats.record(
+                    draw_data.entity,
+                    self.draw_point_cloud(
+                        &mut tracked_pass,
+                        draw_data,
+                        draw_data.pipeline_id,
+                        world,
+                        view,
+                        view_settings,
+                        frustum,
+                        None,
+                    ),
+                );
+            }
+        }
+        drop(tracked_pass);
+
+        // Blended clouds go last, back to front, over the opaque clouds.
+        let mut blended: Vec<(f32, &PointCloudDrawData)> = draw_list
+            .list
+            .iter()
+            .filter(|draw_data| draw_data.blend_mode != PointBlendMode::Opaque)
+            .filter_map(|draw_data| {
+                let (handle, _, uniform, ..) =
+                    self.entity_query.get_manual(world, draw_data.entity).ok()?;
+                let center = render_assets
+                    .get(handle)
+                    .map_or(Vec3A::ZERO, |asset| asset.aabb.center);
+                let distance = uniform
+                    .transform
+                    .transform_point3(center.into())
+                    .distance_squared(view.transform.translation());
+                Some((distance, draw_data))
+            })
+            .collect();
+        blended.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+        let eye_dome_settings = eye_dome_settings.copied().unwrap_or_default();
+        let eye_dome_pipeline = eye_dome_view_target
+            .filter(|_| eye_dome_settings.enabled)
+            .filter(|_| {
+                blended.is_empty()
+                    || eye_dome_settings.blended_clouds != EyeDomeBlendedClouds::DisableEyeDome
+            })
+            .and_then(|eye_dome_view_target| {
+                Some((
+                    eye_dome_view_target,
+                    pipeline_cache.get_render_pipeline(eye_dome_view_target.pipeline_id)?,
+                ))
+            });
+        let eye_dome_lighting = |render_context: &mut bevy::render::renderer::RenderContext| {
+            let Some((eye_dome_view_target, eye_dome_pipeline)) = eye_dome_pipeline else {
+                return;
             };
+            #[cfg(feature = "trace")]
+            let _span = info_span!("eye_dome_lighting").entered();
+            let mut tracked_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                label: Some("eye_dome_lighting"),
+                // NOTE: The opaque pass loads the color
+                // buffer as well as writing to it.
+                color_attachments: &[Some(target.get_color_attachment(Operations {
+                    load: LoadOp::Load,
+                    store: true,
+                }))],
+                depth_stencil_attachment: None,
+            });
+            if let Some(viewport) = camera.viewport.as_ref() {
+                tracked_pass.set_camera_viewport(viewport);
+            }
+            tracked_pass.set_render_pipeline(eye_dome_pipeline);
 
-            tracked_pass.set_render_pipeline(pipeline);
-            tracked_pass.set_bind_group(1, point_cloud_asset.bind_group.as_ref().unwrap(), &[]);
+            tracked_pass.set_bind_group(0, &eye_dome_view_target.bind_group, &[]);
             tracked_pass.set_bind_group(
-                2,
-                bind_groups.model_bind_group.as_ref().unwrap(),
-                &[dynamic_index.index()],
+                1,
+                view_bind_group,
+                &[view_uniform_offset.offset, view_settings_index.index()],
             );
-            tracked_pass.draw(0..4, 0..point_cloud_asset.num_points);
+            tracked_pass
+                .set_vertex_buffer(0, point_cloud_pipeline.instanced_point_quad.slice(0..32));
+            tracked_pass.draw(0..4, 0..1);
+        };
+        let lights_blended_clouds =
+            eye_dome_settings.blended_clouds == EyeDomeBlendedClouds::LitByOpaqueDepth;
+        if !lights_blended_clouds {
+            eye_dome_lighting(render_context);
         }
-        drop(tracked_pass);
 
-        let eye_dome_pipeline =
-            pipeline_cache.get_render_pipeline(eye_dome_view_target.pipeline_id);
-        if eye_dome_pipeline.is_none() {
+        if !blended.is_empty() {
+            // Sorted clouds are skipped until the sort pipelines have compiled.
+            let depth_sorts = depth_sorts.filter(|sorts| sorts.dispatch(render_context, world));
+            let mut tracked_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                label: Some("blended_point_cloud"),
+                color_attachments: &[Some(target.get_color_attachment(Operations {
+                    load: LoadOp::Load,
+                    store: true,
+                }))],
+                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                    view: &depth.view,
+                    depth_ops: Some(Operations {
+                        load: LoadOp::Load,
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            if let Some(viewport) = camera.viewport.as_ref() {
+                tracked_pass.set_camera_viewport(viewport);
+            }
+            tracked_pass.set_bind_group(
+                0,
+                view_bind_group,
+                &[view_uniform_offset.offset, view_settings_index.index()],
+            );
+            tracked_pass.set_vertex_buffer(0, point_cloud_pipeline.instanced_point_quad.slice(..));
+            for (_, draw_data) in blended {
+                stats.record(
+                    draw_data.entity,
+                    self.draw_point_cloud(
+                        &mut tracked_pass,
+                        draw_data,
+                        draw_data.pipeline_id,
+                        world,
+                        view,
+                        view_settings,
+                        frustum,
+                        depth_sorts,
+                    ),
+                );
+            }
+        }
+        if lights_blended_clouds {
+            eye_dome_lighting(render_context);
+        }
+
+        #[cfg(feature = "trace")]
+        span.record("clouds", stats.drawn.len())
+            .record("points", stats.drawn_points);
+        let channel = world.resource::<DrawnPointCloudsChannel>();
+        channel.record_points(stats.drawn.len(), stats.drawn_points);
+        channel.record(stats.drawn);
+        channel.record_culled(stats.culled);
+        Ok(())
+    }
+}
+
+/// Draws the opaque points into the depth prepass of views with a [`DepthPrepass`], so that
+/// screen space effects that read the prepass depth see them. Only added to the render graph
+/// with [`PointCloudPlugin::depth_prepass`](crate::PointCloudPlugin::depth_prepass).
+///
+/// [`DepthPrepass`]: bevy::core_pipeline::prepass::DepthPrepass
+pub struct PointCloudPrepassNode(PointCloudNode);
+
+impl PointCloudPrepassNode {
+    pub const NAME: &'static str = "point_cloud_prepass_node";
+}
+
+impl FromWorld for PointCloudPrepassNode {
+    fn from_world(world: &mut World) -> Self {
+        Self(PointCloudNode::from_world(world))
+    }
+}
+
+impl ViewNode for PointCloudPrepassNode {
+    type ViewQuery = (
+        &'static ExtractedView,
+        &'static ExtractedCamera,
+        &'static ViewDepthTexture,
+        &'static ViewPrepassTextures,
+        &'static ViewUniformOffset,
+        &'static PointCloudDrawList,
+        &'static DynamicUniformIndex<PointCloudViewUniform>,
+        &'static PointCloudViewUniform,
+        &'static Frustum,
+        Has<DeferredPrepass>,
+    );
+
+    fn update(&mut self, world: &mut World) {
+        self.0.update(world);
+    }
+
+    fn run(
+        &self,
+        _graph: &mut bevy::render::render_graph::RenderGraphContext,
+        render_context: &mut bevy::render::renderer::RenderContext,
+        (
+            view,
+            camera,
+            depth,
+            prepass_textures,
+            view_uniform_offset,
+            draw_list,
+            view_settings_index,
+            view_settings,
+            frustum,
+            deferred,
+        ): QueryItem<Self::ViewQuery>,
+        world: &World,
+    ) -> Result<(), bevy::render::render_graph::NodeRunError> {
+        #[cfg(feature = "trace")]
+        let _span = info_span!("point_cloud_prepass").entered();
+        let bind_groups = world.resource::<PointCloudBindGroup>();
+        let (Some(view_bind_group), Some(_)) = (
+            bind_groups.bind_group.as_ref(),
+            bind_groups.model_bind_group.as_ref(),
+        ) else {
+            self.0.diagnostics.missing_bind_group("view or model");
+            return Ok(());
+        };
+        if draw_list
+            .list
+            .iter()
+            .all(|draw_data| draw_data.prepass_pipeline_id.is_none())
+        {
             return Ok(());
         }
-        let eye_dome_pipeline = eye_dome_pipeline.unwrap();
 
         let mut tracked_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
-            label: Some("eye_dome_lighting"),
-            // NOTE: The opaque pass loads the color
-            // buffer as well as writing to it.
-            color_attachments: &[Some(target.get_color_attachment(Operations {
-                load: LoadOp::Load,
-                store: true,
-            }))],
-            depth_stencil_attachment: None,
+            label: Some("point_cloud_prepass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: &depth.view,
+                depth_ops: Some(Operations {
+                    load: LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
         });
         if let Some(viewport) = camera.viewport.as_ref() {
             tracked_pass.set_camera_viewport(viewport);
         }
-        tracked_pass.set_render_pipeline(eye_dome_pipeline);
-
-        let edl_strength: f32 = if view.projection.z_axis.w == -1.0 {
-            // perspective projection
-            // See https://github.com/bitshifter/glam-rs/blob/a35030d130c0464cbb07d6404df6843240182803/src/f32/scalar/mat4.rs#L843
-            1.0
-        } else {
-            // orthographic projection
-            // See https://github.com/bitshifter/glam-rs/blob/a35030d130c0464cbb07d6404df6843240182803/src/f32/scalar/mat4.rs#L924
-            1.0 / view.projection.z_axis.z // near - far
-        };
-
-        tracked_pass.set_push_constants(
-            ShaderStages::FRAGMENT,
+        tracked_pass.set_bind_group(
             0,
-            bytemuck::bytes_of(&edl_strength),
+            view_bind_group,
+            &[view_uniform_offset.offset, view_settings_index.index()],
         );
-        tracked_pass.set_bind_group(0, &eye_dome_view_target.bind_group, &[]);
-        tracked_pass.set_vertex_buffer(0, point_cloud_pipeline.instanced_point_quad.slice(0..32));
-        tracked_pass.draw(0..4, 0..1);
+        tracked_pass.set_vertex_buffer(
+            0,
+            world
+                .resource::<PointCloudPipeline>()
+                .instanced_point_quad
+                .slice(..),
+        );
+        for draw_data in &draw_list.list {
+            if let Some(pipeline_id) = draw_data.prepass_pipeline_id {
+                self.0.draw_point_cloud(
+                    &mut tracked_pass,
+                    draw_data,
+                    pipeline_id,
+                    world,
+                    view,
+                    view_settings,
+                    frustum,
+                    None,
+                );
+            }
+        }
+        drop(tracked_pass);
+
+        // The prepass node has already copied the depth without the points. With a deferred
+        // prepass, the deferred node copies it after this node instead.
+        if !deferred {
+            if let Some(prepass_depth) = &prepass_textures.depth {
+                render_context.command_encoder().copy_texture_to_texture(
+                    depth.texture.as_image_copy(),
+                    prepass_depth.texture.as_image_copy(),
+                    prepass_textures.size,
+                );
+            }
+        }
         Ok(())
     }
 }
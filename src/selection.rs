@@ -0,0 +1,150 @@
+use std::ops::Range;
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssets,
+        render_resource::StorageBuffer,
+        renderer::{RenderDevice, RenderQueue},
+        Extract,
+    },
+    utils::HashMap,
+};
+
+use crate::{PointCloudAsset, PointCloudUniform};
+
+/// Draws some points of a point cloud in `color` instead of their own, for example the points
+/// of a measured segment found with [`PointCloudRaycast`](crate::PointCloudRaycast).
+///
+/// The indices refer to the points of the drawn asset in their current order, which
+/// [`PointCloudAsset::compute_occlusion`] changes, and those past its last point are ignored.
+/// The selection is uploaded as a mask of one bit per point, which is only rebuilt when the
+/// component changes, so changing the selection every frame costs an upload of an eighth of a
+/// byte per point. Changing only the color doesn't rebuild it.
+#[derive(Component, Clone, Debug)]
+pub struct PointCloudSelection {
+    pub indices: Vec<u32>,
+    /// Replaces the shaded color of the selected points, ignoring the tint of the cloud.
+    pub color: Color,
+}
+
+impl PointCloudSelection {
+    /// Selects every point in `ranges`.
+    pub fn from_ranges(ranges: impl IntoIterator<Item = Range<u32>>, color: Color) -> Self {
+        Self {
+            indices: ranges.into_iter().flatten().collect(),
+            color,
+        }
+    }
+}
+
+struct SelectionMask {
+    indices: Vec<u32>,
+    /// In linear RGB.
+    color: Vec4,
+    /// The number of points `mask` was built for, `0` until the asset has been prepared.
+    num_points: u32,
+    mask: Vec<u32>,
+    /// Index of the first word of `mask` in the buffer.
+    offset: u32,
+}
+
+/// The selection masks of all point clouds, one after another in a single storage buffer
+/// bound next to the view uniform.
+#[derive(Resource, Default)]
+pub(crate) struct PointCloudSelectionMasks {
+    selections: HashMap<Entity, SelectionMask>,
+    pub buffer: StorageBuffer<Vec<u32>>,
+    changed: bool,
+}
+
+pub(crate) fn extract_point_cloud_selections(
+    mut masks: ResMut<PointCloudSelectionMasks>,
+    selections: Extract<Query<(Entity, Ref<PointCloudSelection>)>>,
+) {
+    let masks = &mut *masks;
+    let len = masks.selections.len();
+    masks
+        .selections
+        .retain(|&entity, _| selections.contains(entity));
+    masks.changed |= masks.selections.len() != len;
+    for (entity, selection) in &selections {
+        let color = Vec4::from(selection.color.as_linear_rgba_f32());
+        match masks.selections.get_mut(&entity) {
+            Some(mask) if mask.indices == selection.indices || !selection.is_changed() => {
+                mask.color = color;
+            }
+            _ => {
+                masks.selections.insert(
+                    entity,
+                    SelectionMask {
+                        indices: selection.indices.clone(),
+                        color,
+                        num_points: 0,
+                        mask: Vec::new(),
+                        offset: 0,
+                    },
+                );
+                masks.changed = true;
+            }
+        }
+    }
+}
+
+/// Runs in the render world once the assets have been prepared, and before the model
+/// uniforms are uploaded.
+pub(crate) fn prepare_point_cloud_selections(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut masks: ResMut<PointCloudSelectionMasks>,
+    render_assets: Res<RenderAssets<PointCloudAsset>>,
+    mut point_clouds: Query<(Entity, &Handle<PointCloudAsset>, &mut PointCloudUniform)>,
+) {
+    let masks = &mut *masks;
+    for (entity, handle, _) in &point_clouds {
+        let (Some(selection), Some(asset)) =
+            (masks.selections.get_mut(&entity), render_assets.get(handle))
+        else {
+            continue;
+        };
+        if selection.num_points == asset.num_points {
+            continue;
+        }
+        selection.num_points = asset.num_points;
+        selection.mask = vec![0; asset.num_points.div_ceil(32) as usize];
+        for &index in &selection.indices {
+            if index < asset.num_points {
+                selection.mask[index as usize / 32] |= 1 << (index % 32);
+            }
+        }
+        masks.changed = true;
+    }
+
+    if masks.changed || masks.buffer.buffer().is_none() {
+        let words = masks.buffer.get_mut();
+        words.clear();
+        for selection in masks.selections.values_mut() {
+            selection.offset = words.len() as u32;
+            words.extend_from_slice(&selection.mask);
+        }
+        if words.is_empty() {
+            // Bindings can't be empty.
+            words.push(0);
+        }
+        masks.buffer.write_buffer(&render_device, &render_queue);
+        masks.changed = false;
+    }
+
+    for (entity, handle, mut uniform) in &mut point_clouds {
+        let Some(selection) = masks.selections.get(&entity) else {
+            continue;
+        };
+        if render_assets
+            .get(handle)
+            .is_some_and(|asset| asset.num_points == selection.num_points)
+        {
+            uniform.selection_offset = selection.offset;
+            uniform.selection_color = selection.color;
+        }
+    }
+}
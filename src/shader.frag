@@ -3,37 +3,125 @@
 #import bevy_render::view::View
 
 layout(location = 0) out vec4 o_Target;
+#ifdef EYE_DOME_LIGHTING
 layout(location = 1) out float o_Depth;
+#endif
 layout(location = 0) in vec2 in_Point_Location;
 layout(location = 1) in vec3 in_Color;
+layout(location = 2) flat in float in_Point_Size_Scale;
+layout(location = 3) flat in float in_Opacity;
+layout(location = 4) flat in float in_Selected;
 
 layout(set = 0, binding = 0) uniform View view;
+layout(set = 0, binding = 2) uniform PointCloudView {
+    float point_size_multiplier;
+    float min_pixel_size;
+    float max_pixel_size;
+    float eye_dome_strength;
+    float eye_dome_radius;
+    uint eye_dome_samples;
+    float depth_cue_near;
+    float depth_cue_far;
+    float depth_cue_strength;
+    vec4 depth_cue_color;
+};
 layout(set = 2, binding = 0) uniform Model {
     mat4 model_transform;
     float point_size;
+    float near_fade_distance;
+    float edge_softness;
+    uint point_shape;
+    vec4 tint;
+    vec4 selection_color;
 };
 
+const uint POINT_SHAPE_CIRCLE = 1u;
+
+// Interleaved gradient noise, used as a per-pixel dither threshold.
+float dither_threshold(vec2 pixel) {
+    return fract(52.9829189 * fract(dot(pixel, vec2(0.06711056, 0.00583715))));
+}
+
 void main()
 {
     vec2 uv = in_Point_Location * 2.0 - 1.0;
     float depth_offset = sqrt(uv.x * uv.x + uv.y * uv.y);
-    o_Target = vec4(in_Color, 1.0);
+    o_Target = vec4(in_Color * tint.rgb, 1.0);
+    if (in_Selected > 0.0) {
+        o_Target.rgb = selection_color.rgb;
+    }
 
+    float coverage = in_Opacity;
+    if (edge_softness > 0.0) {
+        // Round points, with an edge that fades out over the outer `edge_softness` of the
+        // radius. Nearer geometry still wins the depth test, so only the background shows
+        // through the edge.
+        coverage *= 1.0 - smoothstep(1.0 - edge_softness, 1.0, depth_offset);
+    } else if (point_shape == POINT_SHAPE_CIRCLE && depth_offset > 1.0) {
+        discard;
+    }
+    #ifdef BLENDED
+    coverage *= tint.a;
+    #endif
+    if (coverage < 1.0) {
+        if (coverage <= 0.0) {
+            discard;
+        }
+        #ifdef BLENDED
+        o_Target.a = coverage;
+        #else
+        #ifdef MULTISAMPLED
+        // Resolved by alpha to coverage.
+        o_Target.a = coverage;
+        #else
+        if (coverage < dither_threshold(gl_FragCoord.xy)) {
+            discard;
+        }
+        #endif
+        #endif
+    }
 
     float depth = 1.0 / gl_FragCoord.w; // the world space depth
 
-    if (view.projection[2][3] != -1.0) {
-        // orthographic projection
-        // projection[2][2] is r = 1.0 / (near - far).
-        // This divides the depth offset by (near - far)
-        depth_offset *= view.projection[2][2];
+    if (depth_cue_strength > 0.0) {
+        float view_depth = depth;
+        if (view.projection[2][3] != -1.0) {
+            // orthographic projection
+            // The reverse-z depth is `(far - depth) / (far - near)`, and projection[3][2] is
+            // `far / (far - near)`.
+            view_depth = (view.projection[3][2] - gl_FragCoord.z) / view.projection[2][2];
+        }
+        float cue = depth_cue_far > depth_cue_near
+            ? clamp((view_depth - depth_cue_near) / (depth_cue_far - depth_cue_near), 0.0, 1.0)
+            : step(depth_cue_far, view_depth);
+        o_Target.rgb = mix(o_Target.rgb, depth_cue_color.rgb, cue * depth_cue_strength);
     }
 
+    if (near_fade_distance > 0.0 && view.projection[2][3] == -1.0) {
+        // perspective projection
+        // projection[3][2] is the near plane distance of the infinite reverse-z projection.
+        float near = view.projection[3][2];
+        float fade = clamp((depth - near) / near_fade_distance, 0.0, 1.0);
+        if (fade < dither_threshold(gl_FragCoord.xy)) {
+            discard;
+        }
+    }
 
-    float offseted_depth = depth + point_size * depth_offset;
-
-    float z_near = gl_FragCoord.z * depth;
-    float depth_output = z_near / offseted_depth;
+    float point_depth_offset = point_size * point_size_multiplier * in_Point_Size_Scale * depth_offset;
+    float depth_output;
+    if (view.projection[2][3] == -1.0) {
+        // perspective projection
+        // The reverse-z depth is proportional to the inverse of the view space depth.
+        float z_near = gl_FragCoord.z * depth;
+        depth_output = z_near / (depth + point_depth_offset);
+    } else {
+        // orthographic projection
+        // The depth is linear. Bevy swaps the near and far planes to keep reverse-z, so
+        // projection[2][2] is 1.0 / (far - near).
+        depth_output = gl_FragCoord.z - point_depth_offset * view.projection[2][2];
+    }
     gl_FragDepth = depth_output;
+    #ifdef EYE_DOME_LIGHTING
     o_Depth = depth_output;
+    #endif
 }
@@ -3,9 +3,30 @@
 #import bevy_render::view::View
 
 layout(location = 0) in vec2 in_Position_Point;
+#ifdef DEPTH_SORTED
+// The points in back to front order, see `PointDepthSort`.
+layout(location = 1) in uint in_Point_Index;
+#endif
+#ifdef NO_STORAGE_BUFFERS
+// Without storage buffers, as on WebGL2, every instance reads its point from instance rate
+// vertex buffers, see `PointCloudPipeline::point_stream_layouts`.
+layout(location = 2) in vec3 in_Point_Position;
+#ifdef COLORED
+#ifdef HIGH_PRECISION_COLOR
+layout(location = 3) in uvec2 in_Point_Color;
+#else
+layout(location = 3) in uint in_Point_Color;
+#endif
+#endif
+#endif
 
 layout(location = 0) out vec2 out_Point_Location;
 layout(location = 1) out vec3 out_Color;
+// How much the point was scaled to fit the pixel size limits.
+layout(location = 2) flat out float out_Point_Size_Scale;
+layout(location = 3) flat out float out_Opacity;
+// Whether the point is in the `PointCloudSelection`.
+layout(location = 4) flat out float out_Selected;
 
 layout(set = 0, binding = 0) uniform View view;
 
@@ -20,43 +41,179 @@ layout(set = 0, binding = 1) uniform ClippingPlanes {
     uint num_ranges;
 } clipping_planes;
 
+layout(set = 0, binding = 2) uniform PointCloudView {
+    float point_size_multiplier;
+    float min_pixel_size;
+    float max_pixel_size;
+};
+
+#ifndef NO_STORAGE_BUFFERS
+// The masks of all `PointCloudSelection`s, one bit per point. See `selection_offset`.
+layout(std430, set = 0, binding = 3) readonly buffer Selections {
+    uint[] selection_masks;
+};
+#endif
+
 layout(set = 2, binding = 0) uniform Model {
     mat4 model_transform;
     float point_size_world_space;
+    float near_fade_distance;
+    float edge_softness;
+    uint point_shape;
+    vec4 tint;
+    vec4 selection_color;
+    uint color_mode;
+    uint scalar_offset;
+    float scalar_min;
+    float scalar_max;
+    uint color_ramp_len;
+    vec4 color_ramp[16];
+    vec3 light_factor;
+    float morph_mix;
+    uint morph_interpolate_positions;
+    uint morph_color_format;
+    float opacity;
+    uint point_size_mode;
+    float adaptive_distance;
+    uint radius_offset;
+    float normal_light_intensity;
+    uint num_directional_lights;
+    vec3 ambient_light;
+    vec4 directional_light_directions[4];
+    vec4 directional_light_colors[4];
+    uint confidence_offset;
+    float confidence_threshold;
+    uint confidence_fade;
+    uint selection_offset;
+    uint time_offset;
+    float time_start;
+    float time_end;
 };
 
+const uint COLOR_MODE_RGB = 0u;
+const uint COLOR_MODE_SCALAR = 1u;
+const uint COLOR_MODE_TRANSFER_FUNCTION = 2u;
+const uint COLOR_MODE_HEIGHT = 3u;
+
+const uint POINT_SIZE_MODE_FIXED = 0u;
+const uint POINT_SIZE_MODE_SCREEN = 1u;
+const uint POINT_SIZE_MODE_ADAPTIVE = 2u;
+
+const uint NO_RADII = 0xffffffffu;
+const uint NO_CONFIDENCE = 0xffffffffu;
+const uint NO_SELECTION = 0xffffffffu;
+const uint NO_TIME_WINDOW = 0xffffffffu;
+
 struct PointOffset {
     float position_x;
     float position_y;
     float position_z;
 };
 
+#ifndef NO_STORAGE_BUFFERS
 #ifdef ANIMATED
-layout(std430, set = 1, binding = 1) readonly buffer AnimationOffset {
+layout(std430, set = 1, binding = 2) readonly buffer AnimationOffset {
     float _old_interpolation;
     PointOffset[] prev_offsets;
 };
 
-layout(std430, set = 1, binding = 2) readonly buffer AnimationOffset {
+layout(std430, set = 1, binding = 3) readonly buffer AnimationOffset {
     float interpolation;
     PointOffset[] next_offsets;
 };
 #endif
+#endif
 
-struct Point {
-    float position_x;
-    float position_y;
-    float position_z;
-    #ifdef COLORED
-    float color_r;
-    float color_g;
-    float color_b;
+struct PointPosition {
+    float x;
+    float y;
+    float z;
+};
+
+#ifndef NO_STORAGE_BUFFERS
+layout(std430, set = 1, binding = 0) readonly buffer Positions {
+    PointPosition[] positions;
+};
+
+#ifdef COLORED
+layout(std430, set = 1, binding = 1) readonly buffer Colors {
+    #ifdef HIGH_PRECISION_COLOR
+    // RGBA, 16 bits per channel
+    uvec2[] colors;
+    #else
+    // RGBA, 8 bits per channel
+    uint[] colors;
     #endif
 };
+#endif
+
+layout(std430, set = 1, binding = 4) readonly buffer Scalars {
+    float[] scalars;
+};
+
+layout(std430, set = 1, binding = 5) readonly buffer Normals {
+    PointPosition[] normals;
+};
+#endif
+
+#ifdef INSTANCED
+struct Instance {
+    mat4 transform;
+    vec4 tint;
+    #ifdef BATCHED
+    // The index of the first point of the cloud in the batch, in `x`.
+    uvec4 first_point;
+    #endif
+};
+
+layout(std430, set = 3, binding = 0) readonly buffer Instances {
+    uint num_points;
+    #ifdef BATCHED
+    uint num_instances;
+    #endif
+    Instance[] instances;
+};
+#endif
+
+#ifdef MORPH
+layout(std430, set = 3, binding = 0) readonly buffer MorphPositions {
+    PointPosition[] morph_positions;
+};
 
-layout(std430, set = 1, binding = 0) readonly buffer Asset {
-    Point[] points;
+layout(std430, set = 3, binding = 1) readonly buffer MorphColors {
+    // Either one or two values per point, see `morph_color_format`.
+    uint[] morph_colors;
 };
+#endif
+
+#ifdef TRANSFER_FUNCTION
+layout(set = 3, binding = 0) uniform texture2D transfer_function;
+layout(set = 3, binding = 1) uniform sampler transfer_function_sampler;
+#endif
+
+// Point colors are stored sRGB encoded, like `Color::rgba_u8`, and shaded in linear space.
+vec3 srgb_to_linear(vec3 color) {
+    vec3 linear_part = color / 12.92;
+    vec3 curve_part = pow((color + 0.055) / 1.055, vec3(2.4));
+    return mix(curve_part, linear_part, step(color, vec3(0.04045)));
+}
+
+vec3 sample_color_ramp(float t) {
+    if (color_ramp_len < 2u) {
+        return color_ramp[0].rgb;
+    }
+    float x = clamp(t, 0.0, 1.0) * float(color_ramp_len - 1u);
+    uint i = min(uint(x), color_ramp_len - 2u);
+    return mix(color_ramp[i].rgb, color_ramp[i + 1u].rgb, x - float(i));
+}
+
+// Maps an index to a uniformly distributed value in [0, 1), see
+// https://www.reedbeta.com/blog/hash-functions-for-gpu-rendering/
+float hash_threshold(uint index) {
+    uint state = index * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return float((word >> 22u) ^ word) / 4294967296.0;
+}
 
 void discard_vertex() {
     float nan = uintBitsToFloat(0x7fc00000);
@@ -64,21 +221,82 @@ void discard_vertex() {
 }
 
 void main() {
-    Point p = points[gl_InstanceIndex];
+    #ifdef DEPTH_SORTED
+    uint instance_index = in_Point_Index;
+    #else
+    uint instance_index = uint(gl_InstanceIndex);
+    #endif
+    #ifdef INSTANCED
+    #ifdef BATCHED
+    // All points are drawn at once, and belong to the last cloud starting at or before them.
+    uint point_index = instance_index;
+    uint low = 0u;
+    uint high = num_instances - 1u;
+    while (low < high) {
+        uint middle = (low + high + 1u) / 2u;
+        if (instances[middle].first_point.x <= point_index) {
+            low = middle;
+        } else {
+            high = middle - 1u;
+        }
+    }
+    Instance instance = instances[low];
+    #else
+    // Every instance draws all points of the asset.
+    uint point_index = instance_index % num_points;
+    Instance instance = instances[instance_index / num_points];
+    #endif
+    mat4 transform = model_transform * instance.transform;
+    #else
+    uint point_index = instance_index;
+    mat4 transform = model_transform;
+    #endif
 
-    vec3 in_Pos = vec3(p.position_x, p.position_y, p.position_z);
+    if (opacity < 1.0 && hash_threshold(instance_index) >= opacity) {
+        // Stochastic transparency
+        discard_vertex();
+        return;
+    }
+
+    #ifdef NO_STORAGE_BUFFERS
+    PointPosition p = PointPosition(in_Point_Position.x, in_Point_Position.y, in_Point_Position.z);
+    #else
+    if (time_offset != NO_TIME_WINDOW) {
+        float time = scalars[time_offset + point_index];
+        if (time < time_start || time > time_end) {
+            discard_vertex();
+            return;
+        }
+    }
+
+    PointPosition p = positions[point_index];
+    #endif
+
+    vec3 in_Pos = vec3(p.x, p.y, p.z);
     #ifdef ANIMATED
-    PointOffset prev_offset = prev_offsets[gl_InstanceIndex];
-    PointOffset next_offset = next_offsets[gl_InstanceIndex];
+    PointOffset prev_offset = prev_offsets[point_index];
+    PointOffset next_offset = next_offsets[point_index];
     vec3 prev = vec3(prev_offset.position_x, prev_offset.position_y, prev_offset.position_z);
     vec3 next = vec3(next_offset.position_x, next_offset.position_y, next_offset.position_z);
     vec3 interpolated = prev + (next - prev) * interpolation;
     in_Pos += interpolated;
     #endif
+    #ifdef MORPH
+    if (morph_interpolate_positions != 0u) {
+        PointPosition to = morph_positions[point_index];
+        in_Pos = mix(in_Pos, vec3(to.x, to.y, to.z), morph_mix);
+    }
+    #endif
 
-    vec4 out_Pos = view.view_proj * model_transform * vec4(in_Pos, 1.0);
+    vec4 out_Pos = view.view_proj * transform * vec4(in_Pos, 1.0);
+    if (out_Pos.w <= 0.0) {
+        // The point is behind the camera. Expanding its quad would flip it across the screen
+        // and the point size math below divides by w, so drop it before rasterization.
+        discard_vertex();
+        return;
+    }
     if (clipping_planes.num_ranges > 0u) {
-        vec4 worldPos4 = model_transform * vec4(in_Pos, 1.0);
+        vec4 worldPos4 = transform * vec4(in_Pos, 1.0);
         vec3 worldPos = worldPos4.xyz / worldPos4.w;
 
         // Clip any points that falls out of the allowed ranges.
@@ -92,10 +310,98 @@ void main() {
             }
         }
     }
-    #ifdef COLORED
-    out_Color = vec3(p.color_r, p.color_g, p.color_b);
-    #else
-    out_Color = vec3(p.position_x % 1.0, p.position_y % 1.0, p.position_z % 1.0);
+    out_Opacity = 1.0;
+    out_Selected = 0.0;
+    #ifndef NO_STORAGE_BUFFERS
+    if (selection_offset != NO_SELECTION) {
+        uint word = selection_masks[selection_offset + point_index / 32u];
+        out_Selected = float((word >> (point_index % 32u)) & 1u);
+    }
+    #endif
+    if (color_mode == COLOR_MODE_HEIGHT) {
+        float height = (transform * vec4(in_Pos, 1.0)).y;
+        out_Color = sample_color_ramp((height - scalar_min) / (scalar_max - scalar_min));
+    #ifndef NO_STORAGE_BUFFERS
+    } else if (color_mode == COLOR_MODE_SCALAR) {
+        float value = scalars[scalar_offset + point_index];
+        out_Color = sample_color_ramp((value - scalar_min) / (scalar_max - scalar_min));
+    #endif
+    #ifdef TRANSFER_FUNCTION
+    } else if (color_mode == COLOR_MODE_TRANSFER_FUNCTION) {
+        float value = scalars[scalar_offset + point_index];
+        float t = clamp((value - scalar_min) / (scalar_max - scalar_min), 0.0, 1.0);
+        // Map the range onto the centers of the first and last texels.
+        float width = float(textureSize(sampler2D(transfer_function, transfer_function_sampler), 0).x);
+        vec2 uv = vec2((t * (width - 1.0) + 0.5) / width, 0.5);
+        vec4 color = textureLod(sampler2D(transfer_function, transfer_function_sampler), uv, 0.0);
+        out_Color = color.rgb;
+        out_Opacity = color.a;
+    #endif
+    } else {
+        #ifdef COLORED
+        #ifdef NO_STORAGE_BUFFERS
+        #ifdef HIGH_PRECISION_COLOR
+        uvec2 c = in_Point_Color;
+        #else
+        uint c = in_Point_Color;
+        #endif
+        #else
+        #ifdef HIGH_PRECISION_COLOR
+        uvec2 c = colors[point_index];
+        #else
+        uint c = colors[point_index];
+        #endif
+        #endif
+        #ifdef HIGH_PRECISION_COLOR
+        out_Color = srgb_to_linear(vec3(unpackUnorm2x16(c.x), unpackUnorm2x16(c.y).x));
+        #else
+        out_Color = srgb_to_linear(unpackUnorm4x8(c).rgb);
+        #endif
+        #else
+        out_Color = vec3(p.x % 1.0, p.y % 1.0, p.z % 1.0);
+        #endif
+    }
+    #ifndef NO_STORAGE_BUFFERS
+    if (confidence_offset != NO_CONFIDENCE) {
+        float confidence = scalars[confidence_offset + point_index];
+        if (confidence < confidence_threshold) {
+            discard_vertex();
+            return;
+        }
+        if (confidence_fade != 0u) {
+            out_Opacity *= clamp(confidence, 0.0, 1.0);
+        }
+    }
+    #endif
+    #ifdef MORPH
+    if (color_mode == COLOR_MODE_RGB && morph_color_format != 0u) {
+        vec3 to_color;
+        if (morph_color_format == 2u) {
+            uvec2 c = uvec2(morph_colors[point_index * 2u], morph_colors[point_index * 2u + 1u]);
+            to_color = vec3(unpackUnorm2x16(c.x), unpackUnorm2x16(c.y).x);
+        } else {
+            to_color = unpackUnorm4x8(morph_colors[point_index]).rgb;
+        }
+        out_Color = mix(out_Color, srgb_to_linear(to_color), morph_mix);
+    }
+    #endif
+    #ifndef NO_STORAGE_BUFFERS
+    if (normal_light_intensity > 0.0) {
+        PointPosition n = normals[point_index];
+        vec3 normal = normalize(transpose(inverse(mat3(transform))) * vec3(n.x, n.y, n.z));
+        vec3 light = ambient_light;
+        for (uint i = 0u; i < num_directional_lights; i++) {
+            light += directional_light_colors[i].rgb
+                * max(dot(normal, directional_light_directions[i].xyz), 0.0);
+        }
+        out_Color *= mix(vec3(1.0), light, normal_light_intensity);
+    } else
+    #endif
+    {
+        out_Color *= light_factor;
+    }
+    #ifdef INSTANCED
+    out_Color *= instance.tint.rgb;
     #endif
 
 
@@ -104,16 +410,39 @@ void main() {
         // perspective projection
         float depth = out_Pos.w;
         float one_over_slope = view.projection[1][1]; // (0.5 * fov_y_radians).tan()
-        point_size = vec2(0.5 * point_size_world_space * one_over_slope);
+        point_size = vec2(0.5 * point_size_world_space * point_size_multiplier * one_over_slope);
     } else {
         // orthographic projection
         float a = 2.0 / view.projection[0][0]; // right - left
         float b = 2.0 / view.projection[1][1]; // top - bottom
         float max_scale = max(abs(a), abs(b));
-        point_size = vec2(point_size_world_space / max_scale);
+        point_size = vec2(point_size_world_space * point_size_multiplier / max_scale);
     }
+
+    #ifndef NO_STORAGE_BUFFERS
+    if (radius_offset != NO_RADII) {
+        point_size *= scalars[radius_offset + point_index];
+    }
+    #endif
+
+    // The point size in pixels, see `QUAD_VERTEX_BUF`.
+    float pixel_size = point_size.x / out_Pos.w * view.viewport.z * 0.5;
+    // Rescales the world space size to the size mode's pixel size.
+    float mode_scale = 1.0;
+    if (point_size_mode == POINT_SIZE_MODE_SCREEN) {
+        mode_scale = pixel_size > 0.0 ? point_size_world_space * point_size_multiplier / pixel_size : 1.0;
+    } else if (point_size_mode == POINT_SIZE_MODE_ADAPTIVE && view.projection[2][3] == -1.0) {
+        mode_scale = max(out_Pos.w / adaptive_distance, 1.0);
+    }
+    pixel_size *= mode_scale;
+    float clamped_pixel_size = min(max(pixel_size, min_pixel_size), max_pixel_size);
+    out_Point_Size_Scale = mode_scale * (pixel_size > 0.0 ? clamped_pixel_size / pixel_size : 1.0);
+    point_size *= out_Point_Size_Scale;
+
     point_size.y *= view.viewport.z / view.viewport.w;
 
     out_Point_Location = in_Position_Point;
+    // The quad is expanded in clip space, so it is always screen aligned and faces the
+    // camera whatever its orientation, including roll.
     gl_Position = out_Pos + vec4(in_Position_Point * point_size, 0.0, 0.0);
 }
@@ -0,0 +1,347 @@
+use bevy::{
+    prelude::*,
+    render::{
+        extract_component::ExtractComponent,
+        render_asset::RenderAssets,
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        view::ExtractedView,
+    },
+    utils::{HashMap, HashSet},
+};
+
+use crate::{PointCloudAsset, PointCloudDrawList, PointCloudUniform};
+
+pub(crate) const DEPTH_SORT_SHADER_HANDLE: Handle<Shader> =
+    Handle::weak_from_u128(0x3fc9d1ff70cedf04);
+
+/// Sorts the points of a [`PointBlendMode::AlphaBlend`](crate::PointBlendMode::AlphaBlend)
+/// cloud back to front for every view before drawing them, so overlapping translucent points
+/// blend in the right order. Does nothing with other blend modes, which don't depend on the
+/// order, and for [`InstancedPointCloud`](crate::InstancedPointCloud)s.
+///
+/// The point indices are sorted by view space depth with a bitonic sort in compute shaders,
+/// and the draw reads the points in that order. Points are sorted by their positions in the
+/// asset, before animation and morphing offsets. All points are sorted and drawn, so sorted
+/// clouds aren't thinned out by a [`PointCloudLod`](crate::PointCloudLod), and their
+/// precomputed [`PointCloudOcclusion`](crate::PointCloudOcclusion) isn't used either. Both pick
+/// ranges of the unsorted points.
+///
+/// The cost grows with `n log² n` and is paid for every sorted cloud in every view, every
+/// frame. A cloud of 1M points is padded to 2²⁰ points and sorted in 79 dispatches, which read
+/// and write about 1.3 GB of keys and indices in total. The `depth_sort_cost` example measures
+/// it on the GPU at hand, by the frame times with and without the sort. On llvmpipe, a software
+/// renderer running on a single CPU core, sorting 1M points added 305 ms to every frame. By the
+/// memory traffic, a GPU with 400 GB/s of bandwidth should take about 3 ms, and integrated GPUs
+/// several times that. Prefer it for moderately sized clouds or
+/// [`PointCloudTransparency::Stochastic`](crate::PointCloudTransparency::Stochastic), which
+/// needs no sorting, for huge ones.
+#[derive(Component, Clone, Copy, Debug, Default, ExtractComponent)]
+pub struct PointDepthSort;
+
+const WORKGROUP_SIZE: u32 = 256;
+/// The number of elements sorted in workgroup memory at once, two per invocation.
+const LOCAL_LEN: u32 = WORKGROUP_SIZE * 2;
+/// The largest power of two workgroup count of one dispatch dimension.
+const MAX_WORKGROUPS_X: u32 = 32768;
+
+#[derive(Clone, ShaderType)]
+struct DepthSortParams {
+    depth_plane: Vec4,
+    num_points: u32,
+    block: u32,
+    distance: u32,
+}
+
+#[derive(Resource)]
+pub(crate) struct DepthSortPipeline {
+    layout: BindGroupLayout,
+    compute_keys: CachedComputePipelineId,
+    sort_local: CachedComputePipelineId,
+    merge_local: CachedComputePipelineId,
+    merge_global: CachedComputePipelineId,
+}
+
+impl FromWorld for DepthSortPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let storage = |binding, read_only| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("DepthSortLayout"),
+            entries: &[
+                // Positions
+                storage(0, true),
+                // Keys
+                storage(1, false),
+                // Indices
+                storage(2, false),
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: Some(DepthSortParams::min_size()),
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let queue = |entry_point: &'static str| {
+            pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                label: Some("depth_sort_pipeline".into()),
+                layout: vec![layout.clone()],
+                push_constant_ranges: default(),
+                shader: DEPTH_SORT_SHADER_HANDLE,
+                shader_defs: default(),
+                entry_point: entry_point.into(),
+            })
+        };
+        Self {
+            compute_keys: queue("compute_keys"),
+            sort_local: queue("sort_local"),
+            merge_local: queue("merge_local"),
+            merge_global: queue("merge_global"),
+            layout,
+        }
+    }
+}
+
+/// The sort buffers of every sorted point cloud in every view, kept across frames.
+#[derive(Resource, Default)]
+pub(crate) struct DepthSortBuffers {
+    buffers: HashMap<(Entity, Entity), DepthSortBuffer>,
+    params: DynamicUniformBuffer<DepthSortParams>,
+}
+
+struct DepthSortBuffer {
+    keys: Buffer,
+    indices: Buffer,
+    /// The number of points, padded to a power of two of at least [`LOCAL_LEN`].
+    len: u32,
+}
+
+impl DepthSortBuffer {
+    fn new(render_device: &RenderDevice, len: u32) -> Self {
+        let buffer = |label, usage| {
+            render_device.create_buffer(&BufferDescriptor {
+                label: Some(label),
+                size: len as u64 * 4,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        Self {
+            keys: buffer("depth sort key buffer", BufferUsages::STORAGE),
+            // Read by the draw as an instance rate vertex buffer.
+            indices: buffer(
+                "depth sort index buffer",
+                BufferUsages::STORAGE | BufferUsages::VERTEX,
+            ),
+            len,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum DepthSortStep {
+    ComputeKeys,
+    SortLocal,
+    MergeLocal,
+    MergeGlobal,
+}
+
+struct DepthSortDispatch {
+    step: DepthSortStep,
+    params_offset: u32,
+    workgroups: u32,
+}
+
+struct PreparedDepthSort {
+    bind_group: BindGroup,
+    dispatches: Vec<DepthSortDispatch>,
+    /// Point indices, back to front.
+    indices: Buffer,
+}
+
+/// The [`PointDepthSort`]s of a view.
+#[derive(Component)]
+pub struct PreparedDepthSorts(HashMap<Entity, PreparedDepthSort>);
+
+impl PreparedDepthSorts {
+    /// Records the sorts into a compute pass. Returns `false`, without recording anything, if
+    /// the pipelines haven't been compiled yet.
+    pub(crate) fn dispatch(&self, render_context: &mut RenderContext, world: &World) -> bool {
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let pipeline = world.resource::<DepthSortPipeline>();
+        let (Some(compute_keys), Some(sort_local), Some(merge_local), Some(merge_global)) = (
+            pipeline_cache.get_compute_pipeline(pipeline.compute_keys),
+            pipeline_cache.get_compute_pipeline(pipeline.sort_local),
+            pipeline_cache.get_compute_pipeline(pipeline.merge_local),
+            pipeline_cache.get_compute_pipeline(pipeline.merge_global),
+        ) else {
+            return false;
+        };
+        #[cfg(feature = "trace")]
+        let _span = info_span!("point_depth_sort", sorts = self.0.len()).entered();
+        let mut pass =
+            render_context
+                .command_encoder()
+                .begin_compute_pass(&ComputePassDescriptor {
+                    label: Some("point_depth_sort"),
+                });
+        for sort in self.0.values() {
+            for dispatch in &sort.dispatches {
+                pass.set_pipeline(match dispatch.step {
+                    DepthSortStep::ComputeKeys => compute_keys,
+                    DepthSortStep::SortLocal => sort_local,
+                    DepthSortStep::MergeLocal => merge_local,
+                    DepthSortStep::MergeGlobal => merge_global,
+                });
+                pass.set_bind_group(0, &sort.bind_group, &[dispatch.params_offset]);
+                // Workgroup counts are powers of two, so they split evenly.
+                let x = dispatch.workgroups.min(MAX_WORKGROUPS_X);
+                pass.dispatch_workgroups(x, dispatch.workgroups / x, 1);
+            }
+        }
+        true
+    }
+
+    /// The sorted point indices of `entity`, once [`PreparedDepthSorts::dispatch`] ran.
+    pub(crate) fn indices(&self, entity: Entity) -> Option<&Buffer> {
+        self.0.get(&entity).map(|sort| &sort.indices)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn prepare_depth_sorts(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    pipeline: Res<DepthSortPipeline>,
+    mut buffers: ResMut<DepthSortBuffers>,
+    render_assets: Res<RenderAssets<PointCloudAsset>>,
+    views: Query<(Entity, &ExtractedView, &PointCloudDrawList)>,
+    point_clouds: Query<(&Handle<PointCloudAsset>, &PointCloudUniform)>,
+) {
+    let buffers = &mut *buffers;
+    buffers.params.clear();
+    let mut sorts = Vec::new();
+    for (view_entity, view, draw_list) in &views {
+        let view_from_world = view.transform.compute_matrix().inverse();
+        for draw_data in draw_list
+            .list
+            .iter()
+            .filter(|draw_data| draw_data.depth_sorted)
+        {
+            let Ok((handle, uniform)) = point_clouds.get(draw_data.entity) else {
+                continue;
+            };
+            let Some(asset) = render_assets.get(handle) else {
+                continue;
+            };
+            if asset.num_points == 0 {
+                continue;
+            }
+            let len = asset.num_points.next_power_of_two().max(LOCAL_LEN);
+            let buffer = buffers
+                .buffers
+                .entry((view_entity, draw_data.entity))
+                .or_insert_with(|| DepthSortBuffer::new(&render_device, len));
+            if buffer.len != len {
+                *buffer = DepthSortBuffer::new(&render_device, len);
+            }
+
+            // Views look down -Z.
+            let depth_plane = -(view_from_world * uniform.transform).row(2);
+            let mut dispatch = |step, block, distance, workgroups| DepthSortDispatch {
+                step,
+                params_offset: buffers.params.push(DepthSortParams {
+                    depth_plane,
+                    num_points: asset.num_points,
+                    block,
+                    distance,
+                }),
+                workgroups,
+            };
+            let mut dispatches = vec![
+                dispatch(DepthSortStep::ComputeKeys, 0, 0, len / WORKGROUP_SIZE),
+                dispatch(DepthSortStep::SortLocal, 0, 0, len / LOCAL_LEN),
+            ];
+            let mut block = LOCAL_LEN * 2;
+            while block <= len {
+                let mut distance = block / 2;
+                while distance >= LOCAL_LEN {
+                    dispatches.push(dispatch(
+                        DepthSortStep::MergeGlobal,
+                        block,
+                        distance,
+                        len / LOCAL_LEN,
+                    ));
+                    distance /= 2;
+                }
+                dispatches.push(dispatch(
+                    DepthSortStep::MergeLocal,
+                    block,
+                    0,
+                    len / LOCAL_LEN,
+                ));
+                block *= 2;
+            }
+            sorts.push((
+                view_entity,
+                draw_data.entity,
+                asset.position_buffer.clone(),
+                dispatches,
+            ));
+        }
+    }
+    let used: HashSet<_> = sorts
+        .iter()
+        .map(|(view_entity, entity, ..)| (*view_entity, *entity))
+        .collect();
+    buffers.buffers.retain(|key, _| used.contains(key));
+
+    buffers.params.write_buffer(&render_device, &render_queue);
+    let Some(params) = buffers.params.binding() else {
+        return;
+    };
+    let mut views = HashMap::<Entity, HashMap<Entity, PreparedDepthSort>>::new();
+    for (view_entity, entity, positions, dispatches) in sorts {
+        let buffer = &buffers.buffers[&(view_entity, entity)];
+        let bind_group = render_device.create_bind_group(
+            "depth sort bind group",
+            &pipeline.layout,
+            &BindGroupEntries::sequential((
+                positions.as_entire_binding(),
+                buffer.keys.as_entire_binding(),
+                buffer.indices.as_entire_binding(),
+                params.clone(),
+            )),
+        );
+        views.entry(view_entity).or_default().insert(
+            entity,
+            PreparedDepthSort {
+                bind_group,
+                dispatches,
+                indices: buffer.indices.clone(),
+            },
+        );
+    }
+    for (view_entity, sorts) in views {
+        commands
+            .entity(view_entity)
+            .insert(PreparedDepthSorts(sorts));
+    }
+}
@@ -0,0 +1,45 @@
+use bevy::{asset::AssetPath, ecs::system::EntityCommands, prelude::*};
+
+use crate::{
+    AutoPointSize, FramePointCloudWhenReady, PointCloudBundle, PotreePointCloud,
+    QueuedPointCloudLoad,
+};
+
+/// Spawns point clouds with sensible defaults in a single call.
+pub trait SpawnPointCloudExt<'w, 's> {
+    /// Queues the point cloud at `path` for loading and spawns it at the origin with an
+    /// [`AutoPointSize`]. Once the asset has loaded, the first active camera is moved to frame
+    /// it, see [`FramePointCloudWhenReady`].
+    ///
+    /// The load goes through the [`PointCloudLoadQueue`](crate::PointCloudLoadQueue); insert a
+    /// [`LoadPriority`](crate::LoadPriority) on the returned entity to load it before other
+    /// queued clouds. The entity is valid to use immediately; it starts drawing once the asset
+    /// is ready.
+    fn spawn_point_cloud<'a, 'p>(
+        &'a mut self,
+        path: impl Into<AssetPath<'p>>,
+    ) -> EntityCommands<'w, 's, 'a>;
+}
+
+impl<'w, 's> SpawnPointCloudExt<'w, 's> for Commands<'w, 's> {
+    fn spawn_point_cloud<'a, 'p>(
+        &'a mut self,
+        path: impl Into<AssetPath<'p>>,
+    ) -> EntityCommands<'w, 's, 'a> {
+        let auto_point_size = AutoPointSize::default();
+        self.spawn((
+            PointCloudBundle {
+                point_cloud: PotreePointCloud {
+                    point_size: auto_point_size.min_size,
+                    ..default()
+                },
+                ..default()
+            },
+            auto_point_size,
+            FramePointCloudWhenReady,
+            QueuedPointCloudLoad {
+                path: path.into().into_owned(),
+            },
+        ))
+    }
+}
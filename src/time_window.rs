@@ -0,0 +1,42 @@
+use bevy::prelude::*;
+
+use crate::PointCloudAsset;
+
+/// Name of the scalar field holding per-point timestamps, like the GPS times of LiDAR sweeps.
+/// The LAS loader stores them, if [`LasLoaderSettings::scalar_fields`] keeps them, in seconds
+/// after the earliest point of the file, which is kept in
+/// [`PointCloudAsset::timestamp_origin`], so they stay precise in `f32`.
+///
+/// [`LasLoaderSettings::scalar_fields`]: crate::LasLoaderSettings::scalar_fields
+pub const TIMESTAMP_SCALAR_FIELD: &str = "gps_time";
+
+/// Only draws the points of a [`PotreePointCloud`](crate::PotreePointCloud) whose
+/// [`TIMESTAMP_SCALAR_FIELD`] value is between `start` and `end`, both included, for example
+/// to scrub through a sweep.
+///
+/// The bounds are in the time base of the source file, not relative to the asset, so the same
+/// window can be used for clouds loaded from several files. Does nothing if the asset has no
+/// timestamps.
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct TimeWindow {
+    pub start: f64,
+    pub end: f64,
+}
+
+impl TimeWindow {
+    /// The index of the first timestamp in the asset's scalar buffer, or `u32::MAX` if the
+    /// asset doesn't have them, followed by the bounds relative to
+    /// [`PointCloudAsset::timestamp_origin`].
+    pub(crate) fn gpu(&self, asset: Option<&PointCloudAsset>) -> (u32, f32, f32) {
+        let Some((asset, index)) = asset
+            .and_then(|asset| Some((asset, asset.scalar_field_index(TIMESTAMP_SCALAR_FIELD)?)))
+        else {
+            return (u32::MAX, 0.0, 0.0);
+        };
+        (
+            (index * asset.num_points()) as u32,
+            (self.start - asset.timestamp_origin) as f32,
+            (self.end - asset.timestamp_origin) as f32,
+        )
+    }
+}
@@ -0,0 +1,89 @@
+use bevy::prelude::*;
+
+use crate::PointCloudAsset;
+
+/// How a point cloud is made translucent. Point clouds without it are opaque.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq)]
+pub enum PointCloudTransparency {
+    #[default]
+    Opaque,
+    /// Draws only an `opacity` fraction of the points, picked by a hash of the point index,
+    /// so the cloud looks blended from a distance without sorting or blending.
+    ///
+    /// The remaining points are opaque and write depth, so the cloud is still correctly
+    /// occluded by and occludes other geometry. The effect relies on density: sparse clouds
+    /// look thinned out rather than translucent. The same points are dropped every frame,
+    /// so there is no flickering.
+    Stochastic {
+        /// From `0.0` to `1.0`.
+        opacity: f32,
+    },
+}
+
+impl PointCloudTransparency {
+    /// The fraction of points that is drawn.
+    pub(crate) fn opacity(transparency: Option<&Self>) -> f32 {
+        match transparency {
+            Some(Self::Stochastic { opacity }) => opacity.clamp(0.0, 1.0),
+            Some(Self::Opaque) | None => 1.0,
+        }
+    }
+}
+
+/// Fades or hides the points of a [`PotreePointCloud`](crate::PotreePointCloud) by a per-point
+/// confidence, read from one of the asset's scalar fields. The PLY and PCD loaders keep
+/// properties like `confidence` or `alpha` as scalar fields of the same name.
+///
+/// Does nothing if the asset has no field named `field`.
+#[derive(Component, Clone, Debug, PartialEq)]
+pub struct PointConfidence {
+    pub field: String,
+    /// Points with a lower confidence are not drawn.
+    pub threshold: f32,
+    /// Multiplies the opacity of the drawn points by their confidence, clamped to `0.0..=1.0`.
+    /// Translucent points are blended with [`PointBlendMode::AlphaBlend`], and dithered, or
+    /// use alpha to coverage with MSAA, otherwise.
+    pub fade: bool,
+}
+
+impl Default for PointConfidence {
+    fn default() -> Self {
+        Self {
+            field: "confidence".to_owned(),
+            threshold: 0.0,
+            fade: false,
+        }
+    }
+}
+
+impl PointConfidence {
+    /// The index of the first value of the field in the asset's scalar buffer, or `u32::MAX`
+    /// if the asset doesn't have it.
+    pub(crate) fn scalar_offset(&self, asset: Option<&PointCloudAsset>) -> u32 {
+        asset
+            .and_then(|asset| {
+                let index = asset.scalar_field_index(&self.field)?;
+                Some((index * asset.num_points()) as u32)
+            })
+            .unwrap_or(u32::MAX)
+    }
+}
+
+/// How the points of a cloud are combined with what is already drawn.
+///
+/// Blended clouds are drawn after all opaque clouds. They test against the depth buffer but
+/// don't write to it, so eye dome lighting can't outline them, see
+/// [`EyeDomeBlendedClouds`](crate::EyeDomeBlendedClouds). Clouds are sorted back to front by
+/// the distance to their bounds' center, but the points within a cloud are not sorted, so
+/// overlapping [`PointBlendMode::AlphaBlend`] points may blend in the wrong order. Add a
+/// [`PointDepthSort`](crate::PointDepthSort) to sort them as well.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum PointBlendMode {
+    #[default]
+    Opaque,
+    /// Adds the point colors, weighted by their alpha, so overlapping points accumulate
+    /// brightness. Useful for density visualizations.
+    Additive,
+    /// Regular alpha blending.
+    AlphaBlend,
+}
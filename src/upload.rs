@@ -0,0 +1,211 @@
+use std::sync::{Arc, Mutex, Weak};
+
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::VertexAttributeValues, render_resource::PrimitiveTopology, renderer::RenderQueue,
+    },
+};
+
+use crate::{
+    PointCloudAsset, PreparedPointCloudAsset, ATTRIBUTE_COLOR_PACKED, ATTRIBUTE_COLOR_RGBA16,
+};
+
+/// Changes to a [`PointCloudAsset`] that are written into the buffers it was last prepared
+/// with, instead of extracting and preparing the whole asset again.
+///
+/// Shared by the asset and the copies extracted from it. Clones of the asset get their own,
+/// since they are prepared on their own.
+#[derive(Default)]
+pub(crate) struct PointCloudUploads {
+    shared: Arc<Mutex<SharedUploads>>,
+    /// Set on extracted copies that only hold the changed data.
+    partial: Option<PartialUpload>,
+}
+
+impl Clone for PointCloudUploads {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct SharedUploads {
+    /// The asset as it was last prepared, for partial uploads to write into.
+    prepared: Option<PreparedPointCloudAsset>,
+    /// The changes recorded since the asset was last extracted. Without any, the whole asset
+    /// is extracted, so changes that aren't recorded must not be mixed with recorded ones.
+    pending: Option<PartialUpload>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct PartialUpload {
+    /// Points from this index on were appended.
+    appended_from: Option<usize>,
+    colors: bool,
+}
+
+/// Lets evicted [`PreparedPointCloudAsset`]s drop the copy kept for partial uploads, so that
+/// their buffers are freed. Weak, since the copy holds one too.
+pub(crate) type WeakUploads = Weak<Mutex<SharedUploads>>;
+
+impl PointCloudAsset {
+    /// Uploads the points from `from` on, appended since the asset was last extracted, on
+    /// their own. The asset is prepared again instead when it was changed in other ways
+    /// too, or the buffers it was prepared with can't hold the new points.
+    pub(crate) fn upload_appended_points(&mut self, from: usize) {
+        self.record_upload(|pending| {
+            pending.appended_from = Some(pending.appended_from.map_or(from, |old| old.min(from)));
+        });
+    }
+
+    /// Uploads the colors on their own, like [`Self::upload_appended_points`].
+    pub(crate) fn upload_colors(&mut self) {
+        self.record_upload(|pending| pending.colors = true);
+    }
+
+    fn record_upload(&mut self, record: impl FnOnce(&mut PartialUpload)) {
+        let mut shared = self.uploads.shared.lock().unwrap();
+        // Without a prepared copy to write into, the asset is prepared as a whole anyway.
+        if shared.prepared.is_some() {
+            record(shared.pending.get_or_insert_with(default));
+        }
+    }
+
+    /// A copy of the asset for the render world, with only the data of the pending partial
+    /// upload, if there is one that the prepared buffers can take.
+    pub(crate) fn extract_partial(&self) -> Option<Self> {
+        let mut shared = self.uploads.shared.lock().unwrap();
+        let pending = shared.pending.take()?;
+        let prepared = shared.prepared.as_ref()?;
+        // The animation state is kept by the prepared asset in the render world, not the copy.
+        if prepared.animation_buffer.is_some() {
+            return None;
+        }
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            self.mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            return None;
+        };
+        let from = pending.appended_from.unwrap_or(positions.len());
+        if from > positions.len() || from != prepared.num_points as usize {
+            return None;
+        }
+        if from < positions.len() {
+            // Only the positions of the new points can be written, the other per-point
+            // buffers were sized for the old ones.
+            let capacity =
+                prepared.position_buffer.size() / PreparedPointCloudAsset::POSITION_STRIDE;
+            if positions.len() as u64 > capacity
+                || prepared.color_buffer.is_some()
+                || prepared.scalar_buffer.is_some()
+                || prepared.normal_buffer.is_some()
+            {
+                return None;
+            }
+        }
+        let colors = if pending.colors {
+            let colors = self
+                .mesh
+                .attribute(ATTRIBUTE_COLOR_RGBA16)
+                .map(|colors| (ATTRIBUTE_COLOR_RGBA16, colors))
+                .or_else(|| {
+                    self.mesh
+                        .attribute(ATTRIBUTE_COLOR_PACKED)
+                        .map(|colors| (ATTRIBUTE_COLOR_PACKED, colors))
+                });
+            // The colors must fit the color buffer as it is.
+            match (colors, &prepared.color_buffer) {
+                (Some((attribute, colors)), Some(buffer))
+                    if (attribute.id == ATTRIBUTE_COLOR_RGBA16.id)
+                        == prepared.high_precision_color
+                        && colors.len() == positions.len()
+                        && buffer.size() == colors.get_bytes().len() as u64 =>
+                {
+                    Some((attribute, colors.clone()))
+                }
+                _ => return None,
+            }
+        } else {
+            None
+        };
+
+        let mut mesh = Mesh::new(PrimitiveTopology::PointList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions[from..].to_vec());
+        let mut asset = PointCloudAsset::new(mesh);
+        if let Some((attribute, colors)) = colors {
+            asset.mesh.insert_attribute(attribute, colors);
+        }
+        asset.aabb = self.aabb;
+        asset.pending_points = self.pending_points;
+        asset.uploads = PointCloudUploads {
+            shared: self.uploads.shared.clone(),
+            partial: Some(PartialUpload {
+                appended_from: Some(from),
+                colors: pending.colors,
+            }),
+        };
+        Some(asset)
+    }
+
+    /// Shares the partial uploads of the asset with a copy extracted from it.
+    pub(crate) fn share_uploads(&self, extracted: &mut Self) {
+        self.uploads.shared.lock().unwrap().pending = None;
+        extracted.uploads.shared = self.uploads.shared.clone();
+    }
+}
+
+impl PreparedPointCloudAsset {
+    /// Writes the data of an asset from [`PointCloudAsset::extract_partial`] into the buffers
+    /// the asset was last prepared with. Returns `None` for assets extracted as a whole.
+    pub(crate) fn prepare_partial(
+        extracted_asset: &PointCloudAsset,
+        render_queue: &RenderQueue,
+    ) -> Option<Self> {
+        let partial = extracted_asset.uploads.partial?;
+        let mut shared = extracted_asset.uploads.shared.lock().unwrap();
+        let Some(mut asset) = shared.prepared.clone() else {
+            // Only evicted assets lose their buffers, and those are extracted as a whole.
+            warn!("The buffers of a point cloud asset were dropped before a partial upload");
+            return None;
+        };
+        let from = partial.appended_from.unwrap_or(asset.num_points as usize);
+        if let Some(positions) = extracted_asset
+            .mesh
+            .attribute(Mesh::ATTRIBUTE_POSITION)
+            .filter(|positions| !positions.is_empty())
+        {
+            render_queue.write_buffer(
+                &asset.position_buffer,
+                from as u64 * PreparedPointCloudAsset::POSITION_STRIDE,
+                positions.get_bytes(),
+            );
+            asset.num_points = (from + positions.len()) as u32;
+        }
+        if partial.colors {
+            let colors = extracted_asset
+                .mesh
+                .attribute(ATTRIBUTE_COLOR_RGBA16)
+                .or_else(|| extracted_asset.mesh.attribute(ATTRIBUTE_COLOR_PACKED));
+            if let (Some(colors), Some(buffer)) = (colors, &asset.color_buffer) {
+                render_queue.write_buffer(buffer, 0, colors.get_bytes());
+            }
+        }
+        asset.aabb = extracted_asset.aabb;
+        shared.prepared = Some(asset.clone());
+        Some(asset)
+    }
+
+    /// Keeps a copy of the freshly prepared `self` for partial uploads to write into.
+    pub(crate) fn cache_for_uploads(&mut self, uploads: &PointCloudUploads) {
+        self.uploads = Arc::downgrade(&uploads.shared);
+        uploads.shared.lock().unwrap().prepared = Some(self.clone());
+    }
+
+    /// Drops the copy kept for partial uploads, once the asset was evicted.
+    pub(crate) fn forget_uploads(&self) {
+        if let Some(shared) = self.uploads.upgrade() {
+            shared.lock().unwrap().prepared = None;
+        }
+    }
+}
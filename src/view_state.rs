@@ -0,0 +1,167 @@
+use bevy::{
+    asset::AssetPath,
+    prelude::*,
+    utils::{
+        thiserror::{self, Error},
+        HashMap,
+    },
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{EyeDomeSettings, PointColorMode, PotreePointCloud};
+
+/// The camera and display settings someone last viewed a dataset with.
+///
+/// Capture it with [`ViewState::capture`] and store it in [`ViewStates`] to restore it the next
+/// time the dataset is opened.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ViewState {
+    pub camera_translation: Vec3,
+    pub camera_rotation: Quat,
+    /// See [`PotreePointCloud::point_size`].
+    pub point_size: f32,
+    pub color_mode: PointColorMode,
+    /// See [`EyeDomeSettings::strength`]. States saved without it get the default.
+    #[serde(default = "default_eye_dome_strength")]
+    pub eye_dome_strength: f32,
+    /// See [`EyeDomeSettings::radius`]. States saved without it get the default.
+    #[serde(default = "default_eye_dome_radius")]
+    pub eye_dome_radius: f32,
+}
+
+fn default_eye_dome_strength() -> f32 {
+    EyeDomeSettings::default().strength
+}
+
+fn default_eye_dome_radius() -> f32 {
+    EyeDomeSettings::default().radius
+}
+
+impl ViewState {
+    pub fn capture(
+        camera_transform: &Transform,
+        point_cloud: &PotreePointCloud,
+        color_mode: Option<&PointColorMode>,
+        eye_dome_settings: Option<&EyeDomeSettings>,
+    ) -> Self {
+        let eye_dome_settings = eye_dome_settings.copied().unwrap_or_default();
+        Self {
+            camera_translation: camera_transform.translation,
+            camera_rotation: camera_transform.rotation,
+            point_size: point_cloud.point_size,
+            color_mode: color_mode.cloned().unwrap_or_default(),
+            eye_dome_strength: eye_dome_settings.strength,
+            eye_dome_radius: eye_dome_settings.radius,
+        }
+    }
+
+    /// Moves the camera and sets the point size and the eye dome strength and radius of the
+    /// camera's `eye_dome_settings`. Insert [`ViewState::color_mode`] next to the point cloud to
+    /// restore its colors as well.
+    pub fn apply(
+        &self,
+        camera_transform: &mut Transform,
+        point_cloud: &mut PotreePointCloud,
+        eye_dome_settings: &mut EyeDomeSettings,
+    ) {
+        camera_transform.translation = self.camera_translation;
+        camera_transform.rotation = self.camera_rotation;
+        point_cloud.point_size = self.point_size;
+        eye_dome_settings.strength = self.eye_dome_strength;
+        eye_dome_settings.radius = self.eye_dome_radius;
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum ViewStateError {
+    #[error("Could not serialize the view states: {0}")]
+    Serialize(#[from] ron::Error),
+    #[error("Could not deserialize the view states: {0}")]
+    Deserialize(#[from] ron::error::SpannedError),
+}
+
+/// [`ViewState`]s keyed by the asset path of their dataset.
+///
+/// This doesn't touch the file system. Persist the string from [`ViewStates::to_ron`] wherever
+/// the application keeps its settings, and read it back with [`ViewStates::from_ron`].
+#[derive(Resource, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ViewStates {
+    states: HashMap<String, ViewState>,
+}
+
+impl ViewStates {
+    pub fn save(&mut self, asset_path: &AssetPath, state: ViewState) {
+        self.states.insert(asset_path.to_string(), state);
+    }
+
+    pub fn load(&self, asset_path: &AssetPath) -> Option<&ViewState> {
+        self.states.get(&asset_path.to_string())
+    }
+
+    pub fn remove(&mut self, asset_path: &AssetPath) -> Option<ViewState> {
+        self.states.remove(&asset_path.to_string())
+    }
+
+    pub fn to_ron(&self) -> Result<String, ViewStateError> {
+        Ok(ron::ser::to_string_pretty(self, default())?)
+    }
+
+    pub fn from_ron(ron: &str) -> Result<Self, ViewStateError> {
+        Ok(ron::from_str(ron)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restores_the_eye_dome_settings() {
+        let eye_dome_settings = EyeDomeSettings {
+            strength: 3.0,
+            radius: 2.0,
+            ..default()
+        };
+        let point_cloud = PotreePointCloud {
+            point_size: 0.5,
+            ..default()
+        };
+        let state = ViewState::capture(
+            &Transform::from_xyz(1.0, 2.0, 3.0),
+            &point_cloud,
+            None,
+            Some(&eye_dome_settings),
+        );
+        let mut states = ViewStates::default();
+        let path = AssetPath::from("scan.las");
+        states.save(&path, state);
+        let states = ViewStates::from_ron(&states.to_ron().unwrap()).unwrap();
+
+        let (mut camera_transform, mut point_cloud) = default();
+        let mut eye_dome_settings = EyeDomeSettings::default();
+        states.load(&path).unwrap().apply(
+            &mut camera_transform,
+            &mut point_cloud,
+            &mut eye_dome_settings,
+        );
+        assert_eq!(camera_transform.translation, Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(point_cloud.point_size, 0.5);
+        assert_eq!(
+            (eye_dome_settings.strength, eye_dome_settings.radius),
+            (3.0, 2.0)
+        );
+    }
+
+    #[test]
+    fn loads_states_saved_without_eye_dome_settings() {
+        let state: ViewState = ron::from_str(
+            "(camera_translation: (0.0, 0.0, 0.0), camera_rotation: (0.0, 0.0, 0.0, 1.0), \
+             point_size: 1.0, color_mode: Rgb)",
+        )
+        .unwrap();
+        let defaults = EyeDomeSettings::default();
+        assert_eq!(state.eye_dome_strength, defaults.strength);
+        assert_eq!(state.eye_dome_radius, defaults.radius);
+    }
+}
@@ -0,0 +1,127 @@
+use std::sync::{Arc, Mutex};
+
+use bevy::{
+    math::Affine3A,
+    prelude::*,
+    render::primitives::{Aabb, Frustum},
+    utils::HashSet,
+};
+
+/// The [`PotreePointCloud`](crate::PotreePointCloud) entities that were drawn by at least one
+/// view in the most recently rendered frame.
+///
+/// Clouds that are culled, hidden, or whose asset or pipeline isn't ready yet are not included.
+/// With pipelined rendering this lags one frame behind the main world.
+#[derive(Resource, Default, Debug)]
+pub struct VisiblePointClouds {
+    entities: HashSet<Entity>,
+}
+
+impl VisiblePointClouds {
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.entities.contains(&entity)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.entities.iter().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+}
+
+/// How many point clouds were skipped because their bounds were outside of the view frustum,
+/// in the most recently rendered frame. A cloud culled by several views is counted once per
+/// view. Lags one frame behind the main world with pipelined rendering, like
+/// [`VisiblePointClouds`].
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PointCloudCullingStats {
+    pub culled: usize,
+}
+
+/// What the point cloud render node drew in the most recently rendered frame, summed over all
+/// views, for performance overlays. Lags one frame behind the main world with pipelined
+/// rendering, like [`VisiblePointClouds`].
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PointCloudStats {
+    /// Points submitted for drawing, after frustum culling, occlusion and
+    /// [`PointCloudLod`](crate::PointCloudLod). Points discarded by the shaders, like
+    /// clipped or stochastically transparent ones, are included.
+    pub drawn_points: u64,
+    /// Draws of point clouds. A cloud drawn by several views is counted once per view.
+    pub drawn_clouds: u32,
+    /// Point clouds skipped because their bounds were outside of a view's frustum, counted
+    /// once per view.
+    pub culled_clouds: u32,
+}
+
+#[derive(Default)]
+struct DrawnPointClouds {
+    /// Filled by the render node while the current frame is being rendered.
+    in_progress: HashSet<Entity>,
+    /// The complete set of the last rendered frame.
+    last_frame: HashSet<Entity>,
+    culled_in_progress: usize,
+    culled_last_frame: usize,
+    stats_in_progress: PointCloudStats,
+    stats_last_frame: PointCloudStats,
+}
+
+/// Carries the drawn point clouds from the render world back to the main world.
+/// The same channel is inserted into both worlds.
+#[derive(Resource, Clone, Default)]
+pub(crate) struct DrawnPointCloudsChannel(Arc<Mutex<DrawnPointClouds>>);
+
+impl DrawnPointCloudsChannel {
+    pub fn record(&self, entities: impl IntoIterator<Item = Entity>) {
+        self.0.lock().unwrap().in_progress.extend(entities);
+    }
+
+    pub fn record_culled(&self, count: usize) {
+        let mut drawn = self.0.lock().unwrap();
+        drawn.culled_in_progress += count;
+        drawn.stats_in_progress.culled_clouds += count as u32;
+    }
+
+    pub fn record_points(&self, clouds: usize, points: u64) {
+        let stats = &mut self.0.lock().unwrap().stats_in_progress;
+        stats.drawn_clouds += clouds as u32;
+        stats.drawn_points += points;
+    }
+}
+
+/// Whether `aabb`, in the local space of `transform`, is entirely outside of `frustum`.
+pub(crate) fn is_frustum_culled(frustum: &Frustum, aabb: &Aabb, transform: &Mat4) -> bool {
+    // The far plane is ignored, like Bevy does, since it is at infinity for perspective
+    // projections.
+    !frustum.intersects_obb(aabb, &Affine3A::from_mat4(*transform), true, false)
+}
+
+/// Runs in the render world once all views have been drawn.
+pub(crate) fn finish_drawn_point_clouds(channel: Res<DrawnPointCloudsChannel>) {
+    let mut drawn = channel.0.lock().unwrap();
+    drawn.last_frame = std::mem::take(&mut drawn.in_progress);
+    drawn.culled_last_frame = std::mem::take(&mut drawn.culled_in_progress);
+    drawn.stats_last_frame = std::mem::take(&mut drawn.stats_in_progress);
+}
+
+pub(crate) fn sync_visible_point_clouds(
+    channel: Res<DrawnPointCloudsChannel>,
+    mut visible: ResMut<VisiblePointClouds>,
+    mut culling_stats: ResMut<PointCloudCullingStats>,
+    mut stats: ResMut<PointCloudStats>,
+) {
+    let drawn = channel.0.lock().unwrap();
+    stats.set_if_neq(drawn.stats_last_frame);
+    culling_stats.set_if_neq(PointCloudCullingStats {
+        culled: drawn.culled_last_frame,
+    });
+    if drawn.last_frame != visible.entities {
+        visible.entities = drawn.last_frame.clone();
+    }
+}
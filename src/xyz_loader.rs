@@ -0,0 +1,202 @@
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    math::DVec3,
+    prelude::*,
+    render::render_resource::PrimitiveTopology,
+    utils::{
+        thiserror::{self, Error},
+        BoxedFuture,
+    },
+};
+
+use crate::{color::pack_rgba8, PointCloudAsset, ATTRIBUTE_COLOR_PACKED};
+
+/// Loads `.xyz` text files, with one point per line as `x y z` and optionally `r g b`. Values
+/// are separated by spaces, tabs or commas, and empty lines and lines starting with `#` or
+/// `//` are skipped.
+///
+/// Colors are only read if every point has them, from `0` to `255`, or from `0.0` to `1.0` if
+/// none is above `1.0`. Further columns are ignored. Files without colors load as uncolored
+/// assets; color them with [`PointColorMode::Height`](crate::PointColorMode::Height) or
+/// [`PointColorMode::Uniform`](crate::PointColorMode::Uniform). The points are recentered, with
+/// the center kept in [`PointCloudAsset::origin`].
+#[derive(Default)]
+pub struct XyzLoader;
+
+/// Possible errors that can be produced by [`XyzLoader`]
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum XyzLoaderError {
+    #[error("Could not load asset: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid XYZ file on line {line}: {message}")]
+    Parse { line: usize, message: String },
+}
+
+impl XyzLoader {
+    pub fn load_xyz(text: &str) -> Result<PointCloudAsset, XyzLoaderError> {
+        let mut positions = Vec::new();
+        let mut colors = Vec::new();
+        let mut colored = true;
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+                continue;
+            }
+            let parse_error = |message: String| XyzLoaderError::Parse {
+                line: index + 1,
+                message,
+            };
+            let values = line
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|value| !value.is_empty())
+                .take(6)
+                .map(|value| {
+                    value
+                        .parse::<f64>()
+                        .map_err(|_| parse_error(format!("{value:?} isn't a number")))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if values.len() < 3 {
+                return Err(parse_error("expected at least x, y and z".to_owned()));
+            }
+            positions.push(DVec3::new(values[0], values[1], values[2]));
+            if values.len() == 6 {
+                colors.push([values[3], values[4], values[5]]);
+            } else {
+                colored = false;
+            }
+        }
+
+        // Positions can be far from the origin, so center them in double precision.
+        let (min, max) = positions.iter().fold(
+            (DVec3::splat(f64::MAX), DVec3::splat(f64::MIN)),
+            |(min, max), &position| (min.min(position), max.max(position)),
+        );
+        let origin = if positions.is_empty() {
+            DVec3::ZERO
+        } else {
+            (min + max) / 2.0
+        };
+        let positions: Vec<Vec3> = positions
+            .into_iter()
+            .map(|position| (position - origin).as_vec3())
+            .collect();
+
+        let mut mesh = Mesh::new(PrimitiveTopology::PointList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        if colored && !colors.is_empty() {
+            let scale = if colors.iter().flatten().all(|&channel| channel <= 1.0) {
+                255.0
+            } else {
+                1.0
+            };
+            let colors: Vec<u32> = colors
+                .into_iter()
+                .map(|color| {
+                    let [r, g, b] = color.map(|channel| (channel * scale).clamp(0.0, 255.0) as u8);
+                    pack_rgba8([r, g, b, u8::MAX])
+                })
+                .collect();
+            mesh.insert_attribute(ATTRIBUTE_COLOR_PACKED, colors);
+        }
+        let mut asset = PointCloudAsset::new(mesh);
+        asset.origin = origin;
+        Ok(asset)
+    }
+}
+
+impl AssetLoader for XyzLoader {
+    type Asset = PointCloudAsset;
+    type Settings = ();
+    type Error = XyzLoaderError;
+
+    fn extensions(&self) -> &[&str] {
+        &["xyz"]
+    }
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut text = String::new();
+            reader.read_to_string(&mut text).await?;
+            Self::load_xyz(&text)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::mesh::VertexAttributeValues;
+
+    #[test]
+    fn loads_empty_files() {
+        for file in ["", "# no points\n\n"] {
+            let asset = XyzLoader::load_xyz(file).unwrap();
+            assert_eq!(asset.num_points(), 0);
+            assert!(asset.aabb.center.is_finite());
+            assert!(asset.aabb.half_extents.is_finite());
+            assert_eq!(asset.origin, DVec3::ZERO);
+        }
+    }
+
+    fn colors(asset: &PointCloudAsset) -> Option<&[u32]> {
+        match asset.mesh.attribute(ATTRIBUTE_COLOR_PACKED)? {
+            VertexAttributeValues::Uint32(colors) => Some(colors),
+            _ => panic!("unexpected color format"),
+        }
+    }
+
+    #[test]
+    fn loads_positions_around_their_center() {
+        let file = "# x y z\n1000000.5 2 -3\n\n// comment\n1000001.5,4,\t-1\n";
+        let asset = XyzLoader::load_xyz(file).unwrap();
+        assert_eq!(asset.num_points(), 2);
+        assert_eq!(asset.origin, DVec3::new(1000001.0, 3.0, -2.0));
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            asset.mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            panic!("missing positions");
+        };
+        assert_eq!(positions, &[[-0.5, -1.0, -1.0], [0.5, 1.0, 1.0]]);
+        assert!(colors(&asset).is_none());
+    }
+
+    #[test]
+    fn loads_colors() {
+        let asset = XyzLoader::load_xyz("0 0 0 255 128 0\n1 1 1 0 64 255 0.5\n").unwrap();
+        assert_eq!(
+            colors(&asset).unwrap(),
+            &[
+                pack_rgba8([255, 128, 0, 255]),
+                pack_rgba8([0, 64, 255, 255])
+            ]
+        );
+
+        // Colors are from 0.0 to 1.0 if none is above 1.0.
+        let asset = XyzLoader::load_xyz("0 0 0 1.0 0.5 0\n").unwrap();
+        assert_eq!(colors(&asset).unwrap(), &[pack_rgba8([255, 127, 0, 255])]);
+    }
+
+    #[test]
+    fn only_keeps_colors_of_every_point() {
+        let asset = XyzLoader::load_xyz("0 0 0 255 0 0\n1 1 1\n").unwrap();
+        assert_eq!(asset.num_points(), 2);
+        assert!(colors(&asset).is_none());
+    }
+
+    #[test]
+    fn reports_the_line_of_errors() {
+        for (file, error_line) in [("0 0 0\n\n1 x 1\n", 3), ("0 0\n", 1)] {
+            match XyzLoader::load_xyz(file) {
+                Err(XyzLoaderError::Parse { line, .. }) => assert_eq!(line, error_line),
+                result => panic!("expected a parse error, got {:?}", result.err()),
+            }
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Renders a small cloud with eye dome lighting and compares it with a reference image.
+//!
+//! Run with `cargo test --features headless --test eye_dome_golden`. After an intended change
+//! to the output, set `UPDATE_GOLDEN=1` to write a new reference image, and check it in.
+
+use std::path::{Path, PathBuf};
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        texture::{CompressedImageFormats, ImageSampler, ImageType},
+    },
+};
+use bevy_fsc_point_cloud::{
+    EyeDomeSettings, HeadlessRenderer, PointCloudAsset, PointCloudBundle, PointCloudPlugin,
+    PotreePointCloud,
+};
+
+const SIZE: UVec2 = UVec2::new(128, 128);
+const REFERENCE: &str = "tests/golden/eye_dome.png";
+/// The largest difference allowed in any channel of a pixel, out of 255, for small differences
+/// in rasterization between GPUs.
+const CHANNEL_TOLERANCE: u8 = 8;
+/// The fraction of the pixels that may differ by more than [`CHANNEL_TOLERANCE`], for points
+/// on the edge of a pixel that land on either side on different GPUs.
+const PIXEL_TOLERANCE: f32 = 0.01;
+
+/// Two stacked slabs of points, so that eye dome lighting outlines both the silhouette and the
+/// step between them.
+fn scene_points() -> (Vec<Vec3>, Vec<[u8; 4]>) {
+    let mut positions = Vec::new();
+    for x in -20..=20 {
+        for y in -20..=20 {
+            let z = if x < 0 { 0.0 } else { 2.0 };
+            positions.push(Vec3::new(x as f32 * 0.1, y as f32 * 0.1, z));
+        }
+    }
+    let colors = vec![[200, 200, 200, 255]; positions.len()];
+    (positions, colors)
+}
+
+fn render_scene(eye_dome: EyeDomeSettings) -> Vec<u8> {
+    let mut renderer = HeadlessRenderer::new(SIZE, PointCloudPlugin::default());
+    let target = renderer.render_target();
+    let world = renderer.world_mut();
+    world.insert_resource(Msaa::Off);
+    let (positions, colors) = scene_points();
+    let mesh = world
+        .resource_mut::<Assets<PointCloudAsset>>()
+        .add(PointCloudAsset::from_points(positions, colors).unwrap());
+    world.spawn(PointCloudBundle {
+        point_cloud: PotreePointCloud {
+            mesh,
+            point_size: 0.3,
+            ..default()
+        },
+        ..default()
+    });
+    world.spawn((
+        Camera3dBundle {
+            camera: Camera {
+                target,
+                ..default()
+            },
+            transform: Transform::from_xyz(0.0, 0.0, 8.0).looking_at(Vec3::ZERO, Vec3::Y),
+            ..default()
+        },
+        eye_dome,
+    ));
+
+    // Pipelines compile asynchronously, so render until the frame stops changing.
+    let mut previous = None;
+    for _ in 0..100 {
+        let frame = renderer
+            .render(1)
+            .expect("nothing was rendered to the target");
+        if previous.as_ref() == Some(&frame) && frame.iter().any(|&channel| channel != 0) {
+            return frame;
+        }
+        previous = Some(frame);
+    }
+    panic!("the frame didn't settle");
+}
+
+fn eye_dome_settings() -> EyeDomeSettings {
+    EyeDomeSettings {
+        strength: 2.0,
+        ..default()
+    }
+}
+
+fn reference_path() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(REFERENCE)
+}
+
+fn load_reference() -> Vec<u8> {
+    let reference = Image::from_buffer(
+        &std::fs::read(reference_path())
+            .expect("missing reference image, run with UPDATE_GOLDEN=1"),
+        ImageType::Extension("png"),
+        CompressedImageFormats::NONE,
+        true,
+        ImageSampler::Default,
+    )
+    .unwrap();
+    assert_eq!(reference.size(), SIZE, "reference has a different size");
+    reference.data
+}
+
+/// The fraction of the pixels of `frame` that differ from `reference` by more than
+/// [`CHANNEL_TOLERANCE`].
+fn differing_fraction(frame: &[u8], reference: &[u8]) -> f32 {
+    assert_eq!(frame.len(), reference.len());
+    let differing = frame
+        .chunks_exact(4)
+        .zip(reference.chunks_exact(4))
+        .filter(|(pixel, expected)| {
+            pixel
+                .iter()
+                .zip(expected.iter())
+                .any(|(&a, &b)| a.abs_diff(b) > CHANNEL_TOLERANCE)
+        })
+        .count();
+    differing as f32 / (SIZE.x * SIZE.y) as f32
+}
+
+#[test]
+fn eye_dome_lighting_matches_reference() {
+    let frame = render_scene(eye_dome_settings());
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        let path = reference_path();
+        let image = Image::new(
+            Extent3d {
+                width: SIZE.x,
+                height: SIZE.y,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            frame,
+            TextureFormat::Rgba8UnormSrgb,
+        );
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        image
+            .try_into_dynamic()
+            .unwrap()
+            .save(&path)
+            .expect("failed to write the reference image");
+        return;
+    }
+
+    let fraction = differing_fraction(&frame, &load_reference());
+    assert!(
+        fraction <= PIXEL_TOLERANCE,
+        "{:.1}% of the pixels differ from {REFERENCE}",
+        fraction * 100.0
+    );
+}
+
+/// Makes sure the reference actually shows the outlines, and the test above would catch eye
+/// dome lighting silently not running.
+#[test]
+fn reference_differs_without_eye_dome_lighting() {
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        return;
+    }
+    let frame = render_scene(EyeDomeSettings {
+        enabled: false,
+        ..eye_dome_settings()
+    });
+    assert!(differing_fraction(&frame, &load_reference()) > 0.1);
+}
@@ -0,0 +1,610 @@
+//! Renders small scenes with the headless renderer and checks the pixels of the frames.
+//!
+//! Run with `cargo test --features headless --test rendering`.
+
+use bevy::{
+    core_pipeline::tonemapping::{DebandDither, Tonemapping},
+    prelude::*,
+};
+use bevy_fsc_point_cloud::{
+    EyeDomeBlendedClouds, EyeDomeSettings, HeadlessRenderer, InstancedPointCloud, PointBlendMode,
+    PointCloudAsset, PointCloudBundle, PointCloudPlugin, PointColorMode, PotreePointCloud,
+    XyzLoader,
+};
+
+const SIZE: UVec2 = UVec2::new(64, 64);
+
+/// A renderer with MSAA off and a black background. With the tonemapping and dithering of
+/// [`spawn_camera`] off too, the pixels are exactly the point colors.
+fn renderer() -> HeadlessRenderer {
+    let mut renderer = HeadlessRenderer::new(SIZE, PointCloudPlugin::default());
+    let world = renderer.world_mut();
+    world.insert_resource(Msaa::Off);
+    world.insert_resource(ClearColor(Color::BLACK));
+    renderer
+}
+
+fn spawn_camera(renderer: &mut HeadlessRenderer, transform: Transform) -> Entity {
+    let target = renderer.render_target();
+    renderer
+        .world_mut()
+        .spawn(Camera3dBundle {
+            camera: Camera {
+                target,
+                ..default()
+            },
+            tonemapping: Tonemapping::None,
+            dither: DebandDither::Disabled,
+            transform,
+            ..default()
+        })
+        .id()
+}
+
+/// For tests of the point colors, since eye dome lighting darkens the outlines of the points.
+fn disable_eye_dome_lighting(renderer: &mut HeadlessRenderer, camera: Entity) {
+    renderer
+        .world_mut()
+        .entity_mut(camera)
+        .insert(EyeDomeSettings {
+            enabled: false,
+            ..default()
+        });
+}
+
+fn add_asset(renderer: &mut HeadlessRenderer, asset: PointCloudAsset) -> Handle<PointCloudAsset> {
+    renderer
+        .world_mut()
+        .resource_mut::<Assets<PointCloudAsset>>()
+        .add(asset)
+}
+
+fn spawn_point_cloud(
+    renderer: &mut HeadlessRenderer,
+    mesh: Handle<PointCloudAsset>,
+    point_size: f32,
+) -> Entity {
+    renderer
+        .world_mut()
+        .spawn(PointCloudBundle {
+            point_cloud: PotreePointCloud {
+                mesh,
+                point_size,
+                ..default()
+            },
+            ..default()
+        })
+        .id()
+}
+
+/// Renders until two frames in a row are the same, since pipelines compile asynchronously.
+fn settled_frame(renderer: &mut HeadlessRenderer) -> Vec<u8> {
+    let mut previous = None;
+    for _ in 0..100 {
+        let frame = renderer
+            .render(1)
+            .expect("nothing was rendered to the target");
+        if previous.as_ref() == Some(&frame) {
+            return frame;
+        }
+        previous = Some(frame);
+    }
+    panic!("the frame didn't settle");
+}
+
+/// Like [`settled_frame`], but waits for something other than the background to show up.
+fn settled_non_empty_frame(renderer: &mut HeadlessRenderer) -> Vec<u8> {
+    for _ in 0..100 {
+        let frame = settled_frame(renderer);
+        if covered_pixels(&frame) > 0 {
+            return frame;
+        }
+    }
+    panic!("nothing but the background was rendered");
+}
+
+/// The number of pixels that aren't the black background.
+fn covered_pixels(frame: &[u8]) -> usize {
+    frame
+        .chunks_exact(4)
+        .filter(|pixel| pixel[..3] != [0, 0, 0])
+        .count()
+}
+
+#[test]
+fn empty_point_clouds_render_nothing() {
+    let mut renderer = renderer();
+    spawn_camera(
+        &mut renderer,
+        Transform::from_xyz(0.0, 0.0, 4.0).looking_at(Vec3::ZERO, Vec3::Y),
+    );
+    let empty = add_asset(
+        &mut renderer,
+        PointCloudAsset::from_points(Vec::new(), Vec::new()).unwrap(),
+    );
+    spawn_point_cloud(&mut renderer, empty, 1.0);
+
+    // Give the asset time to be prepared and drawn, if it were.
+    renderer.render(10);
+    let frame = settled_frame(&mut renderer);
+    assert_eq!(covered_pixels(&frame), 0);
+}
+
+#[test]
+fn points_face_the_camera_from_every_direction() {
+    let mut renderer = renderer();
+    let camera = spawn_camera(&mut renderer, Transform::default());
+    let point = add_asset(
+        &mut renderer,
+        PointCloudAsset::from_points(vec![Vec3::ZERO], vec![[255; 4]]).unwrap(),
+    );
+    spawn_point_cloud(&mut renderer, point, 2.0);
+
+    let orientations = [
+        (Vec3::Z, Vec3::Y),
+        (Vec3::X, Vec3::Y),
+        (Vec3::new(1.0, 2.0, -1.0), Vec3::Y),
+        (Vec3::Y, Vec3::X),
+        // Rolled around the view direction.
+        (Vec3::Z, Vec3::new(1.0, 1.0, 0.0)),
+        (Vec3::new(-1.0, 0.5, 1.0), Vec3::new(0.3, -1.0, 0.2)),
+    ];
+    let mut sizes = Vec::new();
+    for (direction, up) in orientations {
+        *renderer.world_mut().get_mut::<Transform>(camera).unwrap() =
+            Transform::from_translation(direction.normalize() * 4.0).looking_at(Vec3::ZERO, up);
+        sizes.push(covered_pixels(&settled_non_empty_frame(&mut renderer)));
+    }
+    assert!(
+        sizes.iter().all(|&size| size == sizes[0]),
+        "the point covers a different number of pixels from different directions: {sizes:?}"
+    );
+}
+
+#[test]
+fn modified_assets_are_uploaded_again() {
+    let mut renderer = renderer();
+    spawn_camera(
+        &mut renderer,
+        Transform::from_xyz(0.0, 0.0, 4.0).looking_at(Vec3::ZERO, Vec3::Y),
+    );
+    let mesh = add_asset(
+        &mut renderer,
+        PointCloudAsset::from_points(vec![Vec3::ZERO], vec![[255; 4]]).unwrap(),
+    );
+    spawn_point_cloud(&mut renderer, mesh.clone(), 1.0);
+    let one_point = covered_pixels(&settled_non_empty_frame(&mut renderer));
+
+    // Like a hot reload of the file, which replaces the asset behind the same handle.
+    let two_points = PointCloudAsset::from_points(
+        vec![Vec3::new(-1.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)],
+        vec![[255; 4]; 2],
+    )
+    .unwrap();
+    renderer
+        .world_mut()
+        .resource_mut::<Assets<PointCloudAsset>>()
+        .insert(mesh, two_points);
+    renderer.render(1);
+    // The points land on the pixel grid differently, so their sizes can be a pixel apart.
+    let two_points = covered_pixels(&settled_non_empty_frame(&mut renderer));
+    assert!(
+        two_points.abs_diff(2 * one_point) <= one_point / 4,
+        "{one_point} pixels with one point, {two_points} with two"
+    );
+}
+
+#[test]
+fn points_behind_the_camera_are_not_drawn() {
+    let in_front = Vec3::new(0.0, 0.0, -4.0);
+    let render_points = |positions: Vec<Vec3>| {
+        let mut renderer = renderer();
+        // Inside the cloud, looking down -Z.
+        spawn_camera(&mut renderer, Transform::default());
+        let colors = vec![[255; 4]; positions.len()];
+        let mesh = add_asset(
+            &mut renderer,
+            PointCloudAsset::from_points(positions, colors).unwrap(),
+        );
+        spawn_point_cloud(&mut renderer, mesh, 1.0);
+        settled_non_empty_frame(&mut renderer)
+    };
+
+    let expected = render_points(vec![in_front]);
+    let frame = render_points(vec![
+        in_front,
+        // Right behind the camera and close to its plane, where the quads would explode.
+        Vec3::new(0.0, 0.0, 0.05),
+        Vec3::new(0.1, -0.2, 0.5),
+        Vec3::new(-0.5, 0.3, 2.0),
+        Vec3::new(0.01, 0.01, 0.0),
+    ]);
+    assert_eq!(covered_pixels(&frame), covered_pixels(&expected));
+    assert!(
+        frame == expected,
+        "points behind the camera changed the frame"
+    );
+}
+
+#[test]
+fn point_clouds_can_share_an_asset() {
+    let mut renderer = renderer();
+    spawn_camera(
+        &mut renderer,
+        Transform::from_xyz(0.0, 0.0, 4.0).looking_at(Vec3::ZERO, Vec3::Y),
+    );
+    let mesh = add_asset(
+        &mut renderer,
+        PointCloudAsset::from_points(vec![Vec3::ZERO], vec![[255; 4]]).unwrap(),
+    );
+    let left = spawn_point_cloud(&mut renderer, mesh.clone(), 1.0);
+    let right = spawn_point_cloud(&mut renderer, mesh, 1.0);
+    for (entity, x) in [(left, -1.0), (right, 1.0)] {
+        renderer
+            .world_mut()
+            .get_mut::<Transform>(entity)
+            .unwrap()
+            .translation
+            .x = x;
+    }
+    let both = covered_pixels(&settled_non_empty_frame(&mut renderer));
+
+    // The other cloud still holds the asset, so its buffers must stay alive.
+    renderer.world_mut().despawn(left);
+    renderer.render(10);
+    let one = covered_pixels(&settled_non_empty_frame(&mut renderer));
+    assert!(
+        one.abs_diff(both / 2) <= both / 8,
+        "{both} pixels with both clouds, {one} after despawning one"
+    );
+}
+
+#[test]
+fn point_clouds_can_be_spawned_many_frames_apart() {
+    let mut renderer = renderer();
+    let camera = spawn_camera(
+        &mut renderer,
+        Transform::from_xyz(0.0, 0.0, 4.0).looking_at(Vec3::ZERO, Vec3::Y),
+    );
+    disable_eye_dome_lighting(&mut renderer, camera);
+    let red = add_asset(
+        &mut renderer,
+        PointCloudAsset::from_points(vec![Vec3::new(-1.0, 0.0, 0.0)], vec![[255, 0, 0, 255]])
+            .unwrap(),
+    );
+    spawn_point_cloud(&mut renderer, red, 1.0);
+    renderer.render(149);
+
+    // A second asset entering the render world must not tear down the first one's buffers.
+    let green = add_asset(
+        &mut renderer,
+        PointCloudAsset::from_points(vec![Vec3::new(1.0, 0.0, 0.0)], vec![[0, 255, 0, 255]])
+            .unwrap(),
+    );
+    spawn_point_cloud(&mut renderer, green, 1.0);
+    renderer.render(1);
+    let frame = settled_non_empty_frame(&mut renderer);
+    let pixels_of = |color: [u8; 3]| {
+        frame
+            .chunks_exact(4)
+            .filter(|pixel| pixel[..3] == color)
+            .count()
+    };
+    assert!(pixels_of([255, 0, 0]) > 0, "the first cloud isn't drawn");
+    assert!(pixels_of([0, 255, 0]) > 0, "the second cloud isn't drawn");
+}
+
+#[test]
+fn srgb_colors_are_linearized() {
+    let mut renderer = renderer();
+    let camera = spawn_camera(
+        &mut renderer,
+        Transform::from_xyz(0.0, 0.0, 4.0).looking_at(Vec3::ZERO, Vec3::Y),
+    );
+    disable_eye_dome_lighting(&mut renderer, camera);
+    let mesh = add_asset(
+        &mut renderer,
+        PointCloudAsset::from_points(vec![Vec3::ZERO], vec![[128, 128, 128, 255]]).unwrap(),
+    );
+    spawn_point_cloud(&mut renderer, mesh, 1.0);
+
+    // The shader has to convert the color to its linear value of about 0.216, for the sRGB
+    // target to encode it back into 128. Without the conversion, the linear value would be
+    // `128.0 / 255.0`, and be written as about 188.
+    let frame = settled_non_empty_frame(&mut renderer);
+    for pixel in frame
+        .chunks_exact(4)
+        .filter(|pixel| pixel[..3] != [0, 0, 0])
+    {
+        for &channel in &pixel[..3] {
+            assert!(
+                channel.abs_diff(128) <= 1,
+                "pixel {pixel:?} is not mid-gray"
+            );
+        }
+    }
+}
+
+#[test]
+fn uncolored_point_clouds_use_the_uniform_color() {
+    let mut renderer = renderer();
+    let camera = spawn_camera(
+        &mut renderer,
+        Transform::from_xyz(0.0, 0.0, 4.0).looking_at(Vec3::ZERO, Vec3::Y),
+    );
+    disable_eye_dome_lighting(&mut renderer, camera);
+    let asset = XyzLoader::load_xyz("-0.5 0 0\n0.5 0 0\n").unwrap();
+    let mesh = add_asset(&mut renderer, asset);
+    let point_cloud = spawn_point_cloud(&mut renderer, mesh, 1.0);
+    renderer
+        .world_mut()
+        .entity_mut(point_cloud)
+        .insert(PointColorMode::Uniform(Color::rgb_u8(0, 255, 0)));
+
+    let frame = settled_non_empty_frame(&mut renderer);
+    for pixel in frame
+        .chunks_exact(4)
+        .filter(|pixel| pixel[..3] != [0, 0, 0])
+    {
+        assert_eq!(pixel, [0, 255, 0, 255]);
+    }
+}
+
+#[test]
+fn instances_are_uploaded_again_when_they_change() {
+    let mut renderer = renderer();
+    spawn_camera(
+        &mut renderer,
+        Transform::from_xyz(0.0, 0.0, 4.0).looking_at(Vec3::ZERO, Vec3::Y),
+    );
+    let point = add_asset(
+        &mut renderer,
+        PointCloudAsset::from_points(vec![Vec3::ZERO], vec![[255; 4]]).unwrap(),
+    );
+    let left = Transform::from_xyz(-1.0, 0.0, 0.0);
+    let right = Transform::from_xyz(1.0, 0.0, 0.0);
+    let instanced = renderer
+        .world_mut()
+        .spawn((
+            InstancedPointCloud::from_transforms(point, [left]),
+            SpatialBundle::default(),
+        ))
+        .id();
+    let left_frame = settled_non_empty_frame(&mut renderer);
+    let one_instance = covered_pixels(&left_frame);
+
+    let set_instances = |renderer: &mut HeadlessRenderer, transforms: &[Transform]| {
+        let mut entity = renderer.world_mut().entity_mut(instanced);
+        let mut point_cloud = entity.get_mut::<InstancedPointCloud>().unwrap();
+        point_cloud.instances = transforms
+            .iter()
+            .map(|&transform| (transform, Color::WHITE))
+            .collect();
+        renderer.render(1);
+        settled_non_empty_frame(renderer)
+    };
+    let two_instances = covered_pixels(&set_instances(&mut renderer, &[left, right]));
+    assert!(
+        two_instances.abs_diff(2 * one_instance) <= one_instance / 4,
+        "{one_instance} pixels with one instance, {two_instances} with two"
+    );
+
+    let right_frame = set_instances(&mut renderer, &[right]);
+    assert_ne!(right_frame, left_frame);
+    assert!(covered_pixels(&right_frame).abs_diff(one_instance) <= one_instance / 4);
+}
+
+/// An opaque point behind a larger, half transparent one, lit with `blended_clouds`, or without
+/// eye dome lighting for `None`.
+fn render_blended_over_opaque(blended_clouds: Option<EyeDomeBlendedClouds>) -> Vec<u8> {
+    let mut renderer = renderer();
+    let camera = spawn_camera(
+        &mut renderer,
+        Transform::from_xyz(0.0, 0.0, 4.0).looking_at(Vec3::ZERO, Vec3::Y),
+    );
+    renderer
+        .world_mut()
+        .entity_mut(camera)
+        .insert(EyeDomeSettings {
+            strength: 2.0,
+            blended_clouds: blended_clouds.unwrap_or_default(),
+            enabled: blended_clouds.is_some(),
+            ..default()
+        });
+    let opaque = add_asset(
+        &mut renderer,
+        PointCloudAsset::from_points(vec![Vec3::new(-0.5, -0.5, 0.0)], vec![[255; 4]]).unwrap(),
+    );
+    spawn_point_cloud(&mut renderer, opaque, 1.0);
+    let blended = add_asset(
+        &mut renderer,
+        PointCloudAsset::from_points(vec![Vec3::new(-0.75, -0.75, 1.0)], vec![[255; 4]]).unwrap(),
+    );
+    renderer.world_mut().spawn(PointCloudBundle {
+        point_cloud: PotreePointCloud {
+            mesh: blended,
+            point_size: 1.5,
+            color: Color::rgba(1.0, 0.0, 0.0, 0.5),
+            blend_mode: PointBlendMode::AlphaBlend,
+            ..default()
+        },
+        ..default()
+    });
+    settled_non_empty_frame(&mut renderer)
+}
+
+#[test]
+fn eye_dome_lighting_can_skip_or_light_blended_clouds() {
+    let without_eye_dome = render_blended_over_opaque(None);
+    assert_eq!(
+        render_blended_over_opaque(Some(EyeDomeBlendedClouds::DisableEyeDome)),
+        without_eye_dome,
+        "eye dome lighting ran in a view with a blended cloud"
+    );
+    let unlit = render_blended_over_opaque(Some(EyeDomeBlendedClouds::Unlit));
+    assert_ne!(unlit, without_eye_dome, "the opaque point wasn't outlined");
+    assert_ne!(
+        render_blended_over_opaque(Some(EyeDomeBlendedClouds::LitByOpaqueDepth)),
+        unlit,
+        "the blended point wasn't darkened by the outline behind it"
+    );
+}
+
+/// An OPD file without frames, the same as written by the OPD loader tests.
+#[cfg(feature = "opd")]
+fn opd_file(positions: &[Vec3]) -> Vec<u8> {
+    let header = format!(
+        r#"{{"version":"1","type":"points","directive":{{"version":"1","meta":{{"projectId":"test","projectName":"test"}},"numCentroids":{},"origin":{{"x":0.0,"y":0.0,"z":0.0}},"precision":1,"scale":[1.0,1.0,1.0],"frames":[]}}}}"#,
+        positions.len()
+    );
+    let mut bytes = b".opd".to_vec();
+    bytes.extend((header.len() as u32).to_be_bytes());
+    bytes.extend(header.as_bytes());
+    for (index, position) in positions.iter().enumerate() {
+        bytes.extend((index as u32).to_be_bytes());
+        for coordinate in position.to_array() {
+            bytes.extend(coordinate.to_be_bytes());
+        }
+    }
+    bytes
+}
+
+#[cfg(feature = "opd")]
+#[test]
+fn streamed_points_are_drawn_from_every_chunk() {
+    use bevy::asset::AssetPath;
+    use bevy_fsc_point_cloud::{PointCloudLoadState, StreamedOpdLoad};
+
+    // The first chunk on one side of the view and the others on the other side, so the points
+    // of every update are checked whether they were read in the same frame or not.
+    let chunk = StreamedOpdLoad::MIN_CHUNK_POINTS;
+    let positions: Vec<_> = (0..StreamedOpdLoad::UPDATES * chunk)
+        .map(|index| Vec3::new(if index < chunk { -1.0 } else { 1.0 }, 0.0, 0.0))
+        .collect();
+    let path = std::env::temp_dir().join(format!(
+        "bevy_potree_streamed_points_{}.opd",
+        std::process::id()
+    ));
+    std::fs::write(&path, opd_file(&positions)).unwrap();
+
+    let mut renderer = renderer();
+    let camera = spawn_camera(
+        &mut renderer,
+        Transform::from_xyz(1.0, 0.0, 6.0).looking_at(Vec3::new(1.0, 0.0, 0.0), Vec3::Y),
+    );
+    disable_eye_dome_lighting(&mut renderer, camera);
+    let point_cloud = spawn_point_cloud(&mut renderer, Handle::default(), 0.5);
+    renderer.world_mut().entity_mut(point_cloud).insert((
+        // Absolute paths replace the root of the default asset source.
+        StreamedOpdLoad {
+            path: AssetPath::from_path(&path).into_owned(),
+        },
+        PointColorMode::Uniform(Color::WHITE),
+    ));
+    for _ in 0..100 {
+        if renderer
+            .world_mut()
+            .get::<StreamedOpdLoad>(point_cloud)
+            .is_none()
+        {
+            break;
+        }
+        renderer.render(1);
+    }
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        renderer.world_mut().get::<PointCloudLoadState>(point_cloud),
+        Some(&PointCloudLoadState::Ready)
+    );
+
+    let frame = settled_non_empty_frame(&mut renderer);
+    let row = SIZE.x as usize * 4;
+    let half_covered = |left: bool| {
+        frame.chunks_exact(row).any(|row| {
+            let (left_half, right_half) = row.split_at(row.len() / 2);
+            covered_pixels(if left { left_half } else { right_half }) > 0
+        })
+    };
+    assert!(half_covered(true), "the first chunk wasn't drawn");
+    assert!(half_covered(false), "the second chunk wasn't drawn");
+}
+
+#[test]
+fn recolored_assets_draw_their_new_colors() {
+    let mut renderer = renderer();
+    let camera = spawn_camera(
+        &mut renderer,
+        Transform::from_xyz(0.0, 0.0, 4.0).looking_at(Vec3::ZERO, Vec3::Y),
+    );
+    disable_eye_dome_lighting(&mut renderer, camera);
+    let mesh = add_asset(
+        &mut renderer,
+        PointCloudAsset::from_points(vec![Vec3::ZERO], vec![[255; 4]]).unwrap(),
+    );
+    spawn_point_cloud(&mut renderer, mesh.clone(), 1.0);
+    settled_non_empty_frame(&mut renderer);
+
+    let recolor = |renderer: &mut HeadlessRenderer, colors: &[[u8; 4]]| {
+        let mut assets = renderer
+            .world_mut()
+            .resource_mut::<Assets<PointCloudAsset>>();
+        assets.get_mut(&mesh).unwrap().update_colors(colors);
+        settled_non_empty_frame(renderer)
+    };
+    let frame = recolor(&mut renderer, &[[255, 0, 0, 255]]);
+    let colors: Vec<_> = frame
+        .chunks_exact(4)
+        .filter(|pixel| pixel[..3] != [0, 0, 0])
+        .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .collect();
+    assert!(!colors.is_empty());
+    assert!(colors.iter().all(|&color| color == [255, 0, 0]));
+
+    // Updates with the wrong number of colors are rejected.
+    assert_eq!(recolor(&mut renderer, &[[0, 255, 0, 255]; 2]), frame);
+}
+
+#[test]
+fn points_are_read_from_vertex_buffers_without_storage_buffers() {
+    let mut renderer = HeadlessRenderer::new(
+        SIZE,
+        PointCloudPlugin {
+            storage_buffers: false,
+            ..default()
+        },
+    );
+    renderer.world_mut().insert_resource(Msaa::Off);
+    renderer
+        .world_mut()
+        .insert_resource(ClearColor(Color::BLACK));
+    let camera = spawn_camera(
+        &mut renderer,
+        Transform::from_xyz(0.0, 0.0, 4.0).looking_at(Vec3::ZERO, Vec3::Y),
+    );
+    disable_eye_dome_lighting(&mut renderer, camera);
+    let mesh = add_asset(
+        &mut renderer,
+        PointCloudAsset::from_points(
+            vec![Vec3::new(-1.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)],
+            vec![[255, 0, 0, 255], [0, 255, 0, 255]],
+        )
+        .unwrap(),
+    );
+    spawn_point_cloud(&mut renderer, mesh, 0.5);
+
+    // Every point gets its own position and color from the instance rate vertex buffers.
+    let frame = settled_non_empty_frame(&mut renderer);
+    let half_colors = |left: bool| -> Vec<[u8; 3]> {
+        frame
+            .chunks_exact(4)
+            .enumerate()
+            .filter(|&(i, pixel)| {
+                ((i as u32 % SIZE.x) < SIZE.x / 2) == left && pixel[..3] != [0, 0, 0]
+            })
+            .map(|(_, pixel)| [pixel[0], pixel[1], pixel[2]])
+            .collect()
+    };
+    let (left, right) = (half_colors(true), half_colors(false));
+    assert!(!left.is_empty() && left.iter().all(|&color| color == [255, 0, 0]));
+    assert!(!right.is_empty() && right.iter().all(|&color| color == [0, 255, 0]));
+}